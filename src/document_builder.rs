@@ -0,0 +1,274 @@
+use html_chunk::{HtmlChunk, ChunkType, Doctype, Namespace};
+use html_document::{Document, NodeId, NodeData, Element, Attribute};
+use html_parser::HtmlParser;
+use serializer::VOID_ELEMENTS;
+use tree_builder::{SPECIAL_TAGS, SCOPE_TAGS, HEADING_TAGS, BLOCK_TAGS};
+
+/// Start tags that close open p apart from block and heading ones, table only does it in no-quirks mode
+const P_CLOSING_TAGS: [&str; 10] = ["dd", "dt", "form", "hr", "li", "listing", "plaintext", "pre", "table", "xmp"];
+
+/// Builds document from chunks balancing tags by simple stack matching
+pub struct DocumentBuilder {
+    document: Document,
+
+    /// Elements that were opened but not closed yet, the last one is current
+    open_elements: Vec<NodeId>,
+}
+
+impl DocumentBuilder {
+    pub fn new() -> DocumentBuilder {
+        DocumentBuilder {
+            document: Document::new(),
+            open_elements: Vec::new(),
+        }
+    }
+
+    /// Adds chunk that parser returned last
+    pub fn add_chunk(&mut self, parser: &mut HtmlParser) {
+        let lenient_doctype = {
+            let chunk = parser.current_chunk();
+
+            chunk.chunk_type == ChunkType::OpenTag && chunk.tag == "!doctype"
+        };
+
+        if lenient_doctype {
+            // lenient parser has no parts of DOCTYPE, so we get them from its HTML
+            parser.set_raw_html();
+        }
+
+        let keep_script_text = parser.extract_between_tags_only;
+        let xml_mode = parser.xml_mode;
+        let chunk = parser.current_chunk();
+
+        match chunk.chunk_type {
+            // template code is kept as it is, so it ends up in text
+            ChunkType::Text | ChunkType::Template => self.add_text(&chunk.html, chunk),
+            ChunkType::OpenTag if xml_mode && !lenient_doctype => {
+                let id = self.add_node(NodeData::Element(element(chunk)), chunk);
+
+                self.open_elements.push(id);
+            },
+            ChunkType::OpenTag if lenient_doctype => {
+                self.add_node(NodeData::Doctype(parse_doctype(&chunk.html)), chunk);
+            },
+            ChunkType::OpenTag => {
+                let id = self.add_node(NodeData::Element(element(chunk)), chunk);
+
+                // conformance mode returns self-closing tags as open tags with end_closure
+                let closed = match chunk.namespace {
+                    Namespace::Html => VOID_ELEMENTS.contains(&chunk.tag.as_str()),
+                    _ => chunk.end_closure,
+                };
+
+                if !closed {
+                    self.open_elements.push(id);
+                }
+            },
+            ChunkType::CloseTag if chunk.end_closure => {
+                // <br/> or <div/> without params, or self-closing SVG and MathML tag
+                self.add_node(NodeData::Element(element(chunk)), chunk);
+            },
+            ChunkType::CloseTag => self.close_element(&chunk.tag),
+            ChunkType::Comment if xml_mode && chunk.tag == "![CDATA[" => {
+                let html = chunk.html.strip_prefix("<![CDATA[").unwrap_or(&chunk.html);
+
+                self.add_text(html.strip_suffix("]]>").unwrap_or(html), chunk);
+            },
+            ChunkType::Comment if xml_mode && chunk.tag == "?" => {},
+            ChunkType::Comment => {
+                self.add_node(NodeData::Comment(chunk.html.clone()), chunk);
+            },
+            ChunkType::Script => {
+                let id = self.add_node(NodeData::Element(element(chunk)), chunk);
+
+                if keep_script_text && !chunk.html.is_empty() {
+                    let text = self.document.create_node(NodeData::Text(chunk.html.clone()));
+
+                    self.document.append_child(id, text);
+                }
+            },
+            ChunkType::Doctype => {
+                let doctype = chunk.doctype.clone().unwrap_or_default();
+
+                self.add_node(NodeData::Doctype(doctype), chunk);
+            },
+        }
+    }
+
+    /// Returns built document, elements that are still open are closed at the end of HTML
+    pub fn finish(self) -> Document {
+        self.document
+    }
+
+    fn current_node(&self) -> NodeId {
+        self.open_elements.last().cloned().unwrap_or_else(|| self.document.root())
+    }
+
+    /// Appends node created from chunk to current node
+    fn add_node(&mut self, data: NodeData, chunk: &HtmlChunk) -> NodeId {
+        let parent = self.current_node();
+        let id = self.document.create_node(data);
+
+        {
+            let node = self.document.node_mut(id);
+
+            node.offset = Some(chunk.chunk_offset);
+            node.length = chunk.chunk_length;
+        }
+
+        self.document.append_child(parent, id);
+
+        id
+    }
+
+    /// Appends text of chunk to current node merging it with text node that is its last child
+    fn add_text(&mut self, text: &str, chunk: &HtmlChunk) {
+        if text.is_empty() {
+            return;
+        }
+
+        let parent = self.current_node();
+
+        if let Some(last) = self.document[parent].last_child() {
+            let node = self.document.node_mut(last);
+
+            if let NodeData::Text(ref mut last_text) = node.data {
+                last_text.push_str(text);
+
+                if let Some(offset) = node.offset {
+                    node.length = chunk.chunk_offset + chunk.chunk_length - offset;
+                }
+
+                return;
+            }
+        }
+
+        self.add_node(NodeData::Text(text.to_string()), chunk);
+    }
+
+    /// Closes the nearest open element with given name along with elements opened after it,
+    /// end tag is ignored if there is no such element
+    fn close_element(&mut self, name: &str) {
+        let document = &self.document;
+
+        if let Some(pos) = self.open_elements.iter().rposition(|&id| document[id].name() == Some(name)) {
+            self.open_elements.truncate(pos);
+        }
+    }
+}
+
+/// Returns whether element that chunk starts is closed by it right away (void or self-closing element,
+/// or script that has got its content and end tag in the same chunk), None if chunk does not start element
+pub fn element_start(chunk: &HtmlChunk, xml_mode: bool) -> Option<bool> {
+    let closed = match chunk.chunk_type {
+        ChunkType::OpenTag if chunk.tag == "!doctype" => return None,
+        ChunkType::OpenTag if xml_mode => false,
+        // conformance mode returns self-closing tags as open tags with end_closure
+        ChunkType::OpenTag => match chunk.namespace {
+            Namespace::Html => VOID_ELEMENTS.contains(&chunk.tag.as_str()),
+            _ => chunk.end_closure,
+        },
+        // <br/> or <div/> without params, or self-closing SVG and MathML tag
+        ChunkType::CloseTag if chunk.end_closure => true,
+        ChunkType::Script => true,
+        _ => return None,
+    };
+
+    Some(closed)
+}
+
+/// Returns position in stack of open elements from which elements are closed implicitly when HTML element
+/// with given name starts, ie position of open li for li or of open p for div, None if nothing is closed.
+/// It follows what tree construction does for p, li, dd, dt, option, td, th and tr, name_of gives name of
+/// open element and it should be empty for elements that are not HTML
+pub fn implied_end<T, F: Fn(&T) -> &str>(open_elements: &[T], name: &str, name_of: F) -> Option<usize> {
+    // li, dd and dt close each other unless there is another special element between them, ie ul
+    let list_boundary = |open: &str| SPECIAL_TAGS.contains(&open) && !matches!(open, "address" | "div" | "p");
+    let current = |names: &[&str], end: usize| {
+        end.checked_sub(1).filter(|&pos| names.contains(&name_of(&open_elements[pos])))
+    };
+
+    let end = match name {
+        "li" => nearest(open_elements, &name_of, &["li"], list_boundary),
+        "dd" | "dt" => nearest(open_elements, &name_of, &["dd", "dt"], list_boundary),
+        "td" | "th" => nearest(open_elements, &name_of, &["td", "th"], |open| open == "table" || open == "tr"),
+        "tr" => nearest(open_elements, &name_of, &["tr"], |open| open == "table"),
+        "option" => current(&["option"], open_elements.len()),
+        "optgroup" => {
+            let option = current(&["option"], open_elements.len());
+
+            current(&["optgroup"], option.unwrap_or(open_elements.len())).or(option)
+        },
+        _ => None,
+    };
+
+    let closes_p = BLOCK_TAGS.contains(&name) || HEADING_TAGS.contains(&name) || P_CLOSING_TAGS.contains(&name);
+
+    if !closes_p {
+        return end;
+    }
+
+    // p is looked for in button scope below elements that were closed already
+    let rest = &open_elements[..end.unwrap_or(open_elements.len())];
+
+    nearest(rest, &name_of, &["p"], |open| SCOPE_TAGS.contains(&open) || open == "button").or(end)
+}
+
+/// Returns position of the nearest open element with one of names, None if boundary element is found first
+fn nearest<T, F, B>(open_elements: &[T], name_of: &F, names: &[&str], boundary: B) -> Option<usize>
+    where F: Fn(&T) -> &str, B: Fn(&str) -> bool {
+    for (pos, open) in open_elements.iter().enumerate().rev() {
+        let name = name_of(open);
+
+        if names.contains(&name) {
+            return Some(pos);
+        }
+
+        if boundary(name) {
+            return None;
+        }
+    }
+
+    None
+}
+
+/// Creates element from tag chunk
+pub fn element(chunk: &HtmlChunk) -> Element {
+    let mut element = Element::new_in(chunk.namespace, &chunk.tag);
+
+    for i in 0..chunk.params_count {
+        element.attributes.push(Attribute {
+            name: chunk.param_names[i].clone(),
+            value: chunk.param_values[i].clone(),
+        });
+    }
+
+    element
+}
+
+/// Parses parts of DOCTYPE from its HTML, ie <!DOCTYPE html PUBLIC "-//W3C//DTD HTML 4.01//EN">
+fn parse_doctype(html: &str) -> Doctype {
+    let rest = html.get(9..).unwrap_or("").trim_end_matches('>');
+
+    // internal subset of XML DOCTYPE is not part of identifiers
+    let rest = rest.split('[').next().unwrap_or(rest);
+    let mut words = rest.split_whitespace();
+    let name = words.next().map(|name| name.to_ascii_lowercase());
+    let keyword = words.next().map(|keyword| keyword.to_ascii_uppercase());
+
+    // quoted identifiers are every second part when split by quotes
+    let ids: Vec<String> = rest.split(['"', '\'']).skip(1).step_by(2).map(String::from).collect();
+
+    let (public_id, system_id) = match keyword.as_deref() {
+        Some("PUBLIC") => (ids.first().cloned(), ids.get(1).cloned()),
+        Some("SYSTEM") => (None, ids.first().cloned()),
+        _ => (None, None),
+    };
+
+    Doctype {
+        name,
+        public_id,
+        system_id,
+        force_quirks: false,
+    }
+}
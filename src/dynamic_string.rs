@@ -103,4 +103,20 @@ impl DynamicString {
 
         &self.text
     }
-}
\ No newline at end of file
+}
+
+/// Converts binary data into string using given encoding, invalid sequences are replaced
+pub fn decode_bytes(enc: EncodingRef, data: &[u8]) -> String {
+    if data.is_empty() {
+        return String::new();
+    }
+
+    if enc.name() == "utf-8" {
+        return String::from_utf8_lossy(data).into_owned();
+    }
+
+    match enc.decode(data, DecoderTrap::Replace) {
+        Ok(s) => s,
+        Err(s) => s.into_owned(),
+    }
+}
@@ -0,0 +1,230 @@
+use html_chunk::{HtmlChunk, ChunkType, Namespace};
+use serializer::VOID_ELEMENTS;
+
+/// SVG element names that are not all lowercase
+const SVG_TAG_NAMES: [&str; 37] = ["altGlyph", "altGlyphDef", "altGlyphItem", "animateColor", "animateMotion",
+    "animateTransform", "clipPath", "feBlend", "feColorMatrix", "feComponentTransfer", "feComposite",
+    "feConvolveMatrix", "feDiffuseLighting", "feDisplacementMap", "feDistantLight", "feDropShadow", "feFlood",
+    "feFuncA", "feFuncB", "feFuncG", "feFuncR", "feGaussianBlur", "feImage", "feMerge", "feMergeNode",
+    "feMorphology", "feOffset", "fePointLight", "feSpecularLighting", "feSpotLight", "feTile", "feTurbulence",
+    "foreignObject", "glyphRef", "linearGradient", "radialGradient", "textPath"];
+
+/// SVG attribute names that are not all lowercase
+const SVG_ATTRIBUTE_NAMES: [&str; 58] = ["attributeName", "attributeType", "baseFrequency", "baseProfile",
+    "calcMode", "clipPathUnits", "diffuseConstant", "edgeMode", "filterUnits", "glyphRef", "gradientTransform",
+    "gradientUnits", "kernelMatrix", "kernelUnitLength", "keyPoints", "keySplines", "keyTimes", "lengthAdjust",
+    "limitingConeAngle", "markerHeight", "markerUnits", "markerWidth", "maskContentUnits", "maskUnits",
+    "numOctaves", "pathLength", "patternContentUnits", "patternTransform", "patternUnits", "pointsAtX",
+    "pointsAtY", "pointsAtZ", "preserveAlpha", "preserveAspectRatio", "primitiveUnits", "refX", "refY",
+    "repeatCount", "repeatDur", "requiredExtensions", "requiredFeatures", "specularConstant",
+    "specularExponent", "spreadMethod", "startOffset", "stdDeviation", "stitchTiles", "surfaceScale",
+    "systemLanguage", "tableValues", "targetX", "targetY", "textLength", "viewBox", "viewTarget",
+    "xChannelSelector", "yChannelSelector", "zoomAndPan"];
+
+/// Start tags that close SVG and MathML elements as they can only be HTML, font does it only if it has
+/// color, face or size
+const BREAKOUT_TAGS: [&str; 45] = ["b", "big", "blockquote", "body", "br", "center", "code", "dd", "div", "dl",
+    "dt", "em", "embed", "font", "h1", "h2", "h3", "h4", "h5", "h6", "head", "hr", "i", "img", "li", "listing",
+    "menu", "meta", "nobr", "ol", "p", "pre", "ruby", "s", "small", "span", "strong", "strike", "sub", "sup",
+    "table", "tt", "u", "ul", "var"];
+
+/// Returns case-adjusted name of SVG element, ie clipPath for clippath
+pub fn adjust_svg_tag_name(name: &str) -> Option<&'static str> {
+    SVG_TAG_NAMES.iter().find(|adjusted| adjusted.eq_ignore_ascii_case(name)).cloned()
+}
+
+/// Returns case-adjusted name of attribute of SVG or MathML element, ie viewBox for viewbox
+pub fn adjust_attribute_name(namespace: Namespace, name: &str) -> Option<&'static str> {
+    match namespace {
+        Namespace::Svg => SVG_ATTRIBUTE_NAMES.iter().find(|adjusted| adjusted.eq_ignore_ascii_case(name)).cloned(),
+        Namespace::MathMl if name.eq_ignore_ascii_case("definitionurl") => Some("definitionURL"),
+        _ => None,
+    }
+}
+
+/// Returns true if start tag closes SVG and MathML elements, has_attribute tells if tag has attribute
+/// with given name
+pub fn is_breakout_tag<F: Fn(&str) -> bool>(name: &str, has_attribute: F) -> bool {
+    BREAKOUT_TAGS.contains(&name)
+        && (name != "font" || has_attribute("color") || has_attribute("face") || has_attribute("size"))
+}
+
+/// Returns true if MathML element contains text and HTML rather than MathML, ie mi
+pub fn is_mathml_text_integration_point(namespace: Namespace, name: &str) -> bool {
+    namespace == Namespace::MathMl && matches!(name, "mi" | "mo" | "mn" | "ms" | "mtext")
+}
+
+/// Returns true if SVG or MathML element contains HTML, ie foreignObject. Encoding is the value of
+/// encoding attribute that makes HTML out of MathML annotation-xml
+pub fn is_html_integration_point(namespace: Namespace, name: &str, encoding: Option<&str>) -> bool {
+    match namespace {
+        Namespace::Svg => matches!(name, "foreignObject" | "desc" | "title"),
+        Namespace::MathMl => name == "annotation-xml" && encoding.is_some_and(|encoding| {
+            encoding.eq_ignore_ascii_case("text/html") || encoding.eq_ignore_ascii_case("application/xhtml+xml")
+        }),
+        Namespace::Html => false,
+    }
+}
+
+/// Element that was opened in SVG or MathML content
+#[derive(Clone)]
+struct OpenElement {
+    name: String,
+    namespace: Namespace,
+
+    /// True if content of element is HTML, ie it is foreignObject
+    html_content: bool,
+}
+
+/// Finds namespaces of tags while chunks are parsed, so that SVG and MathML tags get theirs. It only
+/// balances tags opened inside of svg or math by simple stack matching, so end tag that does not match
+/// any of them closes SVG or MathML content, ie </div> in <div><svg></div>
+#[derive(Clone)]
+pub struct ForeignContent {
+    open_elements: Vec<OpenElement>,
+}
+
+impl ForeignContent {
+    pub fn new() -> ForeignContent {
+        ForeignContent {
+            open_elements: Vec::new(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.open_elements.clear();
+    }
+
+    /// Returns true if current element is SVG or MathML one, CDATA sections are only allowed there
+    pub fn cdata_allowed(&self) -> bool {
+        self.open_elements.last().is_some_and(|element| element.namespace != Namespace::Html)
+    }
+
+    /// Sets namespace of tag chunk adjusting names of SVG and MathML tags and params, self-closing
+    /// tag is an open tag with end_closure or a close tag with both closure and end_closure
+    pub fn process(&mut self, chunk: &mut HtmlChunk) {
+        match chunk.chunk_type {
+            ChunkType::OpenTag if chunk.tag == "!doctype" => {},
+            ChunkType::OpenTag => self.start_tag(chunk, chunk.end_closure),
+            ChunkType::CloseTag if chunk.end_closure => self.start_tag(chunk, true),
+            ChunkType::CloseTag => self.end_tag(chunk),
+            _ => {},
+        }
+    }
+
+    fn start_tag(&mut self, chunk: &mut HtmlChunk, self_closing: bool) {
+        let namespace = self.start_tag_namespace(chunk);
+
+        chunk.namespace = namespace;
+
+        if namespace != Namespace::Html {
+            adjust_names(chunk);
+        }
+
+        if self.open_elements.is_empty() && namespace == Namespace::Html {
+            return;
+        }
+
+        let closed = if namespace == Namespace::Html {
+            // content of script is parsed along with its end tag in lenient mode
+            VOID_ELEMENTS.contains(&chunk.tag.as_str()) || chunk.tag == "script"
+        } else {
+            self_closing
+        };
+
+        if closed {
+            return;
+        }
+
+        let html_content = is_mathml_text_integration_point(namespace, &chunk.tag)
+            || is_html_integration_point(namespace, &chunk.tag, param(chunk, "encoding"));
+
+        self.open_elements.push(OpenElement {
+            name: chunk.tag.clone(),
+            namespace,
+            html_content,
+        });
+    }
+
+    /// Returns namespace of element that is opened by start tag
+    fn start_tag_namespace(&mut self, chunk: &HtmlChunk) -> Namespace {
+        let name = chunk.tag.as_str();
+
+        if let Some(current) = self.open_elements.last() {
+            if current.html_content {
+                if is_mathml_text_integration_point(current.namespace, &current.name)
+                    && (name == "mglyph" || name == "malignmark") {
+                    return Namespace::MathMl;
+                }
+            } else if self.in_foreign_content() {
+                if current.namespace == Namespace::MathMl && current.name == "annotation-xml" && name == "svg" {
+                    return Namespace::Svg;
+                }
+
+                if !is_breakout_tag(name, |attribute| param(chunk, attribute).is_some()) {
+                    return current.namespace;
+                }
+
+                self.close_foreign_elements();
+            }
+        }
+
+        match name {
+            "svg" => Namespace::Svg,
+            "math" => Namespace::MathMl,
+            _ => Namespace::Html,
+        }
+    }
+
+    fn end_tag(&mut self, chunk: &mut HtmlChunk) {
+        let pos = self.open_elements.iter().rposition(|element| element.name.eq_ignore_ascii_case(&chunk.tag));
+
+        match pos {
+            Some(pos) => {
+                chunk.namespace = self.open_elements[pos].namespace;
+                chunk.tag = self.open_elements[pos].name.clone();
+                self.open_elements.truncate(pos);
+            },
+            None => self.close_foreign_elements(),
+        }
+    }
+
+    /// Returns true if current element is SVG or MathML one which content is not HTML
+    fn in_foreign_content(&self) -> bool {
+        self.open_elements.last().is_some_and(|element| element.namespace != Namespace::Html && !element.html_content)
+    }
+
+    /// Closes SVG and MathML elements up to the nearest HTML one or the one with HTML content
+    fn close_foreign_elements(&mut self) {
+        while self.in_foreign_content() {
+            self.open_elements.pop();
+        }
+    }
+}
+
+/// Returns value of param with given (lowercased) name
+fn param<'a>(chunk: &'a HtmlChunk, name: &str) -> Option<&'a str> {
+    (0..chunk.params_count).find(|&i| chunk.param_names[i] == name).map(|i| chunk.param_values[i].as_str())
+}
+
+/// Adjusts case of tag and param names of SVG or MathML tag
+fn adjust_names(chunk: &mut HtmlChunk) {
+    if chunk.namespace == Namespace::Svg {
+        if let Some(name) = adjust_svg_tag_name(&chunk.tag) {
+            chunk.tag = name.to_string();
+        }
+    }
+
+    let mut adjusted = false;
+
+    for i in 0..chunk.params_count {
+        if let Some(name) = adjust_attribute_name(chunk.namespace, &chunk.param_names[i]) {
+            chunk.param_names[i] = name.to_string();
+            adjusted = true;
+        }
+    }
+
+    if adjusted && chunk.hash_mode {
+        chunk.convert_params_to_hash();
+    }
+}
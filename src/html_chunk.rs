@@ -1,10 +1,12 @@
 use std::collections::hash_map::HashMap;
+use line_index::{SourcePosition, ParamPosition};
 use encoding::{Encoding, EncodingRef, EncoderTrap, DecoderTrap};
 use encoding::label::encoding_from_whatwg_label;
 use encoding::all::ASCII;
 
 /// Type of parsed HTML chunk (token), each non-null returned chunk from HTMLparser will have oType set to 
 /// one of these values
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum ChunkType {
     /// Text data from HTML
     Text = 0,
@@ -22,7 +24,7 @@ pub enum ChunkType {
     /// you call set_raw_html function)
     /// 
     /// Note: this can also be CDATA part of XML document - see tag value to determine if its proper comment
-    /// or CDATA or (in the future) something else
+    /// or CDATA, or start (![if) and end (![endif) of IE conditional comment - see condition
     Comment = 3,
 
     /// Script tag (<!-- -->) depending on HtmlParser boolean flags
@@ -31,6 +33,40 @@ pub enum ChunkType {
     /// c) complete RAW HTML representing data between tags and tags themselves (same as you get in a) when
     /// you call set_raw_html function)
     Script = 4,
+
+    /// DOCTYPE declaration, only returned in conformance mode of HtmlParser (see doctype for its parts),
+    /// otherwise it is returned as open tag named !doctype
+    Doctype = 5,
+
+    /// Template code between delimiters set in template_delimiters of HtmlParser, ie {{ name }} or
+    /// <?php echo 1; ?>, html has got the whole code including delimiters
+    Template = 6,
+}
+
+/// Parts of DOCTYPE declaration, ie <!DOCTYPE html PUBLIC "-//W3C//DTD HTML 4.01//EN">, missing parts are None
+/// which is not the same as empty identifier: <!DOCTYPE html SYSTEM "">
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Doctype {
+    /// Lowercased name, normally html
+    pub name: Option<String>,
+
+    /// Public identifier
+    pub public_id: Option<String>,
+
+    /// System identifier
+    pub system_id: Option<String>,
+
+    /// True if DOCTYPE was malformed, so that document must be rendered in quirks mode
+    pub force_quirks: bool,
+}
+
+/// Namespace of element, tags inside of <svg> and <math> are not HTML
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Namespace {
+    #[default]
+    Html,
+    Svg,
+    MathMl,
 }
 
 /// Maximum number of parameters in a 
@@ -43,66 +79,95 @@ pub struct HtmlChunk {
     /// Chunk type showing whether its text, open or close tag, comments or script.
     /// WARNING: if type is comments or script then you have to manually call Finalise(); method
     /// in order to have actual text of comments/scripts in oHTML variable
-    chunk_type: ChunkType,
+    pub chunk_type: ChunkType,
 
     /// If true then tag params will be kept in a hash rather than in a fixed size arrays. 
     /// This will be slow down parsing, but make it easier to use.
-    hash_mode: bool,
+    pub hash_mode: bool,
 
     /// For TAGS: it stores raw HTML that was parsed to generate thus chunk will be here UNLESS
     /// HTMLparser was configured not to store it there as it can improve performance
     /// <p>
     /// For TEXT or COMMENTS: actual text or comments - you MUST call Finalise(); first.
     /// </p>
-    html: String,
+    pub html: String,
 
     /// Offset in html_data data array at which this chunk starts
-    chunk_offset: usize,
+    pub chunk_offset: usize,
 
     /// Length of the chunk in bHTML data array
-    chunk_length: usize,
+    pub chunk_length: usize,
+
+    /// Position at which chunk starts, line and columns are only set if parser tracks positions
+    pub start: SourcePosition,
+
+    /// Position right after the end of chunk, line and columns are only set if parser tracks positions
+    pub end: SourcePosition,
 
     /// If its open/close tag type then this is where lowercased Tag will be kept
-    tag: String,
+    pub tag: String,
 
     /// If true then it must be closed tag
-    closure: bool,
+    pub closure: bool,
 
     /// If true then it must be closed tag and closure sign / was at the END of tag, ie this is a SOLO
     /// tag 
-    end_closure: bool,
+    pub end_closure: bool,
 
     /// If true then it must be comments tag
-    comments: bool,
+    pub comments: bool,
+
+    /// For TAGS: namespace of element, tag and param names of SVG and MathML elements are case-adjusted,
+    /// ie clipPath or viewBox
+    pub namespace: Namespace,
+
+    /// For TAGS in XML mode of HtmlParser: URI of namespace that prefix of tag resolves to, or default
+    /// namespace if tag has no prefix, ie http://www.w3.org/2005/Atom for <feed xmlns="http://www.w3.org/2005/Atom">.
+    /// None if there is no such namespace or it is not XML
+    pub namespace_uri: Option<String>,
 
     /// True if entities were present (and transformed) in the original HTML
-    entities: bool,
+    pub entities: bool,
 
     /// Set to true if &lt; entity (tag start) was found 
-    lt_entity: bool,
+    pub lt_entity: bool,
+
+    /// For IE conditional comments: condition expression, ie "gte mso 9" for <!--[if gte mso 9]>,
+    /// empty for everything else
+    pub condition: String,
+
+    /// For DOCTYPE chunks: parts of DOCTYPE declaration
+    pub doctype: Option<Doctype>,
 
     /// Hashtable with tag parameters: keys are param names and values are param values.
     /// ONLY used if hash_mode is set to true.
-    params: Option<HashMap<String, String>>,
+    pub params: Option<HashMap<String, String>>,
 
     /// Number of parameters and values stored in param_names array, OR in params hashtable if
     /// hash_mode is true
-    params_count: usize,
+    pub params_count: usize,
 
-    /// Param names will be stored here - actual number is in params_count.
-    /// ONLY used if hash_mode is set to false.
-    param_names: Vec<String>,
+    /// Param names will be stored here in the order they appeared in HTML - actual number is in params_count.
+    /// These are filled in regardless of hash_mode, so original order of params is never lost.
+    pub param_names: Vec<String>,
 
     /// Param values will be stored here - actual number is in params_count.
-    /// ONLY used if hash_mode is set to false.
-    param_values: Vec<String>,
+    /// These are filled in regardless of hash_mode.
+    pub param_values: Vec<String>,
 
     /// Character used to quote param's value: it is taken actually from parsed HTML
-    param_chars: Vec<u8>,
+    pub param_chars: Vec<u8>,
+
+    /// Positions of param names and values in HTML, line and columns are only set if parser tracks positions
+    pub param_positions: Vec<ParamPosition>,
+
+    /// URIs of namespaces that prefixes of param names resolve to, they are only set in XML mode of HtmlParser.
+    /// Params without prefix are in no namespace
+    pub param_namespace_uris: Vec<Option<String>>,
 
     /// Encoder to be used for conversion of binary data into strings, ASCII is used by default,
     /// but it can be changed if top level user of the parser detects that encoding was different
-    enc: EncodingRef,
+    pub enc: EncodingRef,
 }
 
 impl HtmlChunk {
@@ -119,17 +184,25 @@ impl HtmlChunk {
             html: String::from(""),
             chunk_offset: 0,
             chunk_length: 0,
+            start: SourcePosition::default(),
+            end: SourcePosition::default(),
             tag: String::from(""),
             closure: false,
             end_closure: false,
             comments: false,
+            namespace: Namespace::Html,
+            namespace_uri: None,
             entities: false,
             lt_entity: false,
+            condition: String::new(),
+            doctype: None,
             params: params_hash,
             params_count: 0,
             param_names: Vec::new(),
             param_chars: Vec::new(),
             param_values: Vec::new(),
+            param_positions: Vec::new(),
+            param_namespace_uris: Vec::new(),
             enc: encoding_from_whatwg_label("ascii").unwrap(),
         }
     }
@@ -161,13 +234,27 @@ impl HtmlChunk {
         self.tag.clear();
         self.html.clear();
 
+        self.condition.clear();
+        self.doctype = None;
         self.lt_entity = false;
         self.entities = false;
         self.comments = false;
+        self.namespace = Namespace::Html;
+        self.namespace_uri = None;
         self.closure = false;
         self.end_closure = false;
 
+        self.clear_params();
+    }
+
+    /// Removes all params from chunk
+    pub fn clear_params(&mut self) {
         self.params_count = 0;
+        self.param_names.clear();
+        self.param_values.clear();
+        self.param_chars.clear();
+        self.param_positions.clear();
+        self.param_namespace_uris.clear();
 
         if self.hash_mode {
             if let Some(ref mut hash) = self.params {
@@ -176,6 +263,42 @@ impl HtmlChunk {
         }
     }
 
+    /// Adds parameter parsed from HTML, quote_ch is the char used to quote the value (space if none was used)
+    pub fn add_param(&mut self, name: String, value: String, quote_ch: u8) {
+        if let Some(ref mut hash) = self.params {
+            if self.hash_mode {
+                hash.insert(name.clone(), value.clone());
+            }
+        }
+
+        self.param_names.push(name);
+        self.param_values.push(value);
+        self.param_chars.push(quote_ch);
+        self.param_positions.push(ParamPosition::default());
+        self.param_namespace_uris.push(None);
+        self.params_count += 1;
+    }
+
+    /// Returns prefix of tag name, ie og for og:image
+    pub fn tag_prefix(&self) -> Option<&str> {
+        split_name(&self.tag).0
+    }
+
+    /// Returns tag name without prefix, ie image for og:image
+    pub fn tag_local_name(&self) -> &str {
+        split_name(&self.tag).1
+    }
+
+    /// Returns prefix of name of param at given index, ie xlink for xlink:href
+    pub fn param_prefix(&self, index: usize) -> Option<&str> {
+        split_name(&self.param_names[index]).0
+    }
+
+    /// Returns name of param at given index without prefix, ie href for xlink:href
+    pub fn param_local_name(&self, index: usize) -> &str {
+        split_name(&self.param_names[index]).1
+    }
+
     /// Generates HTML based on current chunk's data 
     /// Note: this is not a high performance method and if you want ORIGINAL HTML that was parsed to create
     /// this chunk then use relevant HtmlParser method to obtain such HTML then you should use
@@ -218,11 +341,7 @@ impl HtmlChunk {
             ChunkType::Comment => {
                 // note: we might have CDATA here that we treat as comments
                 if self.tag == "!--" {
-                    if self.html.len() == 0 {
-                        new_html = String::from("<!-- n/a -->");
-                    } else {
-                        new_html = String::from("<!--") + &self.html + "-->";
-                    }
+                    new_html = String::from("<!--") + &HtmlChunk::make_safe_comment(&self.html) + "-->";
                 } else {
                     // ref: http://www.w3schools.com/xml/xml_cdata.asp
                     if self.tag == "![CDATA[" {
@@ -231,9 +350,47 @@ impl HtmlChunk {
                         } else {
                             new_html = String::from("<![CDATA[") + &self.html + "]]>";
                         }
+                    } else if self.tag == "?" {
+                        // processing instruction, ?> in its data would end it early
+                        new_html = String::from("<?") + &self.html.replace("?>", "? >") + "?>";
+                    } else if self.tag == "![if" || self.tag == "![endif" {
+                        // start or end of conditional comment - html is the marker itself
+                        if !self.html.is_empty() {
+                            new_html = self.html.clone();
+                        } else if self.tag == "![if" {
+                            new_html = String::from("<![if ") + &self.condition + "]>";
+                        } else {
+                            new_html = String::from("<![endif]>");
+                        }
                     }
                 }
             },
+            ChunkType::Doctype => {
+                new_html = String::from("<!DOCTYPE");
+
+                if let Some(ref doctype) = self.doctype {
+                    if let Some(ref name) = doctype.name {
+                        new_html = new_html + " " + name;
+                    }
+
+                    if let Some(ref public_id) = doctype.public_id {
+                        new_html = new_html + " PUBLIC \"" + public_id + "\"";
+                    }
+
+                    if let Some(ref system_id) = doctype.system_id {
+                        if doctype.public_id.is_none() {
+                            new_html += " SYSTEM";
+                        }
+
+                        new_html = new_html + " \"" + system_id + "\"";
+                    }
+                }
+
+                new_html += ">";
+            },
+            ChunkType::Template => {
+                new_html = self.html.clone();
+            },
             // matched normal text
             ChunkType::Text => {
                 new_html = self.html.clone();
@@ -280,25 +437,6 @@ impl HtmlChunk {
         new_html
     }
 
-    /// Returns value of a parameter
-    fn get_param_value(&self, name: &String) -> String {
-        if self.hash_mode {
-            if let Some(ref params) = self.params {
-                if let Some(val) = params.get(name) {
-                    return val.clone();
-                }
-            }
-        } else {
-            for i in 0..self.params_count {
-                if self.param_values[i] == *name {
-                    return self.param_values[i].clone();
-                }
-            }
-        }
-
-        return String::from("");
-    }
-
     fn generate_param_html(&self, name: &String, val: &String, ch: char) -> String {
         if val.len() > 0 {
             if val.len() > 20 {
@@ -321,33 +459,63 @@ impl HtmlChunk {
         name.clone()
     }
 
+    /// Makes comment data safe to be put between <!-- and --> - data taken from parsed HTML can legally
+    /// contain sequences like "--" or "<!-" that would either end the comment early or make it invalid XML,
+    /// so dashes are split with a space, data ending with dash gets trailing space and data starting
+    /// with > or -> gets leading space
+    pub fn make_safe_comment(data: &str) -> String {
+        let mut new_s = String::with_capacity(data.len() + 2);
+
+        if data.starts_with('>') || data.starts_with("->") {
+            new_s.push(' ');
+        }
+
+        let mut prev_dash = false;
+
+        for ch in data.chars() {
+            if ch == '-' && prev_dash {
+                new_s.push(' ');
+            }
+
+            prev_dash = ch == '-';
+            new_s.push(ch);
+        }
+
+        if prev_dash {
+            new_s.push(' ');
+        }
+
+        new_s
+    }
+
     /// Makes parameter value safe to be used in 
     /// param - this will check for any conflicting quote chars,
     /// but not full entity-encoding
     fn make_safe_param_value(&self, line: &String, quote_ch: char) -> String {
         // we speculatievly expect that in most cases 
         // we don't actually need to entity-encode string
-        for i in 0..line.len() {
-            if line.chars().nth(i).unwrap() != quote_ch {
-                continue;
-            }
-
-            // have to restart here
-            let mut new_s: String = line.chars().take(i).collect();
+        if !line.contains(quote_ch) {
+            return line.clone();
+        }
 
-            for j in i..line.len() {
-                let ch = line.chars().nth(j).unwrap();
+        let mut new_s = String::with_capacity(line.len() + 8);
 
-                if ch == quote_ch {
-                    new_s = new_s + "&#" + &(ch as usize).to_string() + ";";
-                } else {
-                    new_s = new_s + &ch.to_string();
-                }
+        for ch in line.chars() {
+            if ch == quote_ch {
+                new_s = new_s + "&#" + &(ch as usize).to_string() + ";";
+            } else {
+                new_s.push(ch);
             }
-
-            return new_s;
         }
 
-        line.clone()
+        new_s
     }
-}
\ No newline at end of file
+}
+/// Splits qualified name of tag or param into prefix and local name, ie xml:lang into xml and lang.
+/// Names without prefix or with empty parts like :a are not split
+pub fn split_name(name: &str) -> (Option<&str>, &str) {
+    match name.split_once(':') {
+        Some((prefix, local_name)) if !prefix.is_empty() && !local_name.is_empty() => (Some(prefix), local_name),
+        _ => (None, name),
+    }
+}
@@ -0,0 +1,703 @@
+use std::ops::Index;
+
+use html_chunk::{Doctype, Namespace};
+use html_parser::HtmlParser;
+use document_builder::DocumentBuilder;
+use tree_builder::TreeBuilder;
+use selector::{Selector, SelectorError};
+use xpath::{XPath, XPathValue, XPathError};
+use serializer;
+use plain_text;
+
+/// Index of node in its document
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct NodeId(usize);
+
+impl NodeId {
+    /// Returns position of node in the arena of its document, nodes are numbered in order of creation
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+/// Name and value of element attribute
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Attribute {
+    /// Lowercased name
+    pub name: String,
+
+    /// Value, entities are decoded if parser was set to decode them
+    pub value: String,
+}
+
+/// Element with lowercased tag name and attributes in the order they appeared in HTML, names of SVG and
+/// MathML elements and their attributes are case-adjusted, ie clipPath or viewBox
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Element {
+    pub name: String,
+    pub namespace: Namespace,
+    pub attributes: Vec<Attribute>,
+}
+
+impl Element {
+    /// Creates HTML element without attributes
+    pub fn new(name: &str) -> Element {
+        Element::new_in(Namespace::Html, name)
+    }
+
+    /// Creates element in given namespace without attributes
+    pub fn new_in(namespace: Namespace, name: &str) -> Element {
+        Element {
+            name: name.to_string(),
+            namespace,
+            attributes: Vec::new(),
+        }
+    }
+
+    /// Returns value of attribute with given (lowercased) name, None if there is no such attribute
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|attribute| attribute.name == name).map(|attribute| attribute.value.as_str())
+    }
+
+    /// Sets value of attribute, new attribute is added after the existing ones
+    pub fn set_attribute(&mut self, name: &str, value: &str) {
+        match self.attributes.iter_mut().find(|attribute| attribute.name == name) {
+            Some(attribute) => attribute.value = value.to_string(),
+            None => self.attributes.push(Attribute {
+                name: name.to_string(),
+                value: value.to_string(),
+            }),
+        }
+    }
+
+    /// Removes attribute returning its value, None if there was no such attribute
+    pub fn remove_attribute(&mut self, name: &str) -> Option<String> {
+        let pos = self.attributes.iter().position(|attribute| attribute.name == name)?;
+
+        Some(self.attributes.remove(pos).value)
+    }
+}
+
+/// What node is
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum NodeData {
+    /// Root of the document, it is the only node of this type
+    Document,
+
+    Element(Element),
+
+    Text(String),
+
+    Comment(String),
+
+    Doctype(Doctype),
+}
+
+/// Node of document with links to its relatives
+#[derive(Clone, Debug)]
+pub struct Node {
+    pub data: NodeData,
+
+    /// Offset in HTML of the chunk node was created from (start tag for elements), None if node was not
+    /// in HTML (ie it was implied by parser)
+    pub offset: Option<usize>,
+
+    /// Length of the chunk node was created from, adjacent text chunks make single text node
+    pub length: usize,
+
+    // links are only changed by methods of document, so the tree stays consistent
+    parent: Option<NodeId>,
+    prev_sibling: Option<NodeId>,
+    next_sibling: Option<NodeId>,
+    first_child: Option<NodeId>,
+    last_child: Option<NodeId>,
+}
+
+impl Node {
+    fn new(data: NodeData) -> Node {
+        Node {
+            data,
+            offset: None,
+            length: 0,
+            parent: None,
+            prev_sibling: None,
+            next_sibling: None,
+            first_child: None,
+            last_child: None,
+        }
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn prev_sibling(&self) -> Option<NodeId> {
+        self.prev_sibling
+    }
+
+    pub fn next_sibling(&self) -> Option<NodeId> {
+        self.next_sibling
+    }
+
+    pub fn first_child(&self) -> Option<NodeId> {
+        self.first_child
+    }
+
+    pub fn last_child(&self) -> Option<NodeId> {
+        self.last_child
+    }
+
+    /// Returns element if node is element
+    pub fn as_element(&self) -> Option<&Element> {
+        match self.data {
+            NodeData::Element(ref element) => Some(element),
+            _ => None,
+        }
+    }
+
+    /// Returns mutable element if node is element
+    pub fn as_element_mut(&mut self) -> Option<&mut Element> {
+        match self.data {
+            NodeData::Element(ref mut element) => Some(element),
+            _ => None,
+        }
+    }
+
+    /// Returns text if node is text
+    pub fn as_text(&self) -> Option<&str> {
+        match self.data {
+            NodeData::Text(ref text) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// Returns tag name if node is element
+    pub fn name(&self) -> Option<&str> {
+        self.as_element().map(|element| element.name.as_str())
+    }
+}
+
+/// Mode in which browsers would render document, it depends on its DOCTYPE
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum QuirksMode {
+    NoQuirks,
+    LimitedQuirks,
+    Quirks,
+}
+
+/// HTML document as a tree of nodes kept in a single arena, nodes refer to each other by NodeId
+#[derive(Clone, Debug)]
+pub struct Document {
+    nodes: Vec<Node>,
+    quirks_mode: QuirksMode,
+
+    /// True if document was parsed from XML, so HTML rules like void elements do not apply to it
+    xml: bool,
+}
+
+impl Document {
+    /// Creates document that only has root node
+    pub fn new() -> Document {
+        Document {
+            nodes: vec![Node::new(NodeData::Document)],
+            quirks_mode: QuirksMode::NoQuirks,
+            xml: false,
+        }
+    }
+
+    /// Parses HTML into document with entities decoded and whitespace kept as it is
+    pub fn parse(html: &str) -> Document {
+        let mut parser = HtmlParser::new();
+
+        parser.decode_entities = true;
+        parser.compress_whitespace_before_tag = false;
+        parser.init(html);
+
+        Document::from_parser(&mut parser)
+    }
+
+    /// Parses XML into document with entities decoded, CDATA sections become text and processing
+    /// instructions are dropped. Elements are in HTML namespace, but the document is marked as XML, so
+    /// serialization and text extraction do not treat ie link or title as HTML elements
+    pub fn parse_xml(xml: &str) -> Document {
+        let mut parser = HtmlParser::new();
+
+        parser.xml_mode = true;
+        parser.decode_entities = true;
+        parser.compress_whitespace_before_tag = false;
+        parser.init(xml);
+
+        Document::from_parser(&mut parser)
+    }
+
+    /// Builds document from chunks that are left in initialised parser. Tags are balanced by simple
+    /// stack matching: end tag closes the nearest open element with its name and stray end tags are
+    /// ignored. Options of parser matter, ie comments are empty unless keep_comments is set and
+    /// content of scripts is only kept if keep_scripts and extract_between_tags_only are set. In XML mode
+    /// there are no void elements like br, elements are only closed by their end tags or /> and the
+    /// document is marked as XML
+    pub fn from_parser(parser: &mut HtmlParser) -> Document {
+        let mut builder = DocumentBuilder::new();
+
+        while parser.parse_next().is_some() {
+            builder.add_chunk(parser);
+        }
+
+        let mut document = builder.finish();
+
+        document.xml = parser.xml_mode;
+
+        document
+    }
+
+    /// Parses HTML into document following WHATWG tree construction, so that the tree is exactly
+    /// what browsers would build: missing html, head and body are added, misnested tags are fixed and
+    /// so on
+    pub fn parse_html5(html: &str) -> Document {
+        let mut parser = HtmlParser::new();
+
+        parser.conformance_mode = true;
+        parser.init(html);
+
+        Document::from_conformant_parser(&mut parser)
+    }
+
+    /// Builds document from chunks that are left in initialised parser following WHATWG tree construction,
+    /// parser must be in conformance mode. Tree construction switches states of tokenizer itself, so
+    /// auto_switch_states is turned off
+    pub fn from_conformant_parser(parser: &mut HtmlParser) -> Document {
+        assert!(parser.conformance_mode, "tree construction needs parser in conformance mode");
+
+        parser.auto_switch_states = false;
+
+        let builder = TreeBuilder::new(parser.scripting_enabled);
+
+        Document::build_tree(parser, builder)
+    }
+
+    /// Parses HTML as content of context element (ie "tr" or "textarea") the way innerHTML is parsed,
+    /// top level nodes of fragment are children of root of returned document
+    pub fn parse_fragment(html: &str, context: &str) -> Document {
+        let mut parser = HtmlParser::new();
+
+        parser.conformance_mode = true;
+        parser.init(html);
+
+        Document::fragment_from_parser(&mut parser, context)
+    }
+
+    /// Builds fragment from chunks that are left in initialised parser as if they were content of context
+    /// element, parser must be in conformance mode. Tokenizer starts in the state context implies, ie
+    /// RCDATA for textarea, and its options such as keep_comments or scripting_enabled are used as usual
+    pub fn fragment_from_parser(parser: &mut HtmlParser, context: &str) -> Document {
+        Document::namespaced_fragment_from_parser(parser, Namespace::Html, &context.to_ascii_lowercase())
+    }
+
+    /// Same as fragment_from_parser, but context element is in given namespace, ie SVG for "foreignObject"
+    pub fn namespaced_fragment_from_parser(parser: &mut HtmlParser, namespace: Namespace, context: &str)
+        -> Document {
+        assert!(parser.conformance_mode, "tree construction needs parser in conformance mode");
+
+        let builder = TreeBuilder::new_fragment(namespace, context, parser.scripting_enabled);
+
+        parser.auto_switch_states = false;
+        parser.set_last_start_tag(Some(&context.to_ascii_lowercase()));
+
+        Document::build_tree(parser, builder)
+    }
+
+    /// Passes chunks of parser to tree construction switching states of tokenizer as it asks
+    fn build_tree(parser: &mut HtmlParser, mut builder: TreeBuilder) -> Document {
+        loop {
+            if let Some(state) = builder.take_tokenizer_state() {
+                parser.set_tokenizer_state(state);
+            }
+
+            parser.set_cdata_allowed(builder.cdata_allowed());
+
+            if parser.parse_next().is_none() {
+                break;
+            }
+
+            builder.add_chunk(parser.current_chunk());
+        }
+
+        builder.finish()
+    }
+
+    /// Returns quirks mode that was set by DOCTYPE, it is only determined by tree construction
+    pub fn quirks_mode(&self) -> QuirksMode {
+        self.quirks_mode
+    }
+
+    pub fn set_quirks_mode(&mut self, quirks_mode: QuirksMode) {
+        self.quirks_mode = quirks_mode;
+    }
+
+    /// Returns true if document was parsed from XML
+    pub fn is_xml(&self) -> bool {
+        self.xml
+    }
+
+    pub fn set_xml(&mut self, xml: bool) {
+        self.xml = xml;
+    }
+
+    /// Returns root node of the document
+    pub fn root(&self) -> NodeId {
+        NodeId(0)
+    }
+
+    /// Returns number of nodes in the arena including the ones that were detached from the tree
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Returns false as there is always root node
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// Creates node that is not in the tree yet, see append_child
+    pub fn create_node(&mut self, data: NodeData) -> NodeId {
+        self.nodes.push(Node::new(data));
+
+        NodeId(self.nodes.len() - 1)
+    }
+
+    /// Returns mutable node, its relatives can only be changed by methods of document like append_child
+    pub fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id.0]
+    }
+
+    /// Adds node as the last child of parent, node is moved if it already has parent
+    ///
+    /// Panics if node is parent or its ancestor
+    pub fn append_child(&mut self, parent: NodeId, child: NodeId) {
+        if self.nodes[parent.0].last_child == Some(child) {
+            return;
+        }
+
+        self.check_insertion(parent, child);
+        self.detach(child);
+
+        let last = self.nodes[parent.0].last_child;
+
+        self.link(parent, last, None, child);
+    }
+
+    /// Adds node as the first child of parent, node is moved if it already has parent
+    ///
+    /// Panics if node is parent or its ancestor
+    pub fn prepend_child(&mut self, parent: NodeId, child: NodeId) {
+        if self.nodes[parent.0].first_child == Some(child) {
+            return;
+        }
+
+        self.check_insertion(parent, child);
+        self.detach(child);
+
+        let first = self.nodes[parent.0].first_child;
+
+        self.link(parent, None, first, child);
+    }
+
+    /// Inserts node right before reference node, node is moved if it already has parent. Nothing is done
+    /// if node is reference node or it is right before it already
+    ///
+    /// Panics if reference node has no parent or node is ancestor of reference node
+    pub fn insert_before(&mut self, reference: NodeId, child: NodeId) {
+        if reference == child || self.nodes[reference.0].prev_sibling == Some(child) {
+            return;
+        }
+
+        let parent = self.sibling_parent(reference);
+
+        self.check_insertion(parent, child);
+        self.detach(child);
+
+        let prev = self.nodes[reference.0].prev_sibling;
+
+        self.link(parent, prev, Some(reference), child);
+    }
+
+    /// Inserts node right after reference node, node is moved if it already has parent. Nothing is done
+    /// if node is reference node or it is right after it already
+    ///
+    /// Panics if reference node has no parent or node is ancestor of reference node
+    pub fn insert_after(&mut self, reference: NodeId, child: NodeId) {
+        if reference == child || self.nodes[reference.0].next_sibling == Some(child) {
+            return;
+        }
+
+        let parent = self.sibling_parent(reference);
+
+        self.check_insertion(parent, child);
+        self.detach(child);
+
+        let next = self.nodes[reference.0].next_sibling;
+
+        self.link(parent, Some(reference), next, child);
+    }
+
+    /// Puts node in place of old one which is detached
+    ///
+    /// Panics if old node has no parent or new node is ancestor of old one
+    pub fn replace(&mut self, old: NodeId, new: NodeId) {
+        if old != new {
+            self.insert_before(old, new);
+            self.detach(old);
+        }
+    }
+
+    /// Returns parent of node that another node is inserted next to
+    fn sibling_parent(&self, reference: NodeId) -> NodeId {
+        match self.nodes[reference.0].parent {
+            Some(parent) => parent,
+            None => panic!("can not insert next to node {:?} as it has no parent", reference),
+        }
+    }
+
+    /// Panics if child is parent or its ancestor, as inserting it there would make a cycle
+    fn check_insertion(&self, parent: NodeId, child: NodeId) {
+        let mut node = Some(parent);
+
+        while let Some(id) = node {
+            if id == child {
+                panic!("can not insert node {:?} into itself or its descendant {:?}", child, parent);
+            }
+
+            node = self.nodes[id.0].parent;
+        }
+    }
+
+    /// Links detached child into parent between given siblings
+    fn link(&mut self, parent: NodeId, prev: Option<NodeId>, next: Option<NodeId>, child: NodeId) {
+        {
+            let node = &mut self.nodes[child.0];
+
+            node.parent = Some(parent);
+            node.prev_sibling = prev;
+            node.next_sibling = next;
+        }
+
+        match prev {
+            Some(prev) => self.nodes[prev.0].next_sibling = Some(child),
+            None => self.nodes[parent.0].first_child = Some(child),
+        }
+
+        match next {
+            Some(next) => self.nodes[next.0].prev_sibling = Some(child),
+            None => self.nodes[parent.0].last_child = Some(child),
+        }
+    }
+
+    /// Detaches all children of node
+    pub fn remove_children(&mut self, id: NodeId) {
+        while let Some(child) = self.nodes[id.0].first_child {
+            self.detach(child);
+        }
+    }
+
+    /// Sets text of text or comment node, children of other nodes are replaced by single text node
+    pub fn set_text(&mut self, id: NodeId, text: &str) {
+        match self.nodes[id.0].data {
+            NodeData::Text(ref mut own) | NodeData::Comment(ref mut own) => {
+                *own = text.to_string();
+                return;
+            },
+            _ => {},
+        }
+
+        self.remove_children(id);
+
+        if !text.is_empty() {
+            let child = self.create_node(NodeData::Text(text.to_string()));
+
+            self.append_child(id, child);
+        }
+    }
+
+    /// Removes node from its parent, node stays in the arena along with its children, so it can be
+    /// inserted somewhere else
+    pub fn detach(&mut self, id: NodeId) {
+        let (parent, prev, next) = {
+            let node = &mut self.nodes[id.0];
+
+            (node.parent.take(), node.prev_sibling.take(), node.next_sibling.take())
+        };
+
+        let parent = match parent {
+            Some(parent) => parent,
+            None => return,
+        };
+
+        match prev {
+            Some(prev) => self.nodes[prev.0].next_sibling = next,
+            None => self.nodes[parent.0].first_child = next,
+        }
+
+        match next {
+            Some(next) => self.nodes[next.0].prev_sibling = prev,
+            None => self.nodes[parent.0].last_child = prev,
+        }
+    }
+
+    /// Returns iterator over children of node
+    pub fn children(&self, id: NodeId) -> Children<'_> {
+        Children {
+            document: self,
+            next: self[id].first_child,
+        }
+    }
+
+    /// Returns iterator over all nodes below given one in document order, node itself is not included
+    pub fn descendants(&self, id: NodeId) -> Descendants<'_> {
+        Descendants {
+            document: self,
+            root: id,
+            next: self[id].first_child,
+        }
+    }
+
+    /// Returns iterator over element children of node
+    pub fn child_elements(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        self.children(id).filter(move |&child| self[child].as_element().is_some())
+    }
+
+    /// Returns the first element with given (lowercased) name in document order
+    pub fn find_element(&self, name: &str) -> Option<NodeId> {
+        self.descendants(self.root()).find(|&id| self[id].name() == Some(name))
+    }
+
+    /// Returns concatenated text of all text nodes below given node
+    pub fn text_content(&self, id: NodeId) -> String {
+        let mut text = String::new();
+
+        if let Some(own) = self[id].as_text() {
+            text.push_str(own);
+        }
+
+        for descendant in self.descendants(id) {
+            if let Some(own) = self[descendant].as_text() {
+                text.push_str(own);
+            }
+        }
+
+        text
+    }
+
+    /// Returns elements that match CSS selector in document order, see Selector for supported syntax
+    pub fn select(&self, selector: &str) -> Result<Vec<NodeId>, SelectorError> {
+        Ok(Selector::parse(selector)?.select(self, self.root()))
+    }
+
+    /// Serializes the whole document to HTML
+    pub fn to_html(&self) -> String {
+        self.inner_html(self.root())
+    }
+
+    /// Serializes node along with its content to HTML
+    pub fn outer_html(&self, id: NodeId) -> String {
+        let mut html = String::new();
+
+        serializer::serialize(self, id, true, &mut html);
+
+        html
+    }
+
+    /// Serializes content of node to HTML
+    pub fn inner_html(&self, id: NodeId) -> String {
+        let mut html = String::new();
+
+        serializer::serialize(self, id, false, &mut html);
+
+        html
+    }
+
+    /// Returns readable text of the whole document: block elements and br break lines, list items get bullets
+    /// or numbers, table cells are separated by tabs and content of script, style and head is left out. Text
+    /// of XML documents only gets its whitespace collapsed
+    pub fn to_text(&self) -> String {
+        plain_text::to_text(self, self.root())
+    }
+
+    /// Returns readable text of content of node the way to_text does it
+    pub fn inner_text(&self, id: NodeId) -> String {
+        plain_text::to_text(self, id)
+    }
+
+    /// Evaluates XPath 1.0 expression with document node as context node
+    pub fn xpath(&self, expression: &str) -> Result<XPathValue, XPathError> {
+        XPath::parse(expression)?.evaluate(self, self.root())
+    }
+}
+
+impl Default for Document {
+    fn default() -> Document {
+        Document::new()
+    }
+}
+
+impl Index<NodeId> for Document {
+    type Output = Node;
+
+    fn index(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0]
+    }
+}
+
+/// Iterator over children of node
+pub struct Children<'a> {
+    document: &'a Document,
+    next: Option<NodeId>,
+}
+
+impl<'a> Iterator for Children<'a> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let id = self.next?;
+
+        self.next = self.document[id].next_sibling;
+
+        Some(id)
+    }
+}
+
+/// Iterator over descendants of node in document order
+pub struct Descendants<'a> {
+    document: &'a Document,
+    root: NodeId,
+    next: Option<NodeId>,
+}
+
+impl<'a> Iterator for Descendants<'a> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let id = self.next?;
+        let node = &self.document[id];
+
+        self.next = node.first_child.or_else(|| {
+            // going up until there is next sibling, but not above root
+            let mut current = id;
+
+            loop {
+                if current == self.root {
+                    return None;
+                }
+
+                let current_node = &self.document[current];
+
+                if current_node.next_sibling.is_some() {
+                    return current_node.next_sibling;
+                }
+
+                current = current_node.parent?;
+            }
+        });
+
+        Some(id)
+    }
+}
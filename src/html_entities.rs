@@ -1,2 +1,338 @@
+use std::collections::HashMap;
+
+use entities_table::ENTITIES;
+use dynamic_string::decode_bytes;
+use parse_error::{ParseError, ParseErrorCode};
+
+use encoding::EncodingRef;
+
+/// Maximum length of entity name including ;
+const MAX_ENTITY_LENGTH: usize = 32;
+
+/// Entities that will be decoded if only mini set of entities is to be decoded
+const MINI_ENTITIES: [&str; 6] = ["amp", "lt", "gt", "quot", "apos", "nbsp"];
+
+/// Entities that are predefined in XML, the rest must be declared in DOCTYPE
+const XML_ENTITIES: [(&str, &str); 5] = [("amp", "&"), ("apos", "'"), ("gt", ">"), ("lt", "<"), ("quot", "\"")];
+
+/// Replacements of numeric entities for C1 controls 0x80 to 0x9F: browsers treat them as windows-1252
+/// chars, 0 means the code is not remapped
+const WINDOWS_1252: [u32; 32] = [
+    0x20AC, 0, 0x201A, 0x0192, 0x201E, 0x2026, 0x2020, 0x2021,
+    0x02C6, 0x2030, 0x0160, 0x2039, 0x0152, 0, 0x017D, 0,
+    0, 0x2018, 0x2019, 0x201C, 0x201D, 0x2022, 0x2013, 0x2014,
+    0x02DC, 0x2122, 0x0161, 0x203A, 0x0153, 0, 0x017E, 0x0178,
+];
+
+/// Decodes character references (entities) like &amp; &#39; or &#x27; in text and param values, and encodes
+/// chars that can not be written as they are
 pub struct HtmlEntities {
-}
\ No newline at end of file
+    /// If true then only mini set of entities (&amp; &lt; &gt; &quot; &apos; &nbsp;) will be decoded,
+    /// numeric entities are always decoded
+    pub mini_only: bool,
+
+    /// If true then data is attribute value: legacy entities without ; followed by = or alphanumeric
+    /// are not decoded there, so URLs like ?a=1&copy=2 stay intact
+    pub in_attribute: bool,
+
+    /// If true then data is XML: only predefined entities and the ones declared in DOCTYPE are decoded
+    /// (mini set is the predefined ones), their names must be followed by ; and numeric entities are
+    /// not remapped to windows-1252 chars
+    pub xml_mode: bool,
+
+    /// Set to true if data that was decoded last had entities in it
+    pub found_entities: bool,
+
+    /// Set to true if data that was decoded last had &lt; entity in it
+    pub found_lt_entity: bool,
+
+    /// Entities declared in DOCTYPE of XML document
+    declared: HashMap<String, String>,
+}
+
+impl HtmlEntities {
+    pub fn new() -> HtmlEntities {
+        HtmlEntities {
+            mini_only: false,
+            in_attribute: false,
+            xml_mode: false,
+            found_entities: false,
+            found_lt_entity: false,
+            declared: HashMap::new(),
+        }
+    }
+
+    /// Declares XML entity with given value, it is decoded in XML mode unless mini_only is set
+    pub fn declare(&mut self, name: &str, value: String) {
+        // the first declaration is binding in XML
+        self.declared.entry(name.to_string()).or_insert(value);
+    }
+
+    /// Forgets entities declared for the previous XML document
+    pub fn clear_declared(&mut self) {
+        self.declared.clear();
+    }
+
+    /// Returns decoded value of named entity (name must not include &), None if there is no such entity
+    pub fn get_entity(name: &str) -> Option<&'static str> {
+        ENTITIES.binary_search_by(|&(entity, _)| entity.cmp(name)).ok().map(|i| ENTITIES[i].1)
+    }
+
+    /// Appends text encoding &, < and > as entities, " is encoded too if text is attribute value and
+    /// no-break space unless it is XML, where &nbsp; is not predefined
+    pub fn encode(&self, text: &str, html: &mut String) {
+        let mut from = 0;
+
+        for (i, ch) in text.char_indices() {
+            let entity = match ch {
+                '&' => "&amp;",
+                '\u{A0}' if !self.xml_mode => "&nbsp;",
+                '<' => "&lt;",
+                '>' => "&gt;",
+                '"' if self.in_attribute => "&quot;",
+                _ => continue,
+            };
+
+            html.push_str(&text[from..i]);
+            html.push_str(entity);
+            from = i + ch.len_utf8();
+        }
+
+        html.push_str(&text[from..]);
+    }
+
+    /// Decodes entities in data converting it into string using given encoding, offset is position of data
+    /// in HTML and it is used to report errors found in entities
+    pub fn decode(&mut self, data: &[u8], offset: usize, enc: EncodingRef, errors: &mut Vec<ParseError>) -> String {
+        self.found_entities = false;
+        self.found_lt_entity = false;
+
+        let mut text = String::with_capacity(data.len());
+        let mut from = 0;
+        let mut pos = 0;
+
+        while pos < data.len() {
+            if data[pos] != b'&' {
+                pos += 1;
+                continue;
+            }
+
+            match self.decode_reference(data, pos, offset, errors) {
+                Some((ch, end)) => {
+                    text.push_str(&decode_bytes(enc, &data[from..pos]));
+                    text.push_str(&ch);
+
+                    self.found_entities = true;
+
+                    if ch == "<" {
+                        self.found_lt_entity = true;
+                    }
+
+                    from = end;
+                    pos = end;
+                },
+                None => pos += 1,
+            }
+        }
+
+        if from == 0 {
+            return decode_bytes(enc, data);
+        }
+
+        text.push_str(&decode_bytes(enc, &data[from..]));
+
+        text
+    }
+
+    /// Reports errors in entities of data without decoding them, offset is position of data in HTML
+    pub fn check(&self, data: &[u8], offset: usize, errors: &mut Vec<ParseError>) {
+        let mut pos = 0;
+
+        while let Some(found) = data[pos..].iter().position(|&ch| ch == b'&') {
+            let start = pos + found;
+
+            pos = match self.decode_reference(data, start, offset, errors) {
+                Some((_, end)) => end,
+                None => start + 1,
+            };
+        }
+    }
+
+    /// Decodes single entity at pos (pointing to &), offset is position of data in HTML. Returns decoded
+    /// chars and position after entity, None if there is no entity at pos - & is just a char then
+    pub fn decode_reference(&self, data: &[u8], pos: usize, offset: usize, errors: &mut Vec<ParseError>)
+        -> Option<(String, usize)> {
+        if data.get(pos + 1) == Some(&b'#') {
+            self.decode_numeric(data, pos, offset, errors)
+        } else if self.xml_mode {
+            self.decode_xml_named(data, pos, offset, errors)
+        } else {
+            self.decode_named(data, pos, offset, errors)
+        }
+    }
+
+    /// Decodes named entity at pos (pointing to &), returns decoded chars and position after entity
+    fn decode_named(&self, data: &[u8], pos: usize, offset: usize, errors: &mut Vec<ParseError>)
+        -> Option<(String, usize)> {
+        let start = pos + 1;
+        let mut end = start;
+
+        while end < data.len() && data[end].is_ascii_alphanumeric() {
+            end += 1;
+        }
+
+        if end == start {
+            return None;
+        }
+
+        // the longest matching name wins: full name followed by ; is tried first and then shorter names
+        // without ; as legacy entities like &amp or &not do not need it
+        let alnum_end = if end - start >= MAX_ENTITY_LENGTH {
+            start + MAX_ENTITY_LENGTH - 1
+        } else {
+            end
+        };
+
+        let mut name_ends = Vec::with_capacity(alnum_end - start + 1);
+
+        if alnum_end == end && data.get(end) == Some(&b';') {
+            name_ends.push(end + 1);
+        }
+
+        name_ends.extend((start + 1..alnum_end + 1).rev());
+
+        for name_end in name_ends {
+            // names are all ASCII
+            let name = String::from_utf8_lossy(&data[start..name_end]);
+
+            if let Some(value) = HtmlEntities::get_entity(&name) {
+                if data[name_end - 1] != b';' && self.in_attribute {
+                    if let Some(&next) = data.get(name_end) {
+                        if next == b'=' || next.is_ascii_alphanumeric() {
+                            return None;
+                        }
+                    }
+                }
+
+                if data[name_end - 1] != b';' {
+                    errors.push(ParseError::new(ParseErrorCode::MissingSemicolonAfterCharacterReference,
+                        offset + name_end));
+                }
+
+                // errors are the same whichever entities are decoded
+                if self.mini_only && !MINI_ENTITIES.contains(&name.trim_end_matches(';')) {
+                    return None;
+                }
+
+                return Some((value.to_string(), name_end));
+            }
+        }
+
+        if data.get(end) == Some(&b';') {
+            errors.push(ParseError::new(ParseErrorCode::UnknownNamedCharacterReference, offset + end));
+        }
+
+        None
+    }
+
+    /// Decodes named entity of XML at pos (pointing to &), returns decoded chars and position after entity
+    fn decode_xml_named(&self, data: &[u8], pos: usize, offset: usize, errors: &mut Vec<ParseError>)
+        -> Option<(String, usize)> {
+        let start = pos + 1;
+        let mut end = start;
+
+        while end < data.len() && is_xml_name_char(data[end]) {
+            end += 1;
+        }
+
+        if end == start || data.get(end) != Some(&b';') {
+            errors.push(ParseError::new(ParseErrorCode::UnescapedAmpersand, offset + pos));
+            return None;
+        }
+
+        let name = String::from_utf8_lossy(&data[start..end]);
+
+        if let Some(&(_, value)) = XML_ENTITIES.iter().find(|&&(entity, _)| entity == name) {
+            return Some((value.to_string(), end + 1));
+        }
+
+        match self.declared.get(name.as_ref()) {
+            Some(_) if self.mini_only => None,
+            Some(value) => Some((value.clone(), end + 1)),
+            None => {
+                errors.push(ParseError::new(ParseErrorCode::UndefinedEntity, offset + pos));
+                None
+            },
+        }
+    }
+
+    /// Decodes numeric entity at pos (pointing to &), returns decoded char and position after entity
+    fn decode_numeric(&self, data: &[u8], pos: usize, offset: usize, errors: &mut Vec<ParseError>)
+        -> Option<(String, usize)> {
+        let hex = matches!(data.get(pos + 2), Some(&b'x') | Some(&b'X'));
+
+        let start = if hex { pos + 3 } else { pos + 2 };
+        let mut end = start;
+        let mut code: u32 = 0;
+
+        while end < data.len() {
+            let digit = match (data[end] as char).to_digit(if hex { 16 } else { 10 }) {
+                Some(digit) => digit,
+                None => break,
+            };
+
+            // anything above 0x10FFFF is invalid anyway, so we do not need to keep counting
+            code = code.saturating_mul(if hex { 16 } else { 10 }).saturating_add(digit);
+            end += 1;
+        }
+
+        if end == start {
+            errors.push(ParseError::new(ParseErrorCode::AbsenceOfDigitsInNumericCharacterReference,
+                offset + start));
+            return None;
+        }
+
+        if data.get(end) == Some(&b';') {
+            end += 1;
+        } else {
+            errors.push(ParseError::new(ParseErrorCode::MissingSemicolonAfterCharacterReference, offset + end));
+        }
+
+        Some((HtmlEntities::numeric_char(code, !self.xml_mode, offset + pos, errors).to_string(), end))
+    }
+
+    /// Returns char for code of numeric entity at offset: invalid code points become U+FFFD and C1 controls
+    /// are remapped to windows-1252 chars if asked to, just like browsers do
+    fn numeric_char(code: u32, windows_1252: bool, offset: usize, errors: &mut Vec<ParseError>) -> char {
+        let error = match code {
+            0 => Some(ParseErrorCode::NullCharacterReference),
+            0xD800..=0xDFFF => Some(ParseErrorCode::SurrogateCharacterReference),
+            _ if code > 0x10FFFF => Some(ParseErrorCode::CharacterReferenceOutsideUnicodeRange),
+            _ => None,
+        };
+
+        if let Some(code) = error {
+            errors.push(ParseError::new(code, offset));
+            return '\u{FFFD}';
+        }
+
+        if (0xFDD0..=0xFDEF).contains(&code) || (code & 0xFFFE) == 0xFFFE {
+            errors.push(ParseError::new(ParseErrorCode::NoncharacterCharacterReference, offset));
+        } else if code == 0x0D || ((code < 0x20 || (0x7F..0xA0).contains(&code))
+            && code != 0x09 && code != 0x0A && code != 0x0C) {
+            errors.push(ParseError::new(ParseErrorCode::ControlCharacterReference, offset));
+        }
+
+        let code = match code {
+            0x80..=0x9F if windows_1252 && WINDOWS_1252[code as usize - 0x80] != 0 => WINDOWS_1252[code as usize - 0x80],
+            _ => code,
+        };
+
+        ::std::char::from_u32(code).unwrap_or('\u{FFFD}')
+    }
+}
+
+/// Returns true if byte can be part of XML name, non-ASCII bytes are taken as name chars
+fn is_xml_name_char(ch: u8) -> bool {
+    ch.is_ascii_alphanumeric() || ch == b'_' || ch == b'-' || ch == b'.' || ch == b':' || ch >= 0x80
+}
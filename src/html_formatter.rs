@@ -0,0 +1,319 @@
+use html_chunk::Namespace;
+use html_document::{Document, NodeId, NodeData, Element, Attribute};
+use serializer::{self, BLOCK_ELEMENTS};
+
+/// Elements of head that start on their own line along with block elements, the rest are inline and flow with text
+const HEAD_ELEMENTS: [&str; 8] = ["base", "link", "meta", "noscript", "script", "style", "template", "title"];
+
+/// Formats HTML re-indenting it: block elements like div or p start on their own lines and are indented by
+/// their depth, while inline elements and text flow on lines with whitespace collapsed, which are wrapped
+/// between words once they get too long. Content of pre, textarea, script and style is kept as it is
+pub struct HtmlFormatter {
+    /// Number of spaces per indentation level, 0 means tabs are used
+    pub indent_width: usize,
+
+    /// Lines of text and inline elements are wrapped between words so they are not longer than this and start
+    /// tags longer than this have their attributes on separate lines, 0 means there is no limit
+    pub max_line_length: usize,
+
+    /// If true then attributes are sorted by name
+    pub sort_attributes: bool,
+}
+
+impl HtmlFormatter {
+    pub fn new() -> HtmlFormatter {
+        HtmlFormatter {
+            indent_width: 2,
+            max_line_length: 120,
+            sort_attributes: false,
+        }
+    }
+
+    /// Parses HTML the way browsers do it and formats it, html, head and body elements that parser added
+    /// are left out and only their content is formatted
+    pub fn format(&self, html: &str) -> String {
+        let mut document = Document::parse_html5(html);
+
+        let implied: Vec<NodeId> = document.descendants(document.root()).filter(|&id| {
+            let name = match document[id].as_element() {
+                Some(element) if element.namespace == Namespace::Html => element.name.as_str(),
+                _ => return false,
+            };
+
+            // implied elements get offset of the tag that made parser add them
+            let tag = document[id].offset.and_then(|offset| html.get(offset..offset + name.len() + 1));
+
+            matches!(name, "html" | "head" | "body")
+                && !tag.is_some_and(|tag| tag.eq_ignore_ascii_case(&format!("<{}", name)))
+        }).collect();
+
+        for id in implied {
+            while let Some(child) = document[id].first_child() {
+                document.insert_before(id, child);
+            }
+
+            document.detach(id);
+        }
+
+        self.format_document(&document)
+    }
+
+    /// Formats document, every line ends with new line
+    pub fn format_document(&self, document: &Document) -> String {
+        let mut html = String::new();
+
+        self.format_children(document, document.root(), 0, &mut html);
+
+        html
+    }
+
+    /// Formats children of node at given depth, block children go on their own lines and runs of inline
+    /// ones between them are joined into lines
+    fn format_children(&self, document: &Document, id: NodeId, depth: usize, html: &mut String) {
+        let mut inline = String::new();
+        let mut breaks = Vec::new();
+
+        for child in document.children(id) {
+            if is_block(document, child) {
+                self.inline_lines(depth, &inline, &breaks, html);
+                inline.clear();
+                breaks.clear();
+                self.format_block(document, child, depth, html);
+            } else {
+                self.inline(document, child, depth, &mut inline, &mut breaks);
+            }
+        }
+
+        self.inline_lines(depth, &inline, &breaks, html);
+    }
+
+    fn format_block(&self, document: &Document, id: NodeId, depth: usize, html: &mut String) {
+        let element = match document[id].as_element() {
+            Some(element) => element,
+            None => {
+                let mut node = String::new();
+
+                serializer::serialize(document, id, true, &mut node);
+
+                return self.line(depth, &node, html);
+            },
+        };
+
+        let start_tag = self.start_tag(element, depth);
+
+        if serializer::is_void(element) {
+            return self.line(depth, &start_tag, html);
+        }
+
+        let end_tag = format!("</{}>", element.name);
+
+        if preserves_whitespace(element) {
+            let mut content = String::new();
+
+            serializer::serialize(document, id, false, &mut content);
+
+            return self.line(depth, &format!("{}{}{}", start_tag, content, end_tag), html);
+        }
+
+        if document.children(id).any(|child| is_block(document, child)) {
+            self.line(depth, &start_tag, html);
+            self.format_children(document, id, depth + 1, html);
+            self.line(depth, &end_tag, html);
+
+            return;
+        }
+
+        let mut content = String::new();
+        let mut breaks = Vec::new();
+
+        for child in document.children(id) {
+            self.inline(document, child, depth + 1, &mut content, &mut breaks);
+        }
+
+        let content = content.trim_end();
+        let length = self.indent(depth).chars().count() + start_tag.chars().count() + content.chars().count()
+            + end_tag.len();
+        let single_line = !start_tag.contains('\n') && !content.contains('\n');
+
+        if content.is_empty() || self.max_line_length == 0 || (single_line && length <= self.max_line_length) {
+            self.line(depth, &format!("{}{}{}", start_tag, content, end_tag), html);
+        } else {
+            self.line(depth, &start_tag, html);
+            self.inline_lines(depth + 1, content, &breaks, html);
+            self.line(depth, &end_tag, html);
+        }
+    }
+
+    /// Appends node to run of inline content that goes on lines indented by depth collapsing whitespace
+    /// in text, positions of spaces at which lines can be wrapped are added to breaks
+    fn inline(&self, document: &Document, id: NodeId, depth: usize, html: &mut String, breaks: &mut Vec<usize>) {
+        match document[id].data {
+            NodeData::Text(ref text) => {
+                let mut word = String::new();
+                let mut space = html.is_empty() || breaks.last() == Some(&(html.len() - 1));
+
+                for ch in text.chars() {
+                    if !ch.is_ascii_whitespace() {
+                        word.push(ch);
+                        space = false;
+                    } else if !space {
+                        serializer::escape_text(&word, html);
+                        word.clear();
+                        breaks.push(html.len());
+                        html.push(' ');
+                        space = true;
+                    }
+                }
+
+                serializer::escape_text(&word, html);
+            },
+            NodeData::Element(ref element) => {
+                html.push_str(&self.start_tag(element, depth));
+
+                if serializer::is_void(element) {
+                    return;
+                }
+
+                if preserves_whitespace(element) {
+                    serializer::serialize(document, id, false, html);
+                } else {
+                    for child in document.children(id) {
+                        self.inline(document, child, depth, html, breaks);
+                    }
+                }
+
+                html.push_str("</");
+                html.push_str(&element.name);
+                html.push('>');
+            },
+            _ => serializer::serialize(document, id, true, html),
+        }
+    }
+
+    /// Returns start tag of element at given depth, its attributes are on separate lines indented one level
+    /// deeper if it is too long
+    fn start_tag(&self, element: &Element, depth: usize) -> String {
+        let mut tag = String::new();
+
+        serializer::start_tag(&element.name, self.attributes(element), &mut tag);
+
+        let length = self.indent(depth).chars().count() + tag.chars().count();
+
+        if self.max_line_length == 0 || length <= self.max_line_length || element.attributes.len() < 2 {
+            return tag;
+        }
+
+        let indent = self.indent(depth + 1);
+
+        tag.clear();
+        tag.push('<');
+        tag.push_str(&element.name);
+
+        for (name, value) in self.attributes(element) {
+            tag.push('\n');
+            tag.push_str(&indent);
+            tag.push_str(name);
+            tag.push_str("=\"");
+            serializer::escape_attribute(value, &mut tag);
+            tag.push('"');
+        }
+
+        tag.push('>');
+
+        tag
+    }
+
+    /// Returns names and values of attributes of element, sorted if asked to
+    fn attributes<'a>(&self, element: &'a Element) -> Vec<(&'a str, &'a str)> {
+        let mut attributes: Vec<&Attribute> = element.attributes.iter().collect();
+
+        if self.sort_attributes {
+            attributes.sort_by(|a, b| a.name.cmp(&b.name));
+        }
+
+        attributes.iter().map(|attribute| (attribute.name.as_str(), attribute.value.as_str())).collect()
+    }
+
+    fn indent(&self, depth: usize) -> String {
+        match self.indent_width {
+            0 => "\t".repeat(depth),
+            width => " ".repeat(width * depth),
+        }
+    }
+
+    /// Appends run of inline content as lines indented by depth, it is wrapped at breaks so lines are not
+    /// longer than max_line_length unless a single word is longer
+    fn inline_lines(&self, depth: usize, inline: &str, breaks: &[usize], html: &mut String) {
+        let inline = inline.trim_end();
+        let indent_length = self.indent(depth).chars().count();
+        let mut line = String::new();
+        let mut from = 0;
+
+        let ends = breaks.iter().cloned().filter(|&pos| pos < inline.len()).chain(Some(inline.len()));
+
+        for end in ends {
+            let word = &inline[from..end];
+
+            from = end + 1;
+
+            // multiline start tags and pre elements have their own indentation after new lines
+            let line_length = match line.rfind('\n') {
+                Some(pos) => line[pos + 1..].chars().count(),
+                None => indent_length + line.chars().count(),
+            };
+            let word_length = word.split('\n').next().unwrap_or("").chars().count();
+
+            if !line.is_empty() && self.max_line_length > 0 && line_length + 1 + word_length > self.max_line_length {
+                self.line(depth, &line, html);
+                line.clear();
+            } else if !line.is_empty() {
+                line.push(' ');
+            }
+
+            line.push_str(word);
+        }
+
+        self.line(depth, &line, html);
+    }
+
+    /// Appends indented line unless it is empty
+    fn line(&self, depth: usize, line: &str, html: &mut String) {
+        if line.is_empty() {
+            return;
+        }
+
+        html.push_str(&self.indent(depth));
+        html.push_str(line);
+        html.push('\n');
+    }
+}
+
+impl Default for HtmlFormatter {
+    fn default() -> HtmlFormatter {
+        HtmlFormatter::new()
+    }
+}
+
+/// Returns true if node goes on its own line: it is block element, comment, DOCTYPE or element that has
+/// block element in it
+fn is_block(document: &Document, id: NodeId) -> bool {
+    match document[id].data {
+        NodeData::Element(ref element) if is_block_element(element) => true,
+        NodeData::Element(ref element) if preserves_whitespace(element) => false,
+        NodeData::Element(_) => document.children(id).any(|child| is_block(document, child)),
+        NodeData::Comment(_) | NodeData::Doctype(_) => true,
+        _ => false,
+    }
+}
+
+/// Returns true if element is HTML block element or element of head
+fn is_block_element(element: &Element) -> bool {
+    let name = element.name.as_str();
+
+    element.namespace == Namespace::Html && (BLOCK_ELEMENTS.contains(&name) || HEAD_ELEMENTS.contains(&name))
+}
+
+/// Returns true if content of element is written as it is
+fn preserves_whitespace(element: &Element) -> bool {
+    serializer::is_raw_text(element) || serializer::PRESERVE_WHITESPACE_ELEMENTS.contains(&element.name.as_str())
+}
@@ -245,7 +245,7 @@ impl HtmlHeuristics {
             }
 
         } else {
-            if !self.set_hash(first_ch, tag_chars.nth(1).unwrap(), data_id as i16) {
+            if !self.set_hash(first_ch, tag_chars.next().unwrap(), data_id as i16) {
                 return false
             }
         }
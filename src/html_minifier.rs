@@ -0,0 +1,407 @@
+use html_chunk::{HtmlChunk, ChunkType, Namespace};
+use html_parser::HtmlParser;
+use document_builder::element_start;
+use foreign_content::is_breakout_tag;
+use serializer::{VOID_ELEMENTS, RAW_TEXT_ELEMENTS, PRESERVE_WHITESPACE_ELEMENTS, BLOCK_ELEMENTS};
+
+/// Elements which start tags close p, so its end tag can be dropped before them. Table and form are not here as
+/// they do not close p in quirks mode or inside another form
+const P_CLOSING_ELEMENTS: [&str; 29] = ["address", "article", "aside", "blockquote", "details", "dialog", "div", "dl",
+    "fieldset", "figcaption", "figure", "footer", "h1", "h2", "h3", "h4", "h5", "h6", "header", "hgroup", "hr",
+    "main", "menu", "nav", "ol", "p", "pre", "section", "ul"];
+
+/// Elements which end tag can not be dropped from p that is their last child, as p would not be closed there
+const P_KEEPING_PARENTS: [&str; 7] = ["a", "audio", "del", "ins", "map", "noscript", "video"];
+
+/// Attributes that only matter by being present
+const BOOLEAN_ATTRIBUTES: [&str; 24] = ["allowfullscreen", "async", "autofocus", "autoplay", "checked", "controls",
+    "default", "defer", "disabled", "formnovalidate", "inert", "ismap", "itemscope", "loop", "multiple", "muted",
+    "nomodule", "novalidate", "open", "playsinline", "readonly", "required", "reversed", "selected"];
+
+/// Elements, attributes and their values that are the same as if there were no such attributes
+const DEFAULT_ATTRIBUTES: [(&str, &str, &str); 6] = [("button", "type", "submit"), ("form", "method", "get"),
+    ("input", "type", "text"), ("link", "type", "text/css"), ("script", "type", "text/javascript"),
+    ("style", "type", "text/css")];
+
+/// Minifies HTML chunk by chunk as HtmlParser returns them. Text and attribute values keep their entities,
+/// so minified HTML parses to the same tree as original one following WHATWG tree construction, apart from
+/// whitespace that is not rendered and attributes that are removed or collapsed to the same meaning
+pub struct HtmlMinifier {
+    /// If true then runs of whitespace in text are collapsed into single space, and whitespace next to block
+    /// elements and in head is removed. Text of pre, textarea, script and style is kept as it is
+    pub collapse_whitespace: bool,
+
+    /// If true then comments are removed, conditional comments like <!--[if IE]> are kept
+    pub remove_comments: bool,
+
+    /// If true then end tags that are implied by what follows them are dropped, ie </li> before <li>
+    pub remove_optional_tags: bool,
+
+    /// If true then attribute values are not quoted if they do not need to, empty values are dropped
+    pub remove_attribute_quotes: bool,
+
+    /// If true then attributes that have default values are removed, ie type="text/javascript" of script
+    pub remove_default_attributes: bool,
+
+    /// If true then boolean attributes are written without value, ie disabled="disabled" becomes disabled
+    pub collapse_boolean_attributes: bool,
+
+    /// If true then any DOCTYPE is replaced by <!doctype html>, which turns off quirks mode of legacy DOCTYPEs
+    pub shorten_doctype: bool,
+}
+
+/// Piece of HTML that minifier works with
+enum Token {
+    /// Text which whitespace can be collapsed
+    Text(String),
+
+    /// Text or template code that is written as it is
+    Raw(String),
+
+    /// Comment that is kept, written as it is
+    Comment(String),
+
+    Doctype(String),
+    Start(StartTag),
+    End(String),
+}
+
+struct StartTag {
+    name: String,
+
+    /// True if element is in HTML namespace
+    html: bool,
+    attributes: Vec<(String, String)>,
+
+    /// True if element has no content or end tag, ie it is void
+    closed: bool,
+
+    /// True if tag ends with />, which is only kept if it matters
+    self_closing: bool,
+}
+
+impl Token {
+    /// Returns true if whitespace next to token is not rendered as it is next to block element
+    fn is_boundary(&self) -> bool {
+        match *self {
+            Token::Start(ref tag) => tag.html && BLOCK_ELEMENTS.contains(&tag.name.as_str()),
+            Token::End(ref name) => BLOCK_ELEMENTS.contains(&name.as_str()),
+            Token::Doctype(_) => true,
+            _ => false,
+        }
+    }
+}
+
+impl HtmlMinifier {
+    pub fn new() -> HtmlMinifier {
+        HtmlMinifier {
+            collapse_whitespace: true,
+            remove_comments: true,
+            remove_optional_tags: true,
+            remove_attribute_quotes: true,
+            remove_default_attributes: true,
+            collapse_boolean_attributes: true,
+            shorten_doctype: false,
+        }
+    }
+
+    /// Returns minified HTML
+    pub fn minify(&self, html: &str) -> String {
+        let mut tokens = self.tokens(html);
+
+        if self.collapse_whitespace {
+            collapse_whitespace(&mut tokens);
+        }
+
+        let mut minified = String::with_capacity(html.len());
+        // open elements and whether HTML tag in foreign content was in them
+        let mut open_elements: Vec<(&StartTag, bool)> = Vec::new();
+
+        for (i, token) in tokens.iter().enumerate() {
+            match *token {
+                Token::Text(ref text) | Token::Raw(ref text) | Token::Comment(ref text) => minified.push_str(text),
+                Token::Doctype(_) if self.shorten_doctype => minified.push_str("<!doctype html>"),
+                Token::Doctype(ref doctype) => minified.push_str(doctype),
+                Token::Start(ref tag) => {
+                    self.start_tag(tag, &mut minified);
+
+                    let in_foreign = open_elements.iter().any(|&(open, _)| !open.html);
+                    let has_attribute = |name: &str| tag.attributes.iter().any(|(attribute, _)| attribute == name);
+
+                    if in_foreign && is_breakout_tag(&tag.name, has_attribute) {
+                        for open in open_elements.iter_mut() {
+                            open.1 = true;
+                        }
+                    }
+
+                    if !tag.closed {
+                        open_elements.push((tag, false));
+                    }
+                },
+                Token::End(ref name) => {
+                    // only end tag of current element can be implied, HTML tags in foreign content break out of
+                    // it in ways stack of open elements does not show, so end tags around them are kept
+                    let current = match open_elements.last() {
+                        Some(&(open, breakout)) => open.name == *name && open.html && !breakout,
+                        None => false,
+                    };
+                    let in_foreign = open_elements.iter().any(|&(open, _)| !open.html);
+                    let optional = self.remove_optional_tags && current && !in_foreign && {
+                        let parent = open_elements.len().checked_sub(2).map(|i| open_elements[i].0.name.as_str());
+
+                        end_tag_optional(name, tokens.get(i + 1), parent)
+                    };
+
+                    if let Some(pos) = open_elements.iter().rposition(|&(open, _)| open.name == *name) {
+                        open_elements.truncate(pos);
+                    }
+
+                    if !optional {
+                        minified.push_str("</");
+                        minified.push_str(name);
+                        minified.push('>');
+                    }
+                },
+            }
+        }
+
+        minified
+    }
+
+    /// Splits HTML into tokens dropping comments if asked to
+    fn tokens(&self, html: &str) -> Vec<Token> {
+        let mut parser = HtmlParser::new();
+        let mut tokens = Vec::new();
+
+        // names of open elements and whether they are in HTML namespace
+        let mut open_elements: Vec<(String, bool)> = Vec::new();
+
+        parser.compress_whitespace_before_tag = false;
+        parser.init(html);
+
+        while let Some(chunk) = parser.parse_next() {
+            let source = &html[chunk.chunk_offset..chunk.chunk_offset + chunk.chunk_length];
+
+            match chunk.chunk_type {
+                ChunkType::Text => {
+                    let preserve = open_elements.iter().any(|(name, html)| *html && preserves_whitespace(name));
+                    let in_head = open_elements.iter().any(|(name, html)| *html && name == "head");
+
+                    if preserve {
+                        tokens.push(Token::Raw(chunk.html.clone()));
+                    } else if !(self.collapse_whitespace && in_head && is_whitespace(&chunk.html)) {
+                        push_text(&mut tokens, &chunk.html);
+                    }
+                },
+                ChunkType::Template => tokens.push(Token::Raw(source.to_string())),
+                // CDATA, processing instructions and conditional comments are kept
+                ChunkType::Comment if self.remove_comments && chunk.tag == "!--" && chunk.condition.is_empty() => {},
+                ChunkType::Comment => tokens.push(Token::Comment(source.to_string())),
+                ChunkType::Doctype => tokens.push(Token::Doctype(source.to_string())),
+                ChunkType::OpenTag if chunk.tag == "!doctype" => tokens.push(Token::Doctype(source.to_string())),
+                ChunkType::CloseTag if !chunk.end_closure => {
+                    if let Some(pos) = open_elements.iter().rposition(|(name, _)| *name == chunk.tag) {
+                        open_elements.truncate(pos);
+                    }
+
+                    tokens.push(Token::End(chunk.tag.clone()));
+                },
+                ChunkType::Script => {
+                    let mut tag = start_tag(chunk);
+
+                    tag.closed = false;
+                    tokens.push(Token::Start(tag));
+                    tokens.push(Token::Raw(chunk.html.clone()));
+                    tokens.push(Token::End(chunk.tag.clone()));
+                },
+                _ => {
+                    let tag = start_tag(chunk);
+
+                    if !tag.closed {
+                        open_elements.push((tag.name.clone(), tag.html));
+                    }
+
+                    tokens.push(Token::Start(tag));
+                },
+            }
+        }
+
+        tokens
+    }
+
+    fn start_tag(&self, tag: &StartTag, html: &mut String) {
+        // slash is ignored after start tags of HTML elements
+        let self_closing = tag.self_closing && !(tag.html && VOID_ELEMENTS.contains(&tag.name.as_str()));
+
+        html.push('<');
+        html.push_str(&tag.name);
+
+        let attributes = tag.attributes.iter().filter(|(name, value)| {
+            !(self.remove_default_attributes && tag.html && is_default_attribute(&tag.name, name, value))
+        });
+
+        let mut last_unquoted = false;
+
+        for (name, value) in attributes {
+            html.push(' ');
+            html.push_str(name);
+            last_unquoted = false;
+
+            let boolean = tag.html && BOOLEAN_ATTRIBUTES.contains(&name.as_str());
+
+            if (self.collapse_boolean_attributes && boolean) || (self.remove_attribute_quotes && value.is_empty()) {
+                continue;
+            }
+
+            html.push('=');
+
+            let unquoted = !value.is_empty() && !value.contains(|ch: char| {
+                ch.is_ascii_whitespace() || ch == '"' || ch == '\'' || ch == '`' || ch == '=' || ch == '<' || ch == '>'
+            });
+
+            if self.remove_attribute_quotes && unquoted {
+                html.push_str(value);
+                last_unquoted = true;
+            } else {
+                // values keep their entities, so quote is only there if the other quote was used in HTML
+                let quote = if value.contains('"') { '\'' } else { '"' };
+
+                html.push(quote);
+                html.push_str(value);
+                html.push(quote);
+            }
+        }
+
+        if self_closing {
+            // unquoted value would take the slash
+            html.push_str(if last_unquoted { " />" } else { "/>" });
+        } else {
+            html.push('>');
+        }
+    }
+}
+
+impl Default for HtmlMinifier {
+    fn default() -> HtmlMinifier {
+        HtmlMinifier::new()
+    }
+}
+
+/// Creates start tag from chunk that starts element, param values keep their entities
+fn start_tag(chunk: &HtmlChunk) -> StartTag {
+    let attributes = chunk.param_names.iter().zip(chunk.param_values.iter())
+        .take(chunk.params_count)
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+
+    StartTag {
+        name: chunk.tag.clone(),
+        html: chunk.namespace == Namespace::Html,
+        attributes,
+        closed: element_start(chunk, false) != Some(false),
+        self_closing: chunk.end_closure,
+    }
+}
+
+/// Appends text token merging it with text before, so text around removed comment is whole
+fn push_text(tokens: &mut Vec<Token>, text: &str) {
+    match tokens.last_mut() {
+        Some(&mut Token::Text(ref mut last)) => last.push_str(text),
+        _ => tokens.push(Token::Text(text.to_string())),
+    }
+}
+
+/// Collapses runs of whitespace in text tokens and removes whitespace next to block elements
+fn collapse_whitespace(tokens: &mut Vec<Token>) {
+    for i in 0..tokens.len() {
+        let trim_start = i == 0 || tokens[i - 1].is_boundary();
+        let trim_end = tokens.get(i + 1).is_none_or(Token::is_boundary);
+
+        if let Token::Text(ref mut text) = tokens[i] {
+            let mut collapsed = String::with_capacity(text.len());
+            let mut space = false;
+
+            for ch in text.chars() {
+                if !ch.is_ascii_whitespace() {
+                    collapsed.push(ch);
+                    space = false;
+                } else if !space {
+                    collapsed.push(' ');
+                    space = true;
+                }
+            }
+
+            if trim_start && collapsed.starts_with(' ') {
+                collapsed.remove(0);
+            }
+
+            if trim_end && collapsed.ends_with(' ') {
+                collapsed.pop();
+            }
+
+            *text = collapsed;
+        }
+    }
+
+    tokens.retain(|token| match *token {
+        Token::Text(ref text) => !text.is_empty(),
+        _ => true,
+    });
+}
+
+/// Returns true if end tag of element can be dropped as it is implied by token after it, parent is name of
+/// element that contains the element
+fn end_tag_optional(name: &str, next: Option<&Token>, parent: Option<&str>) -> bool {
+    let next_start = match next {
+        Some(Token::Start(tag)) if tag.html => tag.name.as_str(),
+        _ => "",
+    };
+
+    // end of parent element or of the whole document
+    let parent_ends = match next {
+        Some(Token::End(end)) => Some(end.as_str()) == parent,
+        None => true,
+        _ => false,
+    };
+
+    let comment_or_space = match next {
+        Some(Token::Comment(_)) => true,
+        Some(Token::Text(text)) | Some(Token::Raw(text)) => text.starts_with(|ch: char| ch.is_ascii_whitespace()),
+        _ => false,
+    };
+
+    match name {
+        "html" | "body" => !matches!(next, Some(Token::Comment(_))),
+        "head" | "colgroup" | "caption" => !comment_or_space,
+        "li" => next_start == "li" || parent_ends,
+        "dt" => next_start == "dt" || next_start == "dd",
+        "dd" => next_start == "dt" || next_start == "dd" || parent_ends,
+        "p" => P_CLOSING_ELEMENTS.contains(&next_start)
+            || (parent_ends && !parent.is_some_and(|parent| P_KEEPING_PARENTS.contains(&parent))),
+        "rt" | "rp" => next_start == "rt" || next_start == "rp" || parent_ends,
+        "optgroup" => next_start == "optgroup" || parent_ends,
+        "option" => next_start == "option" || next_start == "optgroup" || parent_ends,
+        "thead" => next_start == "tbody" || next_start == "tfoot",
+        "tbody" => next_start == "tbody" || next_start == "tfoot" || parent_ends,
+        "tfoot" => parent_ends,
+        "tr" => next_start == "tr" || parent_ends,
+        "td" | "th" => next_start == "td" || next_start == "th" || parent_ends,
+        _ => false,
+    }
+}
+
+/// Returns true if attribute of element has the value it would have if it was not there
+fn is_default_attribute(element: &str, name: &str, value: &str) -> bool {
+    DEFAULT_ATTRIBUTES.iter().any(|&(default_element, default_name, default_value)| {
+        default_element == element && default_name == name && value.trim().eq_ignore_ascii_case(default_value)
+    })
+}
+
+/// Returns true if whitespace of text in element must be kept
+fn preserves_whitespace(name: &str) -> bool {
+    RAW_TEXT_ELEMENTS.contains(&name) || PRESERVE_WHITESPACE_ELEMENTS.contains(&name)
+}
+
+fn is_whitespace(text: &str) -> bool {
+    text.chars().all(|ch| ch.is_ascii_whitespace())
+}
@@ -1,12 +1,26 @@
 use html_heuristics::HtmlHeuristics;
-use dynamic_string::DynamicString;
-use html_chunk::HtmlChunk;
+use dynamic_string::{DynamicString, decode_bytes};
+use html_chunk::{HtmlChunk, ChunkType, Namespace};
 use tag_parser::TagParser;
 use html_entities::HtmlEntities;
+use parse_error::{ParseError, ParseErrorCode, ParseErrorSink};
+use line_index::{LineIndex, SourcePosition};
+use html_tokenizer::{HtmlTokenizer, scan_comment};
+use input_preprocessor::InputPreprocessor;
+use foreign_content::ForeignContent;
+use xml_content::{XmlContent, entity_declarations};
 
-use encoding::{Encoding, EncodingRef, EncoderTrap, DecoderTrap};
+pub use html_tokenizer::TokenizerState;
+pub use input_preprocessor::InvalidChars;
+
+use encoding::EncodingRef;
 use encoding::label::encoding_from_whatwg_label;
-use encoding::all::ASCII;
+
+/// Elements which content is text up to their closing tag (apart from script that gets own chunk type)
+const RAW_TEXT_TAGS: [&str; 7] = ["style", "xmp", "iframe", "noembed", "noframes", "textarea", "title"];
+
+/// Elements which content is text up to their closing tag, but entities are decoded in it
+const RCDATA_TAGS: [&str; 2] = ["textarea", "title"];
 
 /// Allows to parse HTML by splitting it into small token (HTMLchunks) such as tags, text, comments etc.
 /// 
@@ -14,38 +28,97 @@ use encoding::all::ASCII;
 /// Do NOT call same instance from multiple threads - it is NOT thread safe
 pub struct HtmlParser {
 
-    /// If false (default) then mini entity set (&nbsp;) will be decoded, but not all of them
-    decode_mini_entities: bool,
+    /// If true (default: false) then entities will be decoded in text and param values
+    pub decode_entities: bool,
+
+    /// If true (default: false) then mini entity set (&amp; &lt; &gt; &quot; &apos; &nbsp;) will be decoded,
+    /// but not all of them - this is used if decode_entities is false
+    pub decode_mini_entities: bool,
 
     /// If true (default: false) then parsed tag chunks will contain raw HTML, 
     /// otherwise only comments will have it set
     /// 
     /// Performance hint: keep it as false, you can always get to original HTML as each chunk contains
     /// offset from which parsing started and finished, thus allowing to set exact HTML that was parsed
-    keep_raw_html: bool,
+    pub keep_raw_html: bool,
 
     /// If true (default) then HTML for comments tags 
     /// themselves AND between them will be set to oHTML variable, otherwise it will be empty
     /// but you can always set it later 
-    keep_comments: bool,
+    pub keep_comments: bool,
 
     /// If true (default: false) then HTML for script tags 
     /// themselves AND between them will be set to html variable, otherwise it will be empty
     /// but you can always set it later
-    keep_scripts: bool,
+    pub keep_scripts: bool,
 
     /// If true (and either keep_comments or keep_scripts is true), then html will be set
     /// to data BETWEEN tags excluding those tags themselves, as otherwise FULL HTML will be set, ie:
     /// '<!-- comments -->' but if this is set to true then only ' comments ' will be returned
-    extract_between_tags_only: bool,
+    pub extract_between_tags_only: bool,
 
     /// Long winded name... by default if tag is closed BUT it has got parameters then we will consider it
-    /// open tag, this is not right for proper XML parsing
-    mark_closed_tags_with_params_as_open: bool,
+    /// open tag, this is not right for proper XML parsing. Self-closing SVG and MathML tags are always closed,
+    /// so are all self-closing tags in XML mode
+    pub mark_closed_tags_with_params_as_open: bool,
 
     /// If true (default), then all whitespace before TAG starts will be compressed to single space char (32 or 0x20)
     /// this makes parser run a bit faster, if you need exact whitespace before tags then change this flag to FALSE
-    compress_whitespace_before_tag: bool,
+    pub compress_whitespace_before_tag: bool,
+
+    /// If true (default: false) then content of IE conditional comments like <!--[if mso]> ... <![endif]-->
+    /// will be parsed as HTML: start and end of such comment will be returned as ![if and ![endif comment
+    /// chunks with normal chunks in between, otherwise the whole thing is a single comment with condition set
+    pub parse_conditional_comments: bool,
+
+    /// If true (default: false) then parsing stops at the first parse error: parse_next will not return
+    /// chunk in which error was found and will keep returning None, see errors for what went wrong
+    pub strict: bool,
+
+    /// If true (default: false) then line and columns will be calculated for start and end of each chunk
+    /// and its params, otherwise only byte offsets are set. Set it before calling init
+    pub track_positions: bool,
+
+    /// If true (default: false) then HTML is parsed by spec-conformant tokenizer rather than fast lenient
+    /// parser: chunks are exactly the tokens that WHATWG tokenizer produces, ie newlines are normalized,
+    /// entities are always decoded, script is returned as open tag, text and close tag and DOCTYPE is
+    /// returned as Doctype chunk. Set it before calling init
+    pub conformance_mode: bool,
+
+    /// If true (default) then in conformance mode content of elements like script, style or textarea is
+    /// parsed as text up to their end tag, just like browsers do in HTML content. Set it to false if you
+    /// switch states of tokenizer yourself, see set_tokenizer_state
+    pub auto_switch_states: bool,
+
+    /// If true (default: false) then CR and CRLF are converted into LF in html, tags and params of chunks,
+    /// offsets of chunks still point at original bytes. Conformance mode always normalizes newlines
+    pub normalize_newlines: bool,
+
+    /// If true (default: false) then data is parsed as XML rather than HTML: tag and param names keep their
+    /// case, self-closing tags are always closed, there are no elements with text content like script,
+    /// only the five predefined entities and the ones declared in DOCTYPE are decoded and well-formedness
+    /// errors such as mismatched end tags are reported. Prefixes of tag and param names are resolved to URIs of
+    /// namespaces declared by xmlns params, see namespace_uri of HtmlChunk. Processing instructions like <?xml ?> are returned
+    /// as comments with ? tag. Conformance mode is ignored. Set it before calling init
+    pub xml_mode: bool,
+
+    /// Start and end delimiters of template code like ("{{", "}}"), ("{%", "%}"), ("<%", "%>") or ("<?php", "?>"),
+    /// none by default. Template code is returned as Template chunk, inside of tags it is kept as param named
+    /// after the whole code (with empty value) and it is skipped in param values, so > or quotes in it never
+    /// end tag or value. Only used by lenient parser
+    pub template_delimiters: Vec<(String, String)>,
+
+    /// What to do with NULs and noncharacters in html, tags and params of chunks, they are kept by default
+    pub invalid_chars: InvalidChars,
+
+    /// If true (default: false) then tree construction parses content of noscript as text, just like
+    /// browsers with scripting enabled do, otherwise it is parsed as HTML
+    pub scripting_enabled: bool,
+
+    /// If true (default: false) then more data is going to be added by push_bytes, so chunks that reach the end
+    /// of data are not returned as they may continue in data that comes next: parse_next returns None until
+    /// more data is pushed. Set it to false once all data was pushed to parse the rest
+    pub more_data: bool,
 
     /// Heuristics engine used by Tag Parser to quickly match known tags and attribute names, can be disabled
     /// or you can add more tags to it to fit your most likely cases, it is currently tuned for HTML
@@ -67,16 +140,44 @@ pub struct HtmlParser {
     html_bytes: Option<Box<[u8]>>, 
 
     /// Current position pointing to byte in html_bytes
-    current_position: u32,
+    current_position: usize,
 
     /// Length of bHTML -- it appears to be faster to use it than html_bytes.len()
-    data_length: u32,
+    data_length: usize,
 
     /// Whitespace lookup table - false is not whitespace, otherwise it is
     whitespace: [bool; 256],
 
     /// Entities manager
     entities: HtmlEntities,
+
+    /// Tokenizer used in conformance mode
+    tokenizer: HtmlTokenizer,
+
+    /// Open SVG and MathML elements, they give namespace to tags
+    foreign_content: ForeignContent,
+
+    /// Open elements of XML document, they are checked for well-formedness
+    xml_content: XmlContent,
+
+    /// Parse errors found since last init
+    errors: Vec<ParseError>,
+
+    /// Optional receiver of parse errors as soon as they are found
+    error_sink: Option<Box<dyn ParseErrorSink>>,
+
+    /// If set then we are inside of element (ie style or textarea) which content is text up to its closing tag
+    raw_text_tag: Option<String>,
+
+    /// True if we are parsing content of IE conditional comment, so <![endif]--> will end it
+    in_conditional_comment: bool,
+
+    /// Index of lines in html_bytes, only created if we track positions
+    line_index: Option<LineIndex>,
+
+    /// Last position that was calculated - chunks come one after another, so it is faster to
+    /// calculate next position from it
+    last_position: SourcePosition,
 }
 
 impl HtmlParser {
@@ -84,15 +185,16 @@ impl HtmlParser {
         let mut heuristics = HtmlHeuristics::new();
         let text = DynamicString::new("".to_string());
         let chunk = HtmlChunk::new(true);
-        let tag_parser = TagParser{};
+        let tag_parser = TagParser::new();
         let html_bytes = None;
-        let entities = HtmlEntities{};
+        let entities = HtmlEntities::new();
         let mut whitespace = [false; 256];
 
-        HtmlParser::init_whitespaces(whitespace);
+        HtmlParser::init_whitespaces(&mut whitespace);
         HtmlParser::init_heuristics(&mut heuristics);
 
         let parser = HtmlParser{
+            decode_entities: false,
             decode_mini_entities: false,
             keep_raw_html: false,
             keep_comments: true,
@@ -100,6 +202,17 @@ impl HtmlParser {
             extract_between_tags_only: true,
             mark_closed_tags_with_params_as_open: true,
             compress_whitespace_before_tag: true,
+            parse_conditional_comments: false,
+            strict: false,
+            track_positions: false,
+            conformance_mode: false,
+            auto_switch_states: true,
+            normalize_newlines: false,
+            xml_mode: false,
+            template_delimiters: Vec::new(),
+            invalid_chars: InvalidChars::Keep,
+            scripting_enabled: false,
+            more_data: false,
             heuristics: heuristics,
             text: text,
             chunk: chunk,
@@ -109,14 +222,954 @@ impl HtmlParser {
             current_position: 0,
             data_length: 0,
             entities: entities,
+            tokenizer: HtmlTokenizer::new(),
+            foreign_content: ForeignContent::new(),
+            xml_content: XmlContent::new(),
             whitespace: whitespace,
+            errors: Vec::new(),
+            error_sink: None,
+            raw_text_tag: None,
+            in_conditional_comment: false,
+            line_index: None,
+            last_position: SourcePosition::start(),
         };
 
         parser
     }
 
+    /// Initialises parser with HTML string, parsing will start from its beginning
+    pub fn init(&mut self, html: &str) {
+        self.set_encoding(encoding_from_whatwg_label("utf8").unwrap());
+        self.init_bytes(html.as_bytes().to_vec());
+    }
+
+    /// Initialises parser with binary HTML data, call set_encoding first if data is not in UTF-8
+    pub fn init_bytes(&mut self, html: Vec<u8>) {
+        self.line_index = if self.track_positions {
+            Some(LineIndex::new(&html))
+        } else {
+            None
+        };
+
+        self.last_position = SourcePosition::start();
+        self.data_length = html.len();
+        self.html_bytes = Some(html.into_boxed_slice());
+        self.current_position = 0;
+        self.errors.clear();
+        self.raw_text_tag = None;
+        self.in_conditional_comment = false;
+        self.chunk.clear();
+        self.foreign_content.clear();
+        self.xml_content.clear();
+        self.entities.clear_declared();
+        self.tokenizer.init(self.enc);
+    }
+
+    /// Adds data to parse after data that parser has got, see more_data. Data that was parsed already is
+    /// dropped, so offsets of chunks and errors that come next are relative to data that was kept: returns
+    /// number of dropped bytes that should be added to them to get offsets in the whole input. Parser is
+    /// initialised if it was not. Conformance mode does not support it
+    pub fn push_bytes(&mut self, data: &[u8]) -> usize {
+        assert!(!self.conformance_mode || self.xml_mode, "conformance mode can not parse data that is pushed");
+
+        let html = match self.html_bytes.take() {
+            Some(html) => html,
+            None => {
+                self.init_bytes(data.to_vec());
+                return 0;
+            },
+        };
+
+        let dropped = self.current_position;
+
+        if self.track_positions {
+            // lines and columns keep counting from where kept data starts
+            self.last_position = SourcePosition { offset: 0, ..self.position_from_last(&html, dropped) };
+        }
+
+        let mut kept = Vec::with_capacity(html.len() - dropped + data.len());
+
+        kept.extend_from_slice(&html[dropped..]);
+        kept.extend_from_slice(data);
+
+        self.data_length = kept.len();
+        self.html_bytes = Some(kept.into_boxed_slice());
+        self.current_position = 0;
+        self.line_index = None;
+
+        dropped
+    }
+
+    /// Releases HTML data that was parsed, it is good idea to call it once you finished parsing
+    pub fn clean_up(&mut self) {
+        self.html_bytes = None;
+        self.data_length = 0;
+        self.current_position = 0;
+        self.raw_text_tag = None;
+        self.line_index = None;
+    }
+
+    /// Sets encoding to be used for conversion of binary data into strings
+    pub fn set_encoding(&mut self, encoding: EncodingRef) {
+        self.enc = encoding;
+        self.chunk.set_encoding(encoding);
+    }
+
+    /// Returns parse errors found since parser was initialised, errors in entities are reported whether
+    /// they are decoded or not
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
+    }
+
+    /// Sets receiver of parse errors that will get them as soon as they are found, errors are
+    /// available from errors function as well
+    pub fn set_error_sink(&mut self, sink: Box<dyn ParseErrorSink>) {
+        self.error_sink = Some(sink);
+    }
+
+    /// Removes receiver of parse errors returning it
+    pub fn remove_error_sink(&mut self) -> Option<Box<dyn ParseErrorSink>> {
+        self.error_sink.take()
+    }
+
+    /// Returns index of lines of HTML that is being parsed, it is only available if track_positions
+    /// was set before init
+    pub fn line_index(&self) -> Option<&LineIndex> {
+        self.line_index.as_ref()
+    }
+
+    /// Returns line and columns of byte at given offset of HTML that is being parsed, offsets past the end
+    /// of HTML give position of the end
+    pub fn position(&self, offset: usize) -> SourcePosition {
+        match (&self.html_bytes, &self.line_index) {
+            (Some(data), Some(index)) => index.position(data, offset),
+            (Some(data), None) => SourcePosition::start().advance(data, offset),
+            _ => SourcePosition::default(),
+        }
+    }
+
+    /// Switches tokenizer used in conformance mode to given state, ie to RAWTEXT after open tag of
+    /// style was returned. Normally it is done automatically, see auto_switch_states
+    pub fn set_tokenizer_state(&mut self, state: TokenizerState) {
+        self.tokenizer.set_state(state);
+    }
+
+    /// Sets name of the last open tag for tokenizer used in conformance mode, end tags are only
+    /// recognised in RCDATA, RAWTEXT and script data states if their name matches it
+    pub fn set_last_start_tag(&mut self, tag: Option<&str>) {
+        self.tokenizer.set_last_start_tag(tag);
+    }
+
+    /// Allows CDATA sections in conformance mode, tree construction allows them in SVG and MathML content.
+    /// Normally it is done automatically, see auto_switch_states
+    pub fn set_cdata_allowed(&mut self, allowed: bool) {
+        self.tokenizer.cdata_allowed = allowed;
+    }
+
+    /// Returns HTML data that parser was initialised with, None if it was not initialised or cleaned up
+    pub fn html_bytes(&self) -> Option<&[u8]> {
+        self.html_bytes.as_deref()
+    }
+
+    /// Returns chunk that was parsed last
+    pub fn current_chunk(&self) -> &HtmlChunk {
+        &self.chunk
+    }
+
+    /// Sets html of last parsed chunk to original HTML from which it was parsed
+    pub fn set_raw_html(&mut self) {
+        if let Some(ref data) = self.html_bytes {
+            let from = self.chunk.chunk_offset;
+            let to = from + self.chunk.chunk_length;
+
+            self.chunk.html = decode_bytes(self.enc, &data[from..to]);
+        }
+    }
+
+    /// Parses next chunk of HTML, returns None once end of data was reached
+    pub fn parse_next(&mut self) -> Option<&HtmlChunk> {
+        let data = self.html_bytes.take()?;
+
+        let found = if self.more_data {
+            self.parse_complete_chunk(&data)
+        } else {
+            self.parse_next_chunk(&data)
+        };
+
+        self.html_bytes = Some(data);
+
+        if found {
+            Some(&self.chunk)
+        } else {
+            None
+        }
+    }
+
+    /// Parses next chunk like parse_next_chunk unless it reaches the end of data, as more data may change it:
+    /// state of parser is restored then and errors found in it are not reported
+    fn parse_complete_chunk(&mut self, data: &[u8]) -> bool {
+        let position = self.current_position;
+        let last_position = self.last_position;
+        let raw_text_tag = self.raw_text_tag.clone();
+        let in_conditional_comment = self.in_conditional_comment;
+        let foreign_content = self.foreign_content.clone();
+        let xml_content = self.xml_content.clone();
+        let errors_count = self.errors.len();
+        let sink = self.error_sink.take();
+
+        let found = self.parse_next_chunk(data);
+
+        self.error_sink = sink;
+
+        if self.current_position >= self.data_length {
+            self.current_position = position;
+            self.last_position = last_position;
+            self.raw_text_tag = raw_text_tag;
+            self.in_conditional_comment = in_conditional_comment;
+            self.foreign_content = foreign_content;
+            self.xml_content = xml_content;
+            self.errors.truncate(errors_count);
+            self.chunk.clear();
+
+            return false;
+        }
+
+        if let Some(ref mut sink) = self.error_sink {
+            for error in &self.errors[errors_count..] {
+                sink.parse_error(error);
+            }
+        }
+
+        found
+    }
+
+    /// Parses next chunk from data into self.chunk, returns false if nothing is left to parse
+    fn parse_next_chunk(&mut self, data: &[u8]) -> bool {
+        let errors_count = self.errors.len();
+
+        if self.strict && errors_count > 0 {
+            return false;
+        }
+
+        let found = if self.conformance_mode && !self.xml_mode {
+            self.parse_conformant_chunk(data)
+        } else {
+            self.parse_lenient_chunk(data)
+        };
+
+        if !found {
+            return false;
+        }
+
+        self.chunk.chunk_length = self.current_position - self.chunk.chunk_offset;
+        self.set_positions(data);
+
+        if self.strict && self.errors.len() > errors_count {
+            return false;
+        }
+
+        if self.keep_raw_html {
+            match self.chunk.chunk_type {
+                ChunkType::OpenTag | ChunkType::CloseTag => self.set_raw_html(),
+                _ => {}
+            }
+        }
+
+        let preprocessor = InputPreprocessor {
+            normalize_newlines: self.normalize_newlines,
+            invalid_chars: self.invalid_chars,
+        };
+
+        if preprocessor.is_enabled() {
+            preprocessor.process_chunk(&mut self.chunk);
+        }
+
+        true
+    }
+
+    /// Parses next chunk using spec-conformant tokenizer, returns false if nothing is left to parse
+    fn parse_conformant_chunk(&mut self, data: &[u8]) -> bool {
+        let mut errors = Vec::new();
+
+        self.chunk.clear();
+
+        let found = self.tokenizer.next_chunk(data, &mut self.chunk, &mut errors);
+
+        self.report_errors(errors);
+
+        if !found {
+            return false;
+        }
+
+        self.current_position = self.chunk.chunk_offset + self.chunk.chunk_length;
+        self.foreign_content.process(&mut self.chunk);
+
+        if self.auto_switch_states {
+            self.tokenizer.cdata_allowed = self.foreign_content.cdata_allowed();
+        }
+
+        match self.chunk.chunk_type {
+            ChunkType::Comment => {
+                if !self.keep_comments {
+                    self.chunk.html.clear();
+                } else if !self.extract_between_tags_only {
+                    self.set_raw_html();
+                }
+            },
+            ChunkType::OpenTag if self.auto_switch_states && self.chunk.namespace == Namespace::Html => {
+                let tag = self.chunk.tag.as_str();
+
+                let state = if RCDATA_TAGS.contains(&tag) {
+                    TokenizerState::Rcdata
+                } else if RAW_TEXT_TAGS.contains(&tag) {
+                    TokenizerState::Rawtext
+                } else if tag == "script" {
+                    TokenizerState::ScriptData
+                } else if tag == "plaintext" {
+                    TokenizerState::Plaintext
+                } else {
+                    TokenizerState::Data
+                };
+
+                self.tokenizer.set_state(state);
+            },
+            _ => {}
+        }
+
+        true
+    }
+
+    /// Parses next chunk using fast lenient parser, returns false if nothing is left to parse
+    fn parse_lenient_chunk(&mut self, data: &[u8]) -> bool {
+        if self.tag_parser.template_delimiters != self.template_delimiters {
+            self.tag_parser.template_delimiters = self.template_delimiters.clone();
+        }
+
+        loop {
+            let pos = self.current_position;
+
+            if pos >= self.data_length {
+                if self.xml_mode {
+                    let mut errors = Vec::new();
+
+                    self.xml_content.finish(self.data_length, &mut errors);
+                    self.report_errors(errors);
+                }
+
+                return false;
+            }
+
+            self.chunk.clear();
+            self.chunk.chunk_type = ChunkType::Text;
+            self.chunk.chunk_offset = pos;
+
+            if let Some(tag) = self.raw_text_tag.take() {
+                if self.parse_raw_text(data, &tag) {
+                    break;
+                }
+            }
+
+            if let Some(end) = self.tag_parser.template_end(data, pos) {
+                self.chunk.chunk_type = ChunkType::Template;
+                self.chunk.html = decode_bytes(self.enc, &data[pos..end]);
+                self.current_position = end;
+                break;
+            }
+
+            if data[pos] == b'<' && pos + 1 < self.data_length {
+                let next = data[pos + 1];
+
+                if self.is_tag_name_start(next) {
+                    self.parse_tag(data, pos + 1, false);
+                    break;
+                }
+
+                if next == b'/' && pos + 2 < self.data_length {
+                    let name_ch = data[pos + 2];
+
+                    if self.is_tag_name_start(name_ch) {
+                        self.parse_tag(data, pos + 2, true);
+                        break;
+                    }
+
+                    if name_ch == b'>' {
+                        self.add_error(ParseErrorCode::MissingEndTagName, pos + 2);
+                        self.current_position = pos + 3;
+                        continue;
+                    }
+
+                    self.add_error(ParseErrorCode::InvalidFirstCharacterOfTagName, pos + 2);
+                    self.parse_bogus_comment(data, pos + 2);
+                    break;
+                }
+
+                if next == b'!' {
+                    self.parse_markup_declaration(data, pos);
+                    break;
+                }
+
+                if next == b'?' && self.xml_mode {
+                    self.parse_processing_instruction(data, pos + 2);
+                    break;
+                }
+
+                if next == b'?' {
+                    self.add_error(ParseErrorCode::UnexpectedQuestionMarkInsteadOfTagName, pos + 1);
+                    self.parse_bogus_comment(data, pos + 1);
+                    break;
+                }
+            }
+
+            self.parse_text(data, pos);
+            break;
+        }
+
+        if self.xml_mode {
+            let mut errors = Vec::new();
+
+            self.xml_content.process(&mut self.chunk, &mut errors);
+            self.report_errors(errors);
+        }
+
+        true
+    }
+
+    /// Sets start and end positions of chunk and its params
+    fn set_positions(&mut self, data: &[u8]) {
+        let start = self.chunk.chunk_offset;
+        let end = self.current_position;
+
+        if !self.track_positions {
+            self.chunk.start = SourcePosition { offset: start, ..SourcePosition::default() };
+            self.chunk.end = SourcePosition { offset: end, ..SourcePosition::default() };
+            return;
+        }
+
+        self.chunk.start = self.position_from_last(data, start);
+
+        for i in 0..self.chunk.param_positions.len() {
+            let mut position = self.chunk.param_positions[i];
+
+            position.name_start = self.position_from_last(data, position.name_start.offset);
+            position.name_end = self.position_from_last(data, position.name_end.offset);
+            position.value_start = self.position_from_last(data, position.value_start.offset);
+            position.value_end = self.position_from_last(data, position.value_end.offset);
+
+            self.chunk.param_positions[i] = position;
+        }
+
+        self.chunk.end = self.position_from_last(data, end);
+    }
+
+    /// Returns position of byte at offset calculating it from last position if possible
+    fn position_from_last(&mut self, data: &[u8], offset: usize) -> SourcePosition {
+        let position = if offset >= self.last_position.offset {
+            self.last_position.advance(data, offset)
+        } else {
+            match self.line_index {
+                Some(ref index) => index.position(data, offset),
+                None => SourcePosition::start().advance(data, offset),
+            }
+        };
+
+        self.last_position = position;
+
+        position
+    }
+
+    fn add_error(&mut self, code: ParseErrorCode, offset: usize) {
+        self.report_error(ParseError::new(code, offset));
+    }
+
+    fn report_error(&mut self, error: ParseError) {
+        if let Some(ref mut sink) = self.error_sink {
+            sink.parse_error(&error);
+        }
+
+        self.errors.push(error);
+    }
+
+    /// Reports errors that were found by tag parser or entities
+    fn report_errors(&mut self, errors: Vec<ParseError>) {
+        for error in errors {
+            self.report_error(error);
+        }
+    }
+
+    /// Reports nulls found in data between from and to
+    fn check_nulls(&mut self, data: &[u8], from: usize, to: usize) {
+        for (i, &ch) in data[from..to].iter().enumerate() {
+            if ch == 0 {
+                self.add_error(ParseErrorCode::UnexpectedNullCharacter, from + i);
+            }
+        }
+    }
+
+    /// Reports error for < at pos that does not start a tag
+    fn check_lt_in_text(&mut self, data: &[u8], pos: usize) {
+        if data[pos] != b'<' {
+            return;
+        }
+
+        if pos + 1 >= self.data_length || (data[pos + 1] == b'/' && pos + 2 >= self.data_length) {
+            self.add_error(ParseErrorCode::EofBeforeTagName, self.data_length);
+        } else if data[pos + 1] != b'/' {
+            self.add_error(ParseErrorCode::InvalidFirstCharacterOfTagName, pos + 1);
+        }
+    }
+
+    /// Converts data between from and to into string decoding entities if we need to
+    fn decode_text(&mut self, data: &[u8], from: usize, to: usize) -> String {
+        let mut errors = Vec::new();
+
+        self.entities.mini_only = !self.decode_entities;
+        self.entities.xml_mode = self.xml_mode;
+
+        if !self.decode_entities && !self.decode_mini_entities {
+            self.entities.check(&data[from..to], from, &mut errors);
+            self.report_errors(errors);
+
+            return decode_bytes(self.enc, &data[from..to]);
+        }
+
+        let text = self.entities.decode(&data[from..to], from, self.enc, &mut errors);
+
+        self.chunk.entities = self.entities.found_entities;
+        self.chunk.lt_entity = self.entities.found_lt_entity;
+        self.report_errors(errors);
+
+        text
+    }
+
+    /// Returns true if byte at pos starts tag, comment, template code or other markup rather than text
+    fn is_markup_start(&self, data: &[u8], pos: usize) -> bool {
+        if !self.template_delimiters.is_empty() && self.tag_parser.template_end(data, pos).is_some() {
+            return true;
+        }
+
+        if data[pos] != b'<' || pos + 1 >= self.data_length {
+            return false;
+        }
+
+        let next = data[pos + 1];
+
+        self.is_tag_name_start(next) || next == b'/' || next == b'!' || next == b'?'
+    }
+
+    /// Returns true if byte can start tag name, XML names may also start with _, : or non-ASCII char
+    fn is_tag_name_start(&self, ch: u8) -> bool {
+        ch.is_ascii_alphabetic() || (self.xml_mode && (ch == b'_' || ch == b':' || ch >= 0x80))
+    }
+
+    /// Parses text that starts at pos up to the next tag
+    fn parse_text(&mut self, data: &[u8], pos: usize) {
+        // first byte is always text, even if it is < that did not start a tag
+        let mut end = pos + 1;
+
+        self.check_lt_in_text(data, pos);
+
+        while end < self.data_length && !self.is_markup_start(data, end) {
+            self.check_lt_in_text(data, end);
+            end += 1;
+        }
+
+        self.check_nulls(data, pos, end);
+
+        let mut text_end = end;
+
+        if self.compress_whitespace_before_tag && end < self.data_length {
+            while text_end > pos && self.whitespace[data[text_end - 1] as usize] {
+                text_end -= 1;
+            }
+        }
+
+        self.chunk.html = self.decode_text(data, pos, text_end);
+
+        if text_end < end {
+            self.chunk.html.push(' ');
+        }
+
+        self.current_position = end;
+    }
+
+    /// Parses open or close tag which name starts at pos
+    fn parse_tag(&mut self, data: &[u8], pos: usize, closure: bool) {
+        self.entities.mini_only = !self.decode_entities;
+        self.entities.xml_mode = self.xml_mode;
+
+        self.tag_parser.decode_entities = self.decode_entities || self.decode_mini_entities;
+        self.tag_parser.mark_closed_tags_with_params_as_open = self.mark_closed_tags_with_params_as_open;
+        self.tag_parser.xml_mode = self.xml_mode;
+        self.current_position = self.tag_parser.parse_tag(data, pos, closure, self.enc, &mut self.entities,
+            &mut self.chunk);
+
+        let errors = ::std::mem::take(&mut self.tag_parser.errors);
+
+        if self.chunk.tag == "!doctype" {
+            // DOCTYPE is not really a tag, its parts are not proper params
+            self.chunk.clear_params();
+        } else {
+            self.report_errors(errors);
+        }
+
+        if self.xml_mode {
+            return;
+        }
+
+        self.foreign_content.process(&mut self.chunk);
+
+        if self.chunk.namespace != Namespace::Html {
+            if self.chunk.chunk_type == ChunkType::OpenTag && self.chunk.end_closure {
+                // self-closing tags of SVG and MathML are empty elements even if they have params
+                self.chunk.chunk_type = ChunkType::CloseTag;
+                self.chunk.closure = true;
+            }
+
+            return;
+        }
+
+        if self.chunk.chunk_type != ChunkType::OpenTag {
+            return;
+        }
+
+        if self.chunk.tag == "script" {
+            self.parse_script(data);
+        } else if RAW_TEXT_TAGS.contains(&self.chunk.tag.as_str()) {
+            self.raw_text_tag = Some(self.chunk.tag.clone());
+        }
+    }
+
+    /// Finds closing tag for given (lowercased) tag name starting at pos, returns data length
+    /// if it was not found
+    fn find_closing_tag(&self, data: &[u8], mut pos: usize, tag: &str) -> usize {
+        let tag = tag.as_bytes();
+
+        while pos + 2 + tag.len() <= self.data_length {
+            if data[pos] == b'<' && data[pos + 1] == b'/'
+                && data[pos + 2..pos + 2 + tag.len()].eq_ignore_ascii_case(tag) {
+                let after = pos + 2 + tag.len();
+
+                if after >= self.data_length || TagParser::is_whitespace(data[after])
+                    || data[after] == b'/' || data[after] == b'>' {
+                    return pos;
+                }
+            }
+
+            pos += 1;
+        }
+
+        self.data_length
+    }
+
+    /// Parses content of script (open tag of which was just parsed into chunk) and its closing tag,
+    /// so that whole script is returned as single chunk
+    fn parse_script(&mut self, data: &[u8]) {
+        let content_start = self.current_position;
+        let content_end = self.find_closing_tag(data, content_start, "script");
+
+        let mut end = content_end;
+
+        while end < self.data_length {
+            end += 1;
+
+            if data[end - 1] == b'>' {
+                break;
+            }
+        }
+
+        self.chunk.chunk_type = ChunkType::Script;
+        self.check_nulls(data, content_start, content_end);
+
+        if self.keep_scripts {
+            if self.extract_between_tags_only {
+                self.chunk.html = decode_bytes(self.enc, &data[content_start..content_end]);
+            } else {
+                self.chunk.html = decode_bytes(self.enc, &data[self.chunk.chunk_offset..end]);
+            }
+        }
+
+        self.current_position = end;
+    }
+
+    /// Parses content of raw text element (ie style) up to its closing tag as text,
+    /// returns false if there was no content
+    fn parse_raw_text(&mut self, data: &[u8], tag: &str) -> bool {
+        let pos = self.current_position;
+        let end = self.find_closing_tag(data, pos, tag);
+
+        if end == pos {
+            return false;
+        }
+
+        self.check_nulls(data, pos, end);
+
+        self.chunk.html = if RCDATA_TAGS.contains(&tag) {
+            self.decode_text(data, pos, end)
+        } else {
+            decode_bytes(self.enc, &data[pos..end])
+        };
+
+        self.current_position = end;
+
+        true
+    }
+
+    /// Parses markup declaration starting with <! at pos: comment, CDATA, DOCTYPE or bogus comment
+    fn parse_markup_declaration(&mut self, data: &[u8], pos: usize) {
+        let rest = &data[pos + 2..];
+
+        if rest.starts_with(b"--") {
+            if self.parse_conditional_comments && self.parse_conditional_comment_start(data, pos) {
+                return;
+            }
+
+            self.parse_comment(data, pos + 4);
+        } else if rest.starts_with(b"[") && self.parse_downlevel_revealed(data, pos) {
+            // it was start or end of conditional comment
+        } else if self.xml_mode && rest.starts_with(b"DOCTYPE") {
+            self.parse_xml_doctype(data, pos + 9);
+        } else if rest.len() >= 7 && rest[..7].eq_ignore_ascii_case(b"doctype") {
+            // DOCTYPE is returned as open tag named !doctype with its parts as params
+            self.parse_tag(data, pos + 1, false);
+        } else if rest.starts_with(b"[CDATA[") {
+            self.parse_cdata(data, pos + 9);
+        } else {
+            self.add_error(ParseErrorCode::IncorrectlyOpenedComment, pos + 2);
+            self.parse_bogus_comment(data, pos + 2);
+        }
+    }
+
+    /// Parses comment which data starts at data_start (right after <!--) following HTML5 comment states,
+    /// so that we end up with exactly the same comment data as browsers would
+    fn parse_comment(&mut self, data: &[u8], data_start: usize) {
+        let mut errors = Vec::new();
+        let (data_end, end) = scan_comment(data, data_start, &mut errors);
+
+        self.report_errors(errors);
+        self.set_comment(data, "!--", data_start, data_end, end);
+        self.check_conditional_comment(data, data_start, data_end);
+    }
+
+    /// Parses start of downlevel-hidden conditional comment <!--[if mso]> at pos, so that its content
+    /// will be parsed as HTML, returns false if it was not conditional comment
+    fn parse_conditional_comment_start(&mut self, data: &[u8], pos: usize) -> bool {
+        let (condition, mut end) = match conditional_expression(&data[pos + 4..]) {
+            Some((condition, len)) => (condition, pos + 4 + len),
+            None => return false,
+        };
+
+        if end >= self.data_length || data[end] != b'>' {
+            return false;
+        }
+
+        end += 1;
+
+        if data[end..].starts_with(b"<!-->") {
+            // <!--[if !IE]><!--> is valid way to write downlevel-revealed comment, content follows as HTML anyway
+            end += 5;
+        } else {
+            self.in_conditional_comment = true;
+        }
+
+        self.set_conditional_marker(data, "![if", condition, end);
+
+        true
+    }
+
+    /// Parses <![if !IE]> or <![endif]> of downlevel-revealed conditional comment at pos - these are
+    /// not proper markup declarations, but we want to know about them. If we are inside of downlevel-hidden
+    /// comment then <![endif]--> that ends it is parsed here too. Returns false if it was something else
+    fn parse_downlevel_revealed(&mut self, data: &[u8], pos: usize) -> bool {
+        if self.in_conditional_comment && starts_with_ignore_case(&data[pos..], b"<![endif]-->") {
+            self.in_conditional_comment = false;
+            self.set_conditional_marker(data, "![endif", String::new(), pos + 12);
+            return true;
+        }
+
+        if starts_with_ignore_case(&data[pos..], b"<![endif]>") {
+            self.add_error(ParseErrorCode::IncorrectlyOpenedComment, pos + 2);
+            self.set_conditional_marker(data, "![endif", String::new(), pos + 10);
+            return true;
+        }
+
+        if let Some((condition, len)) = conditional_expression(&data[pos + 2..]) {
+            let end = pos + 2 + len;
+
+            if end < self.data_length && data[end] == b'>' {
+                self.add_error(ParseErrorCode::IncorrectlyOpenedComment, pos + 2);
+                self.set_conditional_marker(data, "![if", condition, end + 1);
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Sets chunk to be start or end marker of conditional comment, their html is always set to the
+    /// marker itself
+    fn set_conditional_marker(&mut self, data: &[u8], tag: &str, condition: String, end: usize) {
+        self.chunk.chunk_type = ChunkType::Comment;
+        self.chunk.comments = true;
+        self.chunk.tag.push_str(tag);
+        self.chunk.condition = condition;
+        self.chunk.html = decode_bytes(self.enc, &data[self.chunk.chunk_offset..end]);
+
+        self.current_position = end;
+    }
+
+    /// Checks if comment that was just parsed is IE conditional comment and sets its condition,
+    /// comments used to mark downlevel-revealed content (<!--[if !IE]><!--> and <!--<![endif]-->) are
+    /// turned into markers just like <![if !IE]> and <![endif]>
+    fn check_conditional_comment(&mut self, data: &[u8], data_start: usize, data_end: usize) {
+        let comment = &data[data_start..data_end];
+
+        if comment.eq_ignore_ascii_case(b"<![endif]") {
+            self.chunk.tag = "![endif".to_string();
+            self.chunk.html = decode_bytes(self.enc, &data[self.chunk.chunk_offset..self.current_position]);
+            return;
+        }
+
+        if let Some((condition, len)) = conditional_expression(comment) {
+            let rest = &comment[len..];
+
+            if rest == b"><!" {
+                self.chunk.tag = "![if".to_string();
+                self.chunk.html = decode_bytes(self.enc, &data[self.chunk.chunk_offset..self.current_position]);
+            } else if !rest.starts_with(b">") {
+                return;
+            }
+
+            self.chunk.condition = condition;
+        }
+    }
+
+    /// Parses CDATA section which data starts at data_start, it is returned as comment with ![CDATA[ tag
+    fn parse_cdata(&mut self, data: &[u8], data_start: usize) {
+        let mut data_end = data_start;
+
+        while data_end < self.data_length && !data[data_end..].starts_with(b"]]>") {
+            data_end += 1;
+        }
+
+        let end = if data_end < self.data_length {
+            data_end + 3
+        } else {
+            data_end
+        };
+
+        self.set_comment(data, "![CDATA[", data_start, data_end, end);
+    }
+
+    /// Parses DOCTYPE of XML document which rest starts at pos (right after <!DOCTYPE), it is returned as open
+    /// tag named !doctype just like in HTML. Entities declared in its internal subset are decoded from now on
+    fn parse_xml_doctype(&mut self, data: &[u8], pos: usize) {
+        let mut end = pos;
+        let mut quote = None;
+        let mut subset_start = None;
+        let mut subset_end = None;
+        let mut closed = false;
+
+        // > ends DOCTYPE unless it is quoted or inside of [internal subset]
+        while end < self.data_length {
+            let ch = data[end];
+            let in_subset = subset_start.is_some() && subset_end.is_none();
+
+            end += 1;
+
+            match quote {
+                Some(quote_ch) if ch == quote_ch => quote = None,
+                Some(_) => {},
+                None => match ch {
+                    b'"' | b'\'' => quote = Some(ch),
+                    b'[' if subset_start.is_none() => subset_start = Some(end),
+                    b']' if in_subset => subset_end = Some(end - 1),
+                    b'>' if !in_subset => {
+                        closed = true;
+                        break;
+                    },
+                    _ => {},
+                },
+            }
+        }
+
+        if !closed {
+            self.add_error(ParseErrorCode::EofInDoctype, self.data_length);
+        }
+
+        if let Some(start) = subset_start {
+            let mut errors = Vec::new();
+
+            self.entities.xml_mode = true;
+            self.entities.mini_only = false;
+
+            for (name, value) in entity_declarations(&data[start..subset_end.unwrap_or(end)]) {
+                let value = self.entities.decode(value, 0, self.enc, &mut errors);
+
+                self.entities.declare(&name, value);
+            }
+        }
+
+        self.chunk.chunk_type = ChunkType::OpenTag;
+        self.chunk.tag.push_str("!doctype");
+        self.current_position = end;
+    }
+
+    /// Parses processing instruction like <?xml version="1.0"?> which data starts at data_start, it is
+    /// returned as comment with ? tag
+    fn parse_processing_instruction(&mut self, data: &[u8], data_start: usize) {
+        let mut data_end = data_start;
+
+        while data_end < self.data_length && !data[data_end..].starts_with(b"?>") {
+            data_end += 1;
+        }
+
+        let end = if data_end < self.data_length {
+            data_end + 2
+        } else {
+            self.add_error(ParseErrorCode::EofInProcessingInstruction, self.data_length);
+            data_end
+        };
+
+        self.set_comment(data, "?", data_start, data_end, end);
+    }
+
+    /// Parses something that is not really a comment but is treated as one - up to the first >
+    fn parse_bogus_comment(&mut self, data: &[u8], data_start: usize) {
+
+        let mut data_end = data_start;
+
+        while data_end < self.data_length && data[data_end] != b'>' {
+            data_end += 1;
+        }
+
+        let end = if data_end < self.data_length {
+            data_end + 1
+        } else {
+            data_end
+        };
+
+        self.set_comment(data, "!--", data_start, data_end, end);
+    }
+
+    /// Sets chunk to be comment with given data, end is position right after the comment
+    fn set_comment(&mut self, data: &[u8], tag: &str, data_start: usize, data_end: usize, end: usize) {
+        self.chunk.chunk_type = ChunkType::Comment;
+        self.chunk.comments = true;
+        self.chunk.tag.push_str(tag);
+
+        if self.keep_comments {
+            if self.extract_between_tags_only {
+                self.chunk.html = decode_bytes(self.enc, &data[data_start..data_end]);
+            } else {
+                self.chunk.html = decode_bytes(self.enc, &data[self.chunk.chunk_offset..end]);
+            }
+        }
+
+        self.current_position = end;
+    }
+
     /// sets flags of whitespace bytes to true
-    fn init_whitespaces(mut whitespace: [bool; 256]) {
+    fn init_whitespaces(whitespace: &mut [bool; 256]) {
         whitespace[9] = true;
         whitespace[10] = true;
         whitespace[13] = true;
@@ -163,4 +1216,27 @@ impl HtmlParser {
         heuristics.add_tag("html", "");
         heuristics.add_tag("body", "");
     }
-}
\ No newline at end of file
+}
+
+/// Returns true if data starts with prefix ignoring ASCII case
+fn starts_with_ignore_case(data: &[u8], prefix: &[u8]) -> bool {
+    data.len() >= prefix.len() && data[..prefix.len()].eq_ignore_ascii_case(prefix)
+}
+
+/// Parses condition of IE conditional comment from data that should start with [if, returns condition
+/// and length of data up to and including closing ]
+fn conditional_expression(data: &[u8]) -> Option<(String, usize)> {
+    if !starts_with_ignore_case(data, b"[if") || data.len() < 4 || !TagParser::is_whitespace(data[3]) {
+        return None;
+    }
+
+    let end = data.iter().position(|&ch| ch == b']' || ch == b'>')?;
+
+    if data[end] != b']' {
+        return None;
+    }
+
+    let condition = String::from_utf8_lossy(&data[3..end]).trim().to_string();
+
+    Some((condition, end + 1))
+}
@@ -0,0 +1,1614 @@
+use html_chunk::{HtmlChunk, ChunkType, Doctype};
+use html_entities::HtmlEntities;
+use dynamic_string::decode_bytes;
+use line_index::{SourcePosition, ParamPosition};
+use parse_error::{ParseError, ParseErrorCode};
+
+use encoding::EncodingRef;
+use std::mem;
+
+/// Tokenizer states in which content of elements is parsed: whoever knows which element was opened last
+/// (normally tree builder) switches between them, see HtmlParser::set_tokenizer_state
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TokenizerState {
+    /// Normal HTML
+    Data,
+
+    /// Text with entities up to the end tag of element, used for title and textarea
+    Rcdata,
+
+    /// Text up to the end tag of element, used for style, xmp, iframe, noembed and noframes
+    Rawtext,
+
+    /// Content of script up to its end tag that is not inside of <!-- <script> -->
+    ScriptData,
+
+    /// Text up to the end of data, used after plaintext tag
+    Plaintext,
+
+    /// Content of CDATA section up to ]]>, CDATA is only allowed in SVG and MathML
+    CdataSection,
+}
+
+/// Text states that end tag may be found in, see State::EndTagOpenIn
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum TextState {
+    Rcdata,
+    Rawtext,
+    ScriptData,
+    ScriptDataEscaped,
+}
+
+/// States of tokenizer, see https://html.spec.whatwg.org/#tokenization - character references are
+/// decoded as soon as & is found and comments are parsed in one go, so there are no states for them
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum State {
+    Data,
+    Rcdata,
+    Rawtext,
+    ScriptData,
+    Plaintext,
+    TagOpen,
+    EndTagOpen,
+    TagName,
+    LessThanSignIn(TextState),
+    EndTagOpenIn(TextState),
+    EndTagNameIn(TextState),
+    ScriptDataLessThanSign,
+    ScriptDataEscapeStart,
+    ScriptDataEscapeStartDash,
+    ScriptDataEscaped,
+    ScriptDataEscapedDash,
+    ScriptDataEscapedDashDash,
+    ScriptDataEscapedLessThanSign,
+    ScriptDataDoubleEscapeStart,
+    ScriptDataDoubleEscaped,
+    ScriptDataDoubleEscapedDash,
+    ScriptDataDoubleEscapedDashDash,
+    ScriptDataDoubleEscapedLessThanSign,
+    ScriptDataDoubleEscapeEnd,
+    BeforeAttributeName,
+    AttributeName,
+    AfterAttributeName,
+    BeforeAttributeValue,
+    AttributeValueQuoted(u8),
+    AttributeValueUnquoted,
+    AfterAttributeValueQuoted,
+    SelfClosingStartTag,
+    BogusComment,
+    MarkupDeclarationOpen,
+    Doctype,
+    BeforeDoctypeName,
+    DoctypeName,
+    AfterDoctypeName,
+    AfterDoctypePublicKeyword,
+    BeforeDoctypePublicIdentifier,
+    DoctypePublicIdentifierQuoted(u8),
+    AfterDoctypePublicIdentifier,
+    BetweenDoctypePublicAndSystemIdentifiers,
+    AfterDoctypeSystemKeyword,
+    BeforeDoctypeSystemIdentifier,
+    DoctypeSystemIdentifierQuoted(u8),
+    AfterDoctypeSystemIdentifier,
+    BogusDoctype,
+    CdataSection,
+    CdataSectionBracket,
+    CdataSectionEnd,
+}
+
+/// Text collected by tokenizer (chars, names, values): runs of bytes taken from HTML as they are get
+/// decoded in one go once something else has to be appended, so that we do not decode char by char
+struct TextBuffer {
+    text: String,
+    run_start: usize,
+    run_end: usize,
+}
+
+impl TextBuffer {
+    fn new() -> TextBuffer {
+        TextBuffer {
+            text: String::new(),
+            run_start: 0,
+            run_end: 0,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.text.is_empty() && self.run_start == self.run_end
+    }
+
+    /// Appends char consumed at pos, bytes of HTML are used unless char was changed (ie lowercased)
+    fn push_at(&mut self, data: &[u8], enc: EncodingRef, pos: usize, ch: u8) {
+        if data[pos] != ch {
+            self.push(data, enc, ch as char);
+        } else if self.run_start == self.run_end {
+            self.run_start = pos;
+            self.run_end = pos + 1;
+        } else if self.run_end == pos {
+            self.run_end += 1;
+        } else {
+            self.flush(data, enc);
+            self.run_start = pos;
+            self.run_end = pos + 1;
+        }
+    }
+
+    fn push(&mut self, data: &[u8], enc: EncodingRef, ch: char) {
+        self.flush(data, enc);
+        self.text.push(ch);
+    }
+
+    fn push_str(&mut self, data: &[u8], enc: EncodingRef, text: &str) {
+        self.flush(data, enc);
+        self.text.push_str(text);
+    }
+
+    fn flush(&mut self, data: &[u8], enc: EncodingRef) {
+        if self.run_start != self.run_end {
+            self.text.push_str(&decode_bytes(enc, &data[self.run_start..self.run_end]));
+            self.run_start = 0;
+            self.run_end = 0;
+        }
+    }
+
+    fn take(&mut self, data: &[u8], enc: EncodingRef) -> String {
+        self.flush(data, enc);
+        mem::take(&mut self.text)
+    }
+
+    fn clear(&mut self) {
+        self.text.clear();
+        self.run_start = 0;
+        self.run_end = 0;
+    }
+}
+
+/// Param of tag that is being parsed
+struct Attribute {
+    name: String,
+    value: String,
+    quote: u8,
+    position: ParamPosition,
+}
+
+/// Token other than chars, chars are collected in text until such token is found
+enum Token {
+    Tag {
+        name: String,
+        end_tag: bool,
+        self_closing: bool,
+        attributes: Vec<Attribute>,
+    },
+    Comment(String),
+    Doctype(Doctype),
+}
+
+/// Spec-conformant tokenizer used by HtmlParser in conformance mode: it follows WHATWG tokenizer states
+/// char by char (including newline normalization and handling of NULs), so it is slower than default
+/// lenient parsing, but its output is exactly what browsers see
+pub struct HtmlTokenizer {
+    state: State,
+
+    /// Name of the last start tag, end tag is only recognised in RCDATA, RAWTEXT and script if it matches
+    last_start_tag: Option<String>,
+
+    /// If true then CDATA sections are parsed, otherwise they are bogus comments as in HTML content
+    pub cdata_allowed: bool,
+
+    enc: EncodingRef,
+    entities: HtmlEntities,
+
+    /// Position of next byte to consume
+    pos: usize,
+
+    /// Position of char that was consumed last
+    cur: usize,
+
+    /// Chars in HTML before this position were checked for control chars and noncharacters
+    checked_to: usize,
+
+    /// Position right after the last token
+    token_end: usize,
+
+    /// Position of < that started token that is being parsed
+    token_start: usize,
+
+    eof: bool,
+
+    /// Chars collected since the last token
+    text: TextBuffer,
+
+    /// Token that was parsed while there were chars collected - they are returned first
+    token: Option<(Token, usize, usize)>,
+
+    tag_name: TextBuffer,
+    end_tag: bool,
+    self_closing: bool,
+    attributes: Vec<Attribute>,
+
+    /// True if attribute is being parsed, its name and value are in attribute_name and attribute_value
+    in_attribute: bool,
+    attribute_name: TextBuffer,
+    attribute_value: TextBuffer,
+    attribute_quote: u8,
+    attribute_position: ParamPosition,
+
+    /// Chars of possible end tag name or "script" in script data
+    temp_buffer: String,
+
+    comment: TextBuffer,
+
+    doctype_name: Option<TextBuffer>,
+    public_id: Option<TextBuffer>,
+    system_id: Option<TextBuffer>,
+    force_quirks: bool,
+}
+
+impl HtmlTokenizer {
+    pub fn new() -> HtmlTokenizer {
+        HtmlTokenizer {
+            state: State::Data,
+            last_start_tag: None,
+            cdata_allowed: false,
+            enc: ::encoding::all::UTF_8,
+            entities: HtmlEntities::new(),
+            pos: 0,
+            cur: 0,
+            checked_to: 0,
+            token_end: 0,
+            token_start: 0,
+            eof: false,
+            text: TextBuffer::new(),
+            token: None,
+            tag_name: TextBuffer::new(),
+            end_tag: false,
+            self_closing: false,
+            attributes: Vec::new(),
+            in_attribute: false,
+            attribute_name: TextBuffer::new(),
+            attribute_value: TextBuffer::new(),
+            attribute_quote: b' ',
+            attribute_position: ParamPosition::default(),
+            temp_buffer: String::new(),
+            comment: TextBuffer::new(),
+            doctype_name: None,
+            public_id: None,
+            system_id: None,
+            force_quirks: false,
+        }
+    }
+
+    /// Prepares tokenizer to parse new HTML in data state
+    pub fn init(&mut self, enc: EncodingRef) {
+        self.state = State::Data;
+        self.last_start_tag = None;
+        self.enc = enc;
+        self.pos = 0;
+        self.cur = 0;
+        self.checked_to = 0;
+        self.token_end = 0;
+        self.eof = false;
+        self.text.clear();
+        self.token = None;
+    }
+
+    /// Switches tokenizer to given state, it takes effect from the next token
+    pub fn set_state(&mut self, state: TokenizerState) {
+        self.state = match state {
+            TokenizerState::Data => State::Data,
+            TokenizerState::Rcdata => State::Rcdata,
+            TokenizerState::Rawtext => State::Rawtext,
+            TokenizerState::ScriptData => State::ScriptData,
+            TokenizerState::Plaintext => State::Plaintext,
+            TokenizerState::CdataSection => State::CdataSection,
+        };
+    }
+
+    /// Sets name of the last start tag, as if it was just parsed
+    pub fn set_last_start_tag(&mut self, tag: Option<&str>) {
+        self.last_start_tag = tag.map(|tag| tag.to_string());
+    }
+
+    /// Parses next chunk of data into chunk (which must be cleared), returns false if there is nothing
+    /// left to parse
+    pub fn next_chunk(&mut self, data: &[u8], chunk: &mut HtmlChunk, errors: &mut Vec<ParseError>) -> bool {
+        while self.token.is_none() && !self.eof {
+            self.step(data, errors);
+        }
+
+        let enc = self.enc;
+
+        if !self.text.is_empty() {
+            let end = match self.token {
+                Some((_, start, _)) => start,
+                None => data.len(),
+            };
+
+            chunk.chunk_type = ChunkType::Text;
+            chunk.html = self.text.take(data, enc);
+            chunk.chunk_offset = self.token_end;
+            chunk.chunk_length = end - self.token_end;
+
+            self.token_end = end;
+
+            return true;
+        }
+
+        let (token, start, end) = match self.token.take() {
+            Some(token) => token,
+            None => return false,
+        };
+
+        chunk.chunk_offset = start;
+        chunk.chunk_length = end - start;
+        self.token_end = end;
+
+        match token {
+            Token::Tag { name, end_tag, self_closing, attributes } => {
+                chunk.chunk_type = if end_tag { ChunkType::CloseTag } else { ChunkType::OpenTag };
+                chunk.tag = name;
+                chunk.closure = end_tag;
+                chunk.end_closure = self_closing;
+
+                for attribute in attributes {
+                    chunk.add_param(attribute.name, attribute.value, attribute.quote);
+
+                    if let Some(position) = chunk.param_positions.last_mut() {
+                        *position = attribute.position;
+                    }
+                }
+            },
+            Token::Comment(comment) => {
+                chunk.chunk_type = ChunkType::Comment;
+                chunk.tag.push_str("!--");
+                chunk.comments = true;
+                chunk.html = comment;
+            },
+            Token::Doctype(doctype) => {
+                chunk.chunk_type = ChunkType::Doctype;
+                chunk.tag.push_str("!doctype");
+                chunk.doctype = Some(doctype);
+            },
+        }
+
+        true
+    }
+
+    /// Consumes next char returning None at the end of data, CR and CRLF are returned as LF
+    fn consume(&mut self, data: &[u8], errors: &mut Vec<ParseError>) -> Option<u8> {
+        self.cur = self.pos;
+
+        let ch = *data.get(self.pos)?;
+
+        self.pos += 1;
+
+        if ch == b'\r' {
+            if data.get(self.pos) == Some(&b'\n') {
+                self.pos += 1;
+            }
+
+            return Some(b'\n');
+        }
+
+        if self.cur >= self.checked_to {
+            self.check_input(data, self.cur, self.pos, errors);
+        }
+
+        Some(ch)
+    }
+
+    /// Makes char that was consumed last to be consumed again in the next state
+    fn reconsume(&mut self) {
+        self.pos = self.cur;
+    }
+
+    /// Reports control chars and noncharacters in data between from and to, as they should not be
+    /// in HTML at all. Only UTF-8 is checked for chars above ASCII
+    fn check_input(&mut self, data: &[u8], from: usize, to: usize, errors: &mut Vec<ParseError>) {
+        let utf8 = self.enc.name() == "utf-8";
+        let mut pos = from.max(self.checked_to);
+
+        while pos < to {
+            let ch = data[pos];
+
+            let code = if ch < 0x80 {
+                ch as u32
+            } else if utf8 && ch >= 0xC0 {
+                let len = match ch {
+                    0xC0..=0xDF => 2,
+                    0xE0..=0xEF => 3,
+                    _ => 4,
+                };
+
+                match data.get(pos..pos + len).and_then(|bytes| ::std::str::from_utf8(bytes).ok()) {
+                    Some(text) => text.chars().next().map_or(0xFFFD, |ch| ch as u32),
+                    None => 0xFFFD,
+                }
+            } else {
+                0xFFFD
+            };
+
+            if (code < 0x20 && code != 0 && !is_whitespace(code as u8) && code != 0x0D) || (0x7F..0xA0).contains(&code) {
+                add_error(errors, ParseErrorCode::ControlCharacterInInputStream, pos);
+            } else if (0xFDD0..=0xFDEF).contains(&code) || (code & 0xFFFE) == 0xFFFE {
+                add_error(errors, ParseErrorCode::NoncharacterInInputStream, pos);
+            }
+
+            pos += 1;
+        }
+
+        self.checked_to = self.checked_to.max(to);
+    }
+
+    /// Appends char consumed last to collected chars
+    fn emit_char(&mut self, data: &[u8], ch: u8) {
+        self.text.push_at(data, self.enc, self.cur, ch);
+    }
+
+    fn emit_str(&mut self, data: &[u8], text: &str) {
+        self.text.push_str(data, self.enc, text);
+    }
+
+    fn emit_token(&mut self, token: Token) {
+        self.token = Some((token, self.token_start, self.pos));
+    }
+
+    /// Reports end of data, whatever token was being parsed is lost
+    fn emit_eof(&mut self) {
+        self.eof = true;
+    }
+
+    fn create_tag(&mut self, end_tag: bool) {
+        self.tag_name.clear();
+        self.end_tag = end_tag;
+        self.self_closing = false;
+        self.attributes.clear();
+        self.in_attribute = false;
+    }
+
+    fn emit_tag(&mut self, data: &[u8], errors: &mut Vec<ParseError>) {
+        self.finish_attribute(data, errors);
+
+        let name = self.tag_name.take(data, self.enc);
+
+        if self.end_tag {
+            if !self.attributes.is_empty() {
+                add_error(errors, ParseErrorCode::EndTagWithAttributes, self.token_start);
+                self.attributes.clear();
+            }
+
+            if self.self_closing {
+                add_error(errors, ParseErrorCode::EndTagWithTrailingSolidus, self.cur);
+                self.self_closing = false;
+            }
+        } else {
+            self.last_start_tag = Some(name.clone());
+        }
+
+        let token = Token::Tag {
+            name,
+            end_tag: self.end_tag,
+            self_closing: self.self_closing,
+            attributes: mem::take(&mut self.attributes),
+        };
+
+        self.emit_token(token);
+    }
+
+    fn start_attribute(&mut self, data: &[u8], errors: &mut Vec<ParseError>) {
+        self.finish_attribute(data, errors);
+
+        let position = SourcePosition { offset: self.cur, ..SourcePosition::default() };
+
+        self.in_attribute = true;
+        self.attribute_quote = b' ';
+        self.attribute_position = ParamPosition {
+            name_start: position,
+            name_end: position,
+            value_start: position,
+            value_end: position,
+        };
+    }
+
+    /// Adds attribute that was parsed to tag unless attribute with the same name is already there
+    fn finish_attribute(&mut self, data: &[u8], errors: &mut Vec<ParseError>) {
+        if !self.in_attribute {
+            return;
+        }
+
+        self.in_attribute = false;
+
+        let name = self.attribute_name.take(data, self.enc);
+        let value = self.attribute_value.take(data, self.enc);
+
+        if self.attributes.iter().any(|attribute| attribute.name == name) {
+            add_error(errors, ParseErrorCode::DuplicateAttribute, self.attribute_position.name_start.offset);
+            return;
+        }
+
+        self.attributes.push(Attribute {
+            name,
+            value,
+            quote: self.attribute_quote,
+            position: self.attribute_position,
+        });
+    }
+
+    fn append_to_attribute_name(&mut self, data: &[u8], ch: u8) {
+        self.attribute_name.push_at(data, self.enc, self.cur, ch);
+        self.attribute_position.name_end.offset = self.pos;
+        self.attribute_position.value_start.offset = self.pos;
+        self.attribute_position.value_end.offset = self.pos;
+    }
+
+    fn append_to_attribute_value(&mut self, data: &[u8], ch: u8) {
+        self.attribute_value.push_at(data, self.enc, self.cur, ch);
+        self.attribute_position.value_end.offset = self.pos;
+    }
+
+    /// Marks start of attribute value right after the char consumed last
+    fn start_attribute_value(&mut self, quote: u8) {
+        self.attribute_quote = quote;
+        self.attribute_position.value_start.offset = self.pos;
+        self.attribute_position.value_end.offset = self.pos;
+    }
+
+    /// Decodes character reference that starts at & consumed last, returns None if there is none
+    fn character_reference(&mut self, data: &[u8], errors: &mut Vec<ParseError>) -> Option<String> {
+        let (chars, end) = self.entities.decode_reference(data, self.cur, 0, errors)?;
+
+        self.pos = end;
+
+        Some(chars)
+    }
+
+    fn emit_comment(&mut self, data: &[u8]) {
+        let comment = self.comment.take(data, self.enc);
+
+        self.emit_token(Token::Comment(comment));
+    }
+
+    fn create_doctype(&mut self) {
+        self.doctype_name = None;
+        self.public_id = None;
+        self.system_id = None;
+        self.force_quirks = false;
+    }
+
+    fn emit_doctype(&mut self, data: &[u8]) {
+        let enc = self.enc;
+        let take = |part: Option<TextBuffer>| part.map(|mut part| part.take(data, enc));
+
+        let doctype = Doctype {
+            name: take(self.doctype_name.take()),
+            public_id: take(self.public_id.take()),
+            system_id: take(self.system_id.take()),
+            force_quirks: self.force_quirks,
+        };
+
+        self.emit_token(Token::Doctype(doctype));
+    }
+
+    /// Reports end of data in DOCTYPE, it is emitted anyway
+    fn eof_in_doctype(&mut self, data: &[u8], errors: &mut Vec<ParseError>) {
+        add_error(errors, ParseErrorCode::EofInDoctype, self.cur);
+        self.force_quirks = true;
+        self.emit_doctype(data);
+        self.emit_eof();
+    }
+
+    /// Returns true if end tag which name is in temp buffer matches the last start tag
+    fn is_appropriate_end_tag(&self) -> bool {
+        match self.last_start_tag {
+            Some(ref tag) => self.temp_buffer.eq_ignore_ascii_case(tag),
+            None => false,
+        }
+    }
+
+    /// Returns state of text that end tag was found in
+    fn text_state(state: TextState) -> State {
+        match state {
+            TextState::Rcdata => State::Rcdata,
+            TextState::Rawtext => State::Rawtext,
+            TextState::ScriptData => State::ScriptData,
+            TextState::ScriptDataEscaped => State::ScriptDataEscaped,
+        }
+    }
+
+    /// Handles markup declaration right after <!, nothing is consumed in its state yet
+    fn markup_declaration_open(&mut self, data: &[u8], errors: &mut Vec<ParseError>) {
+        let rest = &data[self.pos..];
+
+        if rest.starts_with(b"--") {
+            let data_start = self.pos + 2;
+            let (data_end, end) = scan_comment(data, data_start, errors);
+
+            self.check_input(data, data_start, end, errors);
+            self.comment.clear();
+
+            let mut pos = data_start;
+
+            while pos < data_end {
+                match data[pos] {
+                    0 => {
+                        add_error(errors, ParseErrorCode::UnexpectedNullCharacter, pos);
+                        self.comment.push(data, self.enc, '\u{FFFD}');
+                    },
+                    b'\r' => {
+                        if data.get(pos + 1) == Some(&b'\n') {
+                            pos += 1;
+                        }
+
+                        self.comment.push(data, self.enc, '\n');
+                    },
+                    ch => self.comment.push_at(data, self.enc, pos, ch),
+                }
+
+                pos += 1;
+            }
+
+            self.pos = end;
+            self.state = State::Data;
+            self.emit_comment(data);
+        } else if rest.len() >= 7 && rest[..7].eq_ignore_ascii_case(b"doctype") {
+            self.pos += 7;
+            self.state = State::Doctype;
+        } else if rest.starts_with(b"[CDATA[") {
+            self.pos += 7;
+
+            if self.cdata_allowed {
+                self.state = State::CdataSection;
+            } else {
+                add_error(errors, ParseErrorCode::CdataInHtmlContent, self.pos - 7);
+                self.comment.clear();
+                self.comment.push_str(data, self.enc, "[CDATA[");
+                self.state = State::BogusComment;
+            }
+        } else {
+            add_error(errors, ParseErrorCode::IncorrectlyOpenedComment, self.pos);
+            self.comment.clear();
+            self.state = State::BogusComment;
+        }
+    }
+
+    /// Consumes next char and handles it in current state
+    fn step(&mut self, data: &[u8], errors: &mut Vec<ParseError>) {
+        if self.state == State::MarkupDeclarationOpen {
+            self.markup_declaration_open(data, errors);
+            return;
+        }
+
+        let enc = self.enc;
+        let input = self.consume(data, errors);
+
+        match self.state {
+            State::Data => match input {
+                Some(b'&') => match self.character_reference(data, errors) {
+                    Some(chars) => self.emit_str(data, &chars),
+                    None => self.emit_char(data, b'&'),
+                },
+                Some(b'<') => {
+                    self.token_start = self.cur;
+                    self.state = State::TagOpen;
+                },
+                Some(0) => {
+                    add_error(errors, ParseErrorCode::UnexpectedNullCharacter, self.cur);
+                    self.emit_char(data, 0);
+                },
+                Some(ch) => self.emit_char(data, ch),
+                None => self.emit_eof(),
+            },
+            State::Rcdata => match input {
+                Some(b'&') => match self.character_reference(data, errors) {
+                    Some(chars) => self.emit_str(data, &chars),
+                    None => self.emit_char(data, b'&'),
+                },
+                Some(b'<') => {
+                    self.token_start = self.cur;
+                    self.state = State::LessThanSignIn(TextState::Rcdata);
+                },
+                Some(ch) => self.emit_text_char(data, ch, errors),
+                None => self.emit_eof(),
+            },
+            State::Rawtext => match input {
+                Some(b'<') => {
+                    self.token_start = self.cur;
+                    self.state = State::LessThanSignIn(TextState::Rawtext);
+                },
+                Some(ch) => self.emit_text_char(data, ch, errors),
+                None => self.emit_eof(),
+            },
+            State::ScriptData => match input {
+                Some(b'<') => {
+                    self.token_start = self.cur;
+                    self.state = State::ScriptDataLessThanSign;
+                },
+                Some(ch) => self.emit_text_char(data, ch, errors),
+                None => self.emit_eof(),
+            },
+            State::Plaintext => match input {
+                Some(ch) => self.emit_text_char(data, ch, errors),
+                None => self.emit_eof(),
+            },
+            State::TagOpen => match input {
+                Some(b'!') => self.state = State::MarkupDeclarationOpen,
+                Some(b'/') => self.state = State::EndTagOpen,
+                Some(ch) if ch.is_ascii_alphabetic() => {
+                    self.create_tag(false);
+                    self.reconsume();
+                    self.state = State::TagName;
+                },
+                Some(b'?') => {
+                    add_error(errors, ParseErrorCode::UnexpectedQuestionMarkInsteadOfTagName, self.cur);
+                    self.comment.clear();
+                    self.reconsume();
+                    self.state = State::BogusComment;
+                },
+                Some(_) => {
+                    add_error(errors, ParseErrorCode::InvalidFirstCharacterOfTagName, self.cur);
+                    self.emit_str(data, "<");
+                    self.reconsume();
+                    self.state = State::Data;
+                },
+                None => {
+                    add_error(errors, ParseErrorCode::EofBeforeTagName, self.cur);
+                    self.emit_str(data, "<");
+                    self.emit_eof();
+                },
+            },
+            State::EndTagOpen => match input {
+                Some(ch) if ch.is_ascii_alphabetic() => {
+                    self.create_tag(true);
+                    self.reconsume();
+                    self.state = State::TagName;
+                },
+                Some(b'>') => {
+                    add_error(errors, ParseErrorCode::MissingEndTagName, self.cur);
+                    self.state = State::Data;
+                },
+                Some(_) => {
+                    add_error(errors, ParseErrorCode::InvalidFirstCharacterOfTagName, self.cur);
+                    self.comment.clear();
+                    self.reconsume();
+                    self.state = State::BogusComment;
+                },
+                None => {
+                    add_error(errors, ParseErrorCode::EofBeforeTagName, self.cur);
+                    self.emit_str(data, "</");
+                    self.emit_eof();
+                },
+            },
+            State::TagName => match input {
+                Some(ch) if is_whitespace(ch) => self.state = State::BeforeAttributeName,
+                Some(b'/') => self.state = State::SelfClosingStartTag,
+                Some(b'>') => {
+                    self.state = State::Data;
+                    self.emit_tag(data, errors);
+                },
+                Some(0) => {
+                    add_error(errors, ParseErrorCode::UnexpectedNullCharacter, self.cur);
+                    self.tag_name.push(data, enc, '\u{FFFD}');
+                },
+                Some(ch) => self.tag_name.push_at(data, enc, self.cur, ch.to_ascii_lowercase()),
+                None => {
+                    add_error(errors, ParseErrorCode::EofInTag, self.cur);
+                    self.emit_eof();
+                },
+            },
+            State::LessThanSignIn(text_state) => {
+                if input == Some(b'/') {
+                    self.temp_buffer.clear();
+                    self.state = State::EndTagOpenIn(text_state);
+                } else {
+                    self.emit_str(data, "<");
+                    self.reconsume();
+                    self.state = HtmlTokenizer::text_state(text_state);
+                }
+            },
+            State::EndTagOpenIn(text_state) => match input {
+                Some(ch) if ch.is_ascii_alphabetic() => {
+                    self.create_tag(true);
+                    self.reconsume();
+                    self.state = State::EndTagNameIn(text_state);
+                },
+                _ => {
+                    self.emit_str(data, "</");
+                    self.reconsume();
+                    self.state = HtmlTokenizer::text_state(text_state);
+                },
+            },
+            State::EndTagNameIn(text_state) => {
+                let appropriate = self.is_appropriate_end_tag();
+
+                match input {
+                    Some(ch) if is_whitespace(ch) && appropriate => {
+                        self.set_end_tag_name(data);
+                        self.state = State::BeforeAttributeName;
+                    },
+                    Some(b'/') if appropriate => {
+                        self.set_end_tag_name(data);
+                        self.state = State::SelfClosingStartTag;
+                    },
+                    Some(b'>') if appropriate => {
+                        self.set_end_tag_name(data);
+                        self.state = State::Data;
+                        self.emit_tag(data, errors);
+                    },
+                    Some(ch) if ch.is_ascii_alphabetic() => self.temp_buffer.push(ch as char),
+                    _ => {
+                        let chars = format!("</{}", self.temp_buffer);
+
+                        self.emit_str(data, &chars);
+                        self.reconsume();
+                        self.state = HtmlTokenizer::text_state(text_state);
+                    },
+                }
+            },
+            State::ScriptDataLessThanSign => match input {
+                Some(b'/') => {
+                    self.temp_buffer.clear();
+                    self.state = State::EndTagOpenIn(TextState::ScriptData);
+                },
+                Some(b'!') => {
+                    self.emit_str(data, "<!");
+                    self.state = State::ScriptDataEscapeStart;
+                },
+                _ => {
+                    self.emit_str(data, "<");
+                    self.reconsume();
+                    self.state = State::ScriptData;
+                },
+            },
+            State::ScriptDataEscapeStart | State::ScriptDataEscapeStartDash => {
+                if input == Some(b'-') {
+                    self.emit_char(data, b'-');
+                    self.state = if self.state == State::ScriptDataEscapeStart {
+                        State::ScriptDataEscapeStartDash
+                    } else {
+                        State::ScriptDataEscapedDashDash
+                    };
+                } else {
+                    self.reconsume();
+                    self.state = State::ScriptData;
+                }
+            },
+            State::ScriptDataEscaped | State::ScriptDataEscapedDash | State::ScriptDataEscapedDashDash => {
+                match input {
+                    Some(b'-') => {
+                        self.emit_char(data, b'-');
+                        self.state = match self.state {
+                            State::ScriptDataEscaped => State::ScriptDataEscapedDash,
+                            _ => State::ScriptDataEscapedDashDash,
+                        };
+                    },
+                    Some(b'<') => {
+                        self.token_start = self.cur;
+                        self.state = State::ScriptDataEscapedLessThanSign;
+                    },
+                    Some(b'>') if self.state == State::ScriptDataEscapedDashDash => {
+                        self.emit_char(data, b'>');
+                        self.state = State::ScriptData;
+                    },
+                    Some(ch) => {
+                        self.emit_text_char(data, ch, errors);
+                        self.state = State::ScriptDataEscaped;
+                    },
+                    None => {
+                        add_error(errors, ParseErrorCode::EofInScriptHtmlCommentLikeText, self.cur);
+                        self.emit_eof();
+                    },
+                }
+            },
+            State::ScriptDataEscapedLessThanSign => match input {
+                Some(b'/') => {
+                    self.temp_buffer.clear();
+                    self.state = State::EndTagOpenIn(TextState::ScriptDataEscaped);
+                },
+                Some(ch) if ch.is_ascii_alphabetic() => {
+                    self.temp_buffer.clear();
+                    self.emit_str(data, "<");
+                    self.reconsume();
+                    self.state = State::ScriptDataDoubleEscapeStart;
+                },
+                _ => {
+                    self.emit_str(data, "<");
+                    self.reconsume();
+                    self.state = State::ScriptDataEscaped;
+                },
+            },
+            State::ScriptDataDoubleEscapeStart | State::ScriptDataDoubleEscapeEnd => {
+                let (escaped, double_escaped) = if self.state == State::ScriptDataDoubleEscapeStart {
+                    (State::ScriptDataEscaped, State::ScriptDataDoubleEscaped)
+                } else {
+                    (State::ScriptDataDoubleEscaped, State::ScriptDataEscaped)
+                };
+
+                match input {
+                    Some(ch) if is_whitespace(ch) || ch == b'/' || ch == b'>' => {
+                        self.state = if self.temp_buffer == "script" { double_escaped } else { escaped };
+                        self.emit_char(data, ch);
+                    },
+                    Some(ch) if ch.is_ascii_alphabetic() => {
+                        self.temp_buffer.push(ch.to_ascii_lowercase() as char);
+                        self.emit_char(data, ch);
+                    },
+                    _ => {
+                        self.reconsume();
+                        self.state = escaped;
+                    },
+                }
+            },
+            State::ScriptDataDoubleEscaped | State::ScriptDataDoubleEscapedDash
+                | State::ScriptDataDoubleEscapedDashDash => {
+                match input {
+                    Some(b'-') => {
+                        self.emit_char(data, b'-');
+                        self.state = match self.state {
+                            State::ScriptDataDoubleEscaped => State::ScriptDataDoubleEscapedDash,
+                            _ => State::ScriptDataDoubleEscapedDashDash,
+                        };
+                    },
+                    Some(b'<') => {
+                        self.emit_char(data, b'<');
+                        self.state = State::ScriptDataDoubleEscapedLessThanSign;
+                    },
+                    Some(b'>') if self.state == State::ScriptDataDoubleEscapedDashDash => {
+                        self.emit_char(data, b'>');
+                        self.state = State::ScriptData;
+                    },
+                    Some(ch) => {
+                        self.emit_text_char(data, ch, errors);
+                        self.state = State::ScriptDataDoubleEscaped;
+                    },
+                    None => {
+                        add_error(errors, ParseErrorCode::EofInScriptHtmlCommentLikeText, self.cur);
+                        self.emit_eof();
+                    },
+                }
+            },
+            State::ScriptDataDoubleEscapedLessThanSign => {
+                if input == Some(b'/') {
+                    self.temp_buffer.clear();
+                    self.emit_char(data, b'/');
+                    self.state = State::ScriptDataDoubleEscapeEnd;
+                } else {
+                    self.reconsume();
+                    self.state = State::ScriptDataDoubleEscaped;
+                }
+            },
+            State::BeforeAttributeName => match input {
+                Some(ch) if is_whitespace(ch) => {},
+                Some(b'/') | Some(b'>') | None => {
+                    self.reconsume();
+                    self.state = State::AfterAttributeName;
+                },
+                Some(b'=') => {
+                    add_error(errors, ParseErrorCode::UnexpectedEqualsSignBeforeAttributeName, self.cur);
+                    self.start_attribute(data, errors);
+                    self.append_to_attribute_name(data, b'=');
+                    self.state = State::AttributeName;
+                },
+                Some(_) => {
+                    self.start_attribute(data, errors);
+                    self.reconsume();
+                    self.state = State::AttributeName;
+                },
+            },
+            State::AttributeName => match input {
+                Some(ch) if is_whitespace(ch) || ch == b'/' || ch == b'>' => {
+                    self.reconsume();
+                    self.state = State::AfterAttributeName;
+                },
+                None => {
+                    self.reconsume();
+                    self.state = State::AfterAttributeName;
+                },
+                Some(b'=') => self.state = State::BeforeAttributeValue,
+                Some(0) => {
+                    add_error(errors, ParseErrorCode::UnexpectedNullCharacter, self.cur);
+                    self.attribute_name.push(data, enc, '\u{FFFD}');
+                    self.attribute_position.name_end.offset = self.pos;
+                },
+                Some(ch) => {
+                    if ch == b'"' || ch == b'\'' || ch == b'<' {
+                        add_error(errors, ParseErrorCode::UnexpectedCharacterInAttributeName, self.cur);
+                    }
+
+                    self.append_to_attribute_name(data, ch.to_ascii_lowercase());
+                },
+            },
+            State::AfterAttributeName => match input {
+                Some(ch) if is_whitespace(ch) => {},
+                Some(b'/') => self.state = State::SelfClosingStartTag,
+                Some(b'=') => self.state = State::BeforeAttributeValue,
+                Some(b'>') => {
+                    self.state = State::Data;
+                    self.emit_tag(data, errors);
+                },
+                Some(_) => {
+                    self.start_attribute(data, errors);
+                    self.reconsume();
+                    self.state = State::AttributeName;
+                },
+                None => {
+                    add_error(errors, ParseErrorCode::EofInTag, self.cur);
+                    self.emit_eof();
+                },
+            },
+            State::BeforeAttributeValue => match input {
+                Some(ch) if is_whitespace(ch) => {},
+                Some(quote) if quote == b'"' || quote == b'\'' => {
+                    self.start_attribute_value(quote);
+                    self.state = State::AttributeValueQuoted(quote);
+                },
+                Some(b'>') => {
+                    add_error(errors, ParseErrorCode::MissingAttributeValue, self.cur);
+                    self.state = State::Data;
+                    self.emit_tag(data, errors);
+                },
+                _ => {
+                    self.reconsume();
+                    self.start_attribute_value(b' ');
+                    self.state = State::AttributeValueUnquoted;
+                },
+            },
+            State::AttributeValueQuoted(quote) => match input {
+                Some(ch) if ch == quote => self.state = State::AfterAttributeValueQuoted,
+                Some(b'&') => self.attribute_character_reference(data, errors),
+                Some(0) => self.append_null_to_attribute_value(data, errors),
+                Some(ch) => self.append_to_attribute_value(data, ch),
+                None => {
+                    add_error(errors, ParseErrorCode::EofInTag, self.cur);
+                    self.emit_eof();
+                },
+            },
+            State::AttributeValueUnquoted => match input {
+                Some(ch) if is_whitespace(ch) => self.state = State::BeforeAttributeName,
+                Some(b'&') => self.attribute_character_reference(data, errors),
+                Some(b'>') => {
+                    self.state = State::Data;
+                    self.emit_tag(data, errors);
+                },
+                Some(0) => self.append_null_to_attribute_value(data, errors),
+                Some(ch) => {
+                    if ch == b'"' || ch == b'\'' || ch == b'<' || ch == b'=' || ch == b'`' {
+                        add_error(errors, ParseErrorCode::UnexpectedCharacterInUnquotedAttributeValue, self.cur);
+                    }
+
+                    self.append_to_attribute_value(data, ch);
+                },
+                None => {
+                    add_error(errors, ParseErrorCode::EofInTag, self.cur);
+                    self.emit_eof();
+                },
+            },
+            State::AfterAttributeValueQuoted => match input {
+                Some(ch) if is_whitespace(ch) => self.state = State::BeforeAttributeName,
+                Some(b'/') => self.state = State::SelfClosingStartTag,
+                Some(b'>') => {
+                    self.state = State::Data;
+                    self.emit_tag(data, errors);
+                },
+                Some(_) => {
+                    add_error(errors, ParseErrorCode::MissingWhitespaceBetweenAttributes, self.cur);
+                    self.reconsume();
+                    self.state = State::BeforeAttributeName;
+                },
+                None => {
+                    add_error(errors, ParseErrorCode::EofInTag, self.cur);
+                    self.emit_eof();
+                },
+            },
+            State::SelfClosingStartTag => match input {
+                Some(b'>') => {
+                    self.self_closing = true;
+                    self.state = State::Data;
+                    self.emit_tag(data, errors);
+                },
+                Some(_) => {
+                    add_error(errors, ParseErrorCode::UnexpectedSolidusInTag, self.cur);
+                    self.reconsume();
+                    self.state = State::BeforeAttributeName;
+                },
+                None => {
+                    add_error(errors, ParseErrorCode::EofInTag, self.cur);
+                    self.emit_eof();
+                },
+            },
+            State::BogusComment => match input {
+                Some(b'>') => {
+                    self.state = State::Data;
+                    self.emit_comment(data);
+                },
+                Some(0) => {
+                    add_error(errors, ParseErrorCode::UnexpectedNullCharacter, self.cur);
+                    self.comment.push(data, enc, '\u{FFFD}');
+                },
+                Some(ch) => self.comment.push_at(data, enc, self.cur, ch),
+                None => {
+                    self.emit_comment(data);
+                    self.emit_eof();
+                },
+            },
+            State::MarkupDeclarationOpen => {},
+            State::Doctype => match input {
+                Some(ch) if is_whitespace(ch) => self.state = State::BeforeDoctypeName,
+                Some(b'>') => {
+                    self.reconsume();
+                    self.state = State::BeforeDoctypeName;
+                },
+                Some(_) => {
+                    add_error(errors, ParseErrorCode::MissingWhitespaceBeforeDoctypeName, self.cur);
+                    self.reconsume();
+                    self.state = State::BeforeDoctypeName;
+                },
+                None => {
+                    self.create_doctype();
+                    self.eof_in_doctype(data, errors);
+                },
+            },
+            State::BeforeDoctypeName => match input {
+                Some(ch) if is_whitespace(ch) => {},
+                Some(b'>') => {
+                    add_error(errors, ParseErrorCode::MissingDoctypeName, self.cur);
+                    self.create_doctype();
+                    self.force_quirks = true;
+                    self.state = State::Data;
+                    self.emit_doctype(data);
+                },
+                None => {
+                    self.create_doctype();
+                    self.eof_in_doctype(data, errors);
+                },
+                Some(_) => {
+                    // first char of name is handled (lowercased or replaced if it is NUL) in name state
+                    self.create_doctype();
+                    self.doctype_name = Some(TextBuffer::new());
+                    self.reconsume();
+                    self.state = State::DoctypeName;
+                },
+            },
+            State::DoctypeName => match input {
+                Some(ch) if is_whitespace(ch) => self.state = State::AfterDoctypeName,
+                Some(b'>') => {
+                    self.state = State::Data;
+                    self.emit_doctype(data);
+                },
+                Some(ch) => {
+                    let ch = self.doctype_char(ch, errors);
+
+                    if let Some(ref mut name) = self.doctype_name {
+                        push_doctype_char(name, data, enc, self.cur, ch.to_ascii_lowercase());
+                    }
+                },
+                None => self.eof_in_doctype(data, errors),
+            },
+            State::AfterDoctypeName => match input {
+                Some(ch) if is_whitespace(ch) => {},
+                Some(b'>') => {
+                    self.state = State::Data;
+                    self.emit_doctype(data);
+                },
+                Some(_) => {
+                    let rest = &data[self.cur..];
+
+                    if rest.len() >= 6 && rest[..6].eq_ignore_ascii_case(b"public") {
+                        self.pos = self.cur + 6;
+                        self.state = State::AfterDoctypePublicKeyword;
+                    } else if rest.len() >= 6 && rest[..6].eq_ignore_ascii_case(b"system") {
+                        self.pos = self.cur + 6;
+                        self.state = State::AfterDoctypeSystemKeyword;
+                    } else {
+                        add_error(errors, ParseErrorCode::InvalidCharacterSequenceAfterDoctypeName, self.cur);
+                        self.force_quirks = true;
+                        self.reconsume();
+                        self.state = State::BogusDoctype;
+                    }
+                },
+                None => self.eof_in_doctype(data, errors),
+            },
+            State::AfterDoctypePublicKeyword | State::BeforeDoctypePublicIdentifier => {
+                let after_keyword = self.state == State::AfterDoctypePublicKeyword;
+
+                match input {
+                    Some(ch) if is_whitespace(ch) => {
+                        if after_keyword {
+                            self.state = State::BeforeDoctypePublicIdentifier;
+                        }
+                    },
+                    Some(quote) if quote == b'"' || quote == b'\'' => {
+                        if after_keyword {
+                            add_error(errors, ParseErrorCode::MissingWhitespaceAfterDoctypePublicKeyword, self.cur);
+                        }
+
+                        self.public_id = Some(TextBuffer::new());
+                        self.state = State::DoctypePublicIdentifierQuoted(quote);
+                    },
+                    Some(b'>') => {
+                        add_error(errors, ParseErrorCode::MissingDoctypePublicIdentifier, self.cur);
+                        self.force_quirks = true;
+                        self.state = State::Data;
+                        self.emit_doctype(data);
+                    },
+                    Some(_) => {
+                        add_error(errors, ParseErrorCode::MissingQuoteBeforeDoctypePublicIdentifier, self.cur);
+                        self.force_quirks = true;
+                        self.reconsume();
+                        self.state = State::BogusDoctype;
+                    },
+                    None => self.eof_in_doctype(data, errors),
+                }
+            },
+            State::DoctypePublicIdentifierQuoted(quote) | State::DoctypeSystemIdentifierQuoted(quote) => {
+                let public = matches!(self.state, State::DoctypePublicIdentifierQuoted(_));
+
+                match input {
+                    Some(ch) if ch == quote => {
+                        self.state = if public {
+                            State::AfterDoctypePublicIdentifier
+                        } else {
+                            State::AfterDoctypeSystemIdentifier
+                        };
+                    },
+                    Some(b'>') => {
+                        add_error(errors, if public {
+                            ParseErrorCode::AbruptDoctypePublicIdentifier
+                        } else {
+                            ParseErrorCode::AbruptDoctypeSystemIdentifier
+                        }, self.cur);
+                        self.force_quirks = true;
+                        self.state = State::Data;
+                        self.emit_doctype(data);
+                    },
+                    Some(ch) => {
+                        let ch = self.doctype_char(ch, errors);
+                        let cur = self.cur;
+                        let identifier = if public { &mut self.public_id } else { &mut self.system_id };
+
+                        if let Some(ref mut identifier) = *identifier {
+                            push_doctype_char(identifier, data, enc, cur, ch);
+                        }
+                    },
+                    None => self.eof_in_doctype(data, errors),
+                }
+            },
+            State::AfterDoctypePublicIdentifier | State::BetweenDoctypePublicAndSystemIdentifiers => {
+                let after_identifier = self.state == State::AfterDoctypePublicIdentifier;
+
+                match input {
+                    Some(ch) if is_whitespace(ch) => {
+                        if after_identifier {
+                            self.state = State::BetweenDoctypePublicAndSystemIdentifiers;
+                        }
+                    },
+                    Some(b'>') => {
+                        self.state = State::Data;
+                        self.emit_doctype(data);
+                    },
+                    Some(quote) if quote == b'"' || quote == b'\'' => {
+                        if after_identifier {
+                            add_error(errors,
+                                ParseErrorCode::MissingWhitespaceBetweenDoctypePublicAndSystemIdentifiers, self.cur);
+                        }
+
+                        self.system_id = Some(TextBuffer::new());
+                        self.state = State::DoctypeSystemIdentifierQuoted(quote);
+                    },
+                    Some(_) => {
+                        add_error(errors, ParseErrorCode::MissingQuoteBeforeDoctypeSystemIdentifier, self.cur);
+                        self.force_quirks = true;
+                        self.reconsume();
+                        self.state = State::BogusDoctype;
+                    },
+                    None => self.eof_in_doctype(data, errors),
+                }
+            },
+            State::AfterDoctypeSystemKeyword | State::BeforeDoctypeSystemIdentifier => {
+                let after_keyword = self.state == State::AfterDoctypeSystemKeyword;
+
+                match input {
+                    Some(ch) if is_whitespace(ch) => {
+                        if after_keyword {
+                            self.state = State::BeforeDoctypeSystemIdentifier;
+                        }
+                    },
+                    Some(quote) if quote == b'"' || quote == b'\'' => {
+                        if after_keyword {
+                            add_error(errors, ParseErrorCode::MissingWhitespaceAfterDoctypeSystemKeyword, self.cur);
+                        }
+
+                        self.system_id = Some(TextBuffer::new());
+                        self.state = State::DoctypeSystemIdentifierQuoted(quote);
+                    },
+                    Some(b'>') => {
+                        add_error(errors, ParseErrorCode::MissingDoctypeSystemIdentifier, self.cur);
+                        self.force_quirks = true;
+                        self.state = State::Data;
+                        self.emit_doctype(data);
+                    },
+                    Some(_) => {
+                        add_error(errors, ParseErrorCode::MissingQuoteBeforeDoctypeSystemIdentifier, self.cur);
+                        self.force_quirks = true;
+                        self.reconsume();
+                        self.state = State::BogusDoctype;
+                    },
+                    None => self.eof_in_doctype(data, errors),
+                }
+            },
+            State::AfterDoctypeSystemIdentifier => match input {
+                Some(ch) if is_whitespace(ch) => {},
+                Some(b'>') => {
+                    self.state = State::Data;
+                    self.emit_doctype(data);
+                },
+                Some(_) => {
+                    add_error(errors, ParseErrorCode::UnexpectedCharacterAfterDoctypeSystemIdentifier, self.cur);
+                    self.reconsume();
+                    self.state = State::BogusDoctype;
+                },
+                None => self.eof_in_doctype(data, errors),
+            },
+            State::BogusDoctype => match input {
+                Some(b'>') => {
+                    self.state = State::Data;
+                    self.emit_doctype(data);
+                },
+                Some(0) => add_error(errors, ParseErrorCode::UnexpectedNullCharacter, self.cur),
+                Some(_) => {},
+                None => {
+                    self.emit_doctype(data);
+                    self.emit_eof();
+                },
+            },
+            State::CdataSection => match input {
+                Some(b']') => self.state = State::CdataSectionBracket,
+                Some(ch) => self.emit_char(data, ch),
+                None => {
+                    add_error(errors, ParseErrorCode::EofInCdata, self.cur);
+                    self.emit_eof();
+                },
+            },
+            State::CdataSectionBracket => {
+                if input == Some(b']') {
+                    self.state = State::CdataSectionEnd;
+                } else {
+                    self.emit_str(data, "]");
+                    self.reconsume();
+                    self.state = State::CdataSection;
+                }
+            },
+            State::CdataSectionEnd => match input {
+                Some(b']') => self.emit_str(data, "]"),
+                Some(b'>') => self.state = State::Data,
+                _ => {
+                    self.emit_str(data, "]]");
+                    self.reconsume();
+                    self.state = State::CdataSection;
+                },
+            },
+        }
+    }
+
+    /// Emits char of RCDATA, RAWTEXT, script or PLAINTEXT: NUL is replaced there
+    fn emit_text_char(&mut self, data: &[u8], ch: u8, errors: &mut Vec<ParseError>) {
+        if ch == 0 {
+            add_error(errors, ParseErrorCode::UnexpectedNullCharacter, self.cur);
+            self.emit_str(data, "\u{FFFD}");
+        } else {
+            self.emit_char(data, ch);
+        }
+    }
+
+    /// Sets name of end tag from temp buffer once we know it is appropriate end tag
+    fn set_end_tag_name(&mut self, data: &[u8]) {
+        let name = self.temp_buffer.to_ascii_lowercase();
+
+        self.tag_name.clear();
+        self.tag_name.push_str(data, self.enc, &name);
+    }
+
+    /// Decodes character reference in attribute value
+    fn attribute_character_reference(&mut self, data: &[u8], errors: &mut Vec<ParseError>) {
+        self.entities.in_attribute = true;
+
+        let chars = self.character_reference(data, errors);
+
+        self.entities.in_attribute = false;
+
+        match chars {
+            Some(chars) => {
+                self.attribute_value.push_str(data, self.enc, &chars);
+                self.attribute_position.value_end.offset = self.pos;
+            },
+            None => self.append_to_attribute_value(data, b'&'),
+        }
+    }
+
+    fn append_null_to_attribute_value(&mut self, data: &[u8], errors: &mut Vec<ParseError>) {
+        add_error(errors, ParseErrorCode::UnexpectedNullCharacter, self.cur);
+        self.attribute_value.push(data, self.enc, '\u{FFFD}');
+        self.attribute_position.value_end.offset = self.pos;
+    }
+
+    /// Reports NUL in DOCTYPE name or identifiers, push_doctype_char replaces it
+    fn doctype_char(&mut self, ch: u8, errors: &mut Vec<ParseError>) -> u8 {
+        if ch == 0 {
+            add_error(errors, ParseErrorCode::UnexpectedNullCharacter, self.cur);
+        }
+
+        ch
+    }
+}
+
+/// Appends char to part of DOCTYPE, NUL is replaced with U+FFFD
+fn push_doctype_char(part: &mut TextBuffer, data: &[u8], enc: EncodingRef, pos: usize, ch: u8) {
+    if ch == 0 {
+        part.push(data, enc, '\u{FFFD}');
+    } else {
+        part.push_at(data, enc, pos, ch);
+    }
+}
+
+/// Returns true if char is whitespace as far as tokenizer is concerned (CR is normalized to LF)
+#[inline]
+fn is_whitespace(ch: u8) -> bool {
+    ch == b' ' || ch == b'\t' || ch == b'\n' || ch == 0x0C
+}
+
+fn add_error(errors: &mut Vec<ParseError>, code: ParseErrorCode, offset: usize) {
+    errors.push(ParseError::new(code, offset));
+}
+
+/// States of comment parsing, see https://html.spec.whatwg.org/#comment-start-state
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CommentState {
+    Start,
+    StartDash,
+    Data,
+    LessThanSign,
+    LessThanSignBang,
+    LessThanSignBangDash,
+    LessThanSignBangDashDash,
+    EndDash,
+    End,
+    EndBang,
+}
+
+/// Scans comment which data starts at data_start (right after <!--) following HTML5 comment states,
+/// so that we end up with exactly the same comment data as browsers would. Comment data is always
+/// continuous part of HTML, returns its end and position right after the comment
+pub fn scan_comment(data: &[u8], data_start: usize, errors: &mut Vec<ParseError>) -> (usize, usize) {
+    let mut state = CommentState::Start;
+    let mut pos = data_start;
+
+    // dashes that may turn out to be the end of comment are not included in data until we know they are not
+    let mut data_end = data_start;
+
+    let end = loop {
+        if pos >= data.len() {
+            add_error(errors, ParseErrorCode::EofInComment, pos);
+            break pos;
+        }
+
+        let ch = data[pos];
+
+        match state {
+            CommentState::Start => {
+                match ch {
+                    b'-' => state = CommentState::StartDash,
+                    b'>' => {
+                        add_error(errors, ParseErrorCode::AbruptClosingOfEmptyComment, pos);
+                        break pos + 1;
+                    },
+                    _ => {
+                        state = CommentState::Data;
+                        continue;
+                    }
+                }
+            },
+            CommentState::StartDash => {
+                match ch {
+                    b'-' => state = CommentState::End,
+                    b'>' => {
+                        add_error(errors, ParseErrorCode::AbruptClosingOfEmptyComment, pos);
+                        break pos + 1;
+                    },
+                    _ => {
+                        data_end = pos;
+                        state = CommentState::Data;
+                        continue;
+                    }
+                }
+            },
+            CommentState::Data => {
+                match ch {
+                    b'<' => {
+                        data_end = pos + 1;
+                        state = CommentState::LessThanSign;
+                    },
+                    b'-' => state = CommentState::EndDash,
+                    _ => data_end = pos + 1,
+                }
+            },
+            CommentState::LessThanSign => {
+                match ch {
+                    b'!' => {
+                        data_end = pos + 1;
+                        state = CommentState::LessThanSignBang;
+                    },
+                    b'<' => data_end = pos + 1,
+                    _ => {
+                        state = CommentState::Data;
+                        continue;
+                    }
+                }
+            },
+            CommentState::LessThanSignBang => {
+                if ch == b'-' {
+                    state = CommentState::LessThanSignBangDash;
+                } else {
+                    state = CommentState::Data;
+                    continue;
+                }
+            },
+            CommentState::LessThanSignBangDash => {
+                if ch == b'-' {
+                    state = CommentState::LessThanSignBangDashDash;
+                } else {
+                    state = CommentState::EndDash;
+                    continue;
+                }
+            },
+            CommentState::LessThanSignBangDashDash => {
+                if ch != b'>' {
+                    add_error(errors, ParseErrorCode::NestedComment, pos);
+                }
+
+                state = CommentState::End;
+                continue;
+            },
+            CommentState::EndDash => {
+                if ch == b'-' {
+                    state = CommentState::End;
+                } else {
+                    data_end = pos;
+                    state = CommentState::Data;
+                    continue;
+                }
+            },
+            CommentState::End => {
+                match ch {
+                    b'>' => break pos + 1,
+                    b'!' => state = CommentState::EndBang,
+                    b'-' => data_end = pos - 1,
+                    _ => {
+                        data_end = pos;
+                        state = CommentState::Data;
+                        continue;
+                    }
+                }
+            },
+            CommentState::EndBang => {
+                match ch {
+                    b'-' => {
+                        data_end = pos;
+                        state = CommentState::EndDash;
+                    },
+                    b'>' => {
+                        add_error(errors, ParseErrorCode::IncorrectlyClosedComment, pos);
+                        break pos + 1;
+                    },
+                    _ => {
+                        data_end = pos;
+                        state = CommentState::Data;
+                        continue;
+                    }
+                }
+            }
+        }
+
+        pos += 1;
+    };
+
+    (data_end, end)
+}
@@ -0,0 +1,235 @@
+use std::io::{self, Write};
+
+use html_chunk::{HtmlChunk, ChunkType, Namespace};
+use html_entities::HtmlEntities;
+use serializer::{VOID_ELEMENTS, RAW_TEXT_ELEMENTS};
+
+/// Writes HTML to writer tag by tag: text and attribute values are escaped, void elements like br get no
+/// end tag and end tags are always balanced, so end_tag() closes the element that was opened last
+pub struct HtmlWriter<W: Write> {
+    /// If true then elements that are still open are closed by finish()
+    pub auto_close: bool,
+
+    /// If true then XML is written: there are no void or raw text elements and no-break spaces are not escaped
+    pub xml_mode: bool,
+
+    /// Must match extract_between_tags_only of parser which chunks are given to write_chunk: if it is false
+    /// then html of script and comment chunks has their tags already, so it is written as it is
+    pub extract_between_tags_only: bool,
+
+    writer: W,
+
+    /// Names of open elements, the last one is current
+    open_elements: Vec<String>,
+}
+
+impl<W: Write> HtmlWriter<W> {
+    pub fn new(writer: W) -> HtmlWriter<W> {
+        HtmlWriter {
+            auto_close: true,
+            xml_mode: false,
+            extract_between_tags_only: true,
+            writer,
+            open_elements: Vec::new(),
+        }
+    }
+
+    /// Returns names of open elements, the last one is current
+    pub fn open_elements(&self) -> &[String] {
+        &self.open_elements
+    }
+
+    /// Writes start tag with attributes which values are escaped, element stays open unless it is void. It is
+    /// an error if name of tag or attribute would end it early, ie "a onclick=x", nothing is written then
+    pub fn start_tag<'a, I>(&mut self, name: &str, attributes: I) -> io::Result<()>
+        where I: IntoIterator<Item = (&'a str, &'a str)> {
+        if !name.starts_with(|ch: char| ch.is_ascii_alphabetic()) || !is_valid_name(name) {
+            return Err(invalid_name(name));
+        }
+
+        let mut html = String::new();
+        let mut entities = HtmlEntities::new();
+
+        entities.in_attribute = true;
+        entities.xml_mode = self.xml_mode;
+
+        html.push('<');
+        html.push_str(name);
+
+        for (name, value) in attributes {
+            if !is_valid_name(name) || name.contains(['=', '"', '\'', '<']) {
+                return Err(invalid_name(name));
+            }
+
+            html.push(' ');
+            html.push_str(name);
+            html.push_str("=\"");
+            entities.encode(value, &mut html);
+            html.push('"');
+        }
+
+        html.push('>');
+
+        if self.xml_mode || !VOID_ELEMENTS.contains(&name) {
+            self.open_elements.push(name.to_string());
+        }
+
+        self.writer.write_all(html.as_bytes())
+    }
+
+    /// Writes end tag of current element, it is an error if there is no open element
+    pub fn end_tag(&mut self) -> io::Result<()> {
+        match self.open_elements.pop() {
+            Some(name) => write!(self.writer, "</{}>", name),
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "there is no open element to close")),
+        }
+    }
+
+    /// Writes end tags of elements opened after the last element with given name and of that element,
+    /// nothing is written if there is no such open element
+    pub fn end_tag_named(&mut self, name: &str) -> io::Result<()> {
+        if let Some(pos) = self.open_elements.iter().rposition(|open| open == name) {
+            while self.open_elements.len() > pos {
+                self.end_tag()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes escaped text, text of raw text elements like script or style is written as it is. End tags of
+    /// script in its text become <\/script, so they do not end it early, while in other raw text elements
+    /// they are an error as \ would change their text
+    pub fn text(&mut self, text: &str) -> io::Result<()> {
+        let raw_text_element = match self.open_elements.last() {
+            Some(name) if !self.xml_mode && RAW_TEXT_ELEMENTS.contains(&name.as_str()) => Some(name),
+            _ => None,
+        };
+
+        if let Some(name) = raw_text_element {
+            let escaped = escape_end_tags(text, name);
+
+            if name != "script" && escaped.len() != text.len() {
+                let message = format!("text of {} element can not contain its end tag", name);
+
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+            }
+
+            return self.writer.write_all(escaped.as_bytes());
+        }
+
+        let mut html = String::with_capacity(text.len());
+        let mut entities = HtmlEntities::new();
+
+        entities.xml_mode = self.xml_mode;
+        entities.encode(text, &mut html);
+
+        self.writer.write_all(html.as_bytes())
+    }
+
+    /// Writes comment, text is made safe so it does not end comment early, see HtmlChunk::make_safe_comment
+    pub fn comment(&mut self, text: &str) -> io::Result<()> {
+        write!(self.writer, "<!--{}-->", HtmlChunk::make_safe_comment(text))
+    }
+
+    /// Writes HTML as it is
+    pub fn raw(&mut self, html: &str) -> io::Result<()> {
+        self.writer.write_all(html.as_bytes())
+    }
+
+    /// Writes chunk that parser returned, so chunks can be filtered and written again. Text and param values
+    /// are escaped, so parser should decode entities. End tags without open element are dropped and end tag
+    /// closes elements opened after its element. Lenient parser keeps no parts of DOCTYPE, so it is written
+    /// as <!DOCTYPE html> unless html of chunk was set with set_raw_html of parser. Script and comment chunks
+    /// are written as they are if extract_between_tags_only is false
+    pub fn write_chunk(&mut self, chunk: &HtmlChunk) -> io::Result<()> {
+        match chunk.chunk_type {
+            ChunkType::Text => self.text(&chunk.html),
+            ChunkType::Template => self.raw(&chunk.html),
+            ChunkType::OpenTag if chunk.tag == "!doctype" && chunk.html.is_empty() => self.raw("<!DOCTYPE html>"),
+            ChunkType::OpenTag if chunk.tag == "!doctype" => self.raw(&chunk.html),
+            ChunkType::OpenTag => {
+                self.chunk_start_tag(chunk)?;
+
+                // conformance mode returns self-closing tags as open tags with end_closure
+                let self_closing = chunk.end_closure && (self.xml_mode || chunk.namespace != Namespace::Html);
+
+                if self_closing && self.open_elements.last() == Some(&chunk.tag) {
+                    self.end_tag()?;
+                }
+
+                Ok(())
+            },
+            ChunkType::CloseTag if chunk.end_closure => {
+                // <br/> or <div/> without params
+                self.chunk_start_tag(chunk)?;
+
+                if self.open_elements.last() == Some(&chunk.tag) {
+                    self.end_tag()?;
+                }
+
+                Ok(())
+            },
+            ChunkType::CloseTag => self.end_tag_named(&chunk.tag),
+            ChunkType::Comment | ChunkType::Script if !self.extract_between_tags_only => self.raw(&chunk.html),
+            ChunkType::Comment if chunk.tag == "!--" => self.comment(&chunk.html),
+            ChunkType::Comment | ChunkType::Doctype => self.raw(&chunk.generate_html()),
+            ChunkType::Script => {
+                self.chunk_start_tag(chunk)?;
+                self.text(&chunk.html)?;
+                self.end_tag()
+            },
+        }
+    }
+
+    fn chunk_start_tag(&mut self, chunk: &HtmlChunk) -> io::Result<()> {
+        let attributes = chunk.param_names.iter().zip(chunk.param_values.iter())
+            .take(chunk.params_count)
+            .map(|(name, value)| (name.as_str(), value.as_str()));
+
+        self.start_tag(&chunk.tag, attributes)
+    }
+
+    /// Closes elements that are still open if auto_close is set and returns writer
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.auto_close {
+            while !self.open_elements.is_empty() {
+                self.end_tag()?;
+            }
+        }
+
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+}
+
+/// Returns true if name of tag or attribute has no whitespace, / or > that would end it early
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains(|ch: char| ch.is_ascii_whitespace() || ch.is_control() || ch == '/' || ch == '>')
+}
+
+fn invalid_name(name: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("{:?} is not valid name of tag or attribute", name))
+}
+
+/// Puts \ before / of end tags of raw text element in its text, ie </script becomes <\/script
+fn escape_end_tags(text: &str, name: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut escaped = String::with_capacity(text.len());
+    let mut from = 0;
+
+    for (i, _) in text.match_indices("</") {
+        let name_end = i + 2 + name.len();
+
+        if name_end <= bytes.len() && bytes[i + 2..name_end].eq_ignore_ascii_case(name.as_bytes()) {
+            escaped.push_str(&text[from..i + 1]);
+            escaped.push('\\');
+            from = i + 1;
+        }
+    }
+
+    escaped.push_str(&text[from..]);
+
+    escaped
+}
@@ -0,0 +1,98 @@
+use html_chunk::HtmlChunk;
+
+/// What to do with NULs and noncharacters (U+FDD0 to U+FDEF, U+FFFE, U+FFFF and the same at the end
+/// of other planes) found in HTML
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InvalidChars {
+    /// Leave them as they are
+    Keep,
+
+    /// Replace each of them with U+FFFD
+    Replace,
+
+    /// Remove them
+    Strip,
+}
+
+/// Normalizes strings of parsed chunks as HTML5 input stream preprocessing does: CR and CRLF become LF
+/// and NULs are dealt with. Chunks are changed after they were parsed, so their offsets and lengths
+/// still point at original bytes of HTML
+pub struct InputPreprocessor {
+    /// If true then CR and CRLF are converted into LF
+    pub normalize_newlines: bool,
+
+    /// What to do with NULs and noncharacters
+    pub invalid_chars: InvalidChars,
+}
+
+impl InputPreprocessor {
+    /// Returns true if preprocessing would change anything at all
+    pub fn is_enabled(&self) -> bool {
+        self.normalize_newlines || self.invalid_chars != InvalidChars::Keep
+    }
+
+    /// Preprocesses html, tag, condition and params of chunk
+    pub fn process_chunk(&self, chunk: &mut HtmlChunk) {
+        self.process(&mut chunk.html);
+        self.process(&mut chunk.tag);
+        self.process(&mut chunk.condition);
+
+        if chunk.params_count == 0 {
+            return;
+        }
+
+        let mut changed = false;
+
+        for text in chunk.param_names.iter_mut().chain(chunk.param_values.iter_mut()) {
+            changed |= self.process(text);
+        }
+
+        if changed && chunk.hash_mode {
+            chunk.convert_params_to_hash();
+        }
+    }
+
+    /// Preprocesses text in place, returns true if it was changed
+    pub fn process(&self, text: &mut String) -> bool {
+        if !text.chars().any(|ch| self.needs_processing(ch)) {
+            return false;
+        }
+
+        let mut result = String::with_capacity(text.len());
+        let mut chars = text.chars().peekable();
+
+        while let Some(ch) = chars.next() {
+            if ch == '\r' && self.normalize_newlines {
+                if chars.peek() == Some(&'\n') {
+                    chars.next();
+                }
+
+                result.push('\n');
+            } else if ch != '\r' && self.needs_processing(ch) {
+                if self.invalid_chars == InvalidChars::Replace {
+                    result.push('\u{FFFD}');
+                }
+            } else {
+                result.push(ch);
+            }
+        }
+
+        *text = result;
+
+        true
+    }
+
+    fn needs_processing(&self, ch: char) -> bool {
+        match ch {
+            '\r' => self.normalize_newlines,
+            _ => self.invalid_chars != InvalidChars::Keep && is_invalid_char(ch),
+        }
+    }
+}
+
+/// Returns true if char is NUL or noncharacter
+pub fn is_invalid_char(ch: char) -> bool {
+    let code = ch as u32;
+
+    code == 0 || (0xFDD0..=0xFDEF).contains(&code) || (code & 0xFFFE) == 0xFFFE
+}
@@ -4,8 +4,27 @@ extern crate encoding;
 
 mod html_heuristics;
 mod dynamic_string;
-mod html_chunk;
 mod tag_parser;
 mod html_entities;
+mod html_tokenizer;
+mod input_preprocessor;
+mod entities_table;
+mod document_builder;
+mod tree_builder;
+mod foreign_content;
+mod xml_content;
+mod serializer;
+mod plain_text;
 
-pub mod html_parser;
\ No newline at end of file
+pub mod html_chunk;
+pub mod html_parser;
+pub mod parse_error;
+pub mod line_index;
+pub mod html_document;
+pub mod selector;
+pub mod xpath;
+pub mod rewriter;
+pub mod html_writer;
+pub mod html_formatter;
+pub mod html_minifier;
+pub mod markdown;
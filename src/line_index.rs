@@ -0,0 +1,135 @@
+/// Position in HTML data: byte offset along with line and columns, lines and columns start from 1.
+/// Line is 0 if position was not calculated (parser was not tracking positions).
+///
+/// Char and UTF-16 columns are counted assuming data is UTF-8, for other encodings use byte column
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct SourcePosition {
+    /// Offset of byte in HTML data
+    pub offset: usize,
+
+    /// Line number, CR, LF and CRLF are all treated as line breaks
+    pub line: usize,
+
+    /// Column counted in bytes
+    pub column: usize,
+
+    /// Column counted in unicode chars
+    pub char_column: usize,
+
+    /// Column counted in UTF-16 code units, as used by most editors
+    pub utf16_column: usize,
+}
+
+impl SourcePosition {
+    /// Returns position of the first byte of data
+    pub fn start() -> SourcePosition {
+        SourcePosition::line_start(0, 1)
+    }
+
+    fn line_start(offset: usize, line: usize) -> SourcePosition {
+        SourcePosition {
+            offset,
+            line,
+            column: 1,
+            char_column: 1,
+            utf16_column: 1,
+        }
+    }
+
+    /// Returns position of byte at offset that must not be before this position, offsets past the end of
+    /// data give position of the end
+    pub fn advance(&self, data: &[u8], offset: usize) -> SourcePosition {
+        let offset = offset.min(data.len());
+        let mut pos = *self;
+
+        while pos.offset < offset {
+            let ch = data[pos.offset];
+
+            if ch == b'\n' || (ch == b'\r' && data.get(pos.offset + 1) != Some(&b'\n')) {
+                pos = SourcePosition::line_start(pos.offset + 1, pos.line + 1);
+                continue;
+            }
+
+            pos.offset += 1;
+            pos.column += 1;
+
+            // continuation bytes of UTF-8 sequences do not start new char
+            if ch & 0xC0 != 0x80 {
+                pos.char_column += 1;
+                pos.utf16_column += if ch >= 0xF0 { 2 } else { 1 };
+            }
+        }
+
+        pos
+    }
+}
+
+/// Positions of param name and value within a tag, value positions are same as end of name if param
+/// has got no value, quotes are not included in value
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct ParamPosition {
+    pub name_start: SourcePosition,
+    pub name_end: SourcePosition,
+    pub value_start: SourcePosition,
+    pub value_end: SourcePosition,
+}
+
+/// Index of line starts in HTML data that allows to quickly convert byte offsets into lines and columns
+pub struct LineIndex {
+    /// Offsets at which lines start, the first one is always 0
+    line_starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub fn new(data: &[u8]) -> LineIndex {
+        let mut line_starts = vec![0];
+
+        for (i, &ch) in data.iter().enumerate() {
+            if ch == b'\n' || (ch == b'\r' && data.get(i + 1) != Some(&b'\n')) {
+                line_starts.push(i + 1);
+            }
+        }
+
+        LineIndex { line_starts }
+    }
+
+    /// Returns number of lines
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// Returns offset at which line starts, None if there is no such line
+    pub fn line_offset(&self, line: usize) -> Option<usize> {
+        if line == 0 {
+            return None;
+        }
+
+        self.line_starts.get(line - 1).cloned()
+    }
+
+    /// Returns line on which byte at offset is
+    pub fn line(&self, offset: usize) -> usize {
+        match self.line_starts.binary_search(&offset) {
+            Ok(line) => line + 1,
+            Err(line) => line,
+        }
+    }
+
+    /// Returns position of byte at offset in data that this index was created for, offsets past the end of
+    /// data give position of the end
+    pub fn position(&self, data: &[u8], offset: usize) -> SourcePosition {
+        let offset = offset.min(data.len());
+        let line = self.line(offset);
+
+        SourcePosition::line_start(self.line_starts[line - 1], line).advance(data, offset)
+    }
+
+    /// Returns offset of byte at given line and byte column
+    pub fn offset(&self, line: usize, column: usize) -> Option<usize> {
+        if column == 0 {
+            return None;
+        }
+
+        self.line_offset(line).map(|start| start + column - 1)
+    }
+}
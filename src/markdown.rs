@@ -0,0 +1,649 @@
+use html_chunk::Namespace;
+use html_document::{Document, NodeId, NodeData, Element};
+use serializer::BLOCK_ELEMENTS;
+use plain_text::HIDDEN_ELEMENTS;
+
+/// Block elements that Markdown can not express, they are kept as HTML
+const HTML_BLOCK_ELEMENTS: [&str; 11] = ["audio", "canvas", "details", "dialog", "dl", "form", "iframe", "object",
+    "select", "textarea", "video"];
+
+/// Inline elements that Markdown can not express, they are kept as HTML
+const HTML_INLINE_ELEMENTS: [&str; 7] = ["abbr", "kbd", "mark", "sub", "sup", "u", "var"];
+
+/// Converts HTML to CommonMark with GitHub Flavored Markdown tables and strikethrough. Headings, paragraphs,
+/// emphasis, links, images, nested lists, blockquotes, code and simple tables are converted, elements that
+/// Markdown can not express are kept as HTML
+pub struct MarkdownConverter {
+    /// URL of the document that relative URLs of links and images are resolved against, href of base element
+    /// of document is resolved against it first the way browsers do it
+    pub base_url: Option<String>,
+
+    /// Char that starts items of unordered lists: -, * or +
+    pub bullet: char,
+
+    /// If true then elements that Markdown can not express, like tables with merged cells or sup, are kept as
+    /// HTML, otherwise only their content is converted
+    pub keep_html: bool,
+}
+
+impl MarkdownConverter {
+    pub fn new() -> MarkdownConverter {
+        MarkdownConverter {
+            base_url: None,
+            bullet: '-',
+            keep_html: true,
+        }
+    }
+
+    /// Parses HTML the way browsers do it and converts it to Markdown
+    pub fn convert(&self, html: &str) -> String {
+        self.convert_document(&Document::parse_html5(html))
+    }
+
+    /// Converts document to Markdown, blocks are separated by empty lines
+    pub fn convert_document(&self, document: &Document) -> String {
+        // only the first base element with href matters
+        let base_href = document.descendants(document.root())
+            .filter(|&id| html_name(&document[id].data) == Some("base"))
+            .filter_map(|id| document[id].as_element().and_then(|base| base.attribute("href")))
+            .next();
+
+        let base_url = match (self.base_url.as_ref(), base_href) {
+            (Some(url), Some(href)) => Some(resolve_url(url, href)),
+            (Some(url), None) => Some(url.clone()),
+            (None, href) => href.map(|href| href.trim().to_string()),
+        };
+
+        let converter = Converter {
+            options: self,
+            document,
+            base_url,
+        };
+
+        let mut blocks = Vec::new();
+
+        converter.blocks(document.root(), &mut blocks);
+
+        let mut markdown = blocks.join("\n\n");
+
+        if !markdown.is_empty() {
+            markdown.push('\n');
+        }
+
+        markdown
+    }
+}
+
+impl Default for MarkdownConverter {
+    fn default() -> MarkdownConverter {
+        MarkdownConverter::new()
+    }
+}
+
+/// Converts document with options of MarkdownConverter
+struct Converter<'a> {
+    options: &'a MarkdownConverter,
+    document: &'a Document,
+    base_url: Option<String>,
+}
+
+impl<'a> Converter<'a> {
+    /// Converts children of node to blocks, runs of inline content between block elements become paragraphs
+    fn blocks(&self, id: NodeId, blocks: &mut Vec<String>) {
+        let mut inline = String::new();
+
+        for child in self.document.children(id) {
+            if self.is_block(child) {
+                push_paragraph(&inline, blocks);
+                inline.clear();
+                self.block(child, blocks);
+            } else {
+                self.inline(child, &mut inline);
+            }
+        }
+
+        push_paragraph(&inline, blocks);
+    }
+
+    fn is_block(&self, id: NodeId) -> bool {
+        match html_name(&self.document[id].data) {
+            Some(name) => BLOCK_ELEMENTS.contains(&name) || HTML_BLOCK_ELEMENTS.contains(&name),
+            None => false,
+        }
+    }
+
+    fn block(&self, id: NodeId, blocks: &mut Vec<String>) {
+        let name = html_name(&self.document[id].data).unwrap_or("");
+
+        match name {
+            _ if HTML_BLOCK_ELEMENTS.contains(&name) => self.html_block(id, blocks),
+            _ if HIDDEN_ELEMENTS.contains(&name) => {},
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let mut heading = String::new();
+
+                self.children_inline(id, &mut heading);
+
+                let heading = paragraph(&heading).replace("\\\n", " ");
+
+                if !heading.is_empty() {
+                    let level = name[1..].parse().unwrap_or(1);
+
+                    blocks.push(format!("{} {}", "#".repeat(level), heading));
+                }
+            },
+            "p" => {
+                let mut inline = String::new();
+
+                self.children_inline(id, &mut inline);
+                push_paragraph(&inline, blocks);
+            },
+            "blockquote" => {
+                let mut quoted = Vec::new();
+
+                self.blocks(id, &mut quoted);
+
+                if !quoted.is_empty() {
+                    blocks.push(prefix_lines(&quoted.join("\n\n"), "> ", ">"));
+                }
+            },
+            "ol" | "ul" | "menu" => self.list(id, blocks),
+            "pre" => blocks.push(self.code_block(id)),
+            "hr" => blocks.push("---".to_string()),
+            "table" => match self.table(id) {
+                Some(table) => blocks.push(table),
+                None => self.html_block(id, blocks),
+            },
+            _ => self.blocks(id, blocks),
+        }
+    }
+
+    /// Keeps element as HTML if asked to, otherwise converts its content
+    fn html_block(&self, id: NodeId, blocks: &mut Vec<String>) {
+        if self.options.keep_html {
+            blocks.push(self.document.outer_html(id));
+        } else {
+            self.blocks(id, blocks);
+        }
+    }
+
+    /// Converts list, items that have paragraphs are separated by empty lines
+    fn list(&self, id: NodeId, blocks: &mut Vec<String>) {
+        let element = self.document[id].as_element();
+        let mut number = match element {
+            Some(element) if element.name == "ol" => {
+                Some(element.attribute("start").and_then(|start| start.trim().parse().ok()).unwrap_or(1))
+            },
+            _ => None,
+        };
+
+        let items: Vec<NodeId> = self.document.child_elements(id)
+            .filter(|&child| html_name(&self.document[child].data) == Some("li"))
+            .collect();
+
+        let loose = items.iter().any(|&item| {
+            self.document.child_elements(item).any(|child| html_name(&self.document[child].data) == Some("p"))
+        });
+
+        let separator = if loose { "\n\n" } else { "\n" };
+        let mut list = Vec::new();
+
+        for item in items {
+            let marker = match number {
+                Some(ref mut number) => {
+                    *number += 1;
+                    format!("{}. ", *number - 1)
+                },
+                None => format!("{} ", self.options.bullet),
+            };
+
+            let mut content = Vec::new();
+
+            self.blocks(item, &mut content);
+
+            let content = content.join(separator);
+            let indent = " ".repeat(marker.len());
+
+            list.push(format!("{}{}", marker, prefix_lines(&content, &indent, "").trim_start()));
+        }
+
+        if !list.is_empty() {
+            blocks.push(list.join(separator));
+        }
+    }
+
+    /// Converts pre to fenced code block, language is taken from language-* or lang-* class of pre or code in it
+    fn code_block(&self, id: NodeId) -> String {
+        let code = self.document.child_elements(id)
+            .find(|&child| html_name(&self.document[child].data) == Some("code"));
+
+        let language = code.into_iter().chain(Some(id))
+            .filter_map(|id| self.document[id].as_element())
+            .filter_map(|element| element.attribute("class"))
+            .flat_map(|class| class.split_ascii_whitespace())
+            .find_map(|class| class.strip_prefix("language-").or_else(|| class.strip_prefix("lang-")))
+            .unwrap_or("");
+
+        let text = self.document.text_content(id);
+        let text = text.strip_suffix('\n').unwrap_or(&text);
+
+        // fence is longer than any run of backticks in code
+        let fence = "`".repeat(longest_run(text, '`').max(2) + 1);
+
+        format!("{}{}\n{}\n{}", fence, language, text, fence)
+    }
+
+    /// Converts table to GFM table, None if it is not simple: it has merged cells, nested tables or blocks in
+    /// cells. The first row is header
+    fn table(&self, id: NodeId) -> Option<String> {
+        let mut caption = None;
+        let mut rows = Vec::new();
+
+        for child in self.document.child_elements(id) {
+            match html_name(&self.document[child].data) {
+                Some("caption") => caption = Some(child),
+                Some("thead") | Some("tbody") | Some("tfoot") => rows.extend(self.document.child_elements(child)),
+                Some("tr") => rows.push(child),
+                Some("colgroup") => {},
+                _ => return None,
+            }
+        }
+
+        let mut cells: Vec<Vec<String>> = Vec::new();
+
+        for row in rows {
+            let mut row_cells = Vec::new();
+
+            for cell in self.document.child_elements(row) {
+                let element = self.document[cell].as_element()?;
+
+                let merged = ["colspan", "rowspan"].iter()
+                    .any(|&span| element.attribute(span).is_some_and(|span| span.trim() != "1"));
+
+                if !(element.name == "td" || element.name == "th") || merged {
+                    return None;
+                }
+
+                if self.document.descendants(cell).any(|descendant| descendant != cell && self.is_block(descendant)) {
+                    return None;
+                }
+
+                let mut content = String::new();
+
+                self.children_inline(cell, &mut content);
+                row_cells.push(paragraph(&content).replace("\\\n", "<br>").replace('|', "\\|"));
+            }
+
+            cells.push(row_cells);
+        }
+
+        let columns = cells.iter().map(|row| row.len()).max().filter(|&columns| columns > 0)?;
+        let mut table = String::new();
+
+        if let Some(caption) = caption {
+            let mut inline = String::new();
+
+            self.children_inline(caption, &mut inline);
+            table.push_str(&paragraph(&inline));
+            table.push_str("\n\n");
+        }
+
+        for (i, row) in cells.iter().enumerate() {
+            table.push('|');
+
+            for column in 0..columns {
+                table.push(' ');
+                table.push_str(row.get(column).map_or("", |cell| cell.as_str()));
+                table.push_str(" |");
+            }
+
+            table.push('\n');
+
+            if i == 0 {
+                table.push('|');
+                table.push_str(&" --- |".repeat(columns));
+                table.push('\n');
+            }
+        }
+
+        table.pop();
+
+        Some(table)
+    }
+
+    fn children_inline(&self, id: NodeId, markdown: &mut String) {
+        for child in self.document.children(id) {
+            self.inline(child, markdown);
+        }
+    }
+
+    /// Appends inline Markdown of node, whitespace is collapsed
+    fn inline(&self, id: NodeId, markdown: &mut String) {
+        let element = match self.document[id].data {
+            NodeData::Text(ref text) => return push_text(text, markdown),
+            NodeData::Element(ref element) => element,
+            _ => return,
+        };
+
+        let name = html_name(&self.document[id].data).unwrap_or("");
+        let start = markdown.len();
+
+        match name {
+            _ if HTML_INLINE_ELEMENTS.contains(&name) || HTML_BLOCK_ELEMENTS.contains(&name) => {
+                if self.options.keep_html {
+                    markdown.push_str(&self.document.outer_html(id));
+                } else {
+                    self.children_inline(id, markdown);
+                }
+            },
+            _ if HIDDEN_ELEMENTS.contains(&name) => {},
+            "br" => {
+                let trimmed = markdown.trim_end_matches(' ').len();
+
+                markdown.truncate(trimmed);
+                markdown.push_str("\\\n");
+            },
+            "strong" | "b" => {
+                self.children_inline(id, markdown);
+                wrap(markdown, start, "**", "**");
+            },
+            "em" | "i" => {
+                self.children_inline(id, markdown);
+                wrap(markdown, start, "*", "*");
+            },
+            "del" | "s" | "strike" => {
+                self.children_inline(id, markdown);
+                wrap(markdown, start, "~~", "~~");
+            },
+            "code" => {
+                let code = self.document.text_content(id).split_ascii_whitespace().collect::<Vec<_>>().join(" ");
+
+                if !code.is_empty() {
+                    let ticks = "`".repeat(longest_run(&code, '`') + 1);
+                    let pad = if code.starts_with('`') || code.ends_with('`') { " " } else { "" };
+
+                    markdown.push_str(&format!("{}{}{}{}{}", ticks, pad, code, pad, ticks));
+                }
+            },
+            "a" => {
+                self.children_inline(id, markdown);
+
+                if let Some(href) = element.attribute("href") {
+                    let url = self.url(href);
+
+                    if markdown[start..].trim() == url && url.contains(':') && !url.contains([' ', '<', '>']) {
+                        let text = markdown.split_off(start);
+
+                        markdown.push_str(&text.replace(text.trim(), &format!("<{}>", url)));
+                    } else {
+                        let close = format!("]({}{})", destination(&url), title(element));
+
+                        wrap(markdown, start, "[", &close);
+                    }
+                }
+            },
+            "img" => {
+                let mut alt = String::new();
+
+                push_text(element.attribute("alt").unwrap_or(""), &mut alt);
+
+                let src = self.url(element.attribute("src").unwrap_or(""));
+
+                markdown.push_str(&format!("![{}]({}{})", alt.trim(), destination(&src), title(element)));
+            },
+            _ => self.children_inline(id, markdown),
+        }
+    }
+
+    /// Resolves URL against base URL if there is one
+    fn url(&self, url: &str) -> String {
+        match self.base_url {
+            Some(ref base_url) => resolve_url(base_url, url.trim()),
+            None => url.trim().to_string(),
+        }
+    }
+}
+
+/// Returns name of HTML element, None for other nodes
+fn html_name(data: &NodeData) -> Option<&str> {
+    match *data {
+        NodeData::Element(ref element) if element.namespace == Namespace::Html => Some(&element.name),
+        _ => None,
+    }
+}
+
+/// Appends text escaping chars that mean something in Markdown and collapsing whitespace
+fn push_text(text: &str, markdown: &mut String) {
+    for ch in text.chars() {
+        if ch.is_ascii_whitespace() {
+            if !(markdown.is_empty() || markdown.ends_with([' ', '\n'])) {
+                markdown.push(' ');
+            }
+
+            continue;
+        }
+
+        if matches!(ch, '\\' | '`' | '*' | '_' | '[' | ']' | '<') {
+            markdown.push('\\');
+        }
+
+        markdown.push(ch);
+    }
+}
+
+/// Wraps Markdown appended after start into delimiters, spaces at the ends are kept outside as delimiters
+/// must be next to text
+fn wrap(markdown: &mut String, start: usize, open: &str, close: &str) {
+    let inner = markdown.split_off(start);
+    let trimmed = inner.trim_matches(' ');
+
+    if trimmed.is_empty() && open != "[" {
+        return markdown.push_str(&inner);
+    }
+
+    if inner.starts_with(' ') && !(markdown.is_empty() || markdown.ends_with([' ', '\n'])) {
+        markdown.push(' ');
+    }
+
+    markdown.push_str(open);
+    markdown.push_str(trimmed);
+    markdown.push_str(close);
+
+    if inner.ends_with(' ') {
+        markdown.push(' ');
+    }
+}
+
+/// Returns inline Markdown as paragraph: whitespace and hard breaks at the ends are removed and chars that
+/// would start other blocks at line starts are escaped
+fn paragraph(markdown: &str) -> String {
+    let mut text = markdown.trim_start();
+
+    loop {
+        let trimmed = text.trim_end_matches(' ');
+
+        match trimmed.strip_suffix("\\\n") {
+            Some(trimmed) => text = trimmed,
+            None => {
+                text = trimmed;
+                break;
+            },
+        }
+    }
+
+    let lines: Vec<String> = text.split('\n').map(|line| {
+        let line = line.trim_start();
+
+        match block_marker(line) {
+            Some(end) => format!("{}\\{}", &line[..end], &line[end..]),
+            None => line.to_string(),
+        }
+    }).collect();
+
+    lines.join("\n")
+}
+
+/// Returns position of char that has to be escaped if line would start block other than paragraph, ie heading,
+/// list item, blockquote, fence or thematic break
+fn block_marker(line: &str) -> Option<usize> {
+    let first = line.chars().next()?;
+    let run = line.len() - line.trim_start_matches(first).len();
+    let rest = &line[run..];
+    let ends_marker = rest.is_empty() || rest.starts_with(' ');
+
+    match first {
+        '>' => Some(0),
+        '#' if run <= 6 && ends_marker => Some(0),
+        '-' | '+' if run == 1 && ends_marker => Some(0),
+        '-' | '=' if rest.trim().is_empty() => Some(0),
+        '~' if run >= 3 => Some(0),
+        '0'..='9' => {
+            let digits = line.len() - line.trim_start_matches(|ch: char| ch.is_ascii_digit()).len();
+            let rest = &line[digits..];
+
+            if digits <= 9 && rest.starts_with(['.', ')']) && (rest.len() == 1 || rest[1..].starts_with(' ')) {
+                Some(digits)
+            } else {
+                None
+            }
+        },
+        _ => None,
+    }
+}
+
+fn push_paragraph(markdown: &str, blocks: &mut Vec<String>) {
+    let paragraph = paragraph(markdown);
+
+    if !paragraph.is_empty() {
+        blocks.push(paragraph);
+    }
+}
+
+/// Prefixes lines with prefix, empty lines get empty_prefix
+fn prefix_lines(text: &str, prefix: &str, empty_prefix: &str) -> String {
+    text.split('\n')
+        .map(|line| if line.is_empty() { empty_prefix.to_string() } else { format!("{}{}", prefix, line) })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Returns length of the longest run of char in text
+fn longest_run(text: &str, ch: char) -> usize {
+    text.split(|c| c != ch).map(|run| run.len()).max().unwrap_or(0)
+}
+
+/// Returns link destination, it is put between < and > if it has spaces or parentheses
+fn destination(url: &str) -> String {
+    if url.contains([' ', '(', ')', '<', '>']) {
+        format!("<{}>", url.replace('<', "%3C").replace('>', "%3E"))
+    } else {
+        url.to_string()
+    }
+}
+
+/// Returns title of link or image that goes after its destination, empty if there is no title
+fn title(element: &Element) -> String {
+    match element.attribute("title") {
+        Some(title) if !title.is_empty() => format!(" \"{}\"", title.replace('\\', "\\\\").replace('"', "\\\"")),
+        _ => String::new(),
+    }
+}
+
+/// Resolves URL reference against base URL following RFC 3986, ie ../a against http://x.org/b/c is
+/// http://x.org/a. Whitespace around both URLs is ignored
+pub fn resolve_url(base: &str, reference: &str) -> String {
+    // whitespace around URLs is not part of them, ie in href=" a "
+    let base = base.trim();
+    let reference = reference.trim();
+    let scheme_length = reference.find(':').filter(|&colon| {
+        colon > 0 && reference[..colon].chars().all(|ch| ch.is_ascii_alphanumeric() || "+-.".contains(ch))
+            && reference.starts_with(|ch: char| ch.is_ascii_alphabetic())
+    });
+
+    if scheme_length.is_some() {
+        return reference.to_string();
+    }
+
+    // base without fragment, then split into scheme, authority and path with query
+    let base = base.split('#').next().unwrap_or("");
+    let (scheme, rest) = match base.find(':') {
+        Some(colon) => (&base[..=colon], &base[colon + 1..]),
+        None => ("", base),
+    };
+
+    let (authority, path) = match rest.strip_prefix("//") {
+        Some(rest) => {
+            let end = rest.find(['/', '?']).unwrap_or(rest.len());
+
+            (&rest[..end], &rest[end..])
+        },
+        None => ("", rest),
+    };
+
+    let origin = if rest.starts_with("//") { format!("{}//{}", scheme, authority) } else { scheme.to_string() };
+    let path_only = path.split('?').next().unwrap_or("");
+
+    if reference.is_empty() {
+        return base.to_string();
+    }
+
+    if reference.starts_with("//") {
+        return format!("{}{}", scheme, reference);
+    }
+
+    if reference.starts_with('#') {
+        return format!("{}{}", base, reference);
+    }
+
+    if reference.starts_with('?') {
+        return format!("{}{}{}", origin, path_only, reference);
+    }
+
+    // dot segments are only removed from path of reference, not from its query or fragment
+    let path_end = reference.find(['?', '#']).unwrap_or(reference.len());
+    let (reference_path, suffix) = reference.split_at(path_end);
+
+    let merged = if reference_path.starts_with('/') {
+        reference_path.to_string()
+    } else if !authority.is_empty() && path_only.is_empty() {
+        format!("/{}", reference_path)
+    } else {
+        let directory = path_only.rfind('/').map_or("", |slash| &path_only[..=slash]);
+
+        format!("{}{}", directory, reference_path)
+    };
+
+    format!("{}{}{}", origin, remove_dot_segments(&merged), suffix)
+}
+
+/// Removes . and .. segments from path
+fn remove_dot_segments(path: &str) -> String {
+    let absolute = path.starts_with('/');
+    let parts: Vec<&str> = path.split('/').collect();
+    let mut segments: Vec<&str> = Vec::new();
+
+    for (i, &part) in parts.iter().enumerate() {
+        let last = i + 1 == parts.len();
+
+        match part {
+            "." | ".." => {
+                // root of absolute path is kept
+                if part == ".." && segments.len() > usize::from(absolute) {
+                    segments.pop();
+                }
+
+                if last {
+                    segments.push("");
+                }
+            },
+            _ => segments.push(part),
+        }
+    }
+
+    let path = segments.join("/");
+
+    if absolute && !path.starts_with('/') {
+        format!("/{}", path)
+    } else {
+        path
+    }
+}
@@ -0,0 +1,276 @@
+use std::fmt;
+
+/// Codes of parse errors that can be recorded while parsing HTML, names follow WHATWG tokenizer
+/// error codes so that they can be looked up in the spec. XML well-formedness errors are only
+/// reported in XML mode of HtmlParser
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ParseErrorCode {
+    /// Empty comment closed abruptly: <!--> or <!--->
+    AbruptClosingOfEmptyComment,
+
+    /// DOCTYPE public identifier is closed by > before its closing quote
+    AbruptDoctypePublicIdentifier,
+
+    /// DOCTYPE system identifier is closed by > before its closing quote
+    AbruptDoctypeSystemIdentifier,
+
+    /// Numeric entity without digits: &#; or &#x;
+    AbsenceOfDigitsInNumericCharacterReference,
+
+    /// XML: param without value, ie <option selected/>
+    AttributeWithoutValue,
+
+    /// CDATA section found outside of foreign content - treated as comment
+    CdataInHtmlContent,
+
+    /// Numeric entity above 0x10FFFF - it is replaced with U+FFFD
+    CharacterReferenceOutsideUnicodeRange,
+
+    /// Control character other than whitespace or 0 found in data
+    ControlCharacterInInputStream,
+
+    /// Numeric entity of control char, ie &#1; or &#x80; (C1 controls are remapped to windows-1252 chars)
+    ControlCharacterReference,
+
+    /// Param with the same name was already found in tag - it is dropped
+    DuplicateAttribute,
+
+    /// Closing tag has got params, ie </a href=x> - they are dropped
+    EndTagWithAttributes,
+
+    /// Closing tag ends with />
+    EndTagWithTrailingSolidus,
+
+    /// End of data reached right after < or </
+    EofBeforeTagName,
+
+    /// End of data reached inside of CDATA section
+    EofInCdata,
+
+    /// End of data reached inside of comment
+    EofInComment,
+
+    /// End of data reached inside of DOCTYPE
+    EofInDoctype,
+
+    /// XML: end of data reached inside of processing instruction
+    EofInProcessingInstruction,
+
+    /// End of data reached inside of <!-- in script
+    EofInScriptHtmlCommentLikeText,
+
+    /// End of data reached inside of tag
+    EofInTag,
+
+    /// Comment closed with --!> rather than -->
+    IncorrectlyClosedComment,
+
+    /// Markup declaration that is not comment, DOCTYPE or CDATA, ie <!foo> - treated as comment
+    IncorrectlyOpenedComment,
+
+    /// DOCTYPE name is followed by something other than PUBLIC or SYSTEM
+    InvalidCharacterSequenceAfterDoctypeName,
+
+    /// First char of tag name is not a letter, ie </1> that is treated as comment or < 1 that is treated as text
+    InvalidFirstCharacterOfTagName,
+
+    /// XML: end tag does not match element that is open, ie </a> in <a><b></a>
+    MismatchedEndTag,
+
+    /// Param has got = but no value, ie <a href=>
+    MissingAttributeValue,
+
+    /// DOCTYPE without name: <!DOCTYPE>
+    MissingDoctypeName,
+
+    /// PUBLIC keyword of DOCTYPE is not followed by identifier
+    MissingDoctypePublicIdentifier,
+
+    /// SYSTEM keyword of DOCTYPE is not followed by identifier
+    MissingDoctypeSystemIdentifier,
+
+    /// Closing tag without name: </> - it is ignored
+    MissingEndTagName,
+
+    /// DOCTYPE public identifier is not quoted
+    MissingQuoteBeforeDoctypePublicIdentifier,
+
+    /// DOCTYPE system identifier is not quoted
+    MissingQuoteBeforeDoctypeSystemIdentifier,
+
+    /// Entity is not terminated by ;
+    MissingSemicolonAfterCharacterReference,
+
+    /// PUBLIC keyword of DOCTYPE is directly followed by quote
+    MissingWhitespaceAfterDoctypePublicKeyword,
+
+    /// SYSTEM keyword of DOCTYPE is directly followed by quote
+    MissingWhitespaceAfterDoctypeSystemKeyword,
+
+    /// DOCTYPE keyword is directly followed by name: <!DOCTYPEhtml>
+    MissingWhitespaceBeforeDoctypeName,
+
+    /// Params are not separated by whitespace, ie <a x="1"y="2">
+    MissingWhitespaceBetweenAttributes,
+
+    /// DOCTYPE public and system identifiers are not separated by whitespace
+    MissingWhitespaceBetweenDoctypePublicAndSystemIdentifiers,
+
+    /// XML: element found after root element was closed
+    MultipleRootElements,
+
+    /// Comment start <!-- found inside of comment
+    NestedComment,
+
+    /// Numeric entity of noncharacter like &#xFFFE;
+    NoncharacterCharacterReference,
+
+    /// Noncharacter like U+FFFE found in data
+    NoncharacterInInputStream,
+
+    /// Numeric entity of 0: &#0; - it is replaced with U+FFFD
+    NullCharacterReference,
+
+    /// Numeric entity of surrogate like &#xD800; - it is replaced with U+FFFD
+    SurrogateCharacterReference,
+
+    /// XML: text other than whitespace found before or after root element
+    TextOutsideRootElement,
+
+    /// Something other than > follows DOCTYPE system identifier - it is ignored
+    UnexpectedCharacterAfterDoctypeSystemIdentifier,
+
+    /// Param name contains ", ' or <
+    UnexpectedCharacterInAttributeName,
+
+    /// Unquoted param value contains ", ', <, = or `
+    UnexpectedCharacterInUnquotedAttributeValue,
+
+    /// Param name starts with =
+    UnexpectedEqualsSignBeforeAttributeName,
+
+    /// Byte with value 0 found
+    UnexpectedNullCharacter,
+
+    /// Processing instruction like <?xml ?> found in HTML - treated as comment
+    UnexpectedQuestionMarkInsteadOfTagName,
+
+    /// / in tag that is not followed by >, ie <a / href=x>
+    UnexpectedSolidusInTag,
+
+    /// XML: element was not closed before end of data
+    UnclosedElement,
+
+    /// XML: prefix of tag or param name is not bound to namespace, ie og in <og:image>
+    UndeclaredNamespacePrefix,
+
+    /// XML: entity that is neither predefined nor declared in DOCTYPE, ie &nbsp;
+    UndefinedEntity,
+
+    /// XML: & that does not start entity, ie a & b
+    UnescapedAmpersand,
+
+    /// Entity with ; that is not known, ie &foo;
+    UnknownNamedCharacterReference,
+
+    /// XML: param value is not quoted, ie <a href=x>
+    UnquotedAttributeValue,
+}
+
+impl ParseErrorCode {
+    /// Returns WHATWG name of error code, ie "eof-in-comment"
+    pub fn code(&self) -> &'static str {
+        match *self {
+            ParseErrorCode::AbruptClosingOfEmptyComment => "abrupt-closing-of-empty-comment",
+            ParseErrorCode::AbruptDoctypePublicIdentifier => "abrupt-doctype-public-identifier",
+            ParseErrorCode::AbruptDoctypeSystemIdentifier => "abrupt-doctype-system-identifier",
+            ParseErrorCode::AbsenceOfDigitsInNumericCharacterReference => "absence-of-digits-in-numeric-character-reference",
+            ParseErrorCode::AttributeWithoutValue => "attribute-without-value",
+            ParseErrorCode::CdataInHtmlContent => "cdata-in-html-content",
+            ParseErrorCode::CharacterReferenceOutsideUnicodeRange => "character-reference-outside-unicode-range",
+            ParseErrorCode::ControlCharacterInInputStream => "control-character-in-input-stream",
+            ParseErrorCode::ControlCharacterReference => "control-character-reference",
+            ParseErrorCode::DuplicateAttribute => "duplicate-attribute",
+            ParseErrorCode::EndTagWithAttributes => "end-tag-with-attributes",
+            ParseErrorCode::EndTagWithTrailingSolidus => "end-tag-with-trailing-solidus",
+            ParseErrorCode::EofBeforeTagName => "eof-before-tag-name",
+            ParseErrorCode::EofInCdata => "eof-in-cdata",
+            ParseErrorCode::EofInComment => "eof-in-comment",
+            ParseErrorCode::EofInDoctype => "eof-in-doctype",
+            ParseErrorCode::EofInProcessingInstruction => "eof-in-processing-instruction",
+            ParseErrorCode::EofInScriptHtmlCommentLikeText => "eof-in-script-html-comment-like-text",
+            ParseErrorCode::EofInTag => "eof-in-tag",
+            ParseErrorCode::IncorrectlyClosedComment => "incorrectly-closed-comment",
+            ParseErrorCode::IncorrectlyOpenedComment => "incorrectly-opened-comment",
+            ParseErrorCode::InvalidCharacterSequenceAfterDoctypeName => "invalid-character-sequence-after-doctype-name",
+            ParseErrorCode::InvalidFirstCharacterOfTagName => "invalid-first-character-of-tag-name",
+            ParseErrorCode::MismatchedEndTag => "mismatched-end-tag",
+            ParseErrorCode::MissingAttributeValue => "missing-attribute-value",
+            ParseErrorCode::MissingDoctypeName => "missing-doctype-name",
+            ParseErrorCode::MissingDoctypePublicIdentifier => "missing-doctype-public-identifier",
+            ParseErrorCode::MissingDoctypeSystemIdentifier => "missing-doctype-system-identifier",
+            ParseErrorCode::MissingEndTagName => "missing-end-tag-name",
+            ParseErrorCode::MissingQuoteBeforeDoctypePublicIdentifier => "missing-quote-before-doctype-public-identifier",
+            ParseErrorCode::MissingQuoteBeforeDoctypeSystemIdentifier => "missing-quote-before-doctype-system-identifier",
+            ParseErrorCode::MissingSemicolonAfterCharacterReference => "missing-semicolon-after-character-reference",
+            ParseErrorCode::MissingWhitespaceAfterDoctypePublicKeyword => "missing-whitespace-after-doctype-public-keyword",
+            ParseErrorCode::MissingWhitespaceAfterDoctypeSystemKeyword => "missing-whitespace-after-doctype-system-keyword",
+            ParseErrorCode::MissingWhitespaceBeforeDoctypeName => "missing-whitespace-before-doctype-name",
+            ParseErrorCode::MissingWhitespaceBetweenAttributes => "missing-whitespace-between-attributes",
+            ParseErrorCode::MissingWhitespaceBetweenDoctypePublicAndSystemIdentifiers => "missing-whitespace-between-doctype-public-and-system-identifiers",
+            ParseErrorCode::MultipleRootElements => "multiple-root-elements",
+            ParseErrorCode::NestedComment => "nested-comment",
+            ParseErrorCode::NoncharacterCharacterReference => "noncharacter-character-reference",
+            ParseErrorCode::NoncharacterInInputStream => "noncharacter-in-input-stream",
+            ParseErrorCode::NullCharacterReference => "null-character-reference",
+            ParseErrorCode::SurrogateCharacterReference => "surrogate-character-reference",
+            ParseErrorCode::TextOutsideRootElement => "text-outside-root-element",
+            ParseErrorCode::UnexpectedCharacterAfterDoctypeSystemIdentifier => "unexpected-character-after-doctype-system-identifier",
+            ParseErrorCode::UnexpectedCharacterInAttributeName => "unexpected-character-in-attribute-name",
+            ParseErrorCode::UnexpectedCharacterInUnquotedAttributeValue => "unexpected-character-in-unquoted-attribute-value",
+            ParseErrorCode::UnexpectedEqualsSignBeforeAttributeName => "unexpected-equals-sign-before-attribute-name",
+            ParseErrorCode::UnexpectedNullCharacter => "unexpected-null-character",
+            ParseErrorCode::UnexpectedQuestionMarkInsteadOfTagName => "unexpected-question-mark-instead-of-tag-name",
+            ParseErrorCode::UnexpectedSolidusInTag => "unexpected-solidus-in-tag",
+            ParseErrorCode::UnclosedElement => "unclosed-element",
+            ParseErrorCode::UndeclaredNamespacePrefix => "undeclared-namespace-prefix",
+            ParseErrorCode::UndefinedEntity => "undefined-entity",
+            ParseErrorCode::UnescapedAmpersand => "unescaped-ampersand",
+            ParseErrorCode::UnknownNamedCharacterReference => "unknown-named-character-reference",
+            ParseErrorCode::UnquotedAttributeValue => "unquoted-attribute-value",
+        }
+    }
+}
+
+/// Parse error found in HTML
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ParseError {
+    /// What went wrong
+    pub code: ParseErrorCode,
+
+    /// Offset of byte in HTML data at which error was found
+    pub offset: usize,
+}
+
+impl ParseError {
+    pub fn new(code: ParseErrorCode, offset: usize) -> ParseError {
+        ParseError { code, offset }
+    }
+}
+
+/// Receives parse errors as soon as they are found, see HtmlParser::set_error_sink
+pub trait ParseErrorSink {
+    fn parse_error(&mut self, error: &ParseError);
+}
+
+impl<F: FnMut(&ParseError)> ParseErrorSink for F {
+    fn parse_error(&mut self, error: &ParseError) {
+        self(error)
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at offset {}", self.code.code(), self.offset)
+    }
+}
@@ -0,0 +1,222 @@
+use html_chunk::Namespace;
+use html_document::{Document, NodeId, NodeData};
+use serializer::{BLOCK_ELEMENTS, PRESERVE_WHITESPACE_ELEMENTS};
+
+/// Elements which content is not rendered
+pub const HIDDEN_ELEMENTS: [&str; 10] = ["head", "iframe", "noembed", "noframes", "noscript", "script", "style",
+    "template", "title", "xmp"];
+
+/// Returns readable text of children of node: block elements are on their own lines, paragraphs are separated
+/// by empty lines, list items get bullets or numbers and cells of table rows are separated by tabs. Whitespace
+/// is collapsed as browsers do it, except in pre and textarea
+pub fn to_text(document: &Document, id: NodeId) -> String {
+    let mut builder = TextBuilder {
+        text: String::new(),
+        space: false,
+        line_breaks: 0,
+        lists: Vec::new(),
+        row_has_cells: false,
+    };
+
+    // items of list get numbers even if only its content is converted
+    if let Some(list) = list(document, id) {
+        builder.lists.push(list);
+    }
+
+    builder.children(document, id);
+
+    builder.text
+}
+
+/// Returns number of the first item and step to the next one for ordered list element, None for unordered one
+/// and None in option if element is not list. Reversed list counts down from the number of its items by default
+fn list(document: &Document, id: NodeId) -> Option<Option<(i64, i64)>> {
+    let element = document[id].as_element()?;
+
+    if element.namespace != Namespace::Html || document.is_xml() {
+        return None;
+    }
+
+    match element.name.as_str() {
+        "ol" => {
+            let start = element.attribute("start").and_then(|start| start.trim().parse().ok());
+
+            if element.attribute("reversed").is_some() {
+                Some(Some((start.unwrap_or_else(|| list_items(document, id)), -1)))
+            } else {
+                Some(Some((start.unwrap_or(1), 1)))
+            }
+        },
+        "ul" | "menu" => Some(None),
+        _ => None,
+    }
+}
+
+/// Returns number of list items that get numbers of list, ie its li descendants that are not in nested lists
+fn list_items(document: &Document, id: NodeId) -> i64 {
+    document.child_elements(id).map(|child| {
+        match document[child].name() {
+            Some("li") => 1 + list_items(document, child),
+            _ if list(document, child).is_some() => 0,
+            _ => list_items(document, child),
+        }
+    }).sum()
+}
+
+/// Builds text keeping track of whitespace that is only added when text follows it
+struct TextBuilder {
+    text: String,
+
+    /// True if space goes before next text
+    space: bool,
+
+    /// Number of line breaks before next text
+    line_breaks: usize,
+
+    /// Numbers of next items of lists that are open and steps between them, None for unordered ones
+    lists: Vec<Option<(i64, i64)>>,
+
+    /// True if cell of current table row was added
+    row_has_cells: bool,
+}
+
+impl TextBuilder {
+    fn node(&mut self, document: &Document, id: NodeId) {
+        let element = match document[id].data {
+            NodeData::Text(ref text) => return self.text(text),
+            NodeData::Element(ref element) => element,
+            _ => return,
+        };
+
+        // elements of XML documents have no layout even if their names are the same as in HTML
+        let name = match element.namespace {
+            Namespace::Html if !document.is_xml() => element.name.as_str(),
+            _ => "",
+        };
+
+        if HIDDEN_ELEMENTS.contains(&name) {
+            return;
+        }
+
+        match name {
+            "br" => {
+                self.space = false;
+                self.start_text();
+                self.text.push('\n');
+            },
+            "p" => {
+                self.break_lines(2);
+                self.children(document, id);
+                self.break_lines(2);
+            },
+            "ol" | "ul" | "menu" => {
+                self.lists.push(list(document, id).unwrap_or_default());
+                self.break_lines(1);
+                self.children(document, id);
+                self.break_lines(1);
+                self.lists.pop();
+            },
+            "li" => {
+                self.break_lines(1);
+                self.list_item_marker();
+                self.children(document, id);
+                self.break_lines(1);
+            },
+            "tr" => {
+                // rows of tables in cells have their own cells
+                let row_has_cells = self.row_has_cells;
+
+                self.row_has_cells = false;
+                self.break_lines(1);
+                self.children(document, id);
+                self.break_lines(1);
+                self.row_has_cells = row_has_cells;
+            },
+            "td" | "th" => {
+                // cells after the first one in row are separated by tab
+                if self.row_has_cells {
+                    self.space = false;
+                    self.start_text();
+                    self.text.push('\t');
+                }
+
+                self.row_has_cells = true;
+                self.children(document, id);
+            },
+            "textarea" => {
+                self.start_text();
+                self.text.push_str(&document.text_content(id));
+            },
+            _ if PRESERVE_WHITESPACE_ELEMENTS.contains(&name) => {
+                self.break_lines(1);
+                self.start_text();
+                self.text.push_str(&document.text_content(id));
+                self.break_lines(1);
+            },
+            _ if BLOCK_ELEMENTS.contains(&name) => {
+                self.break_lines(1);
+                self.children(document, id);
+                self.break_lines(1);
+            },
+            _ => self.children(document, id),
+        }
+    }
+
+    fn children(&mut self, document: &Document, id: NodeId) {
+        for child in document.children(id) {
+            self.node(document, child);
+        }
+    }
+
+    /// Appends text collapsing its whitespace
+    fn text(&mut self, text: &str) {
+        for ch in text.chars() {
+            if ch.is_ascii_whitespace() {
+                self.space = true;
+            } else {
+                self.start_text();
+                self.text.push(ch);
+            }
+        }
+    }
+
+    /// Adds line breaks or space that go before next text, whitespace at the start is dropped
+    fn start_text(&mut self) {
+        if !self.text.is_empty() {
+            if self.line_breaks > 0 {
+                for _ in 0..self.line_breaks {
+                    self.text.push('\n');
+                }
+            } else if self.space && !self.text.ends_with(['\n', '\t']) {
+                self.text.push(' ');
+            }
+        }
+
+        self.line_breaks = 0;
+        self.space = false;
+    }
+
+    /// Makes sure that next text is at least given number of line breaks after text before
+    fn break_lines(&mut self, count: usize) {
+        let trailing = self.text.len() - self.text.trim_end_matches('\n').len();
+
+        self.line_breaks = self.line_breaks.max(count.saturating_sub(trailing));
+        self.space = false;
+    }
+
+    /// Appends bullet or number of list item indented by depth of its list
+    fn list_item_marker(&mut self) {
+        let depth = self.lists.len().saturating_sub(1);
+
+        self.start_text();
+        self.text.push_str(&"  ".repeat(depth));
+
+        match self.lists.last_mut() {
+            Some(&mut Some((ref mut number, step))) => {
+                self.text.push_str(&format!("{}. ", number));
+                *number += step;
+            },
+            _ => self.text.push_str("* "),
+        }
+    }
+}
@@ -0,0 +1,581 @@
+use std::io::{self, Write};
+
+use html_chunk::{HtmlChunk, ChunkType, Namespace};
+use html_document::Attribute;
+use html_parser::HtmlParser;
+use selector::{StreamingSelector, SelectorError};
+use document_builder::{element_start, implied_end};
+use serializer;
+
+/// How content that handlers insert is written
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ContentType {
+    /// Content is HTML that is written as it is
+    Html,
+
+    /// Content is text that is escaped
+    Text,
+}
+
+/// Handler that is called for elements matched by selector
+pub type ElementHandler<'a> = Box<dyn FnMut(&mut RewriteElement) + 'a>;
+
+/// Rewrites HTML while HtmlParser parses it: handlers registered for selectors can change attributes of
+/// matched elements, insert content around them or remove them. Output is written as chunks are parsed
+/// without building document, HTML that handlers did not touch is copied byte for byte. Start tags close
+/// elements like p or li implicitly just like in StreamingSelector. HTML can be given in parts by stream, so
+/// that only the chunk that is parsed at the moment is kept in memory.
+///
+/// Selectors can only relate element to its ancestors, see StreamingSelector
+pub struct HtmlRewriter<'a> {
+    handlers: Vec<(StreamingSelector, ElementHandler<'a>)>,
+}
+
+/// Element that handler of HtmlRewriter was called for, changes are applied once all handlers that
+/// matched it were called
+pub struct RewriteElement {
+    tag: String,
+    attributes: Vec<Attribute>,
+    attributes_changed: bool,
+
+    /// False for void and self-closing elements
+    can_have_content: bool,
+    before: String,
+    prepend: String,
+
+    /// Content that replaces content of element
+    inner_content: Option<String>,
+    append: String,
+    after: String,
+
+    /// True if start and end tags are not written
+    tags_removed: bool,
+}
+
+impl RewriteElement {
+    /// Returns (lowercased) tag name
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    /// Returns attributes in the order they appeared in HTML, entities in values are decoded
+    pub fn attributes(&self) -> &[Attribute] {
+        &self.attributes
+    }
+
+    /// Returns value of attribute, None if there is no such attribute
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|attribute| attribute.name == name).map(|attribute| attribute.value.as_str())
+    }
+
+    /// Sets value of attribute, start tag is generated again with all values quoted
+    pub fn set_attribute(&mut self, name: &str, value: &str) {
+        self.attributes_changed = true;
+
+        match self.attributes.iter_mut().find(|attribute| attribute.name == name) {
+            Some(attribute) => attribute.value = value.to_string(),
+            None => self.attributes.push(Attribute {
+                name: name.to_string(),
+                value: value.to_string(),
+            }),
+        }
+    }
+
+    /// Removes attribute returning its value, start tag is generated again if it was there
+    pub fn remove_attribute(&mut self, name: &str) -> Option<String> {
+        let pos = self.attributes.iter().position(|attribute| attribute.name == name)?;
+
+        self.attributes_changed = true;
+
+        Some(self.attributes.remove(pos).value)
+    }
+
+    /// Returns false for void and self-closing elements, content can not be inserted into them
+    pub fn can_have_content(&self) -> bool {
+        self.can_have_content
+    }
+
+    /// Inserts content before start tag
+    pub fn before(&mut self, content: &str, content_type: ContentType) {
+        push_content(&mut self.before, content, content_type);
+    }
+
+    /// Inserts content right after start tag
+    pub fn prepend(&mut self, content: &str, content_type: ContentType) {
+        push_content(&mut self.prepend, content, content_type);
+    }
+
+    /// Inserts content right before end tag
+    pub fn append(&mut self, content: &str, content_type: ContentType) {
+        push_content(&mut self.append, content, content_type);
+    }
+
+    /// Inserts content after end tag (after start tag if element has no content)
+    pub fn after(&mut self, content: &str, content_type: ContentType) {
+        let mut after = String::new();
+
+        // the last inserted content goes right after element
+        push_content(&mut after, content, content_type);
+        self.after.insert_str(0, &after);
+    }
+
+    /// Replaces content of element, content inserted by prepend and append stays
+    pub fn set_inner_content(&mut self, content: &str, content_type: ContentType) {
+        let mut inner_content = String::new();
+
+        push_content(&mut inner_content, content, content_type);
+        self.inner_content = Some(inner_content);
+    }
+
+    /// Replaces element along with its content
+    pub fn replace(&mut self, content: &str, content_type: ContentType) {
+        self.remove();
+        push_content(&mut self.before, content, content_type);
+    }
+
+    /// Removes element along with its content, content inserted by before and after stays
+    pub fn remove(&mut self) {
+        self.tags_removed = true;
+        self.inner_content = Some(String::new());
+        self.prepend.clear();
+        self.append.clear();
+    }
+
+    /// Removes start and end tags of element keeping its content
+    pub fn remove_and_keep_content(&mut self) {
+        self.tags_removed = true;
+    }
+}
+
+fn push_content(target: &mut String, content: &str, content_type: ContentType) {
+    match content_type {
+        ContentType::Html => target.push_str(content),
+        ContentType::Text => serializer::escape_text(content, target),
+    }
+}
+
+/// Element that is open while HTML is rewritten
+struct OpenElement {
+    name: String,
+
+    /// True for elements in HTML namespace, only their end tags are implied
+    html: bool,
+
+    /// Content to write before end tag
+    append: String,
+
+    /// Content to write after end tag
+    after: String,
+
+    /// True if original content of element is not written
+    skip_content: bool,
+
+    /// True if end tag is not written
+    skip_end_tag: bool,
+}
+
+/// Writes output of rewriter copying HTML between chunks that were not changed
+struct Output<W: Write> {
+    writer: W,
+
+    /// Position in HTML up to which it was written or skipped
+    pos: usize,
+
+    /// Number of open elements which original content is not written, nothing is written while it is not 0
+    skipping: usize,
+}
+
+impl<W: Write> Output<W> {
+    /// Copies HTML data up to given position
+    fn copy_to(&mut self, html: &[u8], to: usize) -> io::Result<()> {
+        if self.skipping == 0 && to > self.pos {
+            self.writer.write_all(&html[self.pos..to])?;
+        }
+
+        self.pos = self.pos.max(to);
+
+        Ok(())
+    }
+
+    /// Skips HTML up to given position
+    fn skip_to(&mut self, to: usize) {
+        self.pos = self.pos.max(to);
+    }
+
+    /// Writes content that was not in HTML
+    fn write(&mut self, content: &str) -> io::Result<()> {
+        if self.skipping == 0 {
+            self.writer.write_all(content.as_bytes())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// State of rewriting that is kept between chunks
+struct RewriteState<W: Write> {
+    output: Output<W>,
+    open_elements: Vec<OpenElement>,
+    xml_mode: bool,
+}
+
+impl<W: Write> RewriteState<W> {
+    fn new(writer: W, xml_mode: bool) -> RewriteState<W> {
+        RewriteState {
+            output: Output {
+                writer,
+                pos: 0,
+                skipping: 0,
+            },
+            open_elements: Vec::new(),
+            xml_mode,
+        }
+    }
+
+    /// Closes elements that are still open and copies the rest of HTML
+    fn finish(&mut self, html: &[u8]) -> io::Result<()> {
+        while let Some(open) = self.open_elements.pop() {
+            finish_element(&mut self.output, html, open, html.len())?;
+        }
+
+        self.output.copy_to(html, html.len())
+    }
+}
+
+/// Rewrites HTML that is given in parts, see HtmlRewriter::stream. Only the last incomplete chunk (ie text
+/// or tag that may continue in the next part) is kept in memory, output for chunks before it is written
+/// as soon as they are parsed
+pub struct RewriteStream<'r, 'a: 'r, W: Write> {
+    rewriter: &'r mut HtmlRewriter<'a>,
+    parser: &'r mut HtmlParser,
+    state: RewriteState<W>,
+}
+
+impl<'r, 'a, W: Write> RewriteStream<'r, 'a, W> {
+    /// Rewrites the next part of HTML, it may end anywhere, even in the middle of UTF-8 sequence
+    pub fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        let dropped = self.parser.push_bytes(data);
+
+        // output is never past the chunks that were parsed, so it is not in dropped data
+        self.state.output.pos -= dropped;
+
+        self.rewriter.rewrite_chunks(self.parser, &mut self.state)
+    }
+
+    /// Rewrites what is left once all parts were written, returns writer
+    pub fn end(mut self) -> io::Result<W> {
+        self.parser.more_data = false;
+        self.rewriter.rewrite_chunks(self.parser, &mut self.state)?;
+        self.state.finish(self.parser.html_bytes().unwrap_or_default())?;
+
+        Ok(self.state.output.writer)
+    }
+}
+
+impl<'a> HtmlRewriter<'a> {
+    pub fn new() -> HtmlRewriter<'a> {
+        HtmlRewriter {
+            handlers: Vec::new(),
+        }
+    }
+
+    /// Registers handler that is called for elements that match selector, handlers are called in order
+    /// they were registered
+    pub fn on<F>(&mut self, selector: &str, handler: F) -> Result<(), SelectorError>
+        where F: FnMut(&mut RewriteElement) + 'a {
+        self.handlers.push((StreamingSelector::new(selector)?, Box::new(handler)));
+
+        Ok(())
+    }
+
+    /// Rewrites HTML returning the result
+    pub fn rewrite_to_string(&mut self, html: &str) -> String {
+        let mut output = Vec::with_capacity(html.len());
+
+        // writing to vector never fails, handlers only insert strings, so output is valid UTF-8
+        self.rewrite(html, &mut output).expect("writing to vector never fails");
+
+        String::from_utf8(output).expect("output is made of strings")
+    }
+
+    /// Rewrites HTML writing the result to writer, entities are decoded in attribute values that handlers get
+    pub fn rewrite<W: Write>(&mut self, html: &str, writer: &mut W) -> io::Result<()> {
+        let mut parser = HtmlParser::new();
+
+        parser.decode_entities = true;
+        parser.init(html);
+
+        self.rewrite_from_parser(&mut parser, writer)
+    }
+
+    /// Rewrites HTML that parser was initialised with writing the result to writer, HTML that was not changed
+    /// is copied from data of parser, so it keeps its encoding while inserted content is UTF-8. Options of
+    /// parser matter, ie in XML mode there are no void elements and selectors compare names case-sensitively
+    pub fn rewrite_from_parser<W: Write>(&mut self, parser: &mut HtmlParser, writer: &mut W) -> io::Result<()> {
+        let mut state = RewriteState::new(writer, parser.xml_mode);
+
+        self.clear_selectors(parser.xml_mode);
+        self.rewrite_chunks(parser, &mut state)?;
+
+        state.finish(parser.html_bytes().unwrap_or_default())
+    }
+
+    /// Starts rewriting of HTML that is given in parts by write of returned stream, parts are parsed by parser
+    /// which is initialised by the first of them and the result is written to writer as they are parsed.
+    /// Options of parser matter just like for rewrite_from_parser, but conformance mode is not supported
+    pub fn stream<'r, W: Write>(&'r mut self, parser: &'r mut HtmlParser, writer: W) -> RewriteStream<'r, 'a, W> {
+        parser.clean_up();
+        parser.more_data = true;
+        self.clear_selectors(parser.xml_mode);
+
+        RewriteStream {
+            rewriter: self,
+            state: RewriteState::new(writer, parser.xml_mode),
+            parser,
+        }
+    }
+
+    fn clear_selectors(&mut self, xml_mode: bool) {
+        for &mut (ref mut selector, _) in self.handlers.iter_mut() {
+            selector.clear();
+            selector.xml_mode = xml_mode;
+        }
+    }
+
+    /// Rewrites chunks that parser returns until it returns None
+    fn rewrite_chunks<W: Write>(&mut self, parser: &mut HtmlParser, state: &mut RewriteState<W>) -> io::Result<()> {
+        let xml_mode = state.xml_mode;
+        let output = &mut state.output;
+        let open_elements = &mut state.open_elements;
+
+        loop {
+            // what is needed from chunk is taken, so data of parser can be borrowed below
+            let (start, end, chunk_type, tag, html_element, end_closure, element_start) = {
+                let chunk = match parser.parse_next() {
+                    Some(chunk) => chunk,
+                    None => break,
+                };
+
+                let mut element: Option<RewriteElement> = None;
+
+                for &mut (ref mut selector, ref mut handler) in self.handlers.iter_mut() {
+                    let matched = selector.process(chunk);
+
+                    // spans of matches are not needed, so they do not pile up
+                    selector.take_matches();
+
+                    if matched {
+                        handler(element.get_or_insert_with(|| rewrite_element(chunk, xml_mode)));
+                    }
+                }
+
+                let element_start = element_start(chunk, xml_mode).map(|closed| {
+                    (closed, element.unwrap_or_else(|| rewrite_element(chunk, xml_mode)))
+                });
+
+                (chunk.chunk_offset, chunk.chunk_offset + chunk.chunk_length, chunk.chunk_type, chunk.tag.clone(),
+                    !xml_mode && chunk.namespace == Namespace::Html, chunk.end_closure, element_start)
+            };
+
+            let html = parser.html_bytes().unwrap_or_default();
+
+            let (closed, element) = match element_start {
+                Some(element_start) => element_start,
+                None => {
+                    if chunk_type == ChunkType::CloseTag {
+                        close_element(output, html, open_elements, &tag, start, end)?;
+                    } else {
+                        output.copy_to(html, end)?;
+                    }
+
+                    continue;
+                },
+            };
+
+            // start tag may close elements like p or li just like in StreamingSelector
+            if let Some(pos) = implied_end(open_elements, &tag, html_name).filter(|_| html_element) {
+                while open_elements.len() > pos {
+                    if let Some(open) = open_elements.pop() {
+                        output.copy_to(html, start)?;
+                        finish_element(output, html, open, start)?;
+                    }
+                }
+            }
+
+            output.copy_to(html, start)?;
+
+            // script has got its content and end tag in the same chunk
+            let (tag_end, end_tag_start) = if chunk_type == ChunkType::Script {
+                let (tag_end, end_tag_start) = script_parts(&html[start..end]);
+
+                (start + tag_end, start + end_tag_start)
+            } else {
+                (end, end)
+            };
+
+            output.write(&element.before)?;
+
+            if element.tags_removed {
+                output.skip_to(tag_end);
+            } else if element.attributes_changed {
+                let mut start_tag = String::new();
+
+                serializer::start_tag(&element.tag, element.attributes.iter().map(|attribute| {
+                    (attribute.name.as_str(), attribute.value.as_str())
+                }), &mut start_tag);
+
+                if end_closure && !element.can_have_content {
+                    start_tag.insert(start_tag.len() - 1, '/');
+                }
+
+                output.write(&start_tag)?;
+                output.skip_to(tag_end);
+            } else {
+                output.copy_to(html, tag_end)?;
+            }
+
+            let mut open = OpenElement {
+                name: tag,
+                html: html_element,
+                append: element.append,
+                after: element.after,
+                skip_content: element.inner_content.is_some(),
+                skip_end_tag: element.tags_removed,
+            };
+
+            if element.can_have_content {
+                output.write(&element.prepend)?;
+                output.write(element.inner_content.as_deref().unwrap_or(""))?;
+            } else {
+                open.append.clear();
+                open.skip_content = false;
+            }
+
+            if open.skip_content {
+                output.skipping += 1;
+            }
+
+            if closed {
+                if open.skip_content {
+                    output.skip_to(end_tag_start);
+                } else {
+                    output.copy_to(html, end_tag_start)?;
+                }
+
+                finish_element(output, html, open, end)?;
+            } else {
+                open_elements.push(open);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> Default for HtmlRewriter<'a> {
+    fn default() -> HtmlRewriter<'a> {
+        HtmlRewriter::new()
+    }
+}
+
+/// Returns name of open HTML element, other elements have empty name as end tags are not implied for them
+fn html_name(open: &OpenElement) -> &str {
+    if open.html {
+        &open.name
+    } else {
+        ""
+    }
+}
+
+/// Creates element for handlers from chunk that starts it
+fn rewrite_element(chunk: &HtmlChunk, xml_mode: bool) -> RewriteElement {
+    let attributes = (0..chunk.params_count)
+        .map(|i| Attribute {
+            name: chunk.param_names[i].clone(),
+            value: chunk.param_values[i].clone(),
+        })
+        .collect();
+
+    RewriteElement {
+        tag: chunk.tag.clone(),
+        attributes,
+        attributes_changed: false,
+        can_have_content: chunk.chunk_type == ChunkType::Script || element_start(chunk, xml_mode) == Some(false),
+        before: String::new(),
+        prepend: String::new(),
+        inner_content: None,
+        append: String::new(),
+        after: String::new(),
+        tags_removed: false,
+    }
+}
+
+/// Closes the nearest open element with given name by end tag between start and end along with elements
+/// opened after it that are closed implicitly. End tag is copied if there is no such element
+fn close_element<W: Write>(output: &mut Output<W>, html: &[u8], open_elements: &mut Vec<OpenElement>, name: &str,
+    start: usize, end: usize) -> io::Result<()> {
+    let pos = match open_elements.iter().rposition(|open| open.name == name) {
+        Some(pos) => pos,
+        None => return output.copy_to(html, end),
+    };
+
+    while open_elements.len() > pos + 1 {
+        if let Some(open) = open_elements.pop() {
+            output.copy_to(html, start)?;
+            finish_element(output, html, open, start)?;
+        }
+    }
+
+    match open_elements.pop() {
+        Some(open) => {
+            output.copy_to(html, start)?;
+            finish_element(output, html, open, end)
+        },
+        None => Ok(()),
+    }
+}
+
+/// Writes what handlers inserted at the end of element and its end tag that ends at given position,
+/// output must be at start of end tag
+fn finish_element<W: Write>(output: &mut Output<W>, html: &[u8], open: OpenElement, end: usize) -> io::Result<()> {
+    if open.skip_content {
+        output.skipping -= 1;
+    }
+
+    output.write(&open.append)?;
+
+    if open.skip_end_tag {
+        output.skip_to(end);
+    } else {
+        output.copy_to(html, end)?;
+    }
+
+    output.write(&open.after)
+}
+
+/// Returns end of start tag and start of end tag in HTML of script chunk
+fn script_parts(bytes: &[u8]) -> (usize, usize) {
+    let mut quote = None;
+    let mut tag_end = bytes.len();
+
+    for (i, &ch) in bytes.iter().enumerate() {
+        match quote {
+            Some(quote_ch) if ch == quote_ch => quote = None,
+            Some(_) => {},
+            None if ch == b'"' || ch == b'\'' => quote = Some(ch),
+            None if ch == b'>' => {
+                tag_end = i + 1;
+                break;
+            },
+            None => {},
+        }
+    }
+
+    let end_tag_start = match bytes.windows(2).rposition(|window| window == b"</") {
+        Some(pos) if pos >= tag_end && bytes[pos + 2..].len() >= 6 && bytes[pos + 2..pos + 8]
+            .eq_ignore_ascii_case(b"script") => pos,
+        _ => bytes.len(),
+    };
+
+    (tag_end, end_tag_start)
+}
@@ -0,0 +1,1206 @@
+use std::fmt;
+
+use html_chunk::{HtmlChunk, ChunkType, Namespace};
+use html_document::{Document, NodeId, Element};
+use document_builder::{element, element_start, implied_end};
+
+/// Pseudo-classes of user interaction, they never match as there is no user to interact with document
+const DYNAMIC_PSEUDO_CLASSES: [&str; 7] = ["active", "focus", "focus-visible", "focus-within", "hover", "target",
+    "visited"];
+
+/// Pseudo-elements that may be written with single colon, they are not supported
+const LEGACY_PSEUDO_ELEMENTS: [&str; 4] = ["after", "before", "first-letter", "first-line"];
+
+/// Pseudo-classes that depend on siblings, content or descendants of element, StreamingSelector can not
+/// match them as it only knows ancestors
+const NON_STREAMING_PSEUDO_CLASSES: [&str; 12] = ["empty", "first-child", "first-of-type", "has", "last-child",
+    "last-of-type", "nth-child", "nth-last-child", "nth-last-of-type", "nth-of-type", "only-child", "only-of-type"];
+
+/// Error found in selector
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SelectorError {
+    /// What went wrong, ie "unknown pseudo-class :foo"
+    pub message: String,
+
+    /// Offset of byte in selector at which error was found
+    pub offset: usize,
+}
+
+impl fmt::Display for SelectorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at offset {}", self.message, self.offset)
+    }
+}
+
+/// CSS selector: Selectors Level 3 along with :is, :where, :not with lists, :has and :nth-child(an+b of S)
+/// of Level 4. Pseudo-classes of user interaction like :hover never match, pseudo-elements are not supported
+#[derive(Clone, PartialEq, Debug)]
+pub struct Selector {
+    list: Vec<Complex>,
+}
+
+/// How elements matched by adjacent compound selectors are related
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Combinator {
+    /// Whitespace, ie a b
+    Descendant,
+
+    /// a > b
+    Child,
+
+    /// a + b
+    NextSibling,
+
+    /// a ~ b
+    SubsequentSibling,
+}
+
+/// Compound selectors joined by combinators, ie div > p.note
+#[derive(Clone, PartialEq, Debug)]
+struct Complex {
+    /// Combinator that relates the first compound selector to anchor element, only used by :has
+    leading: Combinator,
+
+    compounds: Vec<Compound>,
+
+    /// Combinator at index i is between compound selectors at i and i + 1
+    combinators: Vec<Combinator>,
+}
+
+/// Conditions that single element must satisfy, ie p.note[title]
+#[derive(Clone, PartialEq, Debug)]
+struct Compound {
+    /// Tag name, None for * or when there is no type selector
+    tag: Option<String>,
+
+    conditions: Vec<Condition>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Condition {
+    Id(String),
+    Class(String),
+    Attribute(AttributeCondition),
+    Pseudo(PseudoClass),
+}
+
+/// Attribute selector, ie [href^="https:" i]
+#[derive(Clone, PartialEq, Debug)]
+struct AttributeCondition {
+    name: String,
+
+    /// Operator and value, None if attribute only has to be present
+    operator: Option<(AttributeOperator, String)>,
+
+    /// True if value is compared ignoring ASCII case
+    ignore_case: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum AttributeOperator {
+    /// [a=v]
+    Equals,
+
+    /// [a~=v]: one of whitespace separated words is v
+    Includes,
+
+    /// [a|=v]: v or v followed by -
+    DashMatch,
+
+    /// [a^=v]
+    Prefix,
+
+    /// [a$=v]
+    Suffix,
+
+    /// [a*=v]
+    Substring,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum PseudoClass {
+    Root,
+    Empty,
+    FirstChild,
+    LastChild,
+    OnlyChild,
+    FirstOfType,
+    LastOfType,
+    OnlyOfType,
+
+    /// :nth-child(an+b of S), index is counted among siblings that match S if it is given
+    NthChild(Nth, Option<Vec<Complex>>),
+    NthLastChild(Nth, Option<Vec<Complex>>),
+    NthOfType(Nth),
+    NthLastOfType(Nth),
+
+    Not(Vec<Complex>),
+
+    /// :is and :where, they only differ in specificity
+    Is(Vec<Complex>),
+    Has(Vec<Complex>),
+    Lang(Vec<String>),
+    Link,
+    Checked,
+    Enabled,
+    Disabled,
+
+    /// Pseudo-classes of user interaction like :hover
+    Never,
+}
+
+/// an+b expression of :nth-child and similar pseudo-classes
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Nth {
+    a: i64,
+    b: i64,
+}
+
+impl Nth {
+    /// Returns true if 1-based index is an+b for some non-negative n
+    fn matches(&self, index: i64) -> bool {
+        if self.a == 0 {
+            return index == self.b;
+        }
+
+        let diff = index - self.b;
+
+        diff % self.a == 0 && diff / self.a >= 0
+    }
+}
+
+impl Selector {
+    /// Parses selector or comma separated list of them
+    pub fn parse(selector: &str) -> Result<Selector, SelectorError> {
+        SelectorParser::new(selector, false).parse()
+    }
+
+    /// Returns true if element matches selector, other nodes never match
+    pub fn matches(&self, document: &Document, id: NodeId) -> bool {
+        matches_list(document, id, &self.list)
+    }
+
+    /// Returns elements below scope node (not including it) that match selector in document order
+    pub fn select(&self, document: &Document, scope: NodeId) -> Vec<NodeId> {
+        document.descendants(scope).filter(|&id| self.matches(document, id)).collect()
+    }
+}
+
+/// Matches selector while HtmlParser returns chunks without building document, so it works for pages of
+/// any size. Elements are balanced by stack matching with end tags that HTML lets to omit implied, ie li
+/// closes open li and div closes open p, so ancestors are mostly the ones browsers would see. Selectors can
+/// only relate element to its ancestors: sibling combinators, :has and pseudo-classes like :first-child or
+/// :empty are rejected
+pub struct StreamingSelector {
+    /// If true then chunks are XML: there are no void elements
+    pub xml_mode: bool,
+
+    selector: Selector,
+
+    /// Elements that were opened but not closed yet
+    open_elements: Vec<OpenElement>,
+
+    /// Number of open elements that matched selector
+    open_matches: usize,
+
+    /// Matched elements that were closed but not taken yet
+    matches: Vec<SelectorMatch>,
+}
+
+/// Element that was opened in StreamingSelector
+struct OpenElement {
+    element: Element,
+
+    /// Offset of its open tag
+    offset: usize,
+
+    matched: bool,
+}
+
+/// Returns name of open HTML element, other elements have empty name as end tags are not implied for them
+fn html_name(open: &OpenElement) -> &str {
+    match open.element.namespace {
+        Namespace::Html => &open.element.name,
+        _ => "",
+    }
+}
+
+/// Element that StreamingSelector matched, start and end are offsets of chunks in HTML
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SelectorMatch {
+    /// Tag name of element
+    pub tag: String,
+
+    /// Offset of its open tag
+    pub start: usize,
+
+    /// Offset right after its end tag, or offset of chunk that closed it implicitly (end of data for
+    /// elements that were never closed)
+    pub end: usize,
+}
+
+impl StreamingSelector {
+    /// Parses selector that only relates element to its ancestors
+    pub fn new(selector: &str) -> Result<StreamingSelector, SelectorError> {
+        Ok(StreamingSelector {
+            xml_mode: false,
+            selector: SelectorParser::new(selector, true).parse()?,
+            open_elements: Vec::new(),
+            open_matches: 0,
+            matches: Vec::new(),
+        })
+    }
+
+    /// Forgets elements of the previous document
+    pub fn clear(&mut self) {
+        self.open_elements.clear();
+        self.open_matches = 0;
+        self.matches.clear();
+    }
+
+    /// Processes chunk that parser returned, returns true if it starts element that matches selector.
+    /// Matched elements that it closed can be taken by take_matches
+    pub fn process(&mut self, chunk: &HtmlChunk) -> bool {
+        let chunk_end = chunk.chunk_offset + chunk.chunk_length;
+
+        let closed = match element_start(chunk, self.xml_mode) {
+            Some(closed) => closed,
+            None => {
+                if chunk.chunk_type == ChunkType::CloseTag {
+                    self.close_element(&chunk.tag, chunk.chunk_offset, chunk_end);
+                }
+
+                return false;
+            },
+        };
+
+        if !self.xml_mode && chunk.namespace == Namespace::Html {
+            if let Some(pos) = implied_end(&self.open_elements, &chunk.tag, html_name) {
+                while self.open_elements.len() > pos {
+                    self.pop_element(chunk.chunk_offset);
+                }
+            }
+        }
+
+        self.open_elements.push(OpenElement {
+            element: element(chunk),
+            offset: chunk.chunk_offset,
+            matched: false,
+        });
+
+        let matched = matches_stack_list(&self.open_elements, &self.selector.list);
+
+        if matched {
+            self.open_matches += 1;
+        }
+
+        if let Some(open) = self.open_elements.last_mut() {
+            open.matched = matched;
+        }
+
+        if closed {
+            self.pop_element(chunk_end);
+        }
+
+        matched
+    }
+
+    /// Closes elements that are still open at the end of data, offset is length of data
+    pub fn finish(&mut self, offset: usize) {
+        while !self.open_elements.is_empty() {
+            self.pop_element(offset);
+        }
+    }
+
+    /// Returns true if the last processed chunk is inside of element that matched, ie its text
+    pub fn is_inside_match(&self) -> bool {
+        self.open_matches > 0
+    }
+
+    /// Returns matched elements that were closed since the last call in document order. Elements are only
+    /// returned once they are closed, so match that contains others can come in a later call than them
+    pub fn take_matches(&mut self) -> Vec<SelectorMatch> {
+        let mut matches = ::std::mem::take(&mut self.matches);
+
+        matches.sort_by_key(|selector_match| selector_match.start);
+
+        matches
+    }
+
+    /// Closes the nearest open element with given name at end along with elements opened after it,
+    /// which are closed at offset of end tag. End tag is ignored if there is no such element
+    fn close_element(&mut self, name: &str, offset: usize, end: usize) {
+        let pos = match self.open_elements.iter().rposition(|open| open.element.name == name) {
+            Some(pos) => pos,
+            None => return,
+        };
+
+        while self.open_elements.len() > pos + 1 {
+            self.pop_element(offset);
+        }
+
+        self.pop_element(end);
+    }
+
+    fn pop_element(&mut self, end: usize) {
+        let open = match self.open_elements.pop() {
+            Some(open) => open,
+            None => return,
+        };
+
+        if open.matched {
+            self.open_matches -= 1;
+            self.matches.push(SelectorMatch {
+                tag: open.element.name,
+                start: open.offset,
+                end,
+            });
+        }
+    }
+}
+
+/// Parses selector from string keeping position of the next char
+struct SelectorParser<'a> {
+    input: &'a str,
+    pos: usize,
+
+    /// If true then selectors that need more than ancestors of element are rejected
+    ancestors_only: bool,
+}
+
+impl<'a> SelectorParser<'a> {
+    fn new(input: &'a str, ancestors_only: bool) -> SelectorParser<'a> {
+        SelectorParser {
+            input,
+            pos: 0,
+            ancestors_only,
+        }
+    }
+
+    fn parse(&mut self) -> Result<Selector, SelectorError> {
+        let list = self.parse_list(false)?;
+
+        if self.pos < self.input.len() {
+            return self.error("unexpected )");
+        }
+
+        Ok(Selector { list })
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, SelectorError> {
+        Err(SelectorError {
+            message: message.to_string(),
+            offset: self.pos,
+        })
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn peek_at(&self, skip: usize) -> Option<char> {
+        self.input[self.pos..].chars().nth(skip)
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let ch = self.peek()?;
+
+        self.pos += ch.len_utf8();
+
+        Some(ch)
+    }
+
+    /// Skips whitespace returning true if there was any
+    fn skip_whitespace(&mut self) -> bool {
+        let start = self.pos;
+
+        while self.peek().is_some_and(is_whitespace) {
+            self.pos += 1;
+        }
+
+        self.pos > start
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), SelectorError> {
+        if self.peek() != Some(expected) {
+            return self.error(&format!("expected {}", expected));
+        }
+
+        self.pos += 1;
+
+        Ok(())
+    }
+
+    /// Parses comma separated list of complex selectors up to the end of input or ), relative ones
+    /// may start with combinator
+    fn parse_list(&mut self, relative: bool) -> Result<Vec<Complex>, SelectorError> {
+        let mut list = Vec::new();
+
+        loop {
+            self.skip_whitespace();
+            list.push(self.parse_complex(relative)?);
+            self.skip_whitespace();
+
+            if self.peek() != Some(',') {
+                break;
+            }
+
+            self.pos += 1;
+        }
+
+        match self.peek() {
+            None | Some(')') => Ok(list),
+            Some(_) => self.error("unexpected char"),
+        }
+    }
+
+    fn parse_complex(&mut self, relative: bool) -> Result<Complex, SelectorError> {
+        let leading = match self.parse_combinator() {
+            Some(combinator) if relative => combinator,
+            Some(_) => return self.error("selector must not start with combinator"),
+            None => Combinator::Descendant,
+        };
+
+        self.skip_whitespace();
+
+        let mut complex = Complex {
+            leading,
+            compounds: vec![self.parse_compound()?],
+            combinators: Vec::new(),
+        };
+
+        loop {
+            let whitespace = self.skip_whitespace();
+
+            let combinator = match self.peek() {
+                None | Some(',') | Some(')') => break,
+                Some('+') | Some('~') if self.ancestors_only => {
+                    return self.error("sibling combinators are not supported by streaming selector");
+                },
+                _ => match self.parse_combinator() {
+                    Some(combinator) => {
+                        self.skip_whitespace();
+                        combinator
+                    },
+                    None if whitespace => Combinator::Descendant,
+                    None => return self.error("unexpected char"),
+                },
+            };
+
+            complex.combinators.push(combinator);
+            complex.compounds.push(self.parse_compound()?);
+        }
+
+        Ok(complex)
+    }
+
+    /// Parses >, + or ~ combinator, descendant combinator is whitespace that is handled by caller
+    fn parse_combinator(&mut self) -> Option<Combinator> {
+        let combinator = match self.peek()? {
+            '>' => Combinator::Child,
+            '+' => Combinator::NextSibling,
+            '~' => Combinator::SubsequentSibling,
+            _ => return None,
+        };
+
+        self.pos += 1;
+
+        Some(combinator)
+    }
+
+    fn parse_compound(&mut self) -> Result<Compound, SelectorError> {
+        let start = self.pos;
+        let mut compound = Compound {
+            tag: None,
+            conditions: Vec::new(),
+        };
+
+        if self.peek() == Some('*') {
+            self.pos += 1;
+        } else if self.is_ident_start() {
+            compound.tag = Some(self.parse_ident()?);
+        }
+
+        loop {
+            let condition = match self.peek() {
+                Some('#') => {
+                    self.pos += 1;
+
+                    if !self.peek().is_some_and(|ch| is_name_char(ch) || ch == '\\') {
+                        return self.error("expected id");
+                    }
+
+                    Condition::Id(self.parse_name()?)
+                },
+                Some('.') => {
+                    self.pos += 1;
+
+                    if !self.is_ident_start() {
+                        return self.error("expected class name");
+                    }
+
+                    Condition::Class(self.parse_ident()?)
+                },
+                Some('[') => Condition::Attribute(self.parse_attribute()?),
+                Some(':') => Condition::Pseudo(self.parse_pseudo_class()?),
+                _ => break,
+            };
+
+            compound.conditions.push(condition);
+        }
+
+        if self.pos == start {
+            return self.error("expected selector");
+        }
+
+        Ok(compound)
+    }
+
+    fn parse_attribute(&mut self) -> Result<AttributeCondition, SelectorError> {
+        self.pos += 1;
+        self.skip_whitespace();
+
+        if !self.is_ident_start() {
+            return self.error("expected attribute name");
+        }
+
+        let name = self.parse_ident()?;
+
+        self.skip_whitespace();
+
+        let operator = match (self.peek(), self.peek_at(1)) {
+            (Some(']'), _) => None,
+            (Some('='), _) => Some(AttributeOperator::Equals),
+            (Some('~'), Some('=')) => Some(AttributeOperator::Includes),
+            (Some('|'), Some('=')) => Some(AttributeOperator::DashMatch),
+            (Some('^'), Some('=')) => Some(AttributeOperator::Prefix),
+            (Some('$'), Some('=')) => Some(AttributeOperator::Suffix),
+            (Some('*'), Some('=')) => Some(AttributeOperator::Substring),
+            _ => return self.error("expected attribute operator"),
+        };
+
+        let mut condition = AttributeCondition {
+            name,
+            operator: None,
+            ignore_case: false,
+        };
+
+        if let Some(operator) = operator {
+            self.pos += if operator == AttributeOperator::Equals { 1 } else { 2 };
+            self.skip_whitespace();
+
+            let value = match self.peek() {
+                Some('"') | Some('\'') => self.parse_string()?,
+                _ if self.is_ident_start() => self.parse_ident()?,
+                _ => return self.error("expected attribute value"),
+            };
+
+            condition.operator = Some((operator, value));
+
+            self.skip_whitespace();
+
+            if self.is_ident_start() {
+                let flag_start = self.pos;
+
+                condition.ignore_case = match self.parse_ident()?.to_ascii_lowercase().as_str() {
+                    "i" => true,
+                    "s" => false,
+                    _ => {
+                        self.pos = flag_start;
+                        return self.error("unknown attribute flag");
+                    },
+                };
+
+                self.skip_whitespace();
+            }
+        }
+
+        self.expect(']')?;
+
+        Ok(condition)
+    }
+
+    fn parse_pseudo_class(&mut self) -> Result<PseudoClass, SelectorError> {
+        let start = self.pos;
+
+        self.pos += 1;
+
+        if self.peek() == Some(':') {
+            return self.error("pseudo-elements are not supported");
+        }
+
+        if !self.is_ident_start() {
+            return self.error("expected pseudo-class");
+        }
+
+        let name = self.parse_ident()?.to_ascii_lowercase();
+
+        if self.ancestors_only && NON_STREAMING_PSEUDO_CLASSES.contains(&name.as_str()) {
+            self.pos = start;
+            return self.error(&format!(":{} is not supported by streaming selector", name));
+        }
+
+        if self.peek() == Some('(') {
+            self.pos += 1;
+            self.skip_whitespace();
+
+            let pseudo_class = match name.as_str() {
+                "not" => PseudoClass::Not(self.parse_list(false)?),
+                "is" | "where" | "matches" | "any" => PseudoClass::Is(self.parse_list(false)?),
+                "has" => PseudoClass::Has(self.parse_list(true)?),
+                "nth-child" => {
+                    let nth = self.parse_nth()?;
+
+                    PseudoClass::NthChild(nth, self.parse_of_selector()?)
+                },
+                "nth-last-child" => {
+                    let nth = self.parse_nth()?;
+
+                    PseudoClass::NthLastChild(nth, self.parse_of_selector()?)
+                },
+                "nth-of-type" => PseudoClass::NthOfType(self.parse_nth()?),
+                "nth-last-of-type" => PseudoClass::NthLastOfType(self.parse_nth()?),
+                "lang" => PseudoClass::Lang(self.parse_languages()?),
+                _ => {
+                    self.pos = start;
+                    return self.error(&format!("unknown pseudo-class :{}()", name));
+                },
+            };
+
+            self.skip_whitespace();
+            self.expect(')')?;
+
+            return Ok(pseudo_class);
+        }
+
+        let pseudo_class = match name.as_str() {
+            "root" | "scope" => PseudoClass::Root,
+            "empty" => PseudoClass::Empty,
+            "first-child" => PseudoClass::FirstChild,
+            "last-child" => PseudoClass::LastChild,
+            "only-child" => PseudoClass::OnlyChild,
+            "first-of-type" => PseudoClass::FirstOfType,
+            "last-of-type" => PseudoClass::LastOfType,
+            "only-of-type" => PseudoClass::OnlyOfType,
+            "link" | "any-link" => PseudoClass::Link,
+            "checked" => PseudoClass::Checked,
+            "enabled" => PseudoClass::Enabled,
+            "disabled" => PseudoClass::Disabled,
+            _ if DYNAMIC_PSEUDO_CLASSES.contains(&name.as_str()) => PseudoClass::Never,
+            _ if LEGACY_PSEUDO_ELEMENTS.contains(&name.as_str()) => {
+                self.pos = start;
+                return self.error("pseudo-elements are not supported");
+            },
+            _ => {
+                self.pos = start;
+                return self.error(&format!("unknown pseudo-class :{}", name));
+            },
+        };
+
+        Ok(pseudo_class)
+    }
+
+    /// Parses an+b expression, ie odd, 2n+1 or -n + 3
+    fn parse_nth(&mut self) -> Result<Nth, SelectorError> {
+        let start = self.pos;
+        let rest = &self.input[start..];
+
+        // expression ends at ) or at of that is separated by whitespace
+        let mut end = rest.find(')').unwrap_or(rest.len());
+
+        if let Some(of) = find_of(&rest[..end]) {
+            end = of;
+        }
+
+        let expression: String = rest[..end].chars().filter(|&ch| !is_whitespace(ch)).collect::<String>()
+            .to_ascii_lowercase();
+
+        let nth = match expression.as_str() {
+            "odd" => Some(Nth { a: 2, b: 1 }),
+            "even" => Some(Nth { a: 2, b: 0 }),
+            _ => match expression.split_once('n') {
+                Some((a, b)) => {
+                    let a = match a {
+                        "" | "+" => Some(1),
+                        "-" => Some(-1),
+                        _ => parse_integer(a),
+                    };
+
+                    let b = match b {
+                        "" => Some(0),
+                        _ if b.starts_with('+') || b.starts_with('-') => parse_integer(b),
+                        _ => None,
+                    };
+
+                    a.zip(b).map(|(a, b)| Nth { a, b })
+                },
+                None => parse_integer(&expression).map(|b| Nth { a: 0, b }),
+            },
+        };
+
+        match nth {
+            Some(nth) => {
+                self.pos = start + end;
+                Ok(nth)
+            },
+            None => self.error("expected an+b expression"),
+        }
+    }
+
+    /// Parses "of S" part of :nth-child, if any
+    fn parse_of_selector(&mut self) -> Result<Option<Vec<Complex>>, SelectorError> {
+        self.skip_whitespace();
+
+        if !self.input[self.pos..].starts_with("of") {
+            return Ok(None);
+        }
+
+        self.pos += 2;
+
+        if !self.skip_whitespace() {
+            return self.error("expected whitespace after of");
+        }
+
+        self.parse_list(false).map(Some)
+    }
+
+    /// Parses comma separated list of language ranges of :lang
+    fn parse_languages(&mut self) -> Result<Vec<String>, SelectorError> {
+        let mut languages = Vec::new();
+
+        loop {
+            self.skip_whitespace();
+
+            let language = match self.peek() {
+                Some('"') | Some('\'') => self.parse_string()?,
+                _ if self.is_ident_start() => self.parse_ident()?,
+                _ => return self.error("expected language"),
+            };
+
+            languages.push(language.to_ascii_lowercase());
+            self.skip_whitespace();
+
+            if self.peek() != Some(',') {
+                return Ok(languages);
+            }
+
+            self.pos += 1;
+        }
+    }
+
+    /// Returns true if identifier starts at current position
+    fn is_ident_start(&self) -> bool {
+        let valid_start = |ch: Option<char>| ch.is_some_and(|ch| is_name_start(ch) || ch == '\\');
+
+        match self.peek() {
+            Some('-') => valid_start(self.peek_at(1)) || self.peek_at(1) == Some('-'),
+            ch => valid_start(ch),
+        }
+    }
+
+    /// Parses identifier, caller checks that it starts at current position
+    fn parse_ident(&mut self) -> Result<String, SelectorError> {
+        self.parse_name()
+    }
+
+    /// Parses name chars and escapes, ie id after #
+    fn parse_name(&mut self) -> Result<String, SelectorError> {
+        let mut name = String::new();
+
+        while let Some(ch) = self.peek() {
+            if ch == '\\' {
+                name.push(self.parse_escape()?);
+            } else if is_name_char(ch) {
+                name.push(ch);
+                self.pos += ch.len_utf8();
+            } else {
+                break;
+            }
+        }
+
+        Ok(name)
+    }
+
+    /// Parses escape like \: or \31 that starts at current position
+    fn parse_escape(&mut self) -> Result<char, SelectorError> {
+        self.pos += 1;
+
+        let hex_len = self.input[self.pos..].chars().take(6).take_while(|ch| ch.is_ascii_hexdigit()).count();
+
+        if hex_len > 0 {
+            let code = u32::from_str_radix(&self.input[self.pos..self.pos + hex_len], 16).unwrap_or(0xFFFD);
+
+            self.pos += hex_len;
+
+            // single whitespace ends hex escape
+            if self.peek().is_some_and(is_whitespace) {
+                self.pos += 1;
+            }
+
+            return Ok(match code {
+                0 | 0xD800..=0xDFFF => '\u{FFFD}',
+                _ => ::std::char::from_u32(code).unwrap_or('\u{FFFD}'),
+            });
+        }
+
+        match self.next_char() {
+            Some('\n') | None => self.error("invalid escape"),
+            Some(ch) => Ok(ch),
+        }
+    }
+
+    /// Parses quoted string that starts at current position
+    fn parse_string(&mut self) -> Result<String, SelectorError> {
+        let quote = self.next_char();
+        let mut value = String::new();
+
+        loop {
+            match self.peek() {
+                None => return self.error("unterminated string"),
+                Some('\\') if self.peek_at(1) == Some('\n') => self.pos += 2,
+                Some('\\') => value.push(self.parse_escape()?),
+                Some(ch) if Some(ch) == quote => {
+                    self.pos += 1;
+                    return Ok(value);
+                },
+                Some(ch) => {
+                    value.push(ch);
+                    self.pos += ch.len_utf8();
+                },
+            }
+        }
+    }
+}
+
+fn is_whitespace(ch: char) -> bool {
+    matches!(ch, ' ' | '\t' | '\n' | '\r' | '\x0C')
+}
+
+fn is_name_start(ch: char) -> bool {
+    ch.is_ascii_alphabetic() || ch == '_' || !ch.is_ascii()
+}
+
+fn is_name_char(ch: char) -> bool {
+    is_name_start(ch) || ch.is_ascii_digit() || ch == '-'
+}
+
+/// Returns offset of "of" that is separated by whitespace from the rest of :nth-child argument
+fn find_of(argument: &str) -> Option<usize> {
+    let bytes = argument.as_bytes();
+
+    (1..bytes.len().saturating_sub(2)).find(|&i| {
+        is_whitespace(bytes[i - 1] as char) && bytes[i..].starts_with(b"of") && is_whitespace(bytes[i + 2] as char)
+    })
+}
+
+/// Parses integer with optional sign
+fn parse_integer(text: &str) -> Option<i64> {
+    let digits = text.strip_prefix('+').unwrap_or(text);
+
+    if digits.is_empty() || digits.starts_with('+') {
+        return None;
+    }
+
+    digits.parse().ok()
+}
+
+fn matches_list(document: &Document, id: NodeId, list: &[Complex]) -> bool {
+    list.iter().any(|complex| matches_complex(document, id, complex, complex.compounds.len() - 1, None))
+}
+
+/// Matches element against compound selectors of complex one up to given index going from right to left.
+/// Anchor is element of :has that the first compound selector must be related to
+fn matches_complex(document: &Document, id: NodeId, complex: &Complex, index: usize, anchor: Option<NodeId>)
+    -> bool {
+    if !matches_compound(document, id, &complex.compounds[index]) {
+        return false;
+    }
+
+    if index == 0 {
+        return match anchor {
+            Some(anchor) => is_related(document, anchor, id, complex.leading),
+            None => true,
+        };
+    }
+
+    let matches = |other| matches_complex(document, other, complex, index - 1, anchor);
+
+    match complex.combinators[index - 1] {
+        Combinator::Child => parent_element(document, id).is_some_and(matches),
+        Combinator::Descendant => ancestors(document, id).any(matches),
+        Combinator::NextSibling => prev_element_sibling(document, id).is_some_and(matches),
+        Combinator::SubsequentSibling => prev_element_siblings(document, id).any(matches),
+    }
+}
+
+/// Returns true if element is related to anchor by combinator, ie it is its child for >
+fn is_related(document: &Document, anchor: NodeId, id: NodeId, combinator: Combinator) -> bool {
+    match combinator {
+        Combinator::Child => document[id].parent() == Some(anchor),
+        Combinator::Descendant => ancestors(document, id).any(|ancestor| ancestor == anchor),
+        Combinator::NextSibling => prev_element_sibling(document, id) == Some(anchor),
+        Combinator::SubsequentSibling => prev_element_siblings(document, id).any(|sibling| sibling == anchor),
+    }
+}
+
+fn matches_compound(document: &Document, id: NodeId, compound: &Compound) -> bool {
+    let element = match document[id].as_element() {
+        Some(element) => element,
+        None => return false,
+    };
+
+    matches_tag(element, compound) && compound.conditions.iter()
+        .all(|condition| matches_condition(document, id, element, condition))
+}
+
+/// Returns true if element has tag name of compound selector or it has no type selector
+fn matches_tag(element: &Element, compound: &Compound) -> bool {
+    // HTML names are case-insensitive, SVG and MathML ones are not
+    compound.tag.as_ref().is_none_or(|tag| match element.namespace {
+        Namespace::Html => element.name.eq_ignore_ascii_case(tag),
+        _ => element.name == *tag,
+    })
+}
+
+fn matches_condition(document: &Document, id: NodeId, element: &Element, condition: &Condition) -> bool {
+    match (matches_own_condition(element, condition), condition) {
+        (Some(matches), _) => matches,
+        (None, Condition::Pseudo(pseudo_class)) => matches_pseudo_class(document, id, element, pseudo_class),
+        (None, _) => false,
+    }
+}
+
+/// Matches condition that only depends on element itself, None if it depends on other elements too
+fn matches_own_condition(element: &Element, condition: &Condition) -> Option<bool> {
+    let is_html = |names: &[&str]| element.namespace == Namespace::Html && names.contains(&element.name.as_str());
+
+    let matches = match *condition {
+        Condition::Id(ref id) => attribute(element, "id") == Some(id.as_str()),
+        Condition::Class(ref class) => {
+            attribute(element, "class").is_some_and(|classes| classes.split_whitespace().any(|name| name == class))
+        },
+        Condition::Attribute(ref condition) => matches_attribute(element, condition),
+        Condition::Pseudo(PseudoClass::Link) => is_html(&["a", "area", "link"]) && attribute(element, "href").is_some(),
+        Condition::Pseudo(PseudoClass::Checked) => {
+            let checkable = attribute(element, "type").is_some_and(|kind| {
+                kind.eq_ignore_ascii_case("checkbox") || kind.eq_ignore_ascii_case("radio")
+            });
+
+            (is_html(&["input"]) && checkable && attribute(element, "checked").is_some())
+                || (is_html(&["option"]) && attribute(element, "selected").is_some())
+        },
+        Condition::Pseudo(ref pseudo_class @ PseudoClass::Enabled)
+        | Condition::Pseudo(ref pseudo_class @ PseudoClass::Disabled) => {
+            let disabled = attribute(element, "disabled").is_some();
+
+            is_html(&["button", "input", "select", "textarea", "optgroup", "option", "fieldset"])
+                && disabled == (*pseudo_class == PseudoClass::Disabled)
+        },
+        Condition::Pseudo(PseudoClass::Never) => false,
+        Condition::Pseudo(_) => return None,
+    };
+
+    Some(matches)
+}
+
+/// Returns value of attribute, names of HTML attributes are case-insensitive
+fn attribute<'a>(element: &'a Element, name: &str) -> Option<&'a str> {
+    element.attributes.iter()
+        .find(|attribute| match element.namespace {
+            Namespace::Html => attribute.name.eq_ignore_ascii_case(name),
+            _ => attribute.name == name,
+        })
+        .map(|attribute| attribute.value.as_str())
+}
+
+fn matches_attribute(element: &Element, condition: &AttributeCondition) -> bool {
+    let value = match attribute(element, &condition.name) {
+        Some(value) => value,
+        None => return false,
+    };
+
+    let (operator, expected) = match condition.operator {
+        Some((operator, ref expected)) => (operator, expected.as_str()),
+        None => return true,
+    };
+
+    let (value, expected) = if condition.ignore_case {
+        (value.to_ascii_lowercase(), expected.to_ascii_lowercase())
+    } else {
+        (value.to_string(), expected.to_string())
+    };
+
+    match operator {
+        AttributeOperator::Equals => value == expected,
+        AttributeOperator::Includes => {
+            !expected.is_empty() && !expected.contains(is_whitespace)
+                && value.split(is_whitespace).any(|word| word == expected)
+        },
+        AttributeOperator::DashMatch => {
+            value == expected || (value.starts_with(&expected) && value[expected.len()..].starts_with('-'))
+        },
+        AttributeOperator::Prefix => !expected.is_empty() && value.starts_with(&expected),
+        AttributeOperator::Suffix => !expected.is_empty() && value.ends_with(&expected),
+        AttributeOperator::Substring => !expected.is_empty() && value.contains(&expected),
+    }
+}
+
+fn matches_pseudo_class(document: &Document, id: NodeId, element: &Element, pseudo_class: &PseudoClass) -> bool {
+    let same_type = |other: NodeId| document[other].as_element().is_some_and(|other| {
+        other.namespace == element.namespace && other.name == element.name
+    });
+
+    match *pseudo_class {
+        PseudoClass::Root => document[id].parent() == Some(document.root()),
+        PseudoClass::Empty => document.children(id).all(|child| match document[child].as_text() {
+            Some(text) => text.is_empty(),
+            None => document[child].as_element().is_none(),
+        }),
+        PseudoClass::FirstChild => prev_element_sibling(document, id).is_none(),
+        PseudoClass::LastChild => next_element_sibling(document, id).is_none(),
+        PseudoClass::OnlyChild => {
+            prev_element_sibling(document, id).is_none() && next_element_sibling(document, id).is_none()
+        },
+        PseudoClass::FirstOfType => !prev_element_siblings(document, id).any(same_type),
+        PseudoClass::LastOfType => !next_element_siblings(document, id).any(same_type),
+        PseudoClass::OnlyOfType => {
+            !prev_element_siblings(document, id).any(same_type) && !next_element_siblings(document, id).any(same_type)
+        },
+        PseudoClass::NthChild(nth, ref of) | PseudoClass::NthLastChild(nth, ref of) => {
+            let counted = |other: NodeId| of.as_ref().is_none_or(|list| matches_list(document, other, list));
+
+            if !counted(id) {
+                return false;
+            }
+
+            let index = if matches!(*pseudo_class, PseudoClass::NthChild(..)) {
+                prev_element_siblings(document, id).filter(|&other| counted(other)).count()
+            } else {
+                next_element_siblings(document, id).filter(|&other| counted(other)).count()
+            };
+
+            nth.matches(index as i64 + 1)
+        },
+        PseudoClass::NthOfType(nth) => nth.matches(prev_element_siblings(document, id).filter(|&other| same_type(other))
+            .count() as i64 + 1),
+        PseudoClass::NthLastOfType(nth) => nth.matches(next_element_siblings(document, id)
+            .filter(|&other| same_type(other)).count() as i64 + 1),
+        PseudoClass::Not(ref list) => !matches_list(document, id, list),
+        PseudoClass::Is(ref list) => matches_list(document, id, list),
+        PseudoClass::Has(ref list) => list.iter().any(|relative| {
+            let last = relative.compounds.len() - 1;
+
+            has_candidates(document, id, relative)
+                .any(|candidate| matches_complex(document, candidate, relative, last, Some(id)))
+        }),
+        PseudoClass::Lang(ref languages) => {
+            let lang = Some(id).into_iter().chain(ancestors(document, id))
+                .find_map(|node| document[node].as_element().and_then(|element| attribute(element, "lang")));
+
+            matches_lang(lang, languages)
+        },
+        // the rest only depends on element, see matches_own_condition
+        _ => false,
+    }
+}
+
+/// Returns true if language of element (value of the nearest lang attribute) is one of language ranges or
+/// their subtag
+fn matches_lang(lang: Option<&str>, languages: &[String]) -> bool {
+    lang.is_some_and(|lang| {
+        let lang = lang.to_ascii_lowercase();
+
+        languages.iter().any(|language| {
+            lang == *language || (lang.starts_with(language.as_str()) && lang[language.len()..].starts_with('-'))
+        })
+    })
+}
+
+/// Returns elements that may match relative selector of :has on anchor element: its descendants,
+/// or following siblings and their descendants if selector starts with + or ~
+fn has_candidates<'a>(document: &'a Document, anchor: NodeId, relative: &Complex)
+    -> Box<dyn Iterator<Item = NodeId> + 'a> {
+    match relative.leading {
+        Combinator::Descendant | Combinator::Child => Box::new(document.descendants(anchor)),
+        Combinator::NextSibling | Combinator::SubsequentSibling => {
+            Box::new(next_element_siblings(document, anchor)
+                .flat_map(move |sibling| Some(sibling).into_iter().chain(document.descendants(sibling))))
+        },
+    }
+}
+
+fn parent_element(document: &Document, id: NodeId) -> Option<NodeId> {
+    document[id].parent().filter(|&parent| document[parent].as_element().is_some())
+}
+
+/// Returns ancestors of node that are elements, the nearest one first
+fn ancestors(document: &Document, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+    ::std::iter::successors(parent_element(document, id), move |&parent| parent_element(document, parent))
+}
+
+fn prev_element_sibling(document: &Document, id: NodeId) -> Option<NodeId> {
+    prev_element_siblings(document, id).next()
+}
+
+fn next_element_sibling(document: &Document, id: NodeId) -> Option<NodeId> {
+    next_element_siblings(document, id).next()
+}
+
+/// Returns element siblings before node, the nearest one first
+fn prev_element_siblings(document: &Document, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+    ::std::iter::successors(document[id].prev_sibling(), move |&sibling| document[sibling].prev_sibling())
+        .filter(move |&sibling| document[sibling].as_element().is_some())
+}
+
+/// Returns element siblings after node, the nearest one first
+fn next_element_siblings(document: &Document, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+    ::std::iter::successors(document[id].next_sibling(), move |&sibling| document[sibling].next_sibling())
+        .filter(move |&sibling| document[sibling].as_element().is_some())
+}
+
+/// Matches the last of open elements against selectors, the rest of them are its ancestors
+fn matches_stack_list(stack: &[OpenElement], list: &[Complex]) -> bool {
+    list.iter().any(|complex| matches_stack_complex(stack, complex, complex.compounds.len() - 1))
+}
+
+/// Matches the last of open elements against compound selectors up to given index going from right to left
+fn matches_stack_complex(stack: &[OpenElement], complex: &Complex, index: usize) -> bool {
+    if !matches_stack_compound(stack, &complex.compounds[index]) {
+        return false;
+    }
+
+    if index == 0 {
+        return true;
+    }
+
+    let parent_len = stack.len() - 1;
+
+    match complex.combinators[index - 1] {
+        Combinator::Child => parent_len > 0 && matches_stack_complex(&stack[..parent_len], complex, index - 1),
+        // parser rejects sibling combinators
+        _ => (1..parent_len + 1).rev().any(|len| matches_stack_complex(&stack[..len], complex, index - 1)),
+    }
+}
+
+fn matches_stack_compound(stack: &[OpenElement], compound: &Compound) -> bool {
+    let element = match stack.last() {
+        Some(open) => &open.element,
+        None => return false,
+    };
+
+    matches_tag(element, compound) && compound.conditions.iter().all(|condition| {
+        match (matches_own_condition(element, condition), condition) {
+            (Some(matches), _) => matches,
+            (None, Condition::Pseudo(PseudoClass::Root)) => stack.len() == 1,
+            (None, Condition::Pseudo(PseudoClass::Not(list))) => !matches_stack_list(stack, list),
+            (None, Condition::Pseudo(PseudoClass::Is(list))) => matches_stack_list(stack, list),
+            (None, Condition::Pseudo(PseudoClass::Lang(languages))) => {
+                let lang = stack.iter().rev().find_map(|open| attribute(&open.element, "lang"));
+
+                matches_lang(lang, languages)
+            },
+            // parser rejects the rest
+            (None, _) => false,
+        }
+    })
+}
@@ -0,0 +1,152 @@
+use html_chunk::Namespace;
+use html_entities::HtmlEntities;
+use html_document::{Document, NodeId, NodeData, Element};
+
+/// Elements that never have content, so they are not left open and are serialized without end tag
+pub const VOID_ELEMENTS: [&str; 18] = ["area", "base", "basefont", "bgsound", "br", "col", "embed", "frame", "hr",
+    "img", "input", "keygen", "link", "meta", "param", "source", "track", "wbr"];
+
+/// Elements which text is serialized as it is, without escaping. Noscript is not one of them as documents
+/// are parsed with scripting disabled by default
+pub const RAW_TEXT_ELEMENTS: [&str; 7] = ["iframe", "noembed", "noframes", "plaintext", "script", "style", "xmp"];
+
+/// Elements which content keeps its whitespace when HTML is reformatted, along with raw text elements
+pub const PRESERVE_WHITESPACE_ELEMENTS: [&str; 3] = ["listing", "pre", "textarea"];
+
+/// Elements that browsers render as blocks, which start on new lines
+pub const BLOCK_ELEMENTS: [&str; 53] = ["address", "article", "aside", "blockquote", "body", "caption", "center", "col",
+    "colgroup", "dd", "details", "dialog", "dir", "div", "dl", "dt", "fieldset", "figcaption", "figure", "footer",
+    "form", "frameset", "h1", "h2", "h3", "h4", "h5", "h6", "head", "header", "hgroup", "hr", "html", "legend", "li",
+    "main", "menu", "nav", "ol", "optgroup", "option", "p", "pre", "section", "summary", "table", "tbody", "td",
+    "tfoot", "th", "thead", "tr", "ul"];
+
+/// Serializes node to HTML following HTML5 serialization algorithm, node itself is only included if asked to.
+/// Nodes of XML documents are serialized as XML
+pub fn serialize(document: &Document, id: NodeId, include_node: bool, html: &mut String) {
+    if include_node {
+        serialize_node(document, id, html);
+    } else {
+        serialize_children(document, id, html);
+    }
+}
+
+fn serialize_node(document: &Document, id: NodeId, html: &mut String) {
+    match document[id].data {
+        NodeData::Document => serialize_children(document, id, html),
+        NodeData::Element(ref element) if document.is_xml() => serialize_xml_element(document, id, element, html),
+        NodeData::Element(ref element) => {
+            start_tag(&element.name, element.attributes.iter().map(|attribute| {
+                (attribute.name.as_str(), attribute.value.as_str())
+            }), html);
+
+            if is_void(element) {
+                return;
+            }
+
+            serialize_children(document, id, html);
+
+            html.push_str("</");
+            html.push_str(&element.name);
+            html.push('>');
+        },
+        NodeData::Text(ref text) => {
+            let raw = document[id].parent()
+                .and_then(|parent| document[parent].as_element())
+                .is_some_and(is_raw_text);
+
+            if raw && !document.is_xml() {
+                html.push_str(text);
+            } else {
+                escape(text, false, document.is_xml(), html);
+            }
+        },
+        NodeData::Comment(ref text) => {
+            html.push_str("<!--");
+            html.push_str(text);
+            html.push_str("-->");
+        },
+        NodeData::Doctype(ref doctype) => {
+            html.push_str("<!DOCTYPE ");
+            html.push_str(doctype.name.as_deref().unwrap_or(""));
+            html.push('>');
+        },
+    }
+}
+
+/// Serializes element of XML document, there are no void or raw text elements in XML, so element is only
+/// left without end tag if it is empty, ie <link/>
+fn serialize_xml_element(document: &Document, id: NodeId, element: &Element, html: &mut String) {
+    html.push('<');
+    html.push_str(&element.name);
+
+    for attribute in &element.attributes {
+        html.push(' ');
+        html.push_str(&attribute.name);
+        html.push_str("=\"");
+        escape(&attribute.value, true, true, html);
+        html.push('"');
+    }
+
+    if document[id].first_child().is_none() {
+        html.push_str("/>");
+        return;
+    }
+
+    html.push('>');
+    serialize_children(document, id, html);
+    html.push_str("</");
+    html.push_str(&element.name);
+    html.push('>');
+}
+
+fn serialize_children(document: &Document, id: NodeId, html: &mut String) {
+    for child in document.children(id) {
+        serialize_node(document, child, html);
+    }
+}
+
+/// Appends start tag with attributes which values are escaped, there is never trailing / even for void tags
+pub fn start_tag<'a, I>(name: &str, attributes: I, html: &mut String)
+    where I: IntoIterator<Item = (&'a str, &'a str)> {
+    html.push('<');
+    html.push_str(name);
+
+    for (name, value) in attributes {
+        html.push(' ');
+        html.push_str(name);
+        html.push_str("=\"");
+        escape_attribute(value, html);
+        html.push('"');
+    }
+
+    html.push('>');
+}
+
+/// Returns true if element is HTML element that has no content
+pub fn is_void(element: &Element) -> bool {
+    element.namespace == Namespace::Html && VOID_ELEMENTS.contains(&element.name.as_str())
+}
+
+/// Returns true if text of element must not be escaped
+pub fn is_raw_text(element: &Element) -> bool {
+    element.namespace == Namespace::Html && RAW_TEXT_ELEMENTS.contains(&element.name.as_str())
+}
+
+/// Appends text escaping &, <, > and no-break space
+pub fn escape_text(text: &str, html: &mut String) {
+    escape(text, false, false, html);
+}
+
+/// Appends attribute value escaping &, ", <, > and no-break space, so it can be put between double quotes
+pub fn escape_attribute(value: &str, html: &mut String) {
+    escape(value, true, false, html);
+}
+
+/// Appends escaped text or attribute value, no-break space is kept as it is in XML
+fn escape(text: &str, in_attribute: bool, xml: bool, html: &mut String) {
+    let mut entities = HtmlEntities::new();
+
+    entities.in_attribute = in_attribute;
+    entities.xml_mode = xml;
+    entities.encode(text, html);
+}
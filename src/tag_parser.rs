@@ -1,2 +1,356 @@
+use html_chunk::{HtmlChunk, ChunkType};
+use html_entities::HtmlEntities;
+use dynamic_string::decode_bytes;
+use line_index::{SourcePosition, ParamPosition};
+use parse_error::{ParseError, ParseErrorCode};
+
+use encoding::EncodingRef;
+
+/// Parses tag name and its params, it is used by HtmlParser once it found start of a tag
 pub struct TagParser {
-}
\ No newline at end of file
+    /// By default if tag is closed BUT it has got parameters then we will consider it open tag,
+    /// see HtmlParser for details
+    pub mark_closed_tags_with_params_as_open: bool,
+
+    /// If true then tag is XML: names keep their case, self-closing tags are always closed and params
+    /// must have quoted values
+    pub xml_mode: bool,
+
+    /// Start and end delimiters of template code like {{ and }}, see HtmlParser for details
+    pub template_delimiters: Vec<(String, String)>,
+
+    /// If true then entities in param values are decoded, otherwise they are only checked for errors
+    pub decode_entities: bool,
+
+    /// Parse errors found in tags, HtmlParser takes them from here once tag was parsed
+    pub errors: Vec<ParseError>,
+}
+
+impl TagParser {
+    pub fn new() -> TagParser {
+        TagParser {
+            mark_closed_tags_with_params_as_open: true,
+            xml_mode: false,
+            template_delimiters: Vec::new(),
+            decode_entities: false,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Returns true if byte is whitespace as far as tags are concerned
+    #[inline]
+    pub fn is_whitespace(ch: u8) -> bool {
+        ch == b' ' || ch == b'\t' || ch == b'\n' || ch == b'\r' || ch == 0x0C
+    }
+
+    /// Returns position right after template code that starts at pos, end of data if its end delimiter
+    /// is missing. None if there is no template code at pos
+    pub fn template_end(&self, data: &[u8], pos: usize) -> Option<usize> {
+        let (start, end) = self.template_delimiters.iter()
+            .find(|&(start, end)| !start.is_empty() && !end.is_empty() && data[pos..].starts_with(start.as_bytes()))?;
+        let code_start = pos + start.len();
+
+        let code_end = data[code_start..].windows(end.len())
+            .position(|window| window == end.as_bytes())
+            .map_or(data.len(), |found| code_start + found + end.len());
+
+        Some(code_end)
+    }
+
+    /// Parses tag which name starts at pos (ie right after < or </), the chunk will be set to
+    /// open or close tag with lowercased tag name and params (unless it is XML). Param values will have
+    /// entities decoded if decode_entities is set, errors in entities are reported either way.
+    ///
+    /// Returns position right after the end of tag
+    pub fn parse_tag(&mut self, data: &[u8], mut pos: usize, closure: bool, enc: EncodingRef,
+        entities: &mut HtmlEntities, chunk: &mut HtmlChunk) -> usize {
+        let data_length = data.len();
+        let name_start = pos;
+
+        while pos < data_length {
+            let ch = data[pos];
+
+            if TagParser::is_whitespace(ch) || ch == b'/' || ch == b'>' {
+                break;
+            }
+
+            pos += 1;
+        }
+
+        self.check_nulls(data, name_start, pos);
+
+        chunk.tag = self.name(decode_bytes(enc, &data[name_start..pos]));
+        chunk.closure = closure;
+
+        let mut closed = false;
+        let mut after_quoted_value = false;
+
+        while pos < data_length {
+            let ch = data[pos];
+
+            if TagParser::is_whitespace(ch) {
+                after_quoted_value = false;
+                pos += 1;
+                continue;
+            }
+
+            if let Some(end) = self.template_end(data, pos) {
+                // template code between params is kept as param named after the whole code
+                chunk.add_param(decode_bytes(enc, &data[pos..end]), String::new(), b' ');
+                TagParser::set_param_position(chunk, pos, end, end, end);
+
+                after_quoted_value = false;
+                pos = end;
+                continue;
+            }
+
+            if ch == b'>' {
+                closed = true;
+                pos += 1;
+                break;
+            }
+
+            if ch == b'/' {
+                after_quoted_value = false;
+                pos += 1;
+
+                if pos < data_length && data[pos] == b'>' {
+                    chunk.end_closure = true;
+                } else if pos < data_length {
+                    self.add_error(ParseErrorCode::UnexpectedSolidusInTag, pos - 1);
+                }
+
+                continue;
+            }
+
+            if after_quoted_value {
+                self.add_error(ParseErrorCode::MissingWhitespaceBetweenAttributes, pos);
+            }
+
+            let (end, quoted) = self.parse_param(data, pos, enc, entities, chunk);
+
+            pos = end;
+            after_quoted_value = quoted;
+        }
+
+        if !closed {
+            self.add_error(ParseErrorCode::EofInTag, data_length);
+        }
+
+        // flags of the last decoded value are not good enough - we need to know about any of them
+        entities.found_entities = chunk.entities;
+        entities.found_lt_entity = chunk.lt_entity;
+
+        if closure {
+            // params of closing tags have no meaning
+            if chunk.params_count > 0 {
+                self.add_error(ParseErrorCode::EndTagWithAttributes, name_start);
+            }
+
+            if chunk.end_closure {
+                self.add_error(ParseErrorCode::EndTagWithTrailingSolidus, pos - 2);
+            }
+
+            chunk.clear_params();
+            chunk.end_closure = false;
+            chunk.chunk_type = ChunkType::CloseTag;
+        } else if chunk.end_closure {
+            if self.mark_closed_tags_with_params_as_open && !self.xml_mode && chunk.params_count > 0 {
+                chunk.chunk_type = ChunkType::OpenTag;
+            } else {
+                chunk.closure = true;
+                chunk.chunk_type = ChunkType::CloseTag;
+            }
+        } else {
+            chunk.chunk_type = ChunkType::OpenTag;
+        }
+
+        pos
+    }
+
+    /// Returns name of tag or param, it is lowercased unless it is XML
+    fn name(&self, name: String) -> String {
+        if self.xml_mode {
+            name
+        } else {
+            name.to_lowercase()
+        }
+    }
+
+    fn add_error(&mut self, code: ParseErrorCode, offset: usize) {
+        self.errors.push(ParseError::new(code, offset));
+    }
+
+    /// Reports nulls found in data between from and to
+    fn check_nulls(&mut self, data: &[u8], from: usize, to: usize) {
+        for (i, &ch) in data[from..to].iter().enumerate() {
+            if ch == 0 {
+                self.add_error(ParseErrorCode::UnexpectedNullCharacter, from + i);
+            }
+        }
+    }
+
+    /// Parses single param name and its value (if any) starting at pos, returns position after it
+    /// and whether value was quoted
+    fn parse_param(&mut self, data: &[u8], mut pos: usize, enc: EncodingRef,
+        entities: &mut HtmlEntities, chunk: &mut HtmlChunk) -> (usize, bool) {
+        let data_length = data.len();
+        let name_start = pos;
+
+        // = is allowed as the first char of param name
+        if data[pos] == b'=' {
+            self.add_error(ParseErrorCode::UnexpectedEqualsSignBeforeAttributeName, pos);
+        }
+
+        pos += 1;
+
+        while pos < data_length {
+            let ch = data[pos];
+
+            if TagParser::is_whitespace(ch) || ch == b'/' || ch == b'>' || ch == b'=' {
+                break;
+            }
+
+            if self.template_end(data, pos).is_some() {
+                break;
+            }
+
+            pos += 1;
+        }
+
+        let name_end = pos;
+
+        for (i, &ch) in data[name_start..name_end].iter().enumerate() {
+            match ch {
+                b'"' | b'\'' | b'<' =>
+                    self.add_error(ParseErrorCode::UnexpectedCharacterInAttributeName, name_start + i),
+                0 => self.add_error(ParseErrorCode::UnexpectedNullCharacter, name_start + i),
+                _ => {}
+            }
+        }
+
+        let name = self.name(decode_bytes(enc, &data[name_start..name_end]));
+
+        // later params with the same name are dropped, as browsers do
+        let duplicate = chunk.param_names.contains(&name);
+
+        if duplicate {
+            self.add_error(ParseErrorCode::DuplicateAttribute, name_start);
+        }
+
+        let mut value_pos = pos;
+
+        while value_pos < data_length && TagParser::is_whitespace(data[value_pos]) {
+            value_pos += 1;
+        }
+
+        if value_pos >= data_length || data[value_pos] != b'=' {
+            if self.xml_mode {
+                self.add_error(ParseErrorCode::AttributeWithoutValue, name_start);
+            }
+
+            if !duplicate {
+                chunk.add_param(name, String::new(), b' ');
+                TagParser::set_param_position(chunk, name_start, name_end, name_end, name_end);
+            }
+
+            return (pos, false);
+        }
+
+        pos = value_pos + 1;
+
+        while pos < data_length && TagParser::is_whitespace(data[pos]) {
+            pos += 1;
+        }
+
+        let quote_ch = if pos < data_length { data[pos] } else { b' ' };
+        let quoted = quote_ch == b'"' || quote_ch == b'\'';
+
+        let (value_start, value_end, end) = if quoted {
+            let value_start = pos + 1;
+            let mut value_end = value_start;
+
+            while value_end < data_length && data[value_end] != quote_ch {
+                value_end = self.template_end(data, value_end).unwrap_or(value_end + 1);
+            }
+
+            // end of data inside of value is reported as end of data in tag
+            let end = if value_end < data_length { value_end + 1 } else { value_end };
+
+            (value_start, value_end, end)
+        } else {
+            let value_start = pos;
+
+            if self.xml_mode {
+                self.add_error(ParseErrorCode::UnquotedAttributeValue, pos);
+            }
+
+            while pos < data_length && !TagParser::is_whitespace(data[pos]) && data[pos] != b'>' {
+                if let Some(end) = self.template_end(data, pos) {
+                    pos = end;
+                    continue;
+                }
+
+                match data[pos] {
+                    b'"' | b'\'' | b'<' | b'=' | b'`' =>
+                        self.add_error(ParseErrorCode::UnexpectedCharacterInUnquotedAttributeValue, pos),
+                    _ => {}
+                }
+
+                pos += 1;
+            }
+
+            if pos == value_start && pos < data_length {
+                self.add_error(ParseErrorCode::MissingAttributeValue, pos);
+            }
+
+            (value_start, pos, pos)
+        };
+
+        self.check_nulls(data, value_start, value_end);
+
+        if duplicate {
+            return (end, quoted);
+        }
+
+        let value = if self.decode_entities {
+            entities.in_attribute = true;
+
+            let value = entities.decode(&data[value_start..value_end], value_start, enc, &mut self.errors);
+
+            entities.in_attribute = false;
+
+            chunk.entities |= entities.found_entities;
+            chunk.lt_entity |= entities.found_lt_entity;
+
+            value
+        } else {
+            entities.in_attribute = true;
+            entities.check(&data[value_start..value_end], value_start, &mut self.errors);
+            entities.in_attribute = false;
+
+            decode_bytes(enc, &data[value_start..value_end])
+        };
+
+        chunk.add_param(name, value, if quoted { quote_ch } else { b' ' });
+        TagParser::set_param_position(chunk, name_start, name_end, value_start, value_end);
+
+        (end, quoted)
+    }
+
+    /// Sets offsets of name and value of param that was added last, the rest of position is
+    /// calculated by parser if it tracks positions
+    fn set_param_position(chunk: &mut HtmlChunk, name_start: usize, name_end: usize,
+        value_start: usize, value_end: usize) {
+        let offset = |offset| SourcePosition { offset, ..SourcePosition::default() };
+
+        if let Some(position) = chunk.param_positions.last_mut() {
+            *position = ParamPosition {
+                name_start: offset(name_start),
+                name_end: offset(name_end),
+                value_start: offset(value_start),
+                value_end: offset(value_end),
+            };
+        }
+    }
+}
@@ -0,0 +1,2571 @@
+use std::mem;
+
+use html_chunk::{HtmlChunk, ChunkType, Doctype, Namespace};
+use html_document::{Document, NodeId, NodeData, Element, Attribute, QuirksMode};
+use html_tokenizer::TokenizerState;
+use foreign_content::{adjust_svg_tag_name, adjust_attribute_name, is_breakout_tag, is_mathml_text_integration_point,
+    is_html_integration_point};
+
+/// Elements that have special parsing rules, ie they close p or stop search for matching end tag
+pub const SPECIAL_TAGS: [&str; 82] = ["address", "applet", "area", "article", "aside", "base", "basefont", "bgsound",
+    "blockquote", "body", "br", "button", "caption", "center", "col", "colgroup", "dd", "details", "dir", "div",
+    "dl", "dt", "embed", "fieldset", "figcaption", "figure", "footer", "form", "frame", "frameset", "h1", "h2",
+    "h3", "h4", "h5", "h6", "head", "header", "hgroup", "hr", "html", "iframe", "img", "input", "isindex", "li",
+    "link", "listing", "main", "marquee", "menu", "meta", "nav", "noembed", "noframes", "noscript", "object", "ol",
+    "p", "param", "plaintext", "pre", "script", "section", "select", "source", "style", "summary", "table",
+    "tbody", "td", "template", "textarea", "tfoot", "th", "thead", "title", "tr", "track", "ul", "wbr", "xmp"];
+
+/// Elements that limit default scope
+pub const SCOPE_TAGS: [&str; 9] = ["applet", "caption", "html", "table", "td", "th", "marquee", "object", "template"];
+
+/// Elements that are closed when end tag of their parent is seen
+const IMPLIED_END_TAGS: [&str; 10] = ["dd", "dt", "li", "optgroup", "option", "p", "rb", "rp", "rt", "rtc"];
+
+/// Elements that are closed at the end of template
+const THOROUGH_IMPLIED_END_TAGS: [&str; 18] = ["caption", "colgroup", "dd", "dt", "li", "optgroup", "option", "p",
+    "rb", "rp", "rt", "rtc", "tbody", "td", "tfoot", "th", "thead", "tr"];
+
+/// Formatting elements are reopened when they were closed by other elements
+const FORMATTING_TAGS: [&str; 14] = ["a", "b", "big", "code", "em", "font", "i", "nobr", "s", "small", "strike",
+    "strong", "tt", "u"];
+
+pub const HEADING_TAGS: [&str; 6] = ["h1", "h2", "h3", "h4", "h5", "h6"];
+
+/// Elements which start tags close p and open new block
+pub const BLOCK_TAGS: [&str; 24] = ["address", "article", "aside", "blockquote", "center", "details", "dialog", "dir",
+    "div", "dl", "fieldset", "figcaption", "figure", "footer", "header", "hgroup", "main", "menu", "nav", "ol", "p",
+    "section", "summary", "ul"];
+
+/// Elements which end tags close them along with elements implied to end
+const BLOCK_END_TAGS: [&str; 26] = ["address", "article", "aside", "blockquote", "button", "center", "details",
+    "dialog", "dir", "div", "dl", "fieldset", "figcaption", "figure", "footer", "header", "hgroup", "listing",
+    "main", "menu", "nav", "ol", "pre", "section", "summary", "ul"];
+
+/// Elements in which text is foster parented when they are current node in table
+const TABLE_TAGS: [&str; 5] = ["table", "tbody", "tfoot", "thead", "tr"];
+
+/// Start tags of elements that are processed by rules for head when they are seen after head
+const HEAD_TAGS: [&str; 11] = ["base", "basefont", "bgsound", "link", "meta", "noframes", "script", "style",
+    "template", "title", "head"];
+
+/// Public identifiers (lowercased prefixes) of DOCTYPEs that switch document to quirks mode
+const QUIRKS_PUBLIC_PREFIXES: [&str; 55] = [
+    "+//silmaril//dtd html pro v0r11 19970101//",
+    "-//advasoft ltd//dtd html 3.0 aswedit + extensions//",
+    "-//as//dtd html 3.0 aswedit + extensions//",
+    "-//ietf//dtd html 2.0 level 1//",
+    "-//ietf//dtd html 2.0 level 2//",
+    "-//ietf//dtd html 2.0 strict level 1//",
+    "-//ietf//dtd html 2.0 strict level 2//",
+    "-//ietf//dtd html 2.0 strict//",
+    "-//ietf//dtd html 2.0//",
+    "-//ietf//dtd html 2.1e//",
+    "-//ietf//dtd html 3.0//",
+    "-//ietf//dtd html 3.2 final//",
+    "-//ietf//dtd html 3.2//",
+    "-//ietf//dtd html 3//",
+    "-//ietf//dtd html level 0//",
+    "-//ietf//dtd html level 1//",
+    "-//ietf//dtd html level 2//",
+    "-//ietf//dtd html level 3//",
+    "-//ietf//dtd html strict level 0//",
+    "-//ietf//dtd html strict level 1//",
+    "-//ietf//dtd html strict level 2//",
+    "-//ietf//dtd html strict level 3//",
+    "-//ietf//dtd html strict//",
+    "-//ietf//dtd html//",
+    "-//metrius//dtd metrius presentational//",
+    "-//microsoft//dtd internet explorer 2.0 html strict//",
+    "-//microsoft//dtd internet explorer 2.0 html//",
+    "-//microsoft//dtd internet explorer 2.0 tables//",
+    "-//microsoft//dtd internet explorer 3.0 html strict//",
+    "-//microsoft//dtd internet explorer 3.0 html//",
+    "-//microsoft//dtd internet explorer 3.0 tables//",
+    "-//netscape comm. corp.//dtd html//",
+    "-//netscape comm. corp.//dtd strict html//",
+    "-//o'reilly and associates//dtd html 2.0//",
+    "-//o'reilly and associates//dtd html extended 1.0//",
+    "-//o'reilly and associates//dtd html extended relaxed 1.0//",
+    "-//softquad software//dtd hotmetal pro 6.0::19990601::extensions to html 4.0//",
+    "-//softquad//dtd hotmetal pro 4.0::19971010::extensions to html 4.0//",
+    "-//spyglass//dtd html 2.0 extended//",
+    "-//sq//dtd html 2.0 hotmetal + extensions//",
+    "-//sun microsystems corp.//dtd hotjava html//",
+    "-//sun microsystems corp.//dtd hotjava strict html//",
+    "-//w3c//dtd html 3 1995-03-24//",
+    "-//w3c//dtd html 3.2 draft//",
+    "-//w3c//dtd html 3.2 final//",
+    "-//w3c//dtd html 3.2//",
+    "-//w3c//dtd html 3.2s draft//",
+    "-//w3c//dtd html 4.0 frameset//",
+    "-//w3c//dtd html 4.0 transitional//",
+    "-//w3c//dtd html experimental 19960712//",
+    "-//w3c//dtd html experimental 970421//",
+    "-//w3c//dtd w3 html//",
+    "-//w3o//dtd w3 html 3.0//",
+    "-//webtechs//dtd mozilla html 2.0//",
+    "-//webtechs//dtd mozilla html//",
+];
+
+/// Insertion modes of tree construction, each of them has own rules for tokens
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Mode {
+    Initial,
+    BeforeHtml,
+    BeforeHead,
+    InHead,
+    InHeadNoscript,
+    AfterHead,
+    InBody,
+    Text,
+    InTable,
+    InTableText,
+    InCaption,
+    InColumnGroup,
+    InTableBody,
+    InRow,
+    InCell,
+    InSelect,
+    InSelectInTable,
+    InTemplate,
+    AfterBody,
+    InFrameset,
+    AfterFrameset,
+    AfterAfterBody,
+    AfterAfterFrameset,
+}
+
+/// Kinds of scope in which elements are searched in stack of open elements
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Scope {
+    Default,
+    ListItem,
+    Button,
+    Table,
+    Select,
+}
+
+/// Start tag as tree construction sees it
+#[derive(Clone)]
+struct Tag {
+    name: String,
+    attributes: Vec<Attribute>,
+    self_closing: bool,
+}
+
+impl Tag {
+    fn new(name: &str) -> Tag {
+        Tag {
+            name: name.to_string(),
+            attributes: Vec::new(),
+            self_closing: false,
+        }
+    }
+
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|attribute| attribute.name == name).map(|attribute| attribute.value.as_str())
+    }
+
+    /// Returns true if tags have the same name and attributes, order of attributes does not matter
+    fn same_as(&self, other: &Tag) -> bool {
+        self.name == other.name && self.attributes.len() == other.attributes.len()
+            && self.attributes.iter().all(|attribute| other.attributes.contains(attribute))
+    }
+}
+
+enum Token {
+    Doctype(Doctype),
+    StartTag(Tag),
+    EndTag(String),
+    Text(String),
+    Comment(String),
+    Eof,
+}
+
+/// Entry in list of active formatting elements, tag is kept to create the same element again
+enum Formatting {
+    Marker,
+    Element(NodeId, Tag),
+}
+
+/// Builds document from chunks of conformance mode following WHATWG tree construction
+pub struct TreeBuilder {
+    document: Document,
+    mode: Mode,
+
+    /// Mode to return to after Text or InTableText
+    original_mode: Mode,
+
+    /// Stack of template insertion modes, the last one is current
+    template_modes: Vec<Mode>,
+
+    /// Stack of open elements, the last one is current node
+    open_elements: Vec<NodeId>,
+
+    /// List of active formatting elements with markers
+    active_formatting: Vec<Formatting>,
+
+    head: Option<NodeId>,
+    form: Option<NodeId>,
+    frameset_ok: bool,
+
+    /// If true then nodes that would be inserted in table are inserted before it
+    foster_parenting: bool,
+
+    scripting_enabled: bool,
+
+    /// Chars seen in table, they are inserted all at once when they end
+    pending_table_text: String,
+
+    /// If true then newline at the start of next text is ignored (after pre, listing or textarea)
+    skip_newline: bool,
+
+    /// State that tokenizer must switch to before parsing next token
+    tokenizer_state: Option<TokenizerState>,
+
+    /// Offset and length of chunk that is being processed
+    source: (usize, usize),
+
+    stopped: bool,
+
+    /// Element in which fragment is parsed, it is not in the tree
+    context: Option<NodeId>,
+}
+
+impl TreeBuilder {
+    pub fn new(scripting_enabled: bool) -> TreeBuilder {
+        TreeBuilder {
+            document: Document::new(),
+            mode: Mode::Initial,
+            original_mode: Mode::Initial,
+            template_modes: Vec::new(),
+            open_elements: Vec::new(),
+            active_formatting: Vec::new(),
+            head: None,
+            form: None,
+            frameset_ok: true,
+            foster_parenting: false,
+            scripting_enabled,
+            pending_table_text: String::new(),
+            skip_newline: false,
+            tokenizer_state: None,
+            source: (0, 0),
+            stopped: false,
+            context: None,
+        }
+    }
+
+    /// Creates builder that parses fragment as if it was content of context element, ie tr puts it into
+    /// InRow mode. Tokenizer must switch to the state returned by the first call of take_tokenizer_state
+    pub fn new_fragment(namespace: Namespace, context: &str, scripting_enabled: bool) -> TreeBuilder {
+        let mut builder = TreeBuilder::new(scripting_enabled);
+        let context_node = builder.document.create_node(NodeData::Element(Element::new_in(namespace, context)));
+
+        builder.context = Some(context_node);
+        builder.insert_html(None);
+
+        let html_context = if namespace == Namespace::Html { context } else { "" };
+
+        builder.tokenizer_state = Some(match html_context {
+            "title" | "textarea" => TokenizerState::Rcdata,
+            "style" | "xmp" | "iframe" | "noembed" | "noframes" => TokenizerState::Rawtext,
+            "noscript" if scripting_enabled => TokenizerState::Rawtext,
+            "script" => TokenizerState::ScriptData,
+            "plaintext" => TokenizerState::Plaintext,
+            _ => TokenizerState::Data,
+        });
+
+        if html_context == "template" {
+            builder.template_modes.push(Mode::InTemplate);
+        }
+
+        if html_context == "form" {
+            builder.form = Some(context_node);
+        }
+
+        builder.reset_insertion_mode();
+        builder
+    }
+
+    /// Returns state tokenizer must switch to before parsing next token, if any
+    pub fn take_tokenizer_state(&mut self) -> Option<TokenizerState> {
+        self.tokenizer_state.take()
+    }
+
+    /// Returns true if tokenizer must parse CDATA sections, they are only allowed in SVG and MathML
+    pub fn cdata_allowed(&self) -> bool {
+        self.adjusted_current_node().is_some_and(|node| self.namespace(node) != Namespace::Html)
+    }
+
+    /// Processes chunk returned by parser in conformance mode, see take_tokenizer_state and cdata_allowed
+    /// for what tokenizer must do before parsing next chunk
+    pub fn add_chunk(&mut self, chunk: &HtmlChunk) {
+        self.source = (chunk.chunk_offset, chunk.chunk_length);
+
+        let mut tokens = Vec::with_capacity(1);
+
+        match chunk.chunk_type {
+            ChunkType::Text | ChunkType::Template => tokens.push(Token::Text(chunk.html.clone())),
+            ChunkType::OpenTag => tokens.push(Token::StartTag(tag(chunk))),
+            ChunkType::CloseTag => tokens.push(Token::EndTag(chunk.tag.to_ascii_lowercase())),
+            ChunkType::Comment => tokens.push(Token::Comment(chunk.html.clone())),
+            ChunkType::Doctype => tokens.push(Token::Doctype(chunk.doctype.clone().unwrap_or_default())),
+            ChunkType::Script => {
+                // conformance mode returns scripts as tags and text, but whole script is fine as well
+                tokens.push(Token::StartTag(tag(chunk)));
+                tokens.push(Token::Text(chunk.html.clone()));
+                tokens.push(Token::EndTag(chunk.tag.clone()));
+            },
+        }
+
+        for mut token in tokens {
+            if mem::replace(&mut self.skip_newline, false) {
+                if let Token::Text(ref mut text) = token {
+                    if text.starts_with('\n') {
+                        text.remove(0);
+                    }
+                }
+            }
+
+            self.process(token);
+        }
+    }
+
+    /// Returns built document after processing end of data, top level nodes of fragment are moved
+    /// from html element to the root of document
+    pub fn finish(mut self) -> Document {
+        self.process(Token::Eof);
+
+        if self.context.is_some() {
+            let root = self.document.root();
+            let html = self.document[root].first_child().expect("html element");
+
+            self.document.detach(html);
+
+            while let Some(child) = self.document[html].first_child() {
+                self.document.detach(child);
+                self.document.append_child(root, child);
+            }
+        }
+
+        self.document
+    }
+
+    fn process(&mut self, token: Token) {
+        let mut token = token;
+
+        loop {
+            if self.stopped {
+                return;
+            }
+
+            if let Token::Text(ref text) = token {
+                if text.is_empty() {
+                    return;
+                }
+            }
+
+            let reprocess = if self.is_foreign(&token) {
+                self.foreign_content(token)
+            } else {
+                self.process_in_mode(self.mode, token)
+            };
+
+            match reprocess {
+                Some(next) => token = next,
+                None => return,
+            }
+        }
+    }
+
+    /// Processes token using rules of given mode, returns token if it must be processed again in current mode
+    fn process_in_mode(&mut self, mode: Mode, token: Token) -> Option<Token> {
+        match mode {
+            Mode::Initial => self.initial(token),
+            Mode::BeforeHtml => self.before_html(token),
+            Mode::BeforeHead => self.before_head(token),
+            Mode::InHead => self.in_head(token),
+            Mode::InHeadNoscript => self.in_head_noscript(token),
+            Mode::AfterHead => self.after_head(token),
+            Mode::InBody => self.in_body(token),
+            Mode::Text => self.text(token),
+            Mode::InTable => self.in_table(token),
+            Mode::InTableText => self.in_table_text(token),
+            Mode::InCaption => self.in_caption(token),
+            Mode::InColumnGroup => self.in_column_group(token),
+            Mode::InTableBody => self.in_table_body(token),
+            Mode::InRow => self.in_row(token),
+            Mode::InCell => self.in_cell(token),
+            Mode::InSelect => self.in_select(token),
+            Mode::InSelectInTable => self.in_select_in_table(token),
+            Mode::InTemplate => self.in_template(token),
+            Mode::AfterBody => self.after_body(token),
+            Mode::InFrameset => self.in_frameset(token),
+            Mode::AfterFrameset => self.after_frameset(token),
+            Mode::AfterAfterBody => self.after_after_body(token),
+            Mode::AfterAfterFrameset => self.after_after_frameset(token),
+        }
+    }
+
+    fn initial(&mut self, token: Token) -> Option<Token> {
+        match token {
+            Token::Text(text) => {
+                let rest = trim_whitespace(&text);
+
+                if rest.is_empty() {
+                    return None;
+                }
+
+                self.document.set_quirks_mode(QuirksMode::Quirks);
+                self.mode = Mode::BeforeHtml;
+
+                Some(Token::Text(rest.to_string()))
+            },
+            Token::Comment(text) => {
+                let root = self.document.root();
+
+                self.append_comment(root, text);
+                None
+            },
+            Token::Doctype(doctype) => {
+                let quirks_mode = doctype_quirks_mode(&doctype);
+                let root = self.document.root();
+                let node = self.create_node(NodeData::Doctype(doctype));
+
+                self.document.append_child(root, node);
+                self.document.set_quirks_mode(quirks_mode);
+                self.mode = Mode::BeforeHtml;
+                None
+            },
+            token => {
+                self.document.set_quirks_mode(QuirksMode::Quirks);
+                self.mode = Mode::BeforeHtml;
+
+                Some(token)
+            },
+        }
+    }
+
+    fn before_html(&mut self, token: Token) -> Option<Token> {
+        match token {
+            Token::Text(text) => {
+                let rest = trim_whitespace(&text);
+
+                if rest.is_empty() {
+                    return None;
+                }
+
+                self.insert_html(None);
+
+                Some(Token::Text(rest.to_string()))
+            },
+            Token::Comment(text) => {
+                let root = self.document.root();
+
+                self.append_comment(root, text);
+                None
+            },
+            Token::Doctype(_) => None,
+            Token::StartTag(ref tag) if tag.name == "html" => {
+                self.insert_html(Some(tag));
+                None
+            },
+            Token::EndTag(ref name) if !matches!(name.as_str(), "head" | "body" | "html" | "br") => None,
+            token => {
+                self.insert_html(None);
+
+                Some(token)
+            },
+        }
+    }
+
+    /// Creates html element and switches to BeforeHead, element is implied if there is no tag
+    fn insert_html(&mut self, tag: Option<&Tag>) {
+        let node = match tag {
+            Some(tag) => self.create_element(tag, true),
+            None => self.create_element(&Tag::new("html"), false),
+        };
+        let root = self.document.root();
+
+        self.document.append_child(root, node);
+        self.open_elements.push(node);
+        self.mode = Mode::BeforeHead;
+    }
+
+    fn before_head(&mut self, token: Token) -> Option<Token> {
+        match token {
+            Token::Text(text) => {
+                let rest = trim_whitespace(&text);
+
+                if rest.is_empty() {
+                    return None;
+                }
+
+                self.insert_head(None);
+
+                Some(Token::Text(rest.to_string()))
+            },
+            Token::Comment(text) => {
+                self.insert_comment(text);
+                None
+            },
+            Token::Doctype(_) => None,
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            Token::StartTag(ref tag) if tag.name == "head" => {
+                self.insert_head(Some(tag));
+                None
+            },
+            Token::EndTag(ref name) if !matches!(name.as_str(), "head" | "body" | "html" | "br") => None,
+            token => {
+                self.insert_head(None);
+
+                Some(token)
+            },
+        }
+    }
+
+    fn insert_head(&mut self, tag: Option<&Tag>) {
+        let head = match tag {
+            Some(tag) => self.insert_element(tag),
+            None => self.insert_implied("head"),
+        };
+
+        self.head = Some(head);
+        self.mode = Mode::InHead;
+    }
+
+    fn in_head(&mut self, token: Token) -> Option<Token> {
+        match token {
+            Token::Text(text) => {
+                let (whitespace, rest) = split_whitespace(&text);
+
+                self.insert_text(whitespace);
+
+                if rest.is_empty() {
+                    return None;
+                }
+
+                self.pop();
+                self.mode = Mode::AfterHead;
+
+                Some(Token::Text(rest.to_string()))
+            },
+            Token::Comment(text) => {
+                self.insert_comment(text);
+                None
+            },
+            Token::Doctype(_) => None,
+            Token::StartTag(tag) => {
+                match tag.name.as_str() {
+                    "html" => return self.in_body(Token::StartTag(tag)),
+                    "base" | "basefont" | "bgsound" | "link" | "meta" => {
+                        self.insert_element(&tag);
+                        self.pop();
+                    },
+                    "title" => self.insert_text_element(&tag, TokenizerState::Rcdata),
+                    "noscript" if self.scripting_enabled => self.insert_text_element(&tag, TokenizerState::Rawtext),
+                    "noframes" | "style" => self.insert_text_element(&tag, TokenizerState::Rawtext),
+                    "noscript" => {
+                        self.insert_element(&tag);
+                        self.mode = Mode::InHeadNoscript;
+                    },
+                    "script" => self.insert_text_element(&tag, TokenizerState::ScriptData),
+                    "template" => {
+                        self.insert_element(&tag);
+                        self.active_formatting.push(Formatting::Marker);
+                        self.frameset_ok = false;
+                        self.mode = Mode::InTemplate;
+                        self.template_modes.push(Mode::InTemplate);
+                    },
+                    "head" => {},
+                    _ => {
+                        self.pop();
+                        self.mode = Mode::AfterHead;
+
+                        return Some(Token::StartTag(tag));
+                    },
+                }
+
+                None
+            },
+            Token::EndTag(name) => {
+                match name.as_str() {
+                    "head" => {
+                        self.pop();
+                        self.mode = Mode::AfterHead;
+                    },
+                    "body" | "html" | "br" => {
+                        self.pop();
+                        self.mode = Mode::AfterHead;
+
+                        return Some(Token::EndTag(name));
+                    },
+                    "template" => self.end_template(),
+                    _ => {},
+                }
+
+                None
+            },
+            Token::Eof => {
+                self.pop();
+                self.mode = Mode::AfterHead;
+
+                Some(Token::Eof)
+            },
+        }
+    }
+
+    /// Closes template along with everything that was opened in it
+    fn end_template(&mut self) {
+        if !self.has_open_element("template") {
+            return;
+        }
+
+        self.generate_implied_end_tags_thoroughly();
+        self.pop_until(&["template"]);
+        self.clear_formatting_to_marker();
+        self.template_modes.pop();
+        self.reset_insertion_mode();
+    }
+
+    fn in_head_noscript(&mut self, token: Token) -> Option<Token> {
+        match token {
+            Token::Text(text) => {
+                let (whitespace, rest) = split_whitespace(&text);
+
+                self.insert_text(whitespace);
+
+                if rest.is_empty() {
+                    return None;
+                }
+
+                self.pop();
+                self.mode = Mode::InHead;
+
+                Some(Token::Text(rest.to_string()))
+            },
+            Token::Comment(_) => self.in_head(token),
+            Token::Doctype(_) => None,
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            Token::StartTag(ref tag) if matches!(tag.name.as_str(),
+                "basefont" | "bgsound" | "link" | "meta" | "noframes" | "style") => self.in_head(token),
+            Token::StartTag(ref tag) if tag.name == "head" || tag.name == "noscript" => None,
+            Token::EndTag(ref name) if name == "noscript" => {
+                self.pop();
+                self.mode = Mode::InHead;
+                None
+            },
+            Token::EndTag(ref name) if name != "br" => None,
+            token => {
+                self.pop();
+                self.mode = Mode::InHead;
+
+                Some(token)
+            },
+        }
+    }
+
+    fn after_head(&mut self, token: Token) -> Option<Token> {
+        match token {
+            Token::Text(text) => {
+                let (whitespace, rest) = split_whitespace(&text);
+
+                self.insert_text(whitespace);
+
+                if rest.is_empty() {
+                    return None;
+                }
+
+                self.insert_implied("body");
+                self.mode = Mode::InBody;
+
+                Some(Token::Text(rest.to_string()))
+            },
+            Token::Comment(text) => {
+                self.insert_comment(text);
+                None
+            },
+            Token::Doctype(_) => None,
+            Token::StartTag(tag) => {
+                match tag.name.as_str() {
+                    "html" => return self.in_body(Token::StartTag(tag)),
+                    "body" => {
+                        self.insert_element(&tag);
+                        self.frameset_ok = false;
+                        self.mode = Mode::InBody;
+                    },
+                    "frameset" => {
+                        self.insert_element(&tag);
+                        self.mode = Mode::InFrameset;
+                    },
+                    "head" => {},
+                    name if HEAD_TAGS.contains(&name) => {
+                        let head = self.head.expect("head element");
+
+                        self.open_elements.push(head);
+
+                        let reprocess = self.in_head(Token::StartTag(tag));
+
+                        self.remove_from_stack(head);
+
+                        return reprocess;
+                    },
+                    _ => {
+                        self.insert_implied("body");
+                        self.mode = Mode::InBody;
+
+                        return Some(Token::StartTag(tag));
+                    },
+                }
+
+                None
+            },
+            Token::EndTag(name) => {
+                match name.as_str() {
+                    "template" => return self.in_head(Token::EndTag(name)),
+                    "body" | "html" | "br" => {
+                        self.insert_implied("body");
+                        self.mode = Mode::InBody;
+
+                        return Some(Token::EndTag(name));
+                    },
+                    _ => {},
+                }
+
+                None
+            },
+            Token::Eof => {
+                self.insert_implied("body");
+                self.mode = Mode::InBody;
+
+                Some(Token::Eof)
+            },
+        }
+    }
+
+    fn in_body(&mut self, token: Token) -> Option<Token> {
+        match token {
+            Token::Text(text) => {
+                let text = text.replace('\0', "");
+
+                if text.is_empty() {
+                    return None;
+                }
+
+                self.reconstruct_formatting();
+                self.insert_text(&text);
+
+                if !trim_whitespace(&text).is_empty() {
+                    self.frameset_ok = false;
+                }
+
+                None
+            },
+            Token::Comment(text) => {
+                self.insert_comment(text);
+                None
+            },
+            Token::Doctype(_) => None,
+            Token::StartTag(tag) => self.in_body_start_tag(tag),
+            Token::EndTag(name) => self.in_body_end_tag(name),
+            Token::Eof => {
+                if !self.template_modes.is_empty() {
+                    return self.in_template(Token::Eof);
+                }
+
+                self.stopped = true;
+                None
+            },
+        }
+    }
+
+    fn in_body_start_tag(&mut self, tag: Tag) -> Option<Token> {
+        let name = tag.name.clone();
+
+        match name.as_str() {
+            "html" => {
+                if !self.has_open_element("template") {
+                    let html = self.open_elements[0];
+
+                    self.add_missing_attributes(html, &tag);
+                }
+            },
+            "base" | "basefont" | "bgsound" | "link" | "meta" | "noframes" | "script" | "style" | "template"
+                | "title" => return self.in_head(Token::StartTag(tag)),
+            "body" => {
+                if self.open_elements.len() == 1 || self.name(self.open_elements[1]) != "body"
+                    || self.has_open_element("template") {
+                    return None;
+                }
+
+                self.frameset_ok = false;
+
+                let body = self.open_elements[1];
+
+                self.add_missing_attributes(body, &tag);
+            },
+            "frameset" => {
+                if self.open_elements.len() == 1 || self.name(self.open_elements[1]) != "body" || !self.frameset_ok {
+                    return None;
+                }
+
+                let body = self.open_elements[1];
+
+                self.document.detach(body);
+                self.open_elements.truncate(1);
+                self.insert_element(&tag);
+                self.mode = Mode::InFrameset;
+            },
+            name if BLOCK_TAGS.contains(&name) => {
+                self.close_p_in_button_scope();
+                self.insert_element(&tag);
+            },
+            name if HEADING_TAGS.contains(&name) => {
+                self.close_p_in_button_scope();
+
+                if HEADING_TAGS.contains(&self.current_name()) {
+                    self.pop();
+                }
+
+                self.insert_element(&tag);
+            },
+            "pre" | "listing" => {
+                self.close_p_in_button_scope();
+                self.insert_element(&tag);
+                self.skip_newline = true;
+                self.frameset_ok = false;
+            },
+            "form" => {
+                let in_template = self.has_open_element("template");
+
+                if self.form.is_some() && !in_template {
+                    return None;
+                }
+
+                self.close_p_in_button_scope();
+
+                let form = self.insert_element(&tag);
+
+                if !in_template {
+                    self.form = Some(form);
+                }
+            },
+            "li" | "dd" | "dt" => {
+                self.frameset_ok = false;
+
+                let closed: &[&str] = if name == "li" { &["li"] } else { &["dd", "dt"] };
+
+                for i in (0..self.open_elements.len()).rev() {
+                    let node = self.open_elements[i];
+                    let node_name = self.name(node).to_string();
+
+                    if closed.contains(&node_name.as_str()) {
+                        self.generate_implied_end_tags(&node_name);
+                        self.pop_until(&[&node_name]);
+                        break;
+                    }
+
+                    if self.is_special(node) && !matches!(node_name.as_str(), "address" | "div" | "p") {
+                        break;
+                    }
+                }
+
+                self.close_p_in_button_scope();
+                self.insert_element(&tag);
+            },
+            "plaintext" => {
+                self.close_p_in_button_scope();
+                self.insert_element(&tag);
+                self.tokenizer_state = Some(TokenizerState::Plaintext);
+            },
+            "button" => {
+                if self.in_scope(&["button"], Scope::Default) {
+                    self.generate_implied_end_tags("");
+                    self.pop_until(&["button"]);
+                }
+
+                self.reconstruct_formatting();
+                self.insert_element(&tag);
+                self.frameset_ok = false;
+            },
+            "a" => {
+                let open_a = self.formatting_after_marker("a");
+
+                if let Some(a) = open_a {
+                    self.adoption_agency("a");
+                    self.remove_from_formatting(a);
+                    self.remove_from_stack(a);
+                }
+
+                self.reconstruct_formatting();
+
+                let node = self.insert_element(&tag);
+
+                self.push_formatting(node, tag);
+            },
+            "nobr" => {
+                self.reconstruct_formatting();
+
+                if self.in_scope(&["nobr"], Scope::Default) {
+                    self.adoption_agency("nobr");
+                    self.reconstruct_formatting();
+                }
+
+                let node = self.insert_element(&tag);
+
+                self.push_formatting(node, tag);
+            },
+            name if FORMATTING_TAGS.contains(&name) => {
+                self.reconstruct_formatting();
+
+                let node = self.insert_element(&tag);
+
+                self.push_formatting(node, tag);
+            },
+            "applet" | "marquee" | "object" => {
+                self.reconstruct_formatting();
+                self.insert_element(&tag);
+                self.active_formatting.push(Formatting::Marker);
+                self.frameset_ok = false;
+            },
+            "table" => {
+                if self.document.quirks_mode() != QuirksMode::Quirks {
+                    self.close_p_in_button_scope();
+                }
+
+                self.insert_element(&tag);
+                self.frameset_ok = false;
+                self.mode = Mode::InTable;
+            },
+            "area" | "br" | "embed" | "img" | "keygen" | "wbr" | "input" => {
+                self.reconstruct_formatting();
+                self.insert_element(&tag);
+                self.pop();
+
+                if name != "input" || !is_hidden_input(&tag) {
+                    self.frameset_ok = false;
+                }
+            },
+            "param" | "source" | "track" => {
+                self.insert_element(&tag);
+                self.pop();
+            },
+            "hr" => {
+                self.close_p_in_button_scope();
+                self.insert_element(&tag);
+                self.pop();
+                self.frameset_ok = false;
+            },
+            "image" => {
+                let mut tag = tag;
+
+                tag.name = "img".to_string();
+
+                return Some(Token::StartTag(tag));
+            },
+            "textarea" => {
+                self.insert_text_element(&tag, TokenizerState::Rcdata);
+                self.skip_newline = true;
+                self.frameset_ok = false;
+            },
+            "xmp" => {
+                self.close_p_in_button_scope();
+                self.reconstruct_formatting();
+                self.frameset_ok = false;
+                self.insert_text_element(&tag, TokenizerState::Rawtext);
+            },
+            "iframe" => {
+                self.frameset_ok = false;
+                self.insert_text_element(&tag, TokenizerState::Rawtext);
+            },
+            "noembed" => self.insert_text_element(&tag, TokenizerState::Rawtext),
+            "noscript" if self.scripting_enabled => self.insert_text_element(&tag, TokenizerState::Rawtext),
+            "select" => {
+                self.reconstruct_formatting();
+                self.insert_element(&tag);
+                self.frameset_ok = false;
+                self.mode = match self.mode {
+                    Mode::InTable | Mode::InCaption | Mode::InTableBody | Mode::InRow | Mode::InCell => {
+                        Mode::InSelectInTable
+                    },
+                    _ => Mode::InSelect,
+                };
+            },
+            "optgroup" | "option" => {
+                if self.current_name() == "option" {
+                    self.pop();
+                }
+
+                self.reconstruct_formatting();
+                self.insert_element(&tag);
+            },
+            "rb" | "rtc" => {
+                if self.in_scope(&["ruby"], Scope::Default) {
+                    self.generate_implied_end_tags("");
+                }
+
+                self.insert_element(&tag);
+            },
+            "rp" | "rt" => {
+                if self.in_scope(&["ruby"], Scope::Default) {
+                    self.generate_implied_end_tags("rtc");
+                }
+
+                self.insert_element(&tag);
+            },
+            "math" => {
+                self.reconstruct_formatting();
+                self.insert_foreign_element(&tag, Namespace::MathMl);
+            },
+            "svg" => {
+                self.reconstruct_formatting();
+                self.insert_foreign_element(&tag, Namespace::Svg);
+            },
+            "caption" | "col" | "colgroup" | "frame" | "head" | "tbody" | "td" | "tfoot" | "th" | "thead"
+                | "tr" => {},
+            _ => {
+                self.reconstruct_formatting();
+                self.insert_element(&tag);
+            },
+        }
+
+        None
+    }
+
+    fn in_body_end_tag(&mut self, name: String) -> Option<Token> {
+        match name.as_str() {
+            "template" => return self.in_head(Token::EndTag(name)),
+            "body" => {
+                if self.in_scope(&["body"], Scope::Default) {
+                    self.mode = Mode::AfterBody;
+                }
+            },
+            "html" => {
+                if self.in_scope(&["body"], Scope::Default) {
+                    self.mode = Mode::AfterBody;
+
+                    return Some(Token::EndTag(name));
+                }
+            },
+            name if BLOCK_END_TAGS.contains(&name) => {
+                if self.in_scope(&[name], Scope::Default) {
+                    self.generate_implied_end_tags("");
+                    self.pop_until(&[name]);
+                }
+            },
+            "form" => {
+                if self.has_open_element("template") {
+                    if self.in_scope(&["form"], Scope::Default) {
+                        self.generate_implied_end_tags("");
+                        self.pop_until(&["form"]);
+                    }
+
+                    return None;
+                }
+
+                let form = self.form.take()?;
+
+                if !self.node_in_scope(form) {
+                    return None;
+                }
+
+                self.generate_implied_end_tags("");
+                self.remove_from_stack(form);
+            },
+            "p" => {
+                if !self.in_scope(&["p"], Scope::Button) {
+                    self.insert_implied("p");
+                }
+
+                self.close_p();
+            },
+            "li" => {
+                if self.in_scope(&["li"], Scope::ListItem) {
+                    self.generate_implied_end_tags("li");
+                    self.pop_until(&["li"]);
+                }
+            },
+            "dd" | "dt" => {
+                if self.in_scope(&[&name], Scope::Default) {
+                    self.generate_implied_end_tags(&name);
+                    self.pop_until(&[&name]);
+                }
+            },
+            name if HEADING_TAGS.contains(&name) => {
+                if self.in_scope(&HEADING_TAGS, Scope::Default) {
+                    self.generate_implied_end_tags("");
+                    self.pop_until(&HEADING_TAGS);
+                }
+            },
+            name if FORMATTING_TAGS.contains(&name) => {
+                if !self.adoption_agency(name) {
+                    self.any_other_end_tag(name);
+                }
+            },
+            "applet" | "marquee" | "object" => {
+                if self.in_scope(&[&name], Scope::Default) {
+                    self.generate_implied_end_tags("");
+                    self.pop_until(&[&name]);
+                    self.clear_formatting_to_marker();
+                }
+            },
+            "br" => {
+                return self.in_body_start_tag(Tag::new("br"));
+            },
+            _ => self.any_other_end_tag(&name),
+        }
+
+        None
+    }
+
+    /// Closes the nearest open element with given name unless there is special element before it
+    fn any_other_end_tag(&mut self, name: &str) {
+        for i in (0..self.open_elements.len()).rev() {
+            let node = self.open_elements[i];
+            let node_name = self.name(node).to_string();
+
+            if node_name == name {
+                self.generate_implied_end_tags(name);
+                self.open_elements.truncate(i);
+                return;
+            }
+
+            if self.is_special(node) {
+                return;
+            }
+        }
+    }
+
+    /// Fixes misnested formatting elements, returns false if end tag must be processed as any other end tag
+    fn adoption_agency(&mut self, subject: &str) -> bool {
+        let current = self.current_node();
+
+        if self.name(current) == subject && self.formatting_index(current).is_none() {
+            self.pop();
+            return true;
+        }
+
+        for _ in 0..8 {
+            let formatting_element = match self.formatting_after_marker(subject) {
+                Some(element) => element,
+                None => return false,
+            };
+
+            let formatting_pos = match self.open_elements.iter().position(|&id| id == formatting_element) {
+                Some(pos) => pos,
+                None => {
+                    self.remove_from_formatting(formatting_element);
+                    return true;
+                },
+            };
+
+            if !self.node_in_scope(formatting_element) {
+                return true;
+            }
+
+            let furthest_block = (formatting_pos + 1..self.open_elements.len())
+                .find(|&i| self.is_special(self.open_elements[i]));
+
+            let furthest_block_pos = match furthest_block {
+                Some(pos) => pos,
+                None => {
+                    self.open_elements.truncate(formatting_pos);
+                    self.remove_from_formatting(formatting_element);
+                    return true;
+                },
+            };
+
+            let furthest_block = self.open_elements[furthest_block_pos];
+            let common_ancestor = self.open_elements[formatting_pos - 1];
+            let mut bookmark = self.formatting_index(formatting_element).expect("formatting element");
+            let mut node_pos = furthest_block_pos;
+            let mut last_node = furthest_block;
+            let mut inner = 0;
+
+            loop {
+                inner += 1;
+                node_pos -= 1;
+
+                let node = self.open_elements[node_pos];
+
+                if node == formatting_element {
+                    break;
+                }
+
+                let mut node_index = self.formatting_index(node);
+
+                if inner > 3 {
+                    if let Some(index) = node_index {
+                        self.active_formatting.remove(index);
+
+                        if index < bookmark {
+                            bookmark -= 1;
+                        }
+
+                        node_index = None;
+                    }
+                }
+
+                let node_index = match node_index {
+                    Some(index) => index,
+                    None => {
+                        self.open_elements.remove(node_pos);
+                        continue;
+                    },
+                };
+
+                let tag = match self.active_formatting[node_index] {
+                    Formatting::Element(_, ref tag) => tag.clone(),
+                    Formatting::Marker => unreachable!(),
+                };
+
+                let new_node = self.create_element(&tag, false);
+
+                self.active_formatting[node_index] = Formatting::Element(new_node, tag);
+                self.open_elements[node_pos] = new_node;
+
+                if last_node == furthest_block {
+                    bookmark = node_index + 1;
+                }
+
+                self.document.detach(last_node);
+                self.document.append_child(new_node, last_node);
+
+                last_node = new_node;
+            }
+
+            self.document.detach(last_node);
+
+            let place = self.appropriate_place(Some(common_ancestor));
+
+            self.insert_at(place, last_node);
+
+            let tag = match self.active_formatting[self.formatting_index(formatting_element).unwrap()] {
+                Formatting::Element(_, ref tag) => tag.clone(),
+                Formatting::Marker => unreachable!(),
+            };
+
+            let new_element = self.create_element(&tag, false);
+
+            while let Some(child) = self.document[furthest_block].first_child() {
+                self.document.detach(child);
+                self.document.append_child(new_element, child);
+            }
+
+            self.document.append_child(furthest_block, new_element);
+
+            let index = self.formatting_index(formatting_element).unwrap();
+
+            self.active_formatting.remove(index);
+
+            if index < bookmark {
+                bookmark -= 1;
+            }
+
+            self.active_formatting.insert(bookmark, Formatting::Element(new_element, tag));
+            self.remove_from_stack(formatting_element);
+
+            let furthest_block_pos = self.open_elements.iter().position(|&id| id == furthest_block).unwrap();
+
+            self.open_elements.insert(furthest_block_pos + 1, new_element);
+        }
+
+        true
+    }
+
+    /// Returns true if token must be processed by rules for SVG and MathML content rather than by
+    /// rules of insertion mode
+    fn is_foreign(&self, token: &Token) -> bool {
+        let node = match self.adjusted_current_node() {
+            Some(node) => node,
+            None => return false,
+        };
+
+        if self.namespace(node) == Namespace::Html {
+            return false;
+        }
+
+        let element = self.document[node].as_element().expect("element");
+
+        match *token {
+            Token::Eof => false,
+            Token::Text(_) => !self.has_html_content(node),
+            Token::StartTag(ref tag) => {
+                if is_mathml_text_integration_point(element.namespace, &element.name) {
+                    return tag.name == "mglyph" || tag.name == "malignmark";
+                }
+
+                if element.namespace == Namespace::MathMl && element.name == "annotation-xml" && tag.name == "svg" {
+                    return false;
+                }
+
+                !self.has_html_content(node)
+            },
+            _ => true,
+        }
+    }
+
+    fn foreign_content(&mut self, token: Token) -> Option<Token> {
+        match token {
+            Token::Text(text) => {
+                // replaced NUL does not count as content the way other characters do
+                if text.chars().any(|c| c != '\0' && !is_whitespace(c)) {
+                    self.frameset_ok = false;
+                }
+
+                let text = text.replace('\0', "\u{FFFD}");
+
+                self.insert_text(&text);
+            },
+            Token::Comment(text) => self.insert_comment(text),
+            Token::Doctype(_) | Token::Eof => {},
+            Token::StartTag(tag) => {
+                if is_breakout_tag(&tag.name, |name| tag.attribute(name).is_some()) && self.context.is_none() {
+                    // HTML tag closes SVG or MathML content
+                    self.pop();
+
+                    while self.namespace(self.current_node()) != Namespace::Html
+                        && !self.has_html_content(self.current_node()) {
+                        self.pop();
+                    }
+
+                    return Some(Token::StartTag(tag));
+                }
+
+                let node = self.adjusted_current_node().expect("adjusted current node");
+                let namespace = self.namespace(node);
+
+                self.insert_foreign_element(&tag, namespace);
+            },
+            Token::EndTag(name) => {
+                let mut i = self.open_elements.len() - 1;
+
+                loop {
+                    if i == 0 {
+                        return None;
+                    }
+
+                    let node = self.open_elements[i];
+
+                    if i < self.open_elements.len() - 1 && self.namespace(node) == Namespace::Html {
+                        return self.process_in_mode(self.mode, Token::EndTag(name));
+                    }
+
+                    if self.document[node].name().is_some_and(|node_name| node_name.eq_ignore_ascii_case(&name)) {
+                        self.open_elements.truncate(i);
+                        return None;
+                    }
+
+                    i -= 1;
+                }
+            },
+        }
+
+        None
+    }
+
+    fn text(&mut self, token: Token) -> Option<Token> {
+        match token {
+            Token::Text(text) => self.insert_text(&text),
+            Token::Eof => {
+                self.pop();
+                self.mode = self.original_mode;
+
+                return Some(Token::Eof);
+            },
+            Token::EndTag(_) => {
+                self.pop();
+                self.mode = self.original_mode;
+            },
+            _ => {},
+        }
+
+        None
+    }
+
+    fn in_table(&mut self, token: Token) -> Option<Token> {
+        match token {
+            Token::Text(text) => {
+                if !TABLE_TAGS.contains(&self.current_name()) {
+                    return self.in_table_anything_else(Token::Text(text));
+                }
+
+                self.pending_table_text.clear();
+                self.original_mode = self.mode;
+                self.mode = Mode::InTableText;
+
+                Some(Token::Text(text))
+            },
+            Token::Comment(text) => {
+                self.insert_comment(text);
+                None
+            },
+            Token::Doctype(_) => None,
+            Token::StartTag(tag) => {
+                match tag.name.as_str() {
+                    "caption" => {
+                        self.clear_stack_back_to(&["table", "template", "html"]);
+                        self.active_formatting.push(Formatting::Marker);
+                        self.insert_element(&tag);
+                        self.mode = Mode::InCaption;
+                    },
+                    "colgroup" => {
+                        self.clear_stack_back_to(&["table", "template", "html"]);
+                        self.insert_element(&tag);
+                        self.mode = Mode::InColumnGroup;
+                    },
+                    "col" => {
+                        self.clear_stack_back_to(&["table", "template", "html"]);
+                        self.insert_implied("colgroup");
+                        self.mode = Mode::InColumnGroup;
+
+                        return Some(Token::StartTag(tag));
+                    },
+                    "tbody" | "tfoot" | "thead" => {
+                        self.clear_stack_back_to(&["table", "template", "html"]);
+                        self.insert_element(&tag);
+                        self.mode = Mode::InTableBody;
+                    },
+                    "td" | "th" | "tr" => {
+                        self.clear_stack_back_to(&["table", "template", "html"]);
+                        self.insert_implied("tbody");
+                        self.mode = Mode::InTableBody;
+
+                        return Some(Token::StartTag(tag));
+                    },
+                    "table" => {
+                        if !self.in_scope(&["table"], Scope::Table) {
+                            return None;
+                        }
+
+                        self.pop_until(&["table"]);
+                        self.reset_insertion_mode();
+
+                        return Some(Token::StartTag(tag));
+                    },
+                    "style" | "script" | "template" => return self.in_head(Token::StartTag(tag)),
+                    "input" if is_hidden_input(&tag) => {
+                        self.insert_element(&tag);
+                        self.pop();
+                    },
+                    "form" => {
+                        if self.has_open_element("template") || self.form.is_some() {
+                            return None;
+                        }
+
+                        let form = self.insert_element(&tag);
+
+                        self.form = Some(form);
+                        self.pop();
+                    },
+                    _ => return self.in_table_anything_else(Token::StartTag(tag)),
+                }
+
+                None
+            },
+            Token::EndTag(name) => {
+                match name.as_str() {
+                    "table" => {
+                        if self.in_scope(&["table"], Scope::Table) {
+                            self.pop_until(&["table"]);
+                            self.reset_insertion_mode();
+                        }
+                    },
+                    "body" | "caption" | "col" | "colgroup" | "html" | "tbody" | "td" | "tfoot" | "th" | "thead"
+                        | "tr" => {},
+                    "template" => return self.in_head(Token::EndTag(name)),
+                    _ => return self.in_table_anything_else(Token::EndTag(name)),
+                }
+
+                None
+            },
+            Token::Eof => self.in_body(Token::Eof),
+        }
+    }
+
+    /// Processes token that is not allowed in table by rules of body with foster parenting
+    fn in_table_anything_else(&mut self, token: Token) -> Option<Token> {
+        self.foster_parenting = true;
+
+        let reprocess = self.in_body(token);
+
+        self.foster_parenting = false;
+
+        reprocess
+    }
+
+    fn in_table_text(&mut self, token: Token) -> Option<Token> {
+        if let Token::Text(text) = token {
+            self.pending_table_text.push_str(&text.replace('\0', ""));
+            return None;
+        }
+
+        let text = mem::take(&mut self.pending_table_text);
+
+        if !trim_whitespace(&text).is_empty() {
+            self.in_table_anything_else(Token::Text(text));
+        } else {
+            self.insert_text(&text);
+        }
+
+        self.mode = self.original_mode;
+
+        Some(token)
+    }
+
+    fn in_caption(&mut self, token: Token) -> Option<Token> {
+        match token {
+            Token::EndTag(ref name) if name == "caption" => {
+                self.close_caption();
+                None
+            },
+            Token::StartTag(ref tag) if matches!(tag.name.as_str(),
+                "caption" | "col" | "colgroup" | "tbody" | "td" | "tfoot" | "th" | "thead" | "tr") => {
+                if self.close_caption() { Some(token) } else { None }
+            },
+            Token::EndTag(ref name) if name == "table" => {
+                if self.close_caption() { Some(token) } else { None }
+            },
+            Token::EndTag(ref name) if matches!(name.as_str(),
+                "body" | "col" | "colgroup" | "html" | "tbody" | "td" | "tfoot" | "th" | "thead" | "tr") => None,
+            token => self.in_body(token),
+        }
+    }
+
+    /// Closes caption switching to InTable, returns false if there was no caption
+    fn close_caption(&mut self) -> bool {
+        if !self.in_scope(&["caption"], Scope::Table) {
+            return false;
+        }
+
+        self.generate_implied_end_tags("");
+        self.pop_until(&["caption"]);
+        self.clear_formatting_to_marker();
+        self.mode = Mode::InTable;
+
+        true
+    }
+
+    fn in_column_group(&mut self, token: Token) -> Option<Token> {
+        match token {
+            Token::Text(text) => {
+                let (whitespace, rest) = split_whitespace(&text);
+
+                self.insert_text(whitespace);
+
+                if rest.is_empty() {
+                    return None;
+                }
+
+                self.in_column_group_anything_else(Token::Text(rest.to_string()))
+            },
+            Token::Comment(text) => {
+                self.insert_comment(text);
+                None
+            },
+            Token::Doctype(_) => None,
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            Token::StartTag(ref tag) if tag.name == "col" => {
+                self.insert_element(tag);
+                self.pop();
+                None
+            },
+            Token::EndTag(ref name) if name == "colgroup" => {
+                if self.current_name() == "colgroup" {
+                    self.pop();
+                    self.mode = Mode::InTable;
+                }
+
+                None
+            },
+            Token::EndTag(ref name) if name == "col" => None,
+            Token::StartTag(ref tag) if tag.name == "template" => self.in_head(token),
+            Token::EndTag(ref name) if name == "template" => self.in_head(token),
+            Token::Eof => self.in_body(token),
+            token => self.in_column_group_anything_else(token),
+        }
+    }
+
+    fn in_column_group_anything_else(&mut self, token: Token) -> Option<Token> {
+        if self.current_name() != "colgroup" {
+            return None;
+        }
+
+        self.pop();
+        self.mode = Mode::InTable;
+
+        Some(token)
+    }
+
+    fn in_table_body(&mut self, token: Token) -> Option<Token> {
+        match token {
+            Token::StartTag(ref tag) if tag.name == "tr" => {
+                self.clear_stack_back_to(&["tbody", "tfoot", "thead", "template", "html"]);
+                self.insert_element(tag);
+                self.mode = Mode::InRow;
+                None
+            },
+            Token::StartTag(ref tag) if tag.name == "th" || tag.name == "td" => {
+                self.clear_stack_back_to(&["tbody", "tfoot", "thead", "template", "html"]);
+                self.insert_implied("tr");
+                self.mode = Mode::InRow;
+
+                Some(token)
+            },
+            Token::EndTag(ref name) if matches!(name.as_str(), "tbody" | "tfoot" | "thead") => {
+                if self.in_scope(&[name], Scope::Table) {
+                    self.clear_stack_back_to(&["tbody", "tfoot", "thead", "template", "html"]);
+                    self.pop();
+                    self.mode = Mode::InTable;
+                }
+
+                None
+            },
+            Token::StartTag(ref tag) if matches!(tag.name.as_str(),
+                "caption" | "col" | "colgroup" | "tbody" | "tfoot" | "thead") => self.close_table_body(token),
+            Token::EndTag(ref name) if name == "table" => self.close_table_body(token),
+            Token::EndTag(ref name) if matches!(name.as_str(),
+                "body" | "caption" | "col" | "colgroup" | "html" | "td" | "th" | "tr") => None,
+            token => self.in_table(token),
+        }
+    }
+
+    /// Closes tbody, thead or tfoot and reprocesses token in table
+    fn close_table_body(&mut self, token: Token) -> Option<Token> {
+        if !self.in_scope(&["tbody", "thead", "tfoot"], Scope::Table) {
+            return None;
+        }
+
+        self.clear_stack_back_to(&["tbody", "tfoot", "thead", "template", "html"]);
+        self.pop();
+        self.mode = Mode::InTable;
+
+        Some(token)
+    }
+
+    fn in_row(&mut self, token: Token) -> Option<Token> {
+        match token {
+            Token::StartTag(ref tag) if tag.name == "th" || tag.name == "td" => {
+                self.clear_stack_back_to(&["tr", "template", "html"]);
+                self.insert_element(tag);
+                self.mode = Mode::InCell;
+                self.active_formatting.push(Formatting::Marker);
+                None
+            },
+            Token::EndTag(ref name) if name == "tr" => {
+                self.close_row();
+                None
+            },
+            Token::StartTag(ref tag) if matches!(tag.name.as_str(),
+                "caption" | "col" | "colgroup" | "tbody" | "tfoot" | "thead" | "tr") => {
+                if self.close_row() { Some(token) } else { None }
+            },
+            Token::EndTag(ref name) if name == "table" => {
+                if self.close_row() { Some(token) } else { None }
+            },
+            Token::EndTag(ref name) if matches!(name.as_str(), "tbody" | "tfoot" | "thead") => {
+                if !self.in_scope(&[name], Scope::Table) {
+                    return None;
+                }
+
+                if self.close_row() { Some(token) } else { None }
+            },
+            Token::EndTag(ref name) if matches!(name.as_str(),
+                "body" | "caption" | "col" | "colgroup" | "html" | "td" | "th") => None,
+            token => self.in_table(token),
+        }
+    }
+
+    /// Closes tr switching to InTableBody, returns false if there was no row
+    fn close_row(&mut self) -> bool {
+        if !self.in_scope(&["tr"], Scope::Table) {
+            return false;
+        }
+
+        self.clear_stack_back_to(&["tr", "template", "html"]);
+        self.pop();
+        self.mode = Mode::InTableBody;
+
+        true
+    }
+
+    fn in_cell(&mut self, token: Token) -> Option<Token> {
+        match token {
+            Token::EndTag(ref name) if name == "td" || name == "th" => {
+                if self.in_scope(&[name], Scope::Table) {
+                    self.generate_implied_end_tags("");
+                    self.pop_until(&[name]);
+                    self.clear_formatting_to_marker();
+                    self.mode = Mode::InRow;
+                }
+
+                None
+            },
+            Token::StartTag(ref tag) if matches!(tag.name.as_str(),
+                "caption" | "col" | "colgroup" | "tbody" | "td" | "tfoot" | "th" | "thead" | "tr") => {
+                if !self.in_scope(&["td", "th"], Scope::Table) {
+                    return None;
+                }
+
+                self.close_cell();
+
+                Some(token)
+            },
+            Token::EndTag(ref name) if matches!(name.as_str(), "body" | "caption" | "col" | "colgroup" | "html") => {
+                None
+            },
+            Token::EndTag(ref name) if matches!(name.as_str(), "table" | "tbody" | "tfoot" | "thead" | "tr") => {
+                if !self.in_scope(&[name], Scope::Table) {
+                    return None;
+                }
+
+                self.close_cell();
+
+                Some(token)
+            },
+            token => self.in_body(token),
+        }
+    }
+
+    fn close_cell(&mut self) {
+        self.generate_implied_end_tags("");
+        self.pop_until(&["td", "th"]);
+        self.clear_formatting_to_marker();
+        self.mode = Mode::InRow;
+    }
+
+    fn in_select(&mut self, token: Token) -> Option<Token> {
+        match token {
+            Token::Text(text) => {
+                self.insert_text(&text.replace('\0', ""));
+                None
+            },
+            Token::Comment(text) => {
+                self.insert_comment(text);
+                None
+            },
+            Token::Doctype(_) => None,
+            Token::StartTag(tag) => {
+                match tag.name.as_str() {
+                    "html" => return self.in_body(Token::StartTag(tag)),
+                    "option" => {
+                        if self.current_name() == "option" {
+                            self.pop();
+                        }
+
+                        self.insert_element(&tag);
+                    },
+                    "optgroup" => {
+                        if self.current_name() == "option" {
+                            self.pop();
+                        }
+
+                        if self.current_name() == "optgroup" {
+                            self.pop();
+                        }
+
+                        self.insert_element(&tag);
+                    },
+                    "select" => self.close_select(),
+                    "input" | "keygen" | "textarea" if self.in_scope(&["select"], Scope::Select) => {
+                        self.close_select();
+
+                        return Some(Token::StartTag(tag));
+                    },
+                    "script" | "template" => return self.in_head(Token::StartTag(tag)),
+                    _ => {},
+                }
+
+                None
+            },
+            Token::EndTag(name) => {
+                match name.as_str() {
+                    "optgroup" => {
+                        let len = self.open_elements.len();
+
+                        if self.current_name() == "option" && len > 1
+                            && self.name(self.open_elements[len - 2]) == "optgroup" {
+                            self.pop();
+                        }
+
+                        if self.current_name() == "optgroup" {
+                            self.pop();
+                        }
+                    },
+                    "option" if self.current_name() == "option" => self.pop(),
+                    "select" => self.close_select(),
+                    "template" => return self.in_head(Token::EndTag(name)),
+                    _ => {},
+                }
+
+                None
+            },
+            Token::Eof => self.in_body(Token::Eof),
+        }
+    }
+
+    /// Closes select if it is in select scope
+    fn close_select(&mut self) {
+        if self.in_scope(&["select"], Scope::Select) {
+            self.pop_until(&["select"]);
+            self.reset_insertion_mode();
+        }
+    }
+
+    fn in_select_in_table(&mut self, token: Token) -> Option<Token> {
+        const TAGS: [&str; 8] = ["caption", "table", "tbody", "tfoot", "thead", "tr", "td", "th"];
+
+        match token {
+            Token::StartTag(ref tag) if TAGS.contains(&tag.name.as_str()) => {
+                self.pop_until(&["select"]);
+                self.reset_insertion_mode();
+
+                Some(token)
+            },
+            Token::EndTag(ref name) if TAGS.contains(&name.as_str()) => {
+                if !self.in_scope(&[name], Scope::Table) {
+                    return None;
+                }
+
+                self.pop_until(&["select"]);
+                self.reset_insertion_mode();
+
+                Some(token)
+            },
+            token => self.in_select(token),
+        }
+    }
+
+    fn in_template(&mut self, token: Token) -> Option<Token> {
+        let mode = match token {
+            Token::Text(_) | Token::Comment(_) | Token::Doctype(_) => return self.in_body(token),
+            Token::StartTag(ref tag) if HEAD_TAGS.contains(&tag.name.as_str()) && tag.name != "head" => {
+                return self.in_head(token);
+            },
+            Token::EndTag(ref name) if name == "template" => return self.in_head(token),
+            Token::StartTag(ref tag) => match tag.name.as_str() {
+                "caption" | "colgroup" | "tbody" | "tfoot" | "thead" => Mode::InTable,
+                "col" => Mode::InColumnGroup,
+                "tr" => Mode::InTableBody,
+                "td" | "th" => Mode::InRow,
+                _ => Mode::InBody,
+            },
+            Token::EndTag(_) => return None,
+            Token::Eof => {
+                if !self.has_open_element("template") {
+                    self.stopped = true;
+                    return None;
+                }
+
+                self.pop_until(&["template"]);
+                self.clear_formatting_to_marker();
+                self.template_modes.pop();
+                self.reset_insertion_mode();
+
+                return Some(token);
+            },
+        };
+
+        self.template_modes.pop();
+        self.template_modes.push(mode);
+        self.mode = mode;
+
+        Some(token)
+    }
+
+    fn after_body(&mut self, token: Token) -> Option<Token> {
+        match token {
+            Token::Text(text) => {
+                let (whitespace, rest) = split_whitespace(&text);
+
+                self.in_body(Token::Text(whitespace.to_string()));
+
+                if rest.is_empty() {
+                    return None;
+                }
+
+                self.mode = Mode::InBody;
+
+                Some(Token::Text(rest.to_string()))
+            },
+            Token::Comment(text) => {
+                let html = self.open_elements[0];
+
+                self.append_comment(html, text);
+                None
+            },
+            Token::Doctype(_) => None,
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            Token::EndTag(ref name) if name == "html" => {
+                // fragment can not be closed
+                if self.context.is_none() {
+                    self.mode = Mode::AfterAfterBody;
+                }
+
+                None
+            },
+            Token::Eof => {
+                self.stopped = true;
+                None
+            },
+            token => {
+                self.mode = Mode::InBody;
+
+                Some(token)
+            },
+        }
+    }
+
+    fn in_frameset(&mut self, token: Token) -> Option<Token> {
+        match token {
+            Token::Text(text) => {
+                let whitespace: String = text.chars().filter(|&ch| is_whitespace(ch)).collect();
+
+                self.insert_text(&whitespace);
+                None
+            },
+            Token::Comment(text) => {
+                self.insert_comment(text);
+                None
+            },
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            Token::StartTag(ref tag) if tag.name == "frameset" => {
+                self.insert_element(tag);
+                None
+            },
+            Token::EndTag(ref name) if name == "frameset" => {
+                if self.open_elements.len() > 1 {
+                    self.pop();
+
+                    if self.current_name() != "frameset" {
+                        self.mode = Mode::AfterFrameset;
+                    }
+                }
+
+                None
+            },
+            Token::StartTag(ref tag) if tag.name == "frame" => {
+                self.insert_element(tag);
+                self.pop();
+                None
+            },
+            Token::StartTag(ref tag) if tag.name == "noframes" => self.in_head(token),
+            Token::Eof => {
+                self.stopped = true;
+                None
+            },
+            _ => None,
+        }
+    }
+
+    fn after_frameset(&mut self, token: Token) -> Option<Token> {
+        match token {
+            Token::Text(text) => {
+                let whitespace: String = text.chars().filter(|&ch| is_whitespace(ch)).collect();
+
+                self.insert_text(&whitespace);
+                None
+            },
+            Token::Comment(text) => {
+                self.insert_comment(text);
+                None
+            },
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            Token::EndTag(ref name) if name == "html" => {
+                self.mode = Mode::AfterAfterFrameset;
+                None
+            },
+            Token::StartTag(ref tag) if tag.name == "noframes" => self.in_head(token),
+            Token::Eof => {
+                self.stopped = true;
+                None
+            },
+            _ => None,
+        }
+    }
+
+    fn after_after_body(&mut self, token: Token) -> Option<Token> {
+        match token {
+            Token::Comment(text) => {
+                let root = self.document.root();
+
+                self.append_comment(root, text);
+                None
+            },
+            Token::Text(text) => {
+                let (whitespace, rest) = split_whitespace(&text);
+
+                self.in_body(Token::Text(whitespace.to_string()));
+
+                if rest.is_empty() {
+                    return None;
+                }
+
+                self.mode = Mode::InBody;
+
+                Some(Token::Text(rest.to_string()))
+            },
+            Token::Doctype(_) => self.in_body(token),
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            Token::Eof => {
+                self.stopped = true;
+                None
+            },
+            token => {
+                self.mode = Mode::InBody;
+
+                Some(token)
+            },
+        }
+    }
+
+    fn after_after_frameset(&mut self, token: Token) -> Option<Token> {
+        match token {
+            Token::Comment(text) => {
+                let root = self.document.root();
+
+                self.append_comment(root, text);
+                None
+            },
+            Token::Text(text) => {
+                let whitespace: String = text.chars().filter(|&ch| is_whitespace(ch)).collect();
+
+                self.in_body(Token::Text(whitespace));
+                None
+            },
+            Token::Doctype(_) => self.in_body(token),
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            Token::StartTag(ref tag) if tag.name == "noframes" => self.in_head(token),
+            Token::Eof => {
+                self.stopped = true;
+                None
+            },
+            _ => None,
+        }
+    }
+
+    /// Sets insertion mode depending on what is in stack of open elements
+    fn reset_insertion_mode(&mut self) {
+        for i in (0..self.open_elements.len()).rev() {
+            let last = i == 0;
+            let node = match self.context {
+                Some(context) if last => context,
+                _ => self.open_elements[i],
+            };
+
+            self.mode = match self.name(node) {
+                "select" => {
+                    let in_table = self.open_elements[..i].iter().rev()
+                        .take_while(|&&ancestor| self.name(ancestor) != "template")
+                        .any(|&ancestor| self.name(ancestor) == "table");
+
+                    if in_table { Mode::InSelectInTable } else { Mode::InSelect }
+                },
+                "td" | "th" if !last => Mode::InCell,
+                "tr" => Mode::InRow,
+                "tbody" | "thead" | "tfoot" => Mode::InTableBody,
+                "caption" => Mode::InCaption,
+                "colgroup" => Mode::InColumnGroup,
+                "table" => Mode::InTable,
+                "template" => *self.template_modes.last().expect("template mode"),
+                "head" if !last => Mode::InHead,
+                "body" => Mode::InBody,
+                "frameset" => Mode::InFrameset,
+                "html" => if self.head.is_none() { Mode::BeforeHead } else { Mode::AfterHead },
+                _ if last => Mode::InBody,
+                _ => continue,
+            };
+
+            return;
+        }
+    }
+
+    /// Inserts element for tag and switches to Text mode with given tokenizer state
+    fn insert_text_element(&mut self, tag: &Tag, state: TokenizerState) {
+        self.insert_element(tag);
+        self.tokenizer_state = Some(state);
+        self.original_mode = self.mode;
+        self.mode = Mode::Text;
+    }
+
+    fn create_node(&mut self, data: NodeData) -> NodeId {
+        let node = self.document.create_node(data);
+        let (offset, length) = self.source;
+        let node_ref = self.document.node_mut(node);
+
+        node_ref.offset = Some(offset);
+        node_ref.length = length;
+
+        node
+    }
+
+    /// Creates element for tag, it has source position if tag is from chunk being processed
+    fn create_element(&mut self, tag: &Tag, from_source: bool) -> NodeId {
+        let element = Element {
+            name: tag.name.clone(),
+            namespace: Namespace::Html,
+            attributes: tag.attributes.clone(),
+        };
+
+        if from_source {
+            self.create_node(NodeData::Element(element))
+        } else {
+            self.document.create_node(NodeData::Element(element))
+        }
+    }
+
+    /// Inserts element for tag at appropriate place and pushes it onto stack of open elements
+    fn insert_element(&mut self, tag: &Tag) -> NodeId {
+        let node = self.create_element(tag, true);
+        let place = self.appropriate_place(None);
+
+        self.insert_at(place, node);
+        self.open_elements.push(node);
+
+        node
+    }
+
+    /// Inserts SVG or MathML element adjusting case of its names, self-closing element is not left open
+    fn insert_foreign_element(&mut self, tag: &Tag, namespace: Namespace) -> NodeId {
+        let mut element = Element {
+            name: tag.name.clone(),
+            namespace,
+            attributes: tag.attributes.clone(),
+        };
+
+        if namespace == Namespace::Svg {
+            if let Some(name) = adjust_svg_tag_name(&element.name) {
+                element.name = name.to_string();
+            }
+        }
+
+        for attribute in &mut element.attributes {
+            if let Some(name) = adjust_attribute_name(namespace, &attribute.name) {
+                attribute.name = name.to_string();
+            }
+        }
+
+        let node = self.create_node(NodeData::Element(element));
+        let place = self.appropriate_place(None);
+
+        self.insert_at(place, node);
+
+        if !tag.self_closing {
+            self.open_elements.push(node);
+        }
+
+        node
+    }
+
+    /// Inserts element that was not in HTML
+    fn insert_implied(&mut self, name: &str) -> NodeId {
+        let node = self.create_element(&Tag::new(name), false);
+        let place = self.appropriate_place(None);
+
+        self.insert_at(place, node);
+        self.open_elements.push(node);
+
+        node
+    }
+
+    /// Returns parent and node before which new node must be inserted, None means it is appended
+    fn appropriate_place(&self, target: Option<NodeId>) -> (NodeId, Option<NodeId>) {
+        let target = target.unwrap_or_else(|| self.current_node());
+
+        if !self.foster_parenting || !TABLE_TAGS.contains(&self.name(target)) {
+            return (target, None);
+        }
+
+        let last_template = self.open_elements.iter().rposition(|&id| self.name(id) == "template");
+        let last_table = self.open_elements.iter().rposition(|&id| self.name(id) == "table");
+
+        match (last_template, last_table) {
+            (Some(template), Some(table)) if template > table => (self.open_elements[template], None),
+            (Some(template), None) => (self.open_elements[template], None),
+            (_, None) => (self.open_elements[0], None),
+            (_, Some(table)) => {
+                let table_node = self.open_elements[table];
+
+                match self.document[table_node].parent() {
+                    Some(parent) => (parent, Some(table_node)),
+                    None => (self.open_elements[table - 1], None),
+                }
+            },
+        }
+    }
+
+    fn insert_at(&mut self, place: (NodeId, Option<NodeId>), node: NodeId) {
+        match place {
+            (_, Some(before)) => self.document.insert_before(before, node),
+            (parent, None) => self.document.append_child(parent, node),
+        }
+    }
+
+    /// Inserts text at appropriate place merging it with text node right before it
+    fn insert_text(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+
+        let place = self.appropriate_place(None);
+
+        if place.0 == self.document.root() {
+            return;
+        }
+
+        let prev = match place {
+            (_, Some(before)) => self.document[before].prev_sibling(),
+            (parent, None) => self.document[parent].last_child(),
+        };
+
+        if let Some(prev) = prev {
+            let (offset, length) = self.source;
+            let node = self.document.node_mut(prev);
+
+            if let NodeData::Text(ref mut own) = node.data {
+                own.push_str(text);
+
+                if let Some(start) = node.offset {
+                    if offset >= start {
+                        node.length = node.length.max(offset + length - start);
+                    }
+                }
+
+                return;
+            }
+        }
+
+        let node = self.create_node(NodeData::Text(text.to_string()));
+
+        self.insert_at(place, node);
+    }
+
+    fn insert_comment(&mut self, text: String) {
+        let node = self.create_node(NodeData::Comment(text));
+        let place = self.appropriate_place(None);
+
+        self.insert_at(place, node);
+    }
+
+    fn append_comment(&mut self, parent: NodeId, text: String) {
+        let node = self.create_node(NodeData::Comment(text));
+
+        self.document.append_child(parent, node);
+    }
+
+    /// Adds attributes of tag that element does not have yet
+    fn add_missing_attributes(&mut self, id: NodeId, tag: &Tag) {
+        if let NodeData::Element(ref mut element) = self.document.node_mut(id).data {
+            for attribute in &tag.attributes {
+                if element.attribute(&attribute.name).is_none() {
+                    element.attributes.push(attribute.clone());
+                }
+            }
+        }
+    }
+
+    fn current_node(&self) -> NodeId {
+        self.open_elements.last().cloned().unwrap_or_else(|| self.document.root())
+    }
+
+    fn current_name(&self) -> &str {
+        self.name(self.current_node())
+    }
+
+    /// Returns name of HTML element, empty string for SVG and MathML elements and other nodes
+    fn name(&self, id: NodeId) -> &str {
+        match self.document[id].as_element() {
+            Some(element) if element.namespace == Namespace::Html => &element.name,
+            _ => "",
+        }
+    }
+
+    /// Returns namespace of element, nodes that are not elements are taken as HTML
+    fn namespace(&self, id: NodeId) -> Namespace {
+        self.document[id].as_element().map_or(Namespace::Html, |element| element.namespace)
+    }
+
+    /// Returns true if element has special parsing rules
+    fn is_special(&self, id: NodeId) -> bool {
+        SPECIAL_TAGS.contains(&self.name(id)) || self.is_foreign_scope_boundary(id)
+    }
+
+    /// Returns true if SVG or MathML element limits scope, these are the ones that contain HTML
+    fn is_foreign_scope_boundary(&self, id: NodeId) -> bool {
+        match self.document[id].as_element() {
+            Some(element) => match element.namespace {
+                Namespace::Svg => matches!(element.name.as_str(), "foreignObject" | "desc" | "title"),
+                Namespace::MathMl => {
+                    matches!(element.name.as_str(), "mi" | "mo" | "mn" | "ms" | "mtext" | "annotation-xml")
+                },
+                Namespace::Html => false,
+            },
+            None => false,
+        }
+    }
+
+    /// Returns true if element is HTML integration point or MathML text integration point, content of
+    /// such elements is processed by rules of HTML
+    fn has_html_content(&self, id: NodeId) -> bool {
+        match self.document[id].as_element() {
+            Some(element) => is_mathml_text_integration_point(element.namespace, &element.name)
+                || is_html_integration_point(element.namespace, &element.name, element.attribute("encoding")),
+            None => false,
+        }
+    }
+
+    /// Returns context element if only html element is open in fragment, current node otherwise
+    fn adjusted_current_node(&self) -> Option<NodeId> {
+        match self.context {
+            Some(context) if self.open_elements.len() == 1 => Some(context),
+            _ => self.open_elements.last().cloned(),
+        }
+    }
+
+    fn pop(&mut self) {
+        self.open_elements.pop();
+    }
+
+    /// Pops elements until one of given names was popped
+    fn pop_until(&mut self, names: &[&str]) {
+        while let Some(node) = self.open_elements.pop() {
+            if names.contains(&self.name(node)) {
+                break;
+            }
+        }
+    }
+
+    fn remove_from_stack(&mut self, id: NodeId) {
+        if let Some(pos) = self.open_elements.iter().rposition(|&node| node == id) {
+            self.open_elements.remove(pos);
+        }
+    }
+
+    fn has_open_element(&self, name: &str) -> bool {
+        self.open_elements.iter().any(|&id| self.name(id) == name)
+    }
+
+    /// Pops elements until current node has one of given names
+    fn clear_stack_back_to(&mut self, names: &[&str]) {
+        while !names.contains(&self.current_name()) {
+            self.pop();
+        }
+    }
+
+    /// Returns true if element with one of given names is in given scope
+    fn in_scope(&self, names: &[&str], scope: Scope) -> bool {
+        for &id in self.open_elements.iter().rev() {
+            let name = self.name(id);
+
+            if names.contains(&name) {
+                return true;
+            }
+
+            let default_boundary = || SCOPE_TAGS.contains(&name) || self.is_foreign_scope_boundary(id);
+
+            let boundary = match scope {
+                Scope::Default => default_boundary(),
+                Scope::ListItem => default_boundary() || name == "ol" || name == "ul",
+                Scope::Button => default_boundary() || name == "button",
+                Scope::Table => name == "html" || name == "table" || name == "template",
+                Scope::Select => name != "optgroup" && name != "option",
+            };
+
+            if boundary {
+                return false;
+            }
+        }
+
+        false
+    }
+
+    /// Returns true if given element is in default scope
+    fn node_in_scope(&self, node: NodeId) -> bool {
+        for &id in self.open_elements.iter().rev() {
+            if id == node {
+                return true;
+            }
+
+            if SCOPE_TAGS.contains(&self.name(id)) || self.is_foreign_scope_boundary(id) {
+                return false;
+            }
+        }
+
+        false
+    }
+
+    /// Pops elements that are implied to end apart from the one with given name
+    fn generate_implied_end_tags(&mut self, except: &str) {
+        loop {
+            let name = self.current_name();
+
+            if name == except || !IMPLIED_END_TAGS.contains(&name) {
+                return;
+            }
+
+            self.pop();
+        }
+    }
+
+    fn generate_implied_end_tags_thoroughly(&mut self) {
+        while THOROUGH_IMPLIED_END_TAGS.contains(&self.current_name()) {
+            self.pop();
+        }
+    }
+
+    fn close_p(&mut self) {
+        self.generate_implied_end_tags("p");
+        self.pop_until(&["p"]);
+    }
+
+    fn close_p_in_button_scope(&mut self) {
+        if self.in_scope(&["p"], Scope::Button) {
+            self.close_p();
+        }
+    }
+
+    /// Adds element to list of active formatting elements, no more than three equal elements are kept
+    /// after the last marker
+    fn push_formatting(&mut self, node: NodeId, tag: Tag) {
+        let mut equal = Vec::new();
+
+        for (i, entry) in self.active_formatting.iter().enumerate().rev() {
+            match *entry {
+                Formatting::Marker => break,
+                Formatting::Element(_, ref other) if other.same_as(&tag) => equal.push(i),
+                _ => {},
+            }
+        }
+
+        if equal.len() >= 3 {
+            self.active_formatting.remove(*equal.last().unwrap());
+        }
+
+        self.active_formatting.push(Formatting::Element(node, tag));
+    }
+
+    /// Returns the last formatting element with given name after the last marker
+    fn formatting_after_marker(&self, name: &str) -> Option<NodeId> {
+        for entry in self.active_formatting.iter().rev() {
+            match *entry {
+                Formatting::Marker => return None,
+                Formatting::Element(id, ref tag) if tag.name == name => return Some(id),
+                _ => {},
+            }
+        }
+
+        None
+    }
+
+    fn formatting_index(&self, node: NodeId) -> Option<usize> {
+        self.active_formatting.iter().position(|entry| match *entry {
+            Formatting::Element(id, _) => id == node,
+            Formatting::Marker => false,
+        })
+    }
+
+    fn remove_from_formatting(&mut self, node: NodeId) {
+        if let Some(index) = self.formatting_index(node) {
+            self.active_formatting.remove(index);
+        }
+    }
+
+    fn clear_formatting_to_marker(&mut self) {
+        while let Some(entry) = self.active_formatting.pop() {
+            if let Formatting::Marker = entry {
+                break;
+            }
+        }
+    }
+
+    /// Reopens formatting elements that were closed, ie b in <b>1<p>2</b>3</p>
+    fn reconstruct_formatting(&mut self) {
+        let is_open = |builder: &TreeBuilder, entry: &Formatting| match *entry {
+            Formatting::Marker => true,
+            Formatting::Element(id, _) => builder.open_elements.contains(&id),
+        };
+
+        let mut index = match self.active_formatting.last() {
+            Some(entry) if !is_open(self, entry) => self.active_formatting.len() - 1,
+            _ => return,
+        };
+
+        while index > 0 && !is_open(self, &self.active_formatting[index - 1]) {
+            index -= 1;
+        }
+
+        for i in index..self.active_formatting.len() {
+            let tag = match self.active_formatting[i] {
+                Formatting::Element(_, ref tag) => tag.clone(),
+                Formatting::Marker => unreachable!(),
+            };
+
+            let node = self.create_element(&tag, false);
+            let place = self.appropriate_place(None);
+
+            self.insert_at(place, node);
+            self.open_elements.push(node);
+            self.active_formatting[i] = Formatting::Element(node, tag);
+        }
+    }
+}
+
+/// Creates tag from open tag chunk
+fn tag(chunk: &HtmlChunk) -> Tag {
+    // names are lowercased again as parser adjusts names of SVG and MathML tags on its own
+    Tag {
+        name: chunk.tag.to_ascii_lowercase(),
+        attributes: (0..chunk.params_count)
+            .map(|i| Attribute {
+                name: chunk.param_names[i].to_ascii_lowercase(),
+                value: chunk.param_values[i].clone(),
+            })
+            .collect(),
+        self_closing: chunk.end_closure,
+    }
+}
+
+fn is_hidden_input(tag: &Tag) -> bool {
+    tag.attribute("type").is_some_and(|value| value.eq_ignore_ascii_case("hidden"))
+}
+
+/// Returns true for HTML whitespace: tab, LF, FF, CR and space
+fn is_whitespace(ch: char) -> bool {
+    matches!(ch, '\t' | '\n' | '\x0C' | '\r' | ' ')
+}
+
+/// Returns text without leading whitespace
+fn trim_whitespace(text: &str) -> &str {
+    text.trim_start_matches(is_whitespace)
+}
+
+/// Splits text into leading whitespace and the rest
+fn split_whitespace(text: &str) -> (&str, &str) {
+    let rest = trim_whitespace(text);
+
+    (&text[..text.len() - rest.len()], rest)
+}
+
+/// Returns quirks mode for DOCTYPE
+fn doctype_quirks_mode(doctype: &Doctype) -> QuirksMode {
+    let public_id = doctype.public_id.as_ref().map(|id| id.to_ascii_lowercase());
+    let system_id = doctype.system_id.as_ref().map(|id| id.to_ascii_lowercase());
+    let public_id = public_id.as_deref();
+    let system_id = system_id.as_deref();
+    let public_starts_with = |prefixes: &[&str]| public_id.is_some_and(|id| prefixes.iter().any(|&p| id.starts_with(p)));
+    let html4 = ["-//w3c//dtd html 4.01 frameset//", "-//w3c//dtd html 4.01 transitional//"];
+
+    if doctype.force_quirks || doctype.name.as_deref() != Some("html")
+        || matches!(public_id, Some("-//w3o//dtd w3 html strict 3.0//en//") | Some("-/w3c/dtd html 4.0 transitional/en")
+            | Some("html"))
+        || system_id == Some("http://www.ibm.com/data/dtd/v11/ibmxhtml1-transitional.dtd")
+        || public_starts_with(&QUIRKS_PUBLIC_PREFIXES)
+        || (system_id.is_none() && public_starts_with(&html4)) {
+        QuirksMode::Quirks
+    } else if public_starts_with(&["-//w3c//dtd xhtml 1.0 frameset//", "-//w3c//dtd xhtml 1.0 transitional//"])
+        || (system_id.is_some() && public_starts_with(&html4)) {
+        QuirksMode::LimitedQuirks
+    } else {
+        QuirksMode::NoQuirks
+    }
+}
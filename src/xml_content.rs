@@ -0,0 +1,248 @@
+use html_chunk::{HtmlChunk, ChunkType, split_name};
+use parse_error::{ParseError, ParseErrorCode};
+
+/// Namespace that xml prefix is bound to without declaration
+const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+
+/// Namespace of xmlns params that declare namespaces
+const XMLNS_NAMESPACE: &str = "http://www.w3.org/2000/xmlns/";
+
+/// Element that was opened in XML document
+#[derive(Clone)]
+struct OpenElement {
+    name: String,
+
+    /// Namespaces declared by xmlns params of element: prefix (empty for default namespace) and URI
+    /// (empty if default namespace was undeclared)
+    namespaces: Vec<(String, String)>,
+}
+
+/// Checks that chunks of XML document are well-formed: end tags match open elements and there is a
+/// single root element with nothing but whitespace, comments and processing instructions around it.
+/// It also resolves prefixes of tags and params to URIs of namespaces declared in scope
+#[derive(Clone)]
+pub struct XmlContent {
+    /// Elements that were opened but not closed yet
+    open_elements: Vec<OpenElement>,
+
+    /// True once root element was opened
+    root_found: bool,
+}
+
+impl XmlContent {
+    pub fn new() -> XmlContent {
+        XmlContent {
+            open_elements: Vec::new(),
+            root_found: false,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.open_elements.clear();
+        self.root_found = false;
+    }
+
+    /// Checks chunk that was just parsed reporting errors at its offset, namespace URIs of tags
+    /// and their params are set
+    pub fn process(&mut self, chunk: &mut HtmlChunk, errors: &mut Vec<ParseError>) {
+        let offset = chunk.chunk_offset;
+
+        match chunk.chunk_type {
+            ChunkType::Text if self.open_elements.is_empty()
+                && chunk.html.bytes().any(|ch| !matches!(ch, b' ' | b'\t' | b'\n' | b'\r')) => {
+                errors.push(ParseError::new(ParseErrorCode::TextOutsideRootElement, offset));
+            },
+            ChunkType::OpenTag if chunk.tag == "!doctype" => {},
+            ChunkType::OpenTag => {
+                self.check_root(offset, errors);
+                self.open_element(chunk, errors);
+            },
+            ChunkType::CloseTag if chunk.end_closure => {
+                self.check_root(offset, errors);
+                self.open_element(chunk, errors);
+                self.open_elements.pop();
+            },
+            ChunkType::CloseTag => {
+                let pos = self.open_elements.iter().rposition(|element| element.name == chunk.tag);
+
+                if pos.is_some() {
+                    chunk.namespace_uri = self.resolve_tag(chunk, errors);
+                }
+
+                if pos.is_some_and(|pos| pos + 1 == self.open_elements.len()) {
+                    self.open_elements.pop();
+                    return;
+                }
+
+                errors.push(ParseError::new(ParseErrorCode::MismatchedEndTag, offset));
+
+                // end tag of element that is open further up the stack closes elements opened after it
+                if let Some(pos) = pos {
+                    self.open_elements.truncate(pos);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    /// Reports elements that are still open at the end of data, offset is length of data
+    pub fn finish(&mut self, offset: usize, errors: &mut Vec<ParseError>) {
+        for _ in self.open_elements.drain(..) {
+            errors.push(ParseError::new(ParseErrorCode::UnclosedElement, offset));
+        }
+    }
+
+    /// Pushes element of open tag chunk declaring its namespaces, so that prefixes of tag and its params
+    /// can be resolved
+    fn open_element(&mut self, chunk: &mut HtmlChunk, errors: &mut Vec<ParseError>) {
+        let mut namespaces = Vec::new();
+
+        for i in 0..chunk.params_count {
+            let name = &chunk.param_names[i];
+
+            if name == "xmlns" {
+                namespaces.push((String::new(), chunk.param_values[i].clone()));
+            } else if let (Some("xmlns"), prefix) = split_name(name) {
+                namespaces.push((prefix.to_string(), chunk.param_values[i].clone()));
+            }
+        }
+
+        self.open_elements.push(OpenElement {
+            name: chunk.tag.clone(),
+            namespaces,
+        });
+
+        chunk.namespace_uri = self.resolve_tag(chunk, errors);
+
+        for i in 0..chunk.params_count {
+            let uri = match split_name(&chunk.param_names[i]) {
+                (None, "xmlns") => Some(XMLNS_NAMESPACE.to_string()),
+                (None, _) => None,
+                (Some(prefix), _) => self.resolve(prefix, chunk.param_positions[i].name_start.offset, errors),
+            };
+
+            chunk.param_namespace_uris[i] = uri;
+        }
+    }
+
+    /// Returns URI of namespace of tag: the one its prefix is bound to or the default one
+    fn resolve_tag(&self, chunk: &HtmlChunk, errors: &mut Vec<ParseError>) -> Option<String> {
+        self.resolve(chunk.tag_prefix().unwrap_or(""), chunk.chunk_offset, errors)
+    }
+
+    /// Returns URI of namespace bound to prefix (empty for default namespace), undeclared prefix
+    /// is reported at offset
+    fn resolve(&self, prefix: &str, offset: usize, errors: &mut Vec<ParseError>) -> Option<String> {
+        match prefix {
+            "xml" => return Some(XML_NAMESPACE.to_string()),
+            "xmlns" => return Some(XMLNS_NAMESPACE.to_string()),
+            _ => {},
+        }
+
+        let declared = self.open_elements.iter().rev()
+            .flat_map(|element| element.namespaces.iter())
+            .find(|(declared, _)| declared == prefix);
+
+        match declared {
+            Some((_, uri)) if uri.is_empty() => None,
+            Some((_, uri)) => Some(uri.clone()),
+            None if prefix.is_empty() => None,
+            None => {
+                errors.push(ParseError::new(ParseErrorCode::UndeclaredNamespacePrefix, offset));
+                None
+            },
+        }
+    }
+
+    /// Reports element at offset that is not inside of root element
+    fn check_root(&mut self, offset: usize, errors: &mut Vec<ParseError>) {
+        if self.open_elements.is_empty() {
+            if self.root_found {
+                errors.push(ParseError::new(ParseErrorCode::MultipleRootElements, offset));
+            }
+
+            self.root_found = true;
+        }
+    }
+}
+
+/// Returns names and values of general entities declared in internal subset of DOCTYPE, ie
+/// <!ENTITY copy "&#169;">. Parameter and external entities are skipped, values are not decoded
+pub fn entity_declarations(subset: &[u8]) -> Vec<(String, &[u8])> {
+    let mut declarations = Vec::new();
+    let mut pos = 0;
+
+    while let Some(start) = find(subset, pos, b"<!ENTITY") {
+        let mut words = Words { data: subset, pos: start + 8 };
+
+        pos = start + 8;
+
+        let name = match words.next() {
+            Some(b"%") | None => continue,
+            Some(name) => String::from_utf8_lossy(name).to_string(),
+        };
+
+        if let Some((value, end)) = words.quoted() {
+            declarations.push((name, value));
+            pos = end;
+        }
+    }
+
+    declarations
+}
+
+/// Returns position of needle in data starting at from
+fn find(data: &[u8], from: usize, needle: &[u8]) -> Option<usize> {
+    data.get(from..)?.windows(needle.len()).position(|window| window == needle).map(|pos| from + pos)
+}
+
+/// Splits data into words separated by whitespace
+struct Words<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Words<'a> {
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.data.len() && self.data[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    /// Returns quoted value that follows whitespace along with position after its closing quote, None if
+    /// there is no quote - it is external entity then
+    fn quoted(&mut self) -> Option<(&'a [u8], usize)> {
+        self.skip_whitespace();
+
+        let quote = *self.data.get(self.pos)?;
+
+        if quote != b'"' && quote != b'\'' {
+            return None;
+        }
+
+        let start = self.pos + 1;
+        let end = start + self.data[start..].iter().position(|&ch| ch == quote)?;
+
+        Some((&self.data[start..end], end + 1))
+    }
+}
+
+impl<'a> Iterator for Words<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        self.skip_whitespace();
+
+        let start = self.pos;
+
+        while self.pos < self.data.len() && !self.data[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+
+        if start == self.pos {
+            None
+        } else {
+            Some(&self.data[start..self.pos])
+        }
+    }
+}
@@ -0,0 +1,1391 @@
+use std::fmt;
+use std::f64;
+
+use html_chunk::Namespace;
+use html_document::{Document, NodeId, NodeData};
+
+/// Functions of XPath core library along with minimum and maximum number of arguments
+const FUNCTIONS: [(&str, Function, usize, usize); 27] = [
+    ("boolean", Function::Boolean, 1, 1),
+    ("ceiling", Function::Ceiling, 1, 1),
+    ("concat", Function::Concat, 2, usize::MAX),
+    ("contains", Function::Contains, 2, 2),
+    ("count", Function::Count, 1, 1),
+    ("false", Function::False, 0, 0),
+    ("floor", Function::Floor, 1, 1),
+    ("id", Function::Id, 1, 1),
+    ("lang", Function::Lang, 1, 1),
+    ("last", Function::Last, 0, 0),
+    ("local-name", Function::LocalName, 0, 1),
+    ("name", Function::Name, 0, 1),
+    ("namespace-uri", Function::NamespaceUri, 0, 1),
+    ("normalize-space", Function::NormalizeSpace, 0, 1),
+    ("not", Function::Not, 1, 1),
+    ("number", Function::Number, 0, 1),
+    ("position", Function::Position, 0, 0),
+    ("round", Function::Round, 1, 1),
+    ("starts-with", Function::StartsWith, 2, 2),
+    ("string", Function::String, 0, 1),
+    ("string-length", Function::StringLength, 0, 1),
+    ("substring", Function::Substring, 2, 3),
+    ("substring-after", Function::SubstringAfter, 2, 2),
+    ("substring-before", Function::SubstringBefore, 2, 2),
+    ("sum", Function::Sum, 1, 1),
+    ("translate", Function::Translate, 3, 3),
+    ("true", Function::True, 0, 0),
+];
+
+/// Names that are node tests when they are followed by (, other names followed by ( are functions
+const NODE_TYPES: [&str; 4] = ["comment", "node", "processing-instruction", "text"];
+
+/// Error found in XPath expression or while evaluating it
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct XPathError {
+    /// What went wrong, ie "unknown function foo()"
+    pub message: String,
+
+    /// Offset of byte in expression at which error was found
+    pub offset: usize,
+}
+
+impl fmt::Display for XPathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at offset {}", self.message, self.offset)
+    }
+}
+
+/// Node that XPath selects: node of document or attribute of element, attributes are not nodes in Document
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum XPathNode {
+    Node(NodeId),
+
+    /// Element and index of attribute in its attributes
+    Attribute(NodeId, usize),
+}
+
+impl XPathNode {
+    /// Returns node itself or element of attribute
+    pub fn node_id(self) -> NodeId {
+        match self {
+            XPathNode::Node(id) | XPathNode::Attribute(id, _) => id,
+        }
+    }
+
+    /// Returns string-value of node: text of all text nodes below elements and document, value of attribute
+    pub fn string_value(self, document: &Document) -> String {
+        match self {
+            XPathNode::Node(id) => match document[id].data {
+                NodeData::Document | NodeData::Element(_) => document.text_content(id),
+                NodeData::Text(ref text) | NodeData::Comment(ref text) => text.clone(),
+                NodeData::Doctype(_) => String::new(),
+            },
+            XPathNode::Attribute(id, index) => attribute(document, id, index).map_or(String::new(), |(_, value)| {
+                value.to_string()
+            }),
+        }
+    }
+}
+
+/// Result of XPath expression
+#[derive(Clone, PartialEq, Debug)]
+pub enum XPathValue {
+    /// Nodes in document order
+    NodeSet(Vec<XPathNode>),
+    String(String),
+    Number(f64),
+    Boolean(bool),
+}
+
+impl XPathValue {
+    /// Converts value to boolean like boolean() function does
+    pub fn boolean(&self) -> bool {
+        match *self {
+            XPathValue::NodeSet(ref nodes) => !nodes.is_empty(),
+            XPathValue::String(ref string) => !string.is_empty(),
+            XPathValue::Number(number) => number != 0.0 && !number.is_nan(),
+            XPathValue::Boolean(boolean) => boolean,
+        }
+    }
+
+    /// Converts value to number like number() function does, it is NaN if string is not a number
+    pub fn number(&self, document: &Document) -> f64 {
+        match *self {
+            XPathValue::Number(number) => number,
+            XPathValue::Boolean(boolean) => if boolean { 1.0 } else { 0.0 },
+            _ => string_to_number(&self.string(document)),
+        }
+    }
+
+    /// Converts value to string like string() function does, node set is string-value of its first node
+    pub fn string(&self, document: &Document) -> String {
+        match *self {
+            XPathValue::NodeSet(ref nodes) => nodes.first().map_or(String::new(), |node| node.string_value(document)),
+            XPathValue::String(ref string) => string.clone(),
+            XPathValue::Number(number) => number_to_string(number),
+            XPathValue::Boolean(boolean) => boolean.to_string(),
+        }
+    }
+}
+
+/// XPath 1.0 expression that is evaluated over Document. Element and attribute names of HTML elements
+/// are matched ignoring case, prefixes are matched as part of names as there are no namespace bindings.
+/// Variables are not supported
+#[derive(Clone, PartialEq, Debug)]
+pub struct XPath {
+    expr: Expr,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Expr {
+    Binary(BinaryOperator, Box<Expr>, Box<Expr>),
+    Negate(Box<Expr>),
+
+    /// Node sets joined by |, offset is position of |
+    Union(Box<Expr>, Box<Expr>, usize),
+    Path(PathStart, Vec<Step>),
+
+    /// Expression filtered by predicates, offset is position of expression
+    Filter(Box<Expr>, Vec<Expr>, usize),
+    Literal(String),
+    Number(f64),
+
+    /// Function call, offset is position of function name
+    Function(Function, Vec<Expr>, usize),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum BinaryOperator {
+    Or,
+    And,
+    Equals,
+    NotEquals,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Plus,
+    Minus,
+    Multiply,
+    Div,
+    Mod,
+}
+
+/// Nodes that location path starts from
+#[derive(Clone, PartialEq, Debug)]
+enum PathStart {
+    /// Document node, path starts with /
+    Root,
+
+    /// Context node, path is relative
+    Context,
+
+    /// Node set that expression returns, offset is position of expression
+    Filter(Box<Expr>, usize),
+}
+
+/// Location step, ie child::p[1]
+#[derive(Clone, PartialEq, Debug)]
+struct Step {
+    axis: Axis,
+    test: NodeTest,
+    predicates: Vec<Expr>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Axis {
+    Ancestor,
+    AncestorOrSelf,
+    Attribute,
+    Child,
+    Descendant,
+    DescendantOrSelf,
+    Following,
+    FollowingSibling,
+    Namespace,
+    Parent,
+    Preceding,
+    PrecedingSibling,
+    SelfNode,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum NodeTest {
+    /// Any element, or any attribute on attribute axis
+    Any,
+
+    /// Element or attribute which name has got prefix, ie svg:*
+    Prefix(String),
+    Name(String),
+    Node,
+    Text,
+    Comment,
+    ProcessingInstruction,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Function {
+    Boolean,
+    Ceiling,
+    Concat,
+    Contains,
+    Count,
+    False,
+    Floor,
+    Id,
+    Lang,
+    Last,
+    LocalName,
+    Name,
+    NamespaceUri,
+    NormalizeSpace,
+    Not,
+    Number,
+    Position,
+    Round,
+    StartsWith,
+    String,
+    StringLength,
+    Substring,
+    SubstringAfter,
+    SubstringBefore,
+    Sum,
+    Translate,
+    True,
+}
+
+impl XPath {
+    /// Parses XPath expression
+    pub fn parse(expression: &str) -> Result<XPath, XPathError> {
+        let mut parser = XPathParser {
+            tokens: tokenize(expression)?,
+            index: 0,
+            end: expression.len(),
+        };
+
+        let expr = parser.parse_expr()?;
+
+        if parser.index < parser.tokens.len() {
+            return parser.error("unexpected token");
+        }
+
+        Ok(XPath { expr })
+    }
+
+    /// Evaluates expression with given context node, node sets are returned in document order
+    pub fn evaluate(&self, document: &Document, context: NodeId) -> Result<XPathValue, XPathError> {
+        let evaluator = Evaluator::new(document);
+        let context = Context {
+            node: XPathNode::Node(context),
+            position: 1,
+            size: 1,
+        };
+
+        evaluator.evaluate(&self.expr, context)
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    LeftParen,
+    RightParen,
+    LeftBracket,
+    RightBracket,
+    Dot,
+    DotDot,
+    At,
+    Comma,
+    ColonColon,
+    Slash,
+    DoubleSlash,
+    Pipe,
+    Plus,
+    Minus,
+    Equals,
+    NotEquals,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Multiply,
+    And,
+    Or,
+    Mod,
+    Div,
+
+    /// *, prefix:* or name
+    NameTest(String),
+    NodeType(String),
+    FunctionName(String),
+    AxisName(String),
+    Literal(String),
+    Number(f64),
+    Variable(String),
+}
+
+impl Token {
+    fn is_operator(&self) -> bool {
+        matches!(*self, Token::And | Token::Or | Token::Mod | Token::Div | Token::Multiply | Token::Slash
+            | Token::DoubleSlash | Token::Pipe | Token::Plus | Token::Minus | Token::Equals | Token::NotEquals
+            | Token::Less | Token::LessOrEqual | Token::Greater | Token::GreaterOrEqual)
+    }
+
+    fn binary_operator(&self) -> Option<BinaryOperator> {
+        let operator = match *self {
+            Token::Or => BinaryOperator::Or,
+            Token::And => BinaryOperator::And,
+            Token::Equals => BinaryOperator::Equals,
+            Token::NotEquals => BinaryOperator::NotEquals,
+            Token::Less => BinaryOperator::Less,
+            Token::LessOrEqual => BinaryOperator::LessOrEqual,
+            Token::Greater => BinaryOperator::Greater,
+            Token::GreaterOrEqual => BinaryOperator::GreaterOrEqual,
+            Token::Plus => BinaryOperator::Plus,
+            Token::Minus => BinaryOperator::Minus,
+            Token::Multiply => BinaryOperator::Multiply,
+            Token::Div => BinaryOperator::Div,
+            Token::Mod => BinaryOperator::Mod,
+            _ => return None,
+        };
+
+        Some(operator)
+    }
+}
+
+/// Splits expression into tokens with their offsets. * and names like and are operators only if they
+/// follow something that can be operand, as XPath specification says
+fn tokenize(expression: &str) -> Result<Vec<(Token, usize)>, XPathError> {
+    let mut tokens: Vec<(Token, usize)> = Vec::new();
+    let mut chars = expression.char_indices().peekable();
+
+    while let Some((offset, ch)) = chars.next() {
+        let error = |message: &str| Err(XPathError {
+            message: message.to_string(),
+            offset,
+        });
+
+        let rest = &expression[offset..];
+
+        let operator_expected = tokens.last().is_some_and(|(token, _)| {
+            !matches!(*token, Token::At | Token::ColonColon | Token::LeftParen | Token::LeftBracket | Token::Comma)
+                && !token.is_operator()
+        });
+
+        let token = match ch {
+            ' ' | '\t' | '\n' | '\r' => continue,
+            '(' => Token::LeftParen,
+            ')' => Token::RightParen,
+            '[' => Token::LeftBracket,
+            ']' => Token::RightBracket,
+            '@' => Token::At,
+            ',' => Token::Comma,
+            '|' => Token::Pipe,
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            '=' => Token::Equals,
+            '*' if operator_expected => Token::Multiply,
+            '*' => Token::NameTest("*".to_string()),
+            '!' if rest.starts_with("!=") => Token::NotEquals,
+            '<' if rest.starts_with("<=") => Token::LessOrEqual,
+            '<' => Token::Less,
+            '>' if rest.starts_with(">=") => Token::GreaterOrEqual,
+            '>' => Token::Greater,
+            ':' if rest.starts_with("::") => Token::ColonColon,
+            '/' if rest.starts_with("//") => Token::DoubleSlash,
+            '/' => Token::Slash,
+            '.' if rest.starts_with("..") => Token::DotDot,
+            '"' | '\'' => match rest[1..].find(ch) {
+                Some(end) => Token::Literal(rest[1..end + 1].to_string()),
+                None => return error("unterminated literal"),
+            },
+            '.' | '0'..='9' if rest.trim_start_matches('.').starts_with(|ch: char| ch.is_ascii_digit()) => {
+                let digits = rest.find(|ch: char| !ch.is_ascii_digit() && ch != '.').unwrap_or(rest.len());
+
+                match rest[..digits].parse() {
+                    Ok(number) => Token::Number(number),
+                    Err(_) => return error("invalid number"),
+                }
+            },
+            '.' => Token::Dot,
+            '$' => match name_length(&rest[1..]) {
+                0 => return error("expected variable name"),
+                length => Token::Variable(rest[1..length + 1].to_string()),
+            },
+            _ if is_name_start(ch) => {
+                let name = &rest[..qualified_name_length(rest)];
+                let after = rest[name.len()..].trim_start_matches([' ', '\t', '\n', '\r']);
+
+                if operator_expected {
+                    match name {
+                        "and" => Token::And,
+                        "or" => Token::Or,
+                        "mod" => Token::Mod,
+                        "div" => Token::Div,
+                        _ => return error("expected operator"),
+                    }
+                } else if after.starts_with('(') && NODE_TYPES.contains(&name) {
+                    Token::NodeType(name.to_string())
+                } else if after.starts_with('(') {
+                    Token::FunctionName(name.to_string())
+                } else if after.starts_with("::") {
+                    Token::AxisName(name.to_string())
+                } else {
+                    Token::NameTest(name.to_string())
+                }
+            },
+            _ => return error("unexpected char"),
+        };
+
+        let length = match token {
+            Token::Literal(ref literal) => literal.len() + 2,
+            Token::Number(_) => rest.find(|ch: char| !ch.is_ascii_digit() && ch != '.').unwrap_or(rest.len()),
+            Token::Variable(ref name) => name.len() + 1,
+            Token::NameTest(ref name) | Token::NodeType(ref name) | Token::FunctionName(ref name)
+            | Token::AxisName(ref name) => name.len(),
+            Token::And | Token::Mod | Token::Div => 3,
+            Token::Or | Token::ColonColon | Token::DoubleSlash | Token::DotDot | Token::NotEquals
+            | Token::LessOrEqual | Token::GreaterOrEqual => 2,
+            _ => 1,
+        };
+
+        // the first char was already taken
+        for _ in rest[..length].chars().skip(1) {
+            chars.next();
+        }
+
+        tokens.push((token, offset));
+    }
+
+    Ok(tokens)
+}
+
+fn is_name_start(ch: char) -> bool {
+    ch.is_ascii_alphabetic() || ch == '_' || !ch.is_ascii()
+}
+
+fn is_name_char(ch: char) -> bool {
+    is_name_start(ch) || ch.is_ascii_digit() || ch == '-' || ch == '.'
+}
+
+/// Returns length of name without colons at the start of text, 0 if there is none
+fn name_length(text: &str) -> usize {
+    if !text.starts_with(is_name_start) {
+        return 0;
+    }
+
+    text.find(|ch| !is_name_char(ch)).unwrap_or(text.len())
+}
+
+/// Returns length of name that may have prefix, ie svg:rect or svg:*
+fn qualified_name_length(text: &str) -> usize {
+    let length = name_length(text);
+    let rest = &text[length..];
+
+    if !rest.starts_with(':') || rest.starts_with("::") {
+        return length;
+    }
+
+    match name_length(&rest[1..]) {
+        0 if rest[1..].starts_with('*') => length + 2,
+        0 => length,
+        local => length + 1 + local,
+    }
+}
+
+/// Parses tokens of XPath expression
+struct XPathParser {
+    tokens: Vec<(Token, usize)>,
+    index: usize,
+
+    /// Length of expression, errors at the end of expression are reported there
+    end: usize,
+}
+
+impl XPathParser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index).map(|(token, _)| token)
+    }
+
+    /// Returns offset of the next token
+    fn offset(&self) -> usize {
+        self.tokens.get(self.index).map_or(self.end, |&(_, offset)| offset)
+    }
+
+    /// Skips the next token if it is the expected one returning true
+    fn eat(&mut self, expected: &Token) -> bool {
+        if self.peek() == Some(expected) {
+            self.index += 1;
+            return true;
+        }
+
+        false
+    }
+
+    fn expect(&mut self, expected: Token, name: &str) -> Result<(), XPathError> {
+        if self.eat(&expected) {
+            Ok(())
+        } else {
+            self.error(&format!("expected {}", name))
+        }
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, XPathError> {
+        Err(XPathError {
+            message: message.to_string(),
+            offset: self.offset(),
+        })
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr, XPathError> {
+        self.parse_binary(0)
+    }
+
+    /// Parses binary expressions with operators of given precedence level or higher, levels go from or
+    /// to multiplicative operators and unary expressions are operands of the last one
+    fn parse_binary(&mut self, level: usize) -> Result<Expr, XPathError> {
+        const LEVELS: [&[BinaryOperator]; 6] = [
+            &[BinaryOperator::Or],
+            &[BinaryOperator::And],
+            &[BinaryOperator::Equals, BinaryOperator::NotEquals],
+            &[BinaryOperator::Less, BinaryOperator::LessOrEqual, BinaryOperator::Greater,
+                BinaryOperator::GreaterOrEqual],
+            &[BinaryOperator::Plus, BinaryOperator::Minus],
+            &[BinaryOperator::Multiply, BinaryOperator::Div, BinaryOperator::Mod],
+        ];
+
+        if level == LEVELS.len() {
+            return self.parse_unary();
+        }
+
+        let mut left = self.parse_binary(level + 1)?;
+
+        while let Some(operator) = self.peek().and_then(Token::binary_operator) {
+            if !LEVELS[level].contains(&operator) {
+                break;
+            }
+
+            self.index += 1;
+
+            let right = self.parse_binary(level + 1)?;
+
+            left = Expr::Binary(operator, Box::new(left), Box::new(right));
+        }
+
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, XPathError> {
+        if self.eat(&Token::Minus) {
+            return Ok(Expr::Negate(Box::new(self.parse_unary()?)));
+        }
+
+        let mut left = self.parse_path()?;
+
+        while self.peek() == Some(&Token::Pipe) {
+            let offset = self.offset();
+
+            self.index += 1;
+            left = Expr::Union(Box::new(left), Box::new(self.parse_path()?), offset);
+        }
+
+        Ok(left)
+    }
+
+    fn parse_path(&mut self) -> Result<Expr, XPathError> {
+        let mut steps = Vec::new();
+
+        let start = match self.peek() {
+            Some(&Token::Slash) => {
+                self.index += 1;
+
+                // / alone selects document node
+                if self.is_step_start() {
+                    self.parse_steps(&mut steps)?;
+                }
+
+                PathStart::Root
+            },
+            Some(&Token::DoubleSlash) => {
+                self.index += 1;
+                steps.push(descendant_or_self_step());
+                self.parse_steps(&mut steps)?;
+
+                PathStart::Root
+            },
+            _ if self.is_step_start() => {
+                self.parse_steps(&mut steps)?;
+
+                PathStart::Context
+            },
+            _ => {
+                let offset = self.offset();
+                let filter = self.parse_filter()?;
+
+                match self.peek() {
+                    Some(&Token::Slash) => self.index += 1,
+                    Some(&Token::DoubleSlash) => {
+                        self.index += 1;
+                        steps.push(descendant_or_self_step());
+                    },
+                    _ => return Ok(filter),
+                }
+
+                self.parse_steps(&mut steps)?;
+
+                PathStart::Filter(Box::new(filter), offset)
+            },
+        };
+
+        Ok(Expr::Path(start, steps))
+    }
+
+    fn is_step_start(&self) -> bool {
+        matches!(self.peek(), Some(&Token::Dot) | Some(&Token::DotDot) | Some(&Token::At) | Some(&Token::AxisName(_))
+            | Some(&Token::NameTest(_)) | Some(&Token::NodeType(_)))
+    }
+
+    /// Parses steps of relative location path separated by / or //
+    fn parse_steps(&mut self, steps: &mut Vec<Step>) -> Result<(), XPathError> {
+        loop {
+            steps.push(self.parse_step()?);
+
+            if self.eat(&Token::DoubleSlash) {
+                steps.push(descendant_or_self_step());
+            } else if !self.eat(&Token::Slash) {
+                return Ok(());
+            }
+        }
+    }
+
+    fn parse_step(&mut self) -> Result<Step, XPathError> {
+        let axis = match self.peek().cloned() {
+            Some(Token::Dot) => {
+                self.index += 1;
+                return Ok(Step { axis: Axis::SelfNode, test: NodeTest::Node, predicates: Vec::new() });
+            },
+            Some(Token::DotDot) => {
+                self.index += 1;
+                return Ok(Step { axis: Axis::Parent, test: NodeTest::Node, predicates: Vec::new() });
+            },
+            Some(Token::At) => {
+                self.index += 1;
+                Axis::Attribute
+            },
+            Some(Token::AxisName(name)) => {
+                let axis = match name.as_str() {
+                    "ancestor" => Axis::Ancestor,
+                    "ancestor-or-self" => Axis::AncestorOrSelf,
+                    "attribute" => Axis::Attribute,
+                    "child" => Axis::Child,
+                    "descendant" => Axis::Descendant,
+                    "descendant-or-self" => Axis::DescendantOrSelf,
+                    "following" => Axis::Following,
+                    "following-sibling" => Axis::FollowingSibling,
+                    "namespace" => Axis::Namespace,
+                    "parent" => Axis::Parent,
+                    "preceding" => Axis::Preceding,
+                    "preceding-sibling" => Axis::PrecedingSibling,
+                    "self" => Axis::SelfNode,
+                    _ => return self.error(&format!("unknown axis {}", name)),
+                };
+
+                self.index += 1;
+                self.expect(Token::ColonColon, "::")?;
+
+                axis
+            },
+            _ => Axis::Child,
+        };
+
+        let test = match self.peek().cloned() {
+            Some(Token::NameTest(name)) => {
+                self.index += 1;
+
+                match name.strip_suffix(":*") {
+                    _ if name == "*" => NodeTest::Any,
+                    Some(prefix) => NodeTest::Prefix(prefix.to_string()),
+                    None => NodeTest::Name(name),
+                }
+            },
+            Some(Token::NodeType(name)) => {
+                self.index += 1;
+                self.expect(Token::LeftParen, "(")?;
+
+                let test = match name.as_str() {
+                    "comment" => NodeTest::Comment,
+                    "text" => NodeTest::Text,
+                    "processing-instruction" => {
+                        // target of processing instructions does not matter as document has none
+                        if let Some(&Token::Literal(_)) = self.peek() {
+                            self.index += 1;
+                        }
+
+                        NodeTest::ProcessingInstruction
+                    },
+                    _ => NodeTest::Node,
+                };
+
+                self.expect(Token::RightParen, ")")?;
+
+                test
+            },
+            _ => return self.error("expected node test"),
+        };
+
+        Ok(Step {
+            axis,
+            test,
+            predicates: self.parse_predicates()?,
+        })
+    }
+
+    fn parse_predicates(&mut self) -> Result<Vec<Expr>, XPathError> {
+        let mut predicates = Vec::new();
+
+        while self.eat(&Token::LeftBracket) {
+            predicates.push(self.parse_expr()?);
+            self.expect(Token::RightBracket, "]")?;
+        }
+
+        Ok(predicates)
+    }
+
+    fn parse_filter(&mut self) -> Result<Expr, XPathError> {
+        let offset = self.offset();
+        let primary = self.parse_primary()?;
+        let predicates = self.parse_predicates()?;
+
+        if predicates.is_empty() {
+            Ok(primary)
+        } else {
+            Ok(Expr::Filter(Box::new(primary), predicates, offset))
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, XPathError> {
+        let offset = self.offset();
+
+        match self.peek().cloned() {
+            Some(Token::Variable(_)) => self.error("variables are not supported"),
+            Some(Token::LeftParen) => {
+                self.index += 1;
+
+                let expr = self.parse_expr()?;
+
+                self.expect(Token::RightParen, ")")?;
+
+                Ok(expr)
+            },
+            Some(Token::Literal(literal)) => {
+                self.index += 1;
+                Ok(Expr::Literal(literal))
+            },
+            Some(Token::Number(number)) => {
+                self.index += 1;
+                Ok(Expr::Number(number))
+            },
+            Some(Token::FunctionName(name)) => {
+                let &(_, function, min, max) = match FUNCTIONS.iter().find(|&&(known, ..)| known == name) {
+                    Some(function) => function,
+                    None => return self.error(&format!("unknown function {}()", name)),
+                };
+
+                self.index += 1;
+                self.expect(Token::LeftParen, "(")?;
+
+                let mut args = Vec::new();
+
+                if !self.eat(&Token::RightParen) {
+                    loop {
+                        args.push(self.parse_expr()?);
+
+                        if !self.eat(&Token::Comma) {
+                            break;
+                        }
+                    }
+
+                    self.expect(Token::RightParen, ")")?;
+                }
+
+                if args.len() < min || args.len() > max {
+                    return Err(XPathError {
+                        message: format!("wrong number of arguments of {}()", name),
+                        offset,
+                    });
+                }
+
+                Ok(Expr::Function(function, args, offset))
+            },
+            Some(_) => self.error("unexpected token"),
+            None => self.error("expected expression"),
+        }
+    }
+}
+
+/// Returns step that // stands for: descendant-or-self::node()
+fn descendant_or_self_step() -> Step {
+    Step {
+        axis: Axis::DescendantOrSelf,
+        test: NodeTest::Node,
+        predicates: Vec::new(),
+    }
+}
+
+/// Node that expression is evaluated for along with its position in node set being filtered
+#[derive(Clone, Copy)]
+struct Context {
+    node: XPathNode,
+
+    /// 1-based position of node
+    position: usize,
+
+    /// Number of nodes
+    size: usize,
+}
+
+/// Evaluates expressions over document
+struct Evaluator<'a> {
+    document: &'a Document,
+
+    /// Positions of nodes in document order indexed by their ids, nodes that are not in document are last
+    order: Vec<usize>,
+}
+
+impl<'a> Evaluator<'a> {
+    fn new(document: &'a Document) -> Evaluator<'a> {
+        let mut order = vec![usize::MAX; document.len()];
+        let root = document.root();
+
+        order[root.index()] = 0;
+
+        for (position, id) in document.descendants(root).enumerate() {
+            order[id.index()] = position + 1;
+        }
+
+        Evaluator { document, order }
+    }
+
+    fn evaluate(&self, expr: &Expr, context: Context) -> Result<XPathValue, XPathError> {
+        let value = match *expr {
+            Expr::Binary(BinaryOperator::Or, ref left, ref right) => {
+                XPathValue::Boolean(self.evaluate(left, context)?.boolean() || self.evaluate(right, context)?.boolean())
+            },
+            Expr::Binary(BinaryOperator::And, ref left, ref right) => {
+                XPathValue::Boolean(self.evaluate(left, context)?.boolean() && self.evaluate(right, context)?.boolean())
+            },
+            Expr::Binary(operator, ref left, ref right) => {
+                let left = self.evaluate(left, context)?;
+                let right = self.evaluate(right, context)?;
+
+                match operator {
+                    BinaryOperator::Plus | BinaryOperator::Minus | BinaryOperator::Multiply | BinaryOperator::Div
+                    | BinaryOperator::Mod => {
+                        let left = left.number(self.document);
+                        let right = right.number(self.document);
+
+                        XPathValue::Number(match operator {
+                            BinaryOperator::Plus => left + right,
+                            BinaryOperator::Minus => left - right,
+                            BinaryOperator::Multiply => left * right,
+                            BinaryOperator::Div => left / right,
+                            _ => left % right,
+                        })
+                    },
+                    _ => XPathValue::Boolean(self.compare(operator, &left, &right)),
+                }
+            },
+            Expr::Negate(ref expr) => XPathValue::Number(-self.evaluate(expr, context)?.number(self.document)),
+            Expr::Union(ref left, ref right, offset) => {
+                let mut nodes = self.node_set(left, context, offset)?;
+
+                nodes.extend(self.node_set(right, context, offset)?);
+                self.sort(&mut nodes);
+
+                XPathValue::NodeSet(nodes)
+            },
+            Expr::Path(ref start, ref steps) => {
+                let mut nodes = match *start {
+                    PathStart::Root => vec![XPathNode::Node(self.document.root())],
+                    PathStart::Context => vec![context.node],
+                    PathStart::Filter(ref expr, offset) => self.node_set(expr, context, offset)?,
+                };
+
+                for step in steps {
+                    nodes = self.apply_step(&nodes, step)?;
+                }
+
+                XPathValue::NodeSet(nodes)
+            },
+            Expr::Filter(ref expr, ref predicates, offset) => {
+                let mut nodes = self.node_set(expr, context, offset)?;
+
+                for predicate in predicates {
+                    nodes = self.apply_predicate(nodes, predicate)?;
+                }
+
+                XPathValue::NodeSet(nodes)
+            },
+            Expr::Literal(ref literal) => XPathValue::String(literal.clone()),
+            Expr::Number(number) => XPathValue::Number(number),
+            Expr::Function(function, ref args, offset) => self.call(function, args, context, offset)?,
+        };
+
+        Ok(value)
+    }
+
+    /// Evaluates expression that must return node set, error is reported at offset otherwise
+    fn node_set(&self, expr: &Expr, context: Context, offset: usize) -> Result<Vec<XPathNode>, XPathError> {
+        match self.evaluate(expr, context)? {
+            XPathValue::NodeSet(nodes) => Ok(nodes),
+            _ => Err(XPathError {
+                message: "expected node set".to_string(),
+                offset,
+            }),
+        }
+    }
+
+    /// Sorts nodes in document order removing duplicates, attributes follow their element
+    fn sort(&self, nodes: &mut Vec<XPathNode>) {
+        nodes.sort_by_key(|&node| match node {
+            XPathNode::Node(id) => (self.order[id.index()], 0),
+            XPathNode::Attribute(id, index) => (self.order[id.index()], index + 1),
+        });
+        nodes.dedup();
+    }
+
+    /// Selects nodes of step for every node of node set, result is in document order
+    fn apply_step(&self, nodes: &[XPathNode], step: &Step) -> Result<Vec<XPathNode>, XPathError> {
+        let mut result = Vec::new();
+
+        for &node in nodes {
+            // predicates count positions in order of axis, so the nearest ancestor is the first one
+            let mut selected: Vec<XPathNode> = self.axis(node, step.axis).into_iter()
+                .filter(|&candidate| self.test(candidate, step.axis, &step.test))
+                .collect();
+
+            for predicate in &step.predicates {
+                selected = self.apply_predicate(selected, predicate)?;
+            }
+
+            result.extend(selected);
+        }
+
+        self.sort(&mut result);
+
+        Ok(result)
+    }
+
+    /// Keeps nodes for which predicate is true, number is compared to position of node
+    fn apply_predicate(&self, nodes: Vec<XPathNode>, predicate: &Expr) -> Result<Vec<XPathNode>, XPathError> {
+        let size = nodes.len();
+        let mut result = Vec::new();
+
+        for (i, node) in nodes.into_iter().enumerate() {
+            let context = Context {
+                node,
+                position: i + 1,
+                size,
+            };
+
+            let keep = match self.evaluate(predicate, context)? {
+                XPathValue::Number(number) => number == (i + 1) as f64,
+                value => value.boolean(),
+            };
+
+            if keep {
+                result.push(node);
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Returns nodes on axis of node in order of axis: reverse axes go from the nearest node.
+    /// DOCTYPE is not XPath node, so it is skipped
+    fn axis(&self, node: XPathNode, axis: Axis) -> Vec<XPathNode> {
+        let document = self.document;
+        let id = node.node_id();
+        let is_attribute = matches!(node, XPathNode::Attribute(..));
+
+        let ids: Vec<NodeId> = match axis {
+            Axis::SelfNode => return vec![node],
+            Axis::Attribute if is_attribute => Vec::new(),
+            Axis::Attribute => {
+                let count = document[id].as_element().map_or(0, |element| element.attributes.len());
+
+                return (0..count).map(|index| XPathNode::Attribute(id, index)).collect();
+            },
+            Axis::Namespace => Vec::new(),
+            Axis::Child | Axis::Descendant | Axis::FollowingSibling | Axis::PrecedingSibling if is_attribute => {
+                Vec::new()
+            },
+            Axis::Child => document.children(id).collect(),
+            Axis::Descendant => document.descendants(id).collect(),
+            Axis::DescendantOrSelf if is_attribute => return vec![node],
+            Axis::DescendantOrSelf => Some(id).into_iter().chain(document.descendants(id)).collect(),
+            Axis::Parent if is_attribute => vec![id],
+            Axis::Parent => document[id].parent().into_iter().collect(),
+            Axis::Ancestor | Axis::AncestorOrSelf => {
+                let mut ids: Vec<NodeId> = Vec::new();
+
+                if is_attribute {
+                    ids.push(id);
+                }
+
+                ids.extend(::std::iter::successors(document[id].parent(), |&parent| document[parent].parent()));
+
+                if axis == Axis::AncestorOrSelf {
+                    let mut nodes = vec![node];
+
+                    nodes.extend(ids.into_iter().map(XPathNode::Node));
+
+                    return nodes;
+                }
+
+                ids
+            },
+            Axis::FollowingSibling => {
+                ::std::iter::successors(document[id].next_sibling(), |&sibling| document[sibling].next_sibling()).collect()
+            },
+            Axis::PrecedingSibling => {
+                ::std::iter::successors(document[id].prev_sibling(), |&sibling| document[sibling].prev_sibling()).collect()
+            },
+            Axis::Following => {
+                // descendants of element follow its attributes
+                let mut ids: Vec<NodeId> = if is_attribute { document.descendants(id).collect() } else { Vec::new() };
+
+                for ancestor in ::std::iter::successors(Some(id), |&ancestor| document[ancestor].parent()) {
+                    let siblings = ::std::iter::successors(document[ancestor].next_sibling(), |&sibling| {
+                        document[sibling].next_sibling()
+                    });
+
+                    for sibling in siblings {
+                        ids.push(sibling);
+                        ids.extend(document.descendants(sibling));
+                    }
+                }
+
+                ids
+            },
+            Axis::Preceding => {
+                let mut ids = Vec::new();
+
+                for ancestor in ::std::iter::successors(Some(id), |&ancestor| document[ancestor].parent()) {
+                    let siblings = ::std::iter::successors(document[ancestor].prev_sibling(), |&sibling| {
+                        document[sibling].prev_sibling()
+                    });
+
+                    for sibling in siblings {
+                        let mut subtree: Vec<NodeId> = Some(sibling).into_iter()
+                            .chain(document.descendants(sibling))
+                            .collect();
+
+                        subtree.reverse();
+                        ids.extend(subtree);
+                    }
+                }
+
+                ids
+            },
+        };
+
+        ids.into_iter()
+            .filter(|&id| !matches!(document[id].data, NodeData::Doctype(_)))
+            .map(XPathNode::Node)
+            .collect()
+    }
+
+    /// Returns true if node passes node test, name tests only match principal node type of axis:
+    /// attributes on attribute axis and elements otherwise
+    fn test(&self, node: XPathNode, axis: Axis, test: &NodeTest) -> bool {
+        let data = &self.document[node.node_id()].data;
+
+        let name = match (node, data) {
+            (XPathNode::Attribute(id, index), _) if axis == Axis::Attribute => attribute(self.document, id, index)
+                .map(|(name, _)| name),
+            (XPathNode::Node(_), NodeData::Element(element)) if axis != Axis::Attribute => {
+                Some(element.name.as_str())
+            },
+            _ => None,
+        };
+
+        // names of HTML elements and their attributes are case-insensitive
+        let html = matches!(*data, NodeData::Element(ref element) if element.namespace == Namespace::Html);
+        let same_name = |name: &str, expected: &str| {
+            if html { name.eq_ignore_ascii_case(expected) } else { name == expected }
+        };
+
+        match *test {
+            NodeTest::Any => name.is_some(),
+            NodeTest::Prefix(ref prefix) => {
+                name.and_then(|name| name.split_once(':')).is_some_and(|(name_prefix, _)| same_name(name_prefix, prefix))
+            },
+            NodeTest::Name(ref expected) => name.is_some_and(|name| same_name(name, expected)),
+            NodeTest::Node => true,
+            NodeTest::Text => matches!(node, XPathNode::Node(_)) && matches!(*data, NodeData::Text(_)),
+            NodeTest::Comment => matches!(node, XPathNode::Node(_)) && matches!(*data, NodeData::Comment(_)),
+            NodeTest::ProcessingInstruction => false,
+        }
+    }
+
+    /// Compares values with =, != or relational operator. Node sets are compared by string-values of their
+    /// nodes, comparison is true if it is true for any of them
+    fn compare(&self, operator: BinaryOperator, left: &XPathValue, right: &XPathValue) -> bool {
+        let document = self.document;
+        let strings = |nodes: &[XPathNode]| -> Vec<XPathValue> {
+            nodes.iter().map(|node| XPathValue::String(node.string_value(document))).collect()
+        };
+
+        match (left, right) {
+            (&XPathValue::NodeSet(_), &XPathValue::Boolean(_)) | (&XPathValue::Boolean(_), &XPathValue::NodeSet(_)) => {
+                self.compare_atomic(operator, &XPathValue::Boolean(left.boolean()), &XPathValue::Boolean(right.boolean()))
+            },
+            (XPathValue::NodeSet(nodes), _) => {
+                strings(nodes).iter().any(|left| self.compare(operator, left, right))
+            },
+            (_, XPathValue::NodeSet(nodes)) => {
+                strings(nodes).iter().any(|right| self.compare(operator, left, right))
+            },
+            _ => self.compare_atomic(operator, left, right),
+        }
+    }
+
+    /// Compares values that are not node sets: = and != compare booleans if any of values is boolean,
+    /// numbers if any of them is number and strings otherwise. Relational operators compare numbers
+    fn compare_atomic(&self, operator: BinaryOperator, left: &XPathValue, right: &XPathValue) -> bool {
+        let document = self.document;
+
+        match operator {
+            BinaryOperator::Equals | BinaryOperator::NotEquals => {
+                let equal = match (left, right) {
+                    (&XPathValue::Boolean(_), _) | (_, &XPathValue::Boolean(_)) => left.boolean() == right.boolean(),
+                    (&XPathValue::Number(_), _) | (_, &XPathValue::Number(_)) => {
+                        left.number(document) == right.number(document)
+                    },
+                    _ => left.string(document) == right.string(document),
+                };
+
+                equal == (operator == BinaryOperator::Equals)
+            },
+            _ => {
+                let left = left.number(document);
+                let right = right.number(document);
+
+                match operator {
+                    BinaryOperator::Less => left < right,
+                    BinaryOperator::LessOrEqual => left <= right,
+                    BinaryOperator::Greater => left > right,
+                    _ => left >= right,
+                }
+            },
+        }
+    }
+
+    fn call(&self, function: Function, args: &[Expr], context: Context, offset: usize)
+        -> Result<XPathValue, XPathError> {
+        let document = self.document;
+
+        // most functions take context node when argument is missing
+        let arg = |index: usize| -> Result<XPathValue, XPathError> {
+            match args.get(index) {
+                Some(expr) => self.evaluate(expr, context),
+                None => Ok(XPathValue::NodeSet(vec![context.node])),
+            }
+        };
+        let string = |index: usize| -> Result<String, XPathError> { Ok(arg(index)?.string(document)) };
+        let number = |index: usize| -> Result<f64, XPathError> { Ok(arg(index)?.number(document)) };
+        let first_node = || -> Result<Option<XPathNode>, XPathError> {
+            match args.first() {
+                Some(expr) => Ok(self.node_set(expr, context, offset)?.first().cloned()),
+                None => Ok(Some(context.node)),
+            }
+        };
+
+        let value = match function {
+            Function::Last => XPathValue::Number(context.size as f64),
+            Function::Position => XPathValue::Number(context.position as f64),
+            Function::Count => XPathValue::Number(self.node_set(&args[0], context, offset)?.len() as f64),
+            Function::Id => {
+                let ids = match arg(0)? {
+                    XPathValue::NodeSet(nodes) => nodes.iter().map(|node| node.string_value(document))
+                        .collect::<Vec<_>>().join(" "),
+                    value => value.string(document),
+                };
+
+                let ids: Vec<&str> = ids.split(is_whitespace).filter(|id| !id.is_empty()).collect();
+
+                let nodes = document.descendants(document.root())
+                    .filter(|&id| document[id].as_element()
+                        .and_then(|element| element.attribute("id"))
+                        .is_some_and(|value| ids.contains(&value)))
+                    .map(XPathNode::Node)
+                    .collect();
+
+                XPathValue::NodeSet(nodes)
+            },
+            Function::LocalName | Function::Name | Function::NamespaceUri => {
+                let node = first_node()?;
+
+                let name = node.and_then(|node| match node {
+                    XPathNode::Node(id) => document[id].name(),
+                    XPathNode::Attribute(id, index) => attribute(document, id, index).map(|(name, _)| name),
+                });
+
+                let value = match function {
+                    Function::LocalName => name.map_or("", |name| name.rsplit(':').next().unwrap_or(name)),
+                    Function::Name => name.unwrap_or(""),
+                    _ => match node {
+                        Some(XPathNode::Node(id)) => document[id].as_element()
+                            .map_or("", |element| namespace_uri(element.namespace)),
+                        _ => "",
+                    },
+                };
+
+                XPathValue::String(value.to_string())
+            },
+            Function::String => XPathValue::String(string(0)?),
+            Function::Concat => {
+                let mut result = String::new();
+
+                for index in 0..args.len() {
+                    result.push_str(&string(index)?);
+                }
+
+                XPathValue::String(result)
+            },
+            Function::StartsWith => XPathValue::Boolean(string(0)?.starts_with(&string(1)?)),
+            Function::Contains => XPathValue::Boolean(string(0)?.contains(&string(1)?)),
+            Function::SubstringBefore | Function::SubstringAfter => {
+                let (text, separator) = (string(0)?, string(1)?);
+
+                let result = match text.split_once(&separator) {
+                    Some((before, _)) if function == Function::SubstringBefore => before,
+                    Some((_, after)) if function == Function::SubstringAfter => after,
+                    _ => "",
+                };
+
+                XPathValue::String(result.to_string())
+            },
+            Function::Substring => {
+                let text = string(0)?;
+                let start = round(number(1)?);
+                let end = if args.len() > 2 { start + round(number(2)?) } else { f64::INFINITY };
+
+                // chars are counted from 1, NaN never matches
+                let result = text.chars().enumerate()
+                    .filter(|&(i, _)| (i + 1) as f64 >= start && ((i + 1) as f64) < end)
+                    .map(|(_, ch)| ch)
+                    .collect();
+
+                XPathValue::String(result)
+            },
+            Function::StringLength => XPathValue::Number(string(0)?.chars().count() as f64),
+            Function::NormalizeSpace => {
+                let text = string(0)?;
+
+                XPathValue::String(text.split(is_whitespace).filter(|word| !word.is_empty()).collect::<Vec<_>>()
+                    .join(" "))
+            },
+            Function::Translate => {
+                let from: Vec<char> = string(1)?.chars().collect();
+                let to: Vec<char> = string(2)?.chars().collect();
+
+                let result = string(0)?.chars()
+                    .filter_map(|ch| match from.iter().position(|&from| from == ch) {
+                        Some(pos) => to.get(pos).cloned(),
+                        None => Some(ch),
+                    })
+                    .collect();
+
+                XPathValue::String(result)
+            },
+            Function::Boolean => XPathValue::Boolean(arg(0)?.boolean()),
+            Function::Not => XPathValue::Boolean(!arg(0)?.boolean()),
+            Function::True => XPathValue::Boolean(true),
+            Function::False => XPathValue::Boolean(false),
+            Function::Lang => {
+                let language = string(0)?.to_ascii_lowercase();
+                let id = context.node.node_id();
+
+                let lang = ::std::iter::successors(Some(id), |&ancestor| document[ancestor].parent())
+                    .filter_map(|ancestor| document[ancestor].as_element())
+                    .find_map(|element| element.attribute("xml:lang").or_else(|| element.attribute("lang")));
+
+                XPathValue::Boolean(lang.is_some_and(|lang| {
+                    let lang = lang.to_ascii_lowercase();
+
+                    lang == language || (lang.starts_with(&language) && lang[language.len()..].starts_with('-'))
+                }))
+            },
+            Function::Number => XPathValue::Number(number(0)?),
+            Function::Sum => {
+                let nodes = self.node_set(&args[0], context, offset)?;
+
+                XPathValue::Number(nodes.iter().map(|node| string_to_number(&node.string_value(document))).sum())
+            },
+            Function::Floor => XPathValue::Number(number(0)?.floor()),
+            Function::Ceiling => XPathValue::Number(number(0)?.ceil()),
+            Function::Round => XPathValue::Number(round(number(0)?)),
+        };
+
+        Ok(value)
+    }
+}
+
+/// Returns name and value of attribute of element
+fn attribute(document: &Document, id: NodeId, index: usize) -> Option<(&str, &str)> {
+    document[id].as_element()
+        .and_then(|element| element.attributes.get(index))
+        .map(|attribute| (attribute.name.as_str(), attribute.value.as_str()))
+}
+
+fn namespace_uri(namespace: Namespace) -> &'static str {
+    match namespace {
+        Namespace::Html => "http://www.w3.org/1999/xhtml",
+        Namespace::Svg => "http://www.w3.org/2000/svg",
+        Namespace::MathMl => "http://www.w3.org/1998/Math/MathML",
+    }
+}
+
+/// Returns true if char is whitespace in XPath, unlike Unicode whitespace it does not include no-break space
+fn is_whitespace(ch: char) -> bool {
+    matches!(ch, ' ' | '\t' | '\n' | '\r')
+}
+
+/// Converts string to number: optional minus sign followed by digits with optional decimal point and
+/// whitespace around, anything else is NaN
+fn string_to_number(string: &str) -> f64 {
+    let trimmed = string.trim_matches(is_whitespace);
+    let digits = trimmed.strip_prefix('-').unwrap_or(trimmed);
+
+    let valid = digits.chars().any(|ch| ch.is_ascii_digit()) && digits.matches('.').count() <= 1
+        && digits.chars().all(|ch| ch.is_ascii_digit() || ch == '.');
+
+    if valid {
+        trimmed.parse().unwrap_or(f64::NAN)
+    } else {
+        f64::NAN
+    }
+}
+
+/// Converts number to string: integers have no decimal point and there is never an exponent
+fn number_to_string(number: f64) -> String {
+    if number.is_nan() {
+        "NaN".to_string()
+    } else if number.is_infinite() {
+        if number > 0.0 { "Infinity" } else { "-Infinity" }.to_string()
+    } else if number == 0.0 {
+        // negative zero too
+        "0".to_string()
+    } else {
+        number.to_string()
+    }
+}
+
+/// Rounds number to the closest integer, halves are rounded towards positive infinity
+fn round(number: f64) -> f64 {
+    if number.is_nan() || number.is_infinite() {
+        number
+    } else {
+        (number + 0.5).floor()
+    }
+}
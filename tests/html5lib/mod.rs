@@ -0,0 +1,24 @@
+//! Runners of html5lib-tests (https://github.com/html5lib/html5lib-tests) vendored in tests/html5lib-tests
+
+pub mod tokenizer;
+pub mod tree_construction;
+
+use std::fs::File;
+use std::io::Read;
+
+use serde_json::{self, Value};
+
+/// Reads test file from tests/html5lib-tests
+pub fn read_file(path: &str) -> String {
+    let path = format!("{}/tests/html5lib-tests/{}", env!("CARGO_MANIFEST_DIR"), path);
+    let mut content = String::new();
+
+    File::open(&path).and_then(|mut file| file.read_to_string(&mut content)).expect(&path);
+
+    content
+}
+
+/// Reads JSON test file from tests/html5lib-tests
+pub fn read_json(path: &str) -> Value {
+    serde_json::from_str(&read_file(path)).expect(path)
+}
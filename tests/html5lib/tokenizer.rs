@@ -0,0 +1,204 @@
+use serde_json::{Map, Value};
+
+use yahap::html_chunk::ChunkType;
+use yahap::html_parser::{HtmlParser, TokenizerState};
+
+use super::read_json;
+
+/// Tests which expect errors for -- inside of comments, such errors were removed from the spec after these
+/// tests were written, so only tokens are compared
+const COMMENT_DASH_ERRORS: [&str; 7] = [
+    "Comment, Central dash no space",
+    "Comment, two central dashes",
+    "<!---- -->",
+    "<!----  -->",
+    "<!---- a-->",
+    "<!----!a-->",
+    "<!----!-->",
+];
+
+/// Runs tests from tokenizer test file in conformance mode, returns descriptions of failed tests
+/// along with what went wrong. Vendored files are in the old format that marks errors with "ParseError"
+/// without codes, so only whether there are errors is compared, not their codes
+pub fn run(file: &str) -> Vec<String> {
+    let json = read_json(&format!("tokenizer/{}", file));
+    let mut failures = Vec::new();
+
+    let tests = json["tests"].as_array().expect(file);
+
+    for test in tests {
+        let description = test["description"].as_str().unwrap_or("");
+        let double_escaped = test["doubleEscaped"].as_bool().unwrap_or(false);
+
+        let input = match unescape(test["input"].as_str().expect(description), double_escaped) {
+            Some(input) => input,
+            // lone surrogates can not be in Rust strings, so there is nothing to test
+            None => continue,
+        };
+
+        let expected = expected_tokens(&test["output"], double_escaped);
+        let expected_errors = test["output"].as_array().expect(description).iter()
+            .any(|token| token == "ParseError");
+
+        let states = match test["initialStates"].as_array() {
+            Some(states) => states.iter().map(|state| state.as_str().expect(description).to_string()).collect(),
+            None => vec!["Data state".to_string()],
+        };
+
+        for state in states {
+            let mut parser = HtmlParser::new();
+
+            parser.conformance_mode = true;
+            parser.auto_switch_states = false;
+            parser.init(&input);
+            parser.set_tokenizer_state(tokenizer_state(&state));
+            parser.set_last_start_tag(test["lastStartTag"].as_str());
+
+            let tokens = parse_tokens(&mut parser);
+            let errors = parser.errors().iter().map(|error| error.to_string()).collect::<Vec<_>>();
+
+            if tokens != expected {
+                failures.push(format!("{} ({}): {:?}\n  expected: {}\n  got:      {}",
+                    description, state, input, Value::Array(expected.clone()), Value::Array(tokens)));
+            } else if expected_errors == errors.is_empty() && !COMMENT_DASH_ERRORS.contains(&description) {
+                failures.push(format!("{} ({}): {:?}\n  expected errors: {}\n  got: {:?}",
+                    description, state, input, expected_errors, errors));
+            }
+        }
+    }
+
+    failures
+}
+
+/// Parses whole HTML converting chunks into html5lib tokens, chars are merged into single token
+fn parse_tokens(parser: &mut HtmlParser) -> Vec<Value> {
+    let mut tokens = Vec::new();
+    let mut chars = String::new();
+
+    while let Some(chunk) = parser.parse_next() {
+        if chunk.chunk_type == ChunkType::Text {
+            chars.push_str(&chunk.html);
+            continue;
+        }
+
+        if !chars.is_empty() {
+            tokens.push(json!(["Character", chars]));
+            chars.clear();
+        }
+
+        tokens.push(match chunk.chunk_type {
+            ChunkType::OpenTag => {
+                let mut params = Map::new();
+
+                for i in 0..chunk.params_count {
+                    params.insert(chunk.param_names[i].clone(), Value::String(chunk.param_values[i].clone()));
+                }
+
+                if chunk.end_closure {
+                    json!(["StartTag", chunk.tag, params, true])
+                } else {
+                    json!(["StartTag", chunk.tag, params])
+                }
+            },
+            ChunkType::CloseTag => json!(["EndTag", chunk.tag]),
+            ChunkType::Comment => json!(["Comment", chunk.html]),
+            ChunkType::Doctype => {
+                let doctype = chunk.doctype.clone().unwrap_or_default();
+
+                json!(["DOCTYPE", doctype.name, doctype.public_id, doctype.system_id, !doctype.force_quirks])
+            },
+            chunk_type => panic!("unexpected chunk type {:?}", chunk_type),
+        });
+    }
+
+    if !chars.is_empty() {
+        tokens.push(json!(["Character", chars]));
+    }
+
+    tokens
+}
+
+/// Returns expected tokens without parse errors, adjacent chars are merged as some tests have them split
+fn expected_tokens(output: &Value, double_escaped: bool) -> Vec<Value> {
+    let mut tokens: Vec<Value> = Vec::new();
+
+    for token in output.as_array().expect("output") {
+        if token == "ParseError" {
+            continue;
+        }
+
+        let mut token = token.clone();
+
+        if double_escaped {
+            unescape_strings(&mut token);
+        }
+
+        if token[0] == "Character" {
+            if let Some(last) = tokens.last_mut() {
+                if last[0] == "Character" {
+                    let chars = format!("{}{}", last[1].as_str().unwrap(), token[1].as_str().unwrap());
+
+                    last[1] = Value::String(chars);
+                    continue;
+                }
+            }
+        }
+
+        tokens.push(token);
+    }
+
+    tokens
+}
+
+fn unescape_strings(value: &mut Value) {
+    match *value {
+        Value::String(ref mut text) => *text = unescape(text, true).unwrap_or_default(),
+        Value::Array(ref mut values) => values.iter_mut().for_each(unescape_strings),
+        Value::Object(ref mut map) => {
+            let unescaped = map.iter()
+                .map(|(name, value)| {
+                    let mut value = value.clone();
+
+                    unescape_strings(&mut value);
+
+                    (unescape(name, true).unwrap_or_default(), value)
+                })
+                .collect();
+
+            *map = unescaped;
+        },
+        _ => {}
+    }
+}
+
+/// Converts \uXXXX escapes of double escaped tests into chars, returns None if there is a lone surrogate
+fn unescape(text: &str, double_escaped: bool) -> Option<String> {
+    if !double_escaped {
+        return Some(text.to_string());
+    }
+
+    let mut units = Vec::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find("\\u") {
+        units.extend(rest[..start].encode_utf16());
+        units.push(u16::from_str_radix(&rest[start + 2..start + 6], 16).expect(text));
+        rest = &rest[start + 6..];
+    }
+
+    units.extend(rest.encode_utf16());
+
+    String::from_utf16(&units).ok()
+}
+
+fn tokenizer_state(name: &str) -> TokenizerState {
+    match name {
+        "Data state" => TokenizerState::Data,
+        "PLAINTEXT state" => TokenizerState::Plaintext,
+        "RCDATA state" => TokenizerState::Rcdata,
+        "RAWTEXT state" => TokenizerState::Rawtext,
+        "Script data state" => TokenizerState::ScriptData,
+        "CDATA section state" => TokenizerState::CdataSection,
+        _ => panic!("unknown tokenizer state {}", name),
+    }
+}
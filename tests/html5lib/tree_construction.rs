@@ -0,0 +1,176 @@
+use yahap::html_chunk::Namespace;
+use yahap::html_document::{Document, NodeId, NodeData};
+use yahap::html_parser::HtmlParser;
+
+use super::read_file;
+
+/// Test from .dat file
+struct Test {
+    data: String,
+    fragment_context: Option<String>,
+    scripting: Option<bool>,
+    document: String,
+}
+
+/// Runs tree construction tests from .dat file, returns descriptions of failed tests along with trees
+pub fn run(file: &str) -> Vec<String> {
+    let content = read_file(&format!("tree-construction/{}", file));
+    let mut failures = Vec::new();
+
+    for test in parse_tests(&content) {
+        let modes = match test.scripting {
+            Some(scripting) => vec![scripting],
+            None => vec![false, true],
+        };
+
+        for scripting in modes {
+            let mut parser = HtmlParser::new();
+
+            parser.conformance_mode = true;
+            parser.scripting_enabled = scripting;
+            parser.init(&test.data);
+
+            let document = match test.fragment_context {
+                Some(ref context) => match context.split_once(' ') {
+                    Some(("svg", name)) => Document::namespaced_fragment_from_parser(&mut parser, Namespace::Svg, name),
+                    Some(("math", name)) => {
+                        Document::namespaced_fragment_from_parser(&mut parser, Namespace::MathMl, name)
+                    },
+                    _ => Document::fragment_from_parser(&mut parser, context),
+                },
+                None => Document::from_conformant_parser(&mut parser),
+            };
+            let tree = serialize(&document);
+
+            if tree != test.document {
+                failures.push(format!("{:?} (scripting {}):\n  expected:\n{}\n  got:\n{}",
+                    test.data, if scripting { "on" } else { "off" }, test.document, tree));
+            }
+        }
+    }
+
+    failures
+}
+
+fn parse_tests(content: &str) -> Vec<Test> {
+    let mut tests = Vec::new();
+
+    for block in content.split("\n\n#data\n") {
+        // leading newline lets us find #errors after empty data the same way
+        let block = format!("\n{}", block.trim_start_matches("#data\n"));
+        let errors = block.find("\n#errors\n").expect("#errors");
+        let mut data = block[1..errors.max(1)].to_string();
+        let mut fragment_context = None;
+        let mut scripting = None;
+
+        // some files have scripting flag before #errors
+        for &(line, flag) in &[("\n#script-on", true), ("\n#script-off", false)] {
+            if data.ends_with(line) {
+                data.truncate(data.len() - line.len());
+                scripting = Some(flag);
+            }
+        }
+
+        let mut lines = block[errors + 1..].lines();
+
+        while let Some(line) = lines.next() {
+            match line {
+                "#document-fragment" => fragment_context = lines.next().map(String::from),
+                "#script-on" => scripting = Some(true),
+                "#script-off" => scripting = Some(false),
+                "#document" => break,
+                _ => {},
+            }
+        }
+
+        let document = block.split_once("\n#document\n").expect("#document").1.trim_end_matches('\n');
+
+        tests.push(Test {
+            data,
+            fragment_context,
+            scripting,
+            document: document.to_string(),
+        });
+    }
+
+    tests
+}
+
+/// Serializes document the way trees are written in .dat files
+fn serialize(document: &Document) -> String {
+    let mut lines = Vec::new();
+
+    for child in document.children(document.root()) {
+        serialize_node(document, child, 0, &mut lines);
+    }
+
+    lines.join("\n")
+}
+
+fn serialize_node(document: &Document, id: NodeId, depth: usize, lines: &mut Vec<String>) {
+    let indent = format!("| {}", "  ".repeat(depth));
+    let mut depth = depth;
+
+    match document[id].data {
+        NodeData::Element(ref element) => {
+            let prefix = match element.namespace {
+                Namespace::Html => "",
+                Namespace::Svg => "svg ",
+                Namespace::MathMl => "math ",
+            };
+
+            lines.push(format!("{}<{}{}>", indent, prefix, element.name));
+
+            let mut attributes: Vec<_> = element.attributes.iter()
+                .map(|attribute| {
+                    let name = if element.namespace == Namespace::Html {
+                        attribute.name.clone()
+                    } else {
+                        attribute_name_string(&attribute.name)
+                    };
+
+                    (name, attribute.value.clone())
+                })
+                .collect();
+
+            attributes.sort();
+
+            for (name, value) in attributes {
+                lines.push(format!("{}  {}=\"{}\"", indent, name, value));
+            }
+
+            if element.namespace == Namespace::Html && element.name == "template" {
+                lines.push(format!("{}  content", indent));
+                depth += 1;
+            }
+        },
+        NodeData::Text(ref text) => lines.push(format!("{}\"{}\"", indent, text)),
+        NodeData::Comment(ref text) => lines.push(format!("{}<!-- {} -->", indent, text)),
+        NodeData::Doctype(ref doctype) => {
+            let name = doctype.name.as_deref().unwrap_or("");
+            let public_id = doctype.public_id.as_deref().unwrap_or("");
+            let system_id = doctype.system_id.as_deref().unwrap_or("");
+
+            if public_id.is_empty() && system_id.is_empty() {
+                lines.push(format!("{}<!DOCTYPE {}>", indent, name));
+            } else {
+                lines.push(format!("{}<!DOCTYPE {} \"{}\" \"{}\">", indent, name, public_id, system_id));
+            }
+        },
+        NodeData::Document => {},
+    }
+
+    for child in document.children(id) {
+        serialize_node(document, child, depth + 1, lines);
+    }
+}
+
+/// Returns attribute name of SVG or MathML element with namespace designator instead of prefix
+fn attribute_name_string(name: &str) -> String {
+    match name {
+        "xlink:actuate" | "xlink:arcrole" | "xlink:href" | "xlink:role" | "xlink:show" | "xlink:title"
+            | "xlink:type" | "xml:lang" | "xml:space" | "xmlns:xlink" => name.replacen(':', " ", 1),
+        "xmlns" => "xmlns xmlns".to_string(),
+        _ => name.to_string(),
+    }
+}
@@ -1 +1,1913 @@
-extern crate yahap;
\ No newline at end of file
+extern crate yahap;
+#[macro_use]
+extern crate serde_json;
+
+mod html5lib;
+
+use yahap::html_parser::{HtmlParser, InvalidChars};
+use yahap::html_chunk::{HtmlChunk, ChunkType, Namespace};
+use yahap::parse_error::ParseErrorCode;
+use yahap::html_document::{Document, NodeId, NodeData, Element, QuirksMode};
+use yahap::selector::{StreamingSelector, SelectorMatch};
+use yahap::xpath::{XPathValue, XPathNode};
+use yahap::rewriter::{HtmlRewriter, ContentType};
+use yahap::html_writer::HtmlWriter;
+use yahap::html_formatter::HtmlFormatter;
+use yahap::html_minifier::HtmlMinifier;
+use yahap::markdown::MarkdownConverter;
+
+/// Parses whole HTML returning copies of all chunks
+fn parse_all(parser: &mut HtmlParser, html: &str) -> Vec<(ChunkType, String, String)> {
+    let mut chunks = Vec::new();
+
+    parser.init(html);
+
+    while let Some(chunk) = parser.parse_next() {
+        chunks.push((chunk.chunk_type, chunk.tag.clone(), chunk.html.clone()));
+    }
+
+    chunks
+}
+
+fn error_codes(parser: &HtmlParser) -> Vec<ParseErrorCode> {
+    parser.errors().iter().map(|e| e.code).collect()
+}
+
+fn comment(data: &str) -> (ChunkType, String, String) {
+    (ChunkType::Comment, "!--".to_string(), data.to_string())
+}
+
+#[test]
+fn parses_tags_and_text() {
+    let mut parser = HtmlParser::new();
+    let chunks = parse_all(&mut parser, "<p class=a>Hello</p>");
+
+    assert_eq!(chunks, vec![
+        (ChunkType::OpenTag, "p".to_string(), "".to_string()),
+        (ChunkType::Text, "".to_string(), "Hello".to_string()),
+        (ChunkType::CloseTag, "p".to_string(), "".to_string()),
+    ]);
+}
+
+#[test]
+fn comment_data_follows_spec() {
+    let mut parser = HtmlParser::new();
+
+    assert_eq!(parse_all(&mut parser, "<!-- a -->"), vec![comment(" a ")]);
+    assert_eq!(parse_all(&mut parser, "<!--a--->"), vec![comment("a-")]);
+    assert_eq!(parse_all(&mut parser, "<!--a--b-->"), vec![comment("a--b")]);
+    assert_eq!(parse_all(&mut parser, "<!---a-->"), vec![comment("-a")]);
+    assert!(parser.errors().is_empty());
+}
+
+#[test]
+fn abruptly_closed_empty_comments() {
+    let mut parser = HtmlParser::new();
+
+    for html in &["<!-->", "<!--->"] {
+        let mut chunks = parse_all(&mut parser, &format!("{}x", html));
+
+        assert_eq!(chunks.remove(0), comment(""));
+        assert_eq!(chunks, vec![(ChunkType::Text, "".to_string(), "x".to_string())]);
+        assert_eq!(error_codes(&parser), vec![ParseErrorCode::AbruptClosingOfEmptyComment]);
+    }
+}
+
+#[test]
+fn incorrectly_closed_comment() {
+    let mut parser = HtmlParser::new();
+
+    assert_eq!(parse_all(&mut parser, "<!--a--!>b"), vec![
+        comment("a"),
+        (ChunkType::Text, "".to_string(), "b".to_string()),
+    ]);
+    assert_eq!(error_codes(&parser), vec![ParseErrorCode::IncorrectlyClosedComment]);
+
+    assert_eq!(parse_all(&mut parser, "<!--a--!b-->"), vec![comment("a--!b")]);
+    assert!(parser.errors().is_empty());
+}
+
+#[test]
+fn nested_comment() {
+    let mut parser = HtmlParser::new();
+
+    assert_eq!(parse_all(&mut parser, "<!--<!--x-->"), vec![comment("<!--x")]);
+    assert_eq!(error_codes(&parser), vec![ParseErrorCode::NestedComment]);
+
+    assert_eq!(parse_all(&mut parser, "<!--<!-->"), vec![comment("<!")]);
+    assert!(parser.errors().is_empty());
+}
+
+#[test]
+fn unterminated_comments_at_eof() {
+    let mut parser = HtmlParser::new();
+
+    for &(html, data) in &[("<!--abc", "abc"), ("<!--abc-", "abc"), ("<!--abc--", "abc"),
+        ("<!--abc--!", "abc"), ("<!--", ""), ("<!---", "")] {
+        assert_eq!(parse_all(&mut parser, html), vec![comment(data)], "{}", html);
+        assert_eq!(error_codes(&parser), vec![ParseErrorCode::EofInComment], "{}", html);
+    }
+}
+
+#[test]
+fn bogus_comments() {
+    let mut parser = HtmlParser::new();
+
+    assert_eq!(parse_all(&mut parser, "<!foo>"), vec![comment("foo")]);
+    assert_eq!(error_codes(&parser), vec![ParseErrorCode::IncorrectlyOpenedComment]);
+
+    assert_eq!(parse_all(&mut parser, "<?php echo 1 ?>"), vec![comment("?php echo 1 ?")]);
+    assert_eq!(error_codes(&parser), vec![ParseErrorCode::UnexpectedQuestionMarkInsteadOfTagName]);
+}
+
+#[test]
+fn comment_with_full_html() {
+    let mut parser = HtmlParser::new();
+    parser.extract_between_tags_only = false;
+
+    assert_eq!(parse_all(&mut parser, "<!--a--!>"), vec![comment("<!--a--!>")]);
+
+    parser.keep_comments = false;
+
+    assert_eq!(parse_all(&mut parser, "<!--a-->"), vec![comment("")]);
+}
+
+#[test]
+fn generated_comment_is_valid() {
+    let mut parser = HtmlParser::new();
+
+    for html in &["<!--a--b-->", "<!--a--->", "<!--<!--x-->", "<!-- ok -->"] {
+        parser.init(html);
+
+        let generated = parser.parse_next().unwrap().generate_html();
+
+        assert!(generated.starts_with("<!--") && generated.ends_with("-->"), "{}", generated);
+        assert!(!generated[4..generated.len() - 3].contains("--"), "{}", generated);
+
+        let mut reparsed = HtmlParser::new();
+        let chunks = parse_all(&mut reparsed, &generated);
+
+        assert_eq!(chunks.len(), 1, "{}", generated);
+        assert!(reparsed.errors().is_empty(), "{}", generated);
+    }
+
+    let mut chunk = HtmlChunk::new(false);
+    chunk.chunk_type = ChunkType::Comment;
+    chunk.tag = "!--".to_string();
+    chunk.html = "->x-".to_string();
+
+    assert_eq!(chunk.generate_html(), "<!-- ->x- -->");
+
+    chunk.html.clear();
+
+    assert_eq!(chunk.generate_html(), "<!---->");
+}
+
+#[test]
+fn generated_params_keep_non_ascii_values() {
+    let mut chunk = HtmlChunk::new(false);
+    chunk.chunk_type = ChunkType::OpenTag;
+    chunk.tag = "a".to_string();
+    chunk.add_param("title".to_string(), "ééééééééééé".to_string(), b'"');
+    chunk.add_param("alt".to_string(), "é\"ééééééééééé".to_string(), b'"');
+
+    assert_eq!(chunk.generate_html(), "<a title=\"ééééééééééé\" alt=\"é&#34;ééééééééééé\">");
+}
+
+/// Parses whole HTML returning type, tag, condition and html of each chunk
+fn parse_conditional(parser: &mut HtmlParser, html: &str) -> Vec<(ChunkType, String, String, String)> {
+    let mut chunks = Vec::new();
+
+    parser.init(html);
+
+    while let Some(chunk) = parser.parse_next() {
+        chunks.push((chunk.chunk_type, chunk.tag.clone(), chunk.condition.clone(), chunk.html.clone()));
+    }
+
+    chunks
+}
+
+fn chunk4(chunk_type: ChunkType, tag: &str, condition: &str, html: &str) -> (ChunkType, String, String, String) {
+    (chunk_type, tag.to_string(), condition.to_string(), html.to_string())
+}
+
+#[test]
+fn conditional_comment_as_single_comment() {
+    let mut parser = HtmlParser::new();
+
+    assert_eq!(parse_conditional(&mut parser, "<!--[if gte mso 9]><table></table><![endif]-->"), vec![
+        chunk4(ChunkType::Comment, "!--", "gte mso 9", "[if gte mso 9]><table></table><![endif]"),
+    ]);
+
+    assert_eq!(parse_conditional(&mut parser, "<!--[ifnot]>-->"), vec![
+        chunk4(ChunkType::Comment, "!--", "", "[ifnot]>"),
+    ]);
+}
+
+#[test]
+fn conditional_comment_content_parsed() {
+    let mut parser = HtmlParser::new();
+    parser.parse_conditional_comments = true;
+
+    assert_eq!(parse_conditional(&mut parser, "<!--[if mso]><table><!-- x --></table><![endif]-->"), vec![
+        chunk4(ChunkType::Comment, "![if", "mso", "<!--[if mso]>"),
+        chunk4(ChunkType::OpenTag, "table", "", ""),
+        chunk4(ChunkType::Comment, "!--", "", " x "),
+        chunk4(ChunkType::CloseTag, "table", "", ""),
+        chunk4(ChunkType::Comment, "![endif", "", "<![endif]-->"),
+    ]);
+    assert!(parser.errors().is_empty());
+}
+
+#[test]
+fn downlevel_revealed_conditional_comments() {
+    let mut parser = HtmlParser::new();
+
+    assert_eq!(parse_conditional(&mut parser, "<![if !IE]><p><![endif]>"), vec![
+        chunk4(ChunkType::Comment, "![if", "!IE", "<![if !IE]>"),
+        chunk4(ChunkType::OpenTag, "p", "", ""),
+        chunk4(ChunkType::Comment, "![endif", "", "<![endif]>"),
+    ]);
+    assert_eq!(error_codes(&parser), vec![ParseErrorCode::IncorrectlyOpenedComment; 2]);
+
+    for &parse in &[false, true] {
+        parser.parse_conditional_comments = parse;
+
+        assert_eq!(parse_conditional(&mut parser, "<!--[if !mso]><!--><p><!--<![endif]-->"), vec![
+            chunk4(ChunkType::Comment, "![if", "!mso", "<!--[if !mso]><!-->"),
+            chunk4(ChunkType::OpenTag, "p", "", ""),
+            chunk4(ChunkType::Comment, "![endif", "", "<!--<![endif]-->"),
+        ]);
+    }
+}
+
+#[test]
+fn conditional_markers_generate_html() {
+    let mut chunk = HtmlChunk::new(false);
+    chunk.chunk_type = ChunkType::Comment;
+    chunk.tag = "![if".to_string();
+    chunk.condition = "lt IE 9".to_string();
+
+    assert_eq!(chunk.generate_html(), "<![if lt IE 9]>");
+
+    chunk.tag = "![endif".to_string();
+
+    assert_eq!(chunk.generate_html(), "<![endif]>");
+}
+
+#[test]
+fn chunk_positions_are_tracked() {
+    let mut parser = HtmlParser::new();
+    parser.track_positions = true;
+    parser.init("<p>\r\n  <a href=\"x\" title=\u{e9}t\u{e9}>\u{1F600}</a>\n</p>");
+
+    let mut starts = Vec::new();
+
+    while let Some(chunk) = parser.parse_next() {
+        starts.push((chunk.tag.clone(), chunk.start.line, chunk.start.column, chunk.start.utf16_column));
+
+        if chunk.tag == "a" && chunk.chunk_type == ChunkType::OpenTag {
+            let href = chunk.param_positions[0];
+
+            assert_eq!((href.name_start.line, href.name_start.column), (2, 6));
+            assert_eq!((href.value_start.column, href.value_end.column), (12, 13));
+
+            let title = chunk.param_positions[1];
+
+            // title value is two 2-byte chars with single byte char between them
+            assert_eq!((title.value_start.column, title.value_end.column), (21, 26));
+            assert_eq!((title.value_start.char_column, title.value_end.char_column), (21, 24));
+            assert_eq!(chunk.end.column, 27);
+        }
+    }
+
+    assert_eq!(starts, vec![
+        ("p".to_string(), 1, 1, 1),
+        ("".to_string(), 1, 4, 4),
+        ("a".to_string(), 2, 3, 3),
+        ("".to_string(), 2, 27, 25),
+        ("a".to_string(), 2, 31, 27),
+        ("".to_string(), 2, 35, 31),
+        ("p".to_string(), 3, 1, 1),
+    ]);
+}
+
+#[test]
+fn line_index_converts_offsets() {
+    let mut parser = HtmlParser::new();
+    parser.track_positions = true;
+    parser.init("a\nb\r\nc\rd");
+
+    let index = parser.line_index().unwrap();
+
+    assert_eq!(index.line_count(), 4);
+    assert_eq!(index.line(0), 1);
+    assert_eq!(index.line(3), 2);
+    assert_eq!(index.line(4), 2);
+    assert_eq!(index.line(5), 3);
+    assert_eq!(index.offset(4, 1), Some(7));
+
+    let position = parser.position(7);
+
+    assert_eq!((position.line, position.column), (4, 1));
+
+    // offsets past the end are clamped to it
+    let position = parser.position(100);
+
+    assert_eq!((position.offset, position.line, position.column), (8, 4, 2));
+
+    // positions are not tracked by default
+    let mut parser = HtmlParser::new();
+    parser.init("\n<p>");
+
+    assert_eq!(parser.parse_next().unwrap().start.line, 0);
+    assert_eq!(parser.parse_next().unwrap().start.offset, 1);
+    assert_eq!(parser.position(1).line, 2);
+    assert_eq!(parser.position(10).offset, 4);
+}
+
+#[test]
+fn error_sink_receives_errors() {
+    use std::rc::Rc;
+    use std::cell::RefCell;
+
+    let found = Rc::new(RefCell::new(Vec::new()));
+    let sink_found = found.clone();
+
+    let mut parser = HtmlParser::new();
+    parser.set_error_sink(Box::new(move |error: &yahap::parse_error::ParseError| {
+        sink_found.borrow_mut().push((error.code.code(), error.offset));
+    }));
+
+    parse_all(&mut parser, "<p a=1 a=2 b=\"x\"c>\u{0}</p><a");
+
+    assert_eq!(*found.borrow(), vec![
+        ("duplicate-attribute", 7),
+        ("missing-whitespace-between-attributes", 16),
+        ("unexpected-null-character", 18),
+        ("eof-in-tag", 25),
+    ]);
+    assert_eq!(parser.errors().len(), 4);
+}
+
+#[test]
+fn duplicate_params_are_dropped() {
+    let mut parser = HtmlParser::new();
+    parser.init("<a href=1 HREF=2>");
+
+    let chunk = parser.parse_next().unwrap();
+
+    assert_eq!(chunk.params_count, 1);
+    assert_eq!(chunk.param_values[0], "1");
+}
+
+#[test]
+fn tag_errors() {
+    let mut parser = HtmlParser::new();
+
+    parse_all(&mut parser, "<a =b c=> </a x/> a < b <");
+    assert_eq!(error_codes(&parser), vec![
+        ParseErrorCode::UnexpectedEqualsSignBeforeAttributeName,
+        ParseErrorCode::MissingAttributeValue,
+        ParseErrorCode::EndTagWithAttributes,
+        ParseErrorCode::EndTagWithTrailingSolidus,
+        ParseErrorCode::InvalidFirstCharacterOfTagName,
+        ParseErrorCode::EofBeforeTagName,
+    ]);
+
+    parse_all(&mut parser, "<a b'=c d=e\"f / >");
+    assert_eq!(error_codes(&parser), vec![
+        ParseErrorCode::UnexpectedCharacterInAttributeName,
+        ParseErrorCode::UnexpectedCharacterInUnquotedAttributeValue,
+        ParseErrorCode::UnexpectedSolidusInTag,
+    ]);
+
+    // DOCTYPE is not a tag, so its parts are not checked as params
+    parse_all(&mut parser, "<!DOCTYPE html PUBLIC \"-//W3C//DTD XHTML 1.0 Strict//EN\">");
+    assert!(parser.errors().is_empty());
+}
+
+#[test]
+fn entities_are_decoded() {
+    let mut parser = HtmlParser::new();
+    parser.decode_entities = true;
+
+    assert_eq!(parse_all(&mut parser, "a &amp; b &lt;&notit; &#169;&#x263a; &unknown; &ampx")[0].2,
+        "a & b <\u{ac}it; \u{a9}\u{263a} &unknown; &x");
+    assert_eq!(error_codes(&parser), vec![
+        ParseErrorCode::MissingSemicolonAfterCharacterReference,
+        ParseErrorCode::UnknownNamedCharacterReference,
+        ParseErrorCode::MissingSemicolonAfterCharacterReference,
+    ]);
+    assert_eq!(parser.errors()[0].offset, 18);
+
+    parser.init("<a title='&quot;x&quot;'>&lt;b&gt;<textarea>&lt;</textarea><style>&lt;</style>");
+
+    let mut values = Vec::new();
+
+    while let Some(chunk) = parser.parse_next() {
+        if chunk.params_count > 0 {
+            values.push(chunk.param_values[0].clone());
+        } else if chunk.chunk_type == ChunkType::Text {
+            assert_eq!(chunk.lt_entity, chunk.html.starts_with('<'));
+            values.push(chunk.html.clone());
+        }
+    }
+
+    assert_eq!(values, vec!["\"x\"", "<b>", "<", "&lt;"]);
+
+    parser.decode_entities = false;
+    parser.decode_mini_entities = true;
+
+    assert_eq!(parse_all(&mut parser, "&lt;&copy;&#65;")[0].2, "<&copy;A");
+
+    parser.decode_mini_entities = false;
+
+    // errors are reported even if entities are not decoded
+    assert_eq!(parse_all(&mut parser, "&#;&lt;<a title='&copy'>")[0].2, "&#;&lt;");
+    assert_eq!(error_codes(&parser), vec![
+        ParseErrorCode::AbsenceOfDigitsInNumericCharacterReference,
+        ParseErrorCode::MissingSemicolonAfterCharacterReference,
+    ]);
+}
+
+#[test]
+fn xml_mode_keeps_case_and_closes_tags() {
+    let mut parser = HtmlParser::new();
+    parser.xml_mode = true;
+    parser.decode_entities = true;
+
+    let xml = "<?xml version=\"1.0\"?>\n<rss><Channel isPermaLink='false'/><script>a &lt; <b/>&nbsp;</script></rss>";
+
+    assert_eq!(parse_all(&mut parser, xml), vec![
+        (ChunkType::Comment, "?".to_string(), "xml version=\"1.0\"".to_string()),
+        (ChunkType::Text, String::new(), " ".to_string()),
+        (ChunkType::OpenTag, "rss".to_string(), String::new()),
+        (ChunkType::CloseTag, "Channel".to_string(), String::new()),
+        (ChunkType::OpenTag, "script".to_string(), String::new()),
+        (ChunkType::Text, String::new(), "a < ".to_string()),
+        (ChunkType::CloseTag, "b".to_string(), String::new()),
+        (ChunkType::Text, String::new(), "&nbsp;".to_string()),
+        (ChunkType::CloseTag, "script".to_string(), String::new()),
+        (ChunkType::CloseTag, "rss".to_string(), String::new()),
+    ]);
+    assert_eq!(error_codes(&parser), vec![ParseErrorCode::UndefinedEntity]);
+    assert_eq!(parser.current_chunk().tag, "rss");
+
+    parser.init(xml);
+
+    assert_eq!(parser.parse_next().unwrap().generate_html(), "<?xml version=\"1.0\"?>");
+
+    let xml = "<!DOCTYPE doc [\n<!ENTITY copy \"&#169;\">\n<!ENTITY % p 'x'>\n]><doc a=\"&copy;&amp;\">&copy;</doc>";
+
+    assert_eq!(parse_all(&mut parser, xml)[1..], [
+        (ChunkType::OpenTag, "doc".to_string(), String::new()),
+        (ChunkType::Text, String::new(), "\u{a9}".to_string()),
+        (ChunkType::CloseTag, "doc".to_string(), String::new()),
+    ]);
+    assert!(parser.errors().is_empty());
+
+    parser.init(xml);
+    parser.parse_next();
+
+    assert_eq!(parser.parse_next().unwrap().param_values[0], "\u{a9}&");
+}
+
+#[test]
+fn xml_mode_reports_well_formedness_errors() {
+    let mut parser = HtmlParser::new();
+    parser.xml_mode = true;
+    parser.decode_entities = true;
+
+    parse_all(&mut parser, "x<a b=1 c><i></a><a>&</a><b>");
+    assert_eq!(error_codes(&parser), vec![
+        ParseErrorCode::TextOutsideRootElement,
+        ParseErrorCode::UnquotedAttributeValue,
+        ParseErrorCode::AttributeWithoutValue,
+        ParseErrorCode::MismatchedEndTag,
+        ParseErrorCode::MultipleRootElements,
+        ParseErrorCode::UnescapedAmpersand,
+        ParseErrorCode::MultipleRootElements,
+        ParseErrorCode::UnclosedElement,
+    ]);
+    assert_eq!(parser.errors()[7].to_string(), "unclosed-element at offset 28");
+
+    let document = Document::parse_xml("<feed><br><entry><![CDATA[<p>]]></entry></br></feed>");
+    let br = document.find_element("br").unwrap();
+
+    assert_eq!(child_names(&document, br), vec!["entry"]);
+    assert_eq!(document.text_content(br), "<p>");
+    assert!(document.is_xml() && !Document::parse("<feed>").is_xml());
+
+    let document = Document::parse_xml("<rss><channel>\n<title>T</title>\n<head>h</head>\n<ol><li>x</li></ol>\
+        </channel></rss>");
+
+    assert_eq!(document.to_text(), "T h x");
+
+    let xml = "<rss><channel><link>http://x/?a&amp;b</link><title>T\u{A0}</title><meta>m</meta><br/><script>\
+        a &lt; b</script></channel></rss>";
+
+    assert_eq!(Document::parse_xml(xml).to_html(), xml);
+}
+
+#[test]
+fn xml_namespaces_are_resolved() {
+    let mut parser = HtmlParser::new();
+
+    parser.init("<meta property=og:image><og:image xml:lang=en>");
+
+    let chunk = parser.parse_next().unwrap();
+
+    assert_eq!((chunk.tag_prefix(), chunk.tag_local_name()), (None, "meta"));
+    assert_eq!(chunk.namespace_uri, None);
+
+    let chunk = parser.parse_next().unwrap();
+
+    assert_eq!((chunk.tag_prefix(), chunk.tag_local_name()), (Some("og"), "image"));
+    assert_eq!((chunk.param_prefix(0), chunk.param_local_name(0)), (Some("xml"), "lang"));
+    assert_eq!(chunk.param_namespace_uris[0], None);
+
+    parser.xml_mode = true;
+    parser.init("<feed xmlns='http://www.w3.org/2005/Atom' xmlns:x='urn:x'><x:a x:b='1' c='2'/>\
+        <e xmlns=''><f xml:lang='en'/></e></feed><g:h/>");
+
+    let mut uris = Vec::new();
+
+    while let Some(chunk) = parser.parse_next() {
+        uris.push((chunk.tag.clone(), chunk.namespace_uri.clone(), chunk.param_namespace_uris.clone()));
+    }
+
+    let uri = |uri: &str| Some(uri.to_string());
+
+    assert_eq!(uris, vec![
+        ("feed".to_string(), uri("http://www.w3.org/2005/Atom"),
+            vec![uri("http://www.w3.org/2000/xmlns/"), uri("http://www.w3.org/2000/xmlns/")]),
+        ("x:a".to_string(), uri("urn:x"), vec![uri("urn:x"), None]),
+        ("e".to_string(), None, vec![uri("http://www.w3.org/2000/xmlns/")]),
+        ("f".to_string(), None, vec![uri("http://www.w3.org/XML/1998/namespace")]),
+        ("e".to_string(), None, vec![]),
+        ("feed".to_string(), uri("http://www.w3.org/2005/Atom"), vec![]),
+        ("g:h".to_string(), None, vec![]),
+    ]);
+    assert_eq!(error_codes(&parser), vec![
+        ParseErrorCode::MultipleRootElements,
+        ParseErrorCode::UndeclaredNamespacePrefix,
+    ]);
+}
+
+#[test]
+fn template_code_is_kept_whole() {
+    let mut parser = HtmlParser::new();
+
+    parser.template_delimiters = vec![("{{".to_string(), "}}".to_string()), ("{%".to_string(), "%}".to_string()),
+        ("<?php".to_string(), "?>".to_string())];
+
+    let html = "<div {% if x > 1 %}class=\"a\"{% endif %} title=\"{{ \"a>b\" }}\" id={{ id }}>Hi {{ user.name }}!\
+        <?php echo '<b>'; ?></div>";
+
+    assert_eq!(parse_all(&mut parser, html), vec![
+        (ChunkType::OpenTag, "div".to_string(), String::new()),
+        (ChunkType::Text, String::new(), "Hi ".to_string()),
+        (ChunkType::Template, String::new(), "{{ user.name }}".to_string()),
+        (ChunkType::Text, String::new(), "!".to_string()),
+        (ChunkType::Template, String::new(), "<?php echo '<b>'; ?>".to_string()),
+        (ChunkType::CloseTag, "div".to_string(), String::new()),
+    ]);
+    assert!(parser.errors().is_empty());
+
+    parser.init(html);
+
+    let chunk = parser.parse_next().unwrap();
+    let params: Vec<_> = (0..chunk.params_count)
+        .map(|i| (chunk.param_names[i].as_str(), chunk.param_values[i].as_str()))
+        .collect();
+
+    assert_eq!(params, vec![("{% if x > 1 %}", ""), ("class", "a"), ("{% endif %}", ""), ("title", "{{ \"a>b\" }}"),
+        ("id", "{{ id }}")]);
+}
+
+#[test]
+fn strict_mode_stops_at_first_error() {
+    let mut parser = HtmlParser::new();
+    parser.strict = true;
+
+    let chunks = parse_all(&mut parser, "<p>ok</p><a x=1 x=2><b>");
+
+    assert_eq!(chunks.len(), 3);
+    assert_eq!(error_codes(&parser), vec![ParseErrorCode::DuplicateAttribute]);
+    assert!(parser.parse_next().is_none());
+    assert_eq!(parser.errors()[0].to_string(), "duplicate-attribute at offset 16");
+}
+
+#[test]
+fn conformance_mode_reports_error_codes() {
+    // vendored html5lib tokenizer tests only say whether there are errors, so codes are checked here
+    let cases = [
+        ("<a b=1 b=2>", vec![ParseErrorCode::DuplicateAttribute]),
+        ("<a", vec![ParseErrorCode::EofInTag]),
+        ("</>", vec![ParseErrorCode::MissingEndTagName]),
+        ("</a b>", vec![ParseErrorCode::EndTagWithAttributes]),
+        ("<a/ b>", vec![ParseErrorCode::UnexpectedSolidusInTag]),
+        ("<a b='c'd>", vec![ParseErrorCode::MissingWhitespaceBetweenAttributes]),
+        ("<?x>", vec![ParseErrorCode::UnexpectedQuestionMarkInsteadOfTagName]),
+        ("<!-- a", vec![ParseErrorCode::EofInComment]),
+        ("<!DOCTYPE>", vec![ParseErrorCode::MissingDoctypeName]),
+        ("&#x;", vec![ParseErrorCode::AbsenceOfDigitsInNumericCharacterReference]),
+        ("&#0;", vec![ParseErrorCode::NullCharacterReference]),
+        ("&notit;", vec![ParseErrorCode::MissingSemicolonAfterCharacterReference]),
+        ("a\0", vec![ParseErrorCode::UnexpectedNullCharacter]),
+    ];
+
+    for &(html, ref codes) in cases.iter() {
+        let mut parser = HtmlParser::new();
+
+        parser.conformance_mode = true;
+        parse_all(&mut parser, html);
+
+        assert_eq!(&error_codes(&parser), codes, "{}", html);
+    }
+}
+
+#[test]
+fn conformance_mode_switches_states() {
+    let mut parser = HtmlParser::new();
+
+    parser.conformance_mode = true;
+
+    let chunks = parse_all(&mut parser, "<!DOCTYPE html><script>a<b</script><title>&lt;x\r\n</title>");
+
+    assert_eq!(chunks, vec![
+        (ChunkType::Doctype, "!doctype".to_string(), "".to_string()),
+        (ChunkType::OpenTag, "script".to_string(), "".to_string()),
+        (ChunkType::Text, "".to_string(), "a<b".to_string()),
+        (ChunkType::CloseTag, "script".to_string(), "".to_string()),
+        (ChunkType::OpenTag, "title".to_string(), "".to_string()),
+        (ChunkType::Text, "".to_string(), "<x\n".to_string()),
+        (ChunkType::CloseTag, "title".to_string(), "".to_string()),
+    ]);
+
+    parser.init("<!DOCTYPE html><br/>x");
+
+    let doctype = parser.parse_next().unwrap().doctype.clone().unwrap();
+
+    assert_eq!(doctype.name, Some("html".to_string()));
+    assert_eq!(doctype.public_id, None);
+    assert!(!doctype.force_quirks);
+
+    let chunk = parser.parse_next().unwrap();
+
+    assert_eq!((chunk.chunk_type, chunk.end_closure), (ChunkType::OpenTag, true));
+    assert_eq!((chunk.chunk_offset, chunk.chunk_length), (15, 5));
+    assert_eq!(chunk.generate_html(), "<br>");
+}
+
+#[test]
+fn entities_in_params_keep_urls_intact() {
+    let html = "<a href='?a=1&copy=2&amp=3&not;&notx&lt' src=?b&amp1&copy>&copy=2";
+
+    for &conformance_mode in &[false, true] {
+        let mut parser = HtmlParser::new();
+        parser.decode_entities = true;
+        parser.conformance_mode = conformance_mode;
+        parser.init(html);
+
+        let chunk = parser.parse_next().unwrap();
+
+        assert_eq!(chunk.param_values[0], "?a=1&copy=2&amp=3\u{ac}&notx<");
+        assert_eq!(chunk.param_values[1], "?b&amp1\u{a9}");
+        assert_eq!(parser.parse_next().unwrap().html, "\u{a9}=2");
+        assert_eq!(error_codes(&parser), vec![
+            ParseErrorCode::MissingSemicolonAfterCharacterReference,
+            ParseErrorCode::MissingSemicolonAfterCharacterReference,
+            ParseErrorCode::MissingSemicolonAfterCharacterReference,
+        ]);
+    }
+}
+
+#[test]
+fn numeric_entities_follow_browsers() {
+    let mut parser = HtmlParser::new();
+    parser.decode_entities = true;
+
+    assert_eq!(parse_all(&mut parser, "&#146;&#x80;&#150;&#x81;&#0;&#xD800;&#x110000;&#9;")[0].2,
+        "\u{2019}\u{20AC}\u{2013}\u{81}\u{FFFD}\u{FFFD}\u{FFFD}\t");
+    assert_eq!(error_codes(&parser), vec![
+        ParseErrorCode::ControlCharacterReference,
+        ParseErrorCode::ControlCharacterReference,
+        ParseErrorCode::ControlCharacterReference,
+        ParseErrorCode::ControlCharacterReference,
+        ParseErrorCode::NullCharacterReference,
+        ParseErrorCode::SurrogateCharacterReference,
+        ParseErrorCode::CharacterReferenceOutsideUnicodeRange,
+    ]);
+    assert_eq!(parser.errors()[1].offset, 6);
+}
+
+#[test]
+fn input_preprocessing() {
+    let mut parser = HtmlParser::new();
+    let html = "a\r\nb\rc\0<p title='x\r\ny\u{FFFF}'>";
+
+    parser.normalize_newlines = true;
+    parser.invalid_chars = InvalidChars::Replace;
+    parser.init(html);
+
+    let chunk = parser.parse_next().unwrap();
+
+    assert_eq!(chunk.html, "a\nb\nc\u{FFFD}");
+    assert_eq!((chunk.chunk_offset, chunk.chunk_length), (0, 7));
+
+    let chunk = parser.parse_next().unwrap();
+
+    assert_eq!(chunk.param_values[0], "x\ny\u{FFFD}");
+    assert_eq!(chunk.params.as_ref().unwrap()["title"], "x\ny\u{FFFD}");
+    assert_eq!(chunk.chunk_offset, 7);
+
+    parser.invalid_chars = InvalidChars::Strip;
+    parser.normalize_newlines = false;
+
+    assert_eq!(parse_all(&mut parser, "a\r\n\0b")[0].2, "a\r\nb");
+}
+
+/// Returns names of element children of node or text of other nodes
+fn child_names(document: &Document, id: NodeId) -> Vec<String> {
+    document.children(id)
+        .map(|child| match document[child].data {
+            NodeData::Element(ref element) => element.name.clone(),
+            NodeData::Text(ref text) => format!("'{}'", text),
+            NodeData::Comment(ref comment) => format!("<!--{}-->", comment),
+            NodeData::Doctype(ref doctype) => format!("<!DOCTYPE {}>", doctype.name.clone().unwrap_or_default()),
+            NodeData::Document => "#document".to_string(),
+        })
+        .collect()
+}
+
+#[test]
+fn document_is_built_from_chunks() {
+    let html = "<!DOCTYPE html><html><body class=main><p>a &amp; b<br>c</p><!-- x -->\
+        <ul><li>1<li>2</ul></i><div/><script src=s.js>if (a < b) {}</script>";
+    let document = Document::parse(html);
+    let root = document.root();
+
+    assert_eq!(child_names(&document, root), vec!["<!DOCTYPE html>", "html"]);
+
+    let body = document.find_element("body").unwrap();
+
+    assert_eq!(document[body].as_element().unwrap().attribute("class"), Some("main"));
+    assert_eq!(document[body].offset, Some(21));
+    assert_eq!(document[body].length, 17);
+    assert_eq!(child_names(&document, body), vec!["p", "<!-- x -->", "ul", "div", "script"]);
+
+    let p = document.find_element("p").unwrap();
+
+    assert_eq!(child_names(&document, p), vec!["'a & b'", "br", "'c'"]);
+    assert_eq!(document.text_content(p), "a & bc");
+
+    // li is not closed by the next li in simple stack matching
+    let ul = document.find_element("ul").unwrap();
+    let li = document.child_elements(ul).next().unwrap();
+
+    assert_eq!(child_names(&document, li), vec!["'1'", "li"]);
+
+    let script = document.find_element("script").unwrap();
+
+    assert_eq!(document.text_content(script), "if (a < b) {}");
+    assert_eq!(document[script].parent(), Some(body));
+    assert_eq!(document[document[script].prev_sibling().unwrap()].name(), Some("div"));
+    assert_eq!(document.descendants(root).count(), 16);
+
+    // track and wbr are void, so content after them is not lost in them
+    let document = Document::parse("<p>a<wbr>b</p><video><track src=x>c</video>");
+
+    assert_eq!(document.to_html(), "<p>a<wbr>b</p><video><track src=\"x\">c</video>");
+}
+
+#[test]
+fn document_from_conformant_chunks() {
+    let mut parser = HtmlParser::new();
+
+    parser.conformance_mode = true;
+    parser.init("<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01//EN\"><title>a&lt;b</title>x\0y");
+
+    let document = Document::from_parser(&mut parser);
+    let doctype = document.children(document.root()).next().unwrap();
+
+    match document[doctype].data {
+        NodeData::Doctype(ref doctype) => {
+            assert_eq!(doctype.name, Some("html".to_string()));
+            assert_eq!(doctype.public_id, Some("-//W3C//DTD HTML 4.01//EN".to_string()));
+        },
+        ref data => panic!("unexpected node {:?}", data),
+    }
+
+    let title = document.find_element("title").unwrap();
+
+    assert_eq!(document.text_content(title), "a<b");
+
+    let text = document[title].next_sibling().unwrap();
+
+    assert_eq!(document[text].as_text(), Some("x\0y"));
+    assert_eq!(document[text].offset, Some(71));
+    assert_eq!(document[text].length, 3);
+
+    let lenient = Document::parse("<!doctype HTML system 'about:legacy-compat'>");
+
+    match lenient[lenient.children(lenient.root()).next().unwrap()].data {
+        NodeData::Doctype(ref doctype) => {
+            assert_eq!(doctype.name, Some("html".to_string()));
+            assert_eq!(doctype.system_id, Some("about:legacy-compat".to_string()));
+        },
+        ref data => panic!("unexpected node {:?}", data),
+    }
+}
+
+#[test]
+fn document_follows_tree_construction() {
+    let document = Document::parse_html5("<title>t</title><p><b>1<i>2</p>3</b>4</i><table>x<tr><td>5</table>");
+    let body = document.find_element("body").unwrap();
+
+    assert_eq!(document.quirks_mode(), QuirksMode::Quirks);
+    assert_eq!(child_names(&document, document.root()), vec!["html"]);
+    assert_eq!(child_names(&document, document.find_element("head").unwrap()), vec!["title"]);
+    assert_eq!(document[document.find_element("head").unwrap()].offset, None);
+
+    // b and i are reopened after p was closed, text is moved out of table
+    assert_eq!(child_names(&document, body), vec!["p", "b", "i", "'x'", "table"]);
+    assert_eq!(document.text_content(body), "1234x5");
+
+    let title = document.find_element("title").unwrap();
+
+    assert_eq!(document[title].offset, Some(0));
+    assert_eq!(document[title].length, 7);
+
+    let document = Document::parse_html5("<!DOCTYPE html><pre>\nline</pre><textarea>\n&lt;b></textarea>");
+
+    assert_eq!(document.quirks_mode(), QuirksMode::NoQuirks);
+    assert_eq!(document.text_content(document.find_element("pre").unwrap()), "line");
+    assert_eq!(document.text_content(document.find_element("textarea").unwrap()), "<b>");
+}
+
+#[test]
+fn fragment_is_parsed_in_context() {
+    let document = Document::parse_fragment("<td>1<td>2", "tr");
+
+    assert_eq!(child_names(&document, document.root()), vec!["td", "td"]);
+
+    let document = Document::parse_fragment("<b>&amp;</textarea>x", "TEXTAREA");
+
+    assert_eq!(child_names(&document, document.root()), vec!["'<b>&x'"]);
+
+    let mut parser = HtmlParser::new();
+
+    parser.conformance_mode = true;
+    parser.keep_comments = false;
+    parser.init("<li>a<!-- b --></ul>c");
+
+    let document = Document::fragment_from_parser(&mut parser, "ul");
+
+    assert_eq!(child_names(&document, document.root()), vec!["li"]);
+    assert_eq!(child_names(&document, document.find_element("li").unwrap()), vec!["'a'", "<!---->", "'c'"]);
+}
+
+#[test]
+fn svg_and_mathml_tags_get_namespaces() {
+    let mut parser = HtmlParser::new();
+    let mut tags = Vec::new();
+
+    parser.init("<p><svg><path d='x'/><clippath viewbox=0></clippath></svg><math><mi><b></b></mi></math>");
+
+    while let Some(chunk) = parser.parse_next() {
+        let params = (0..chunk.params_count).map(|i| chunk.param_names[i].clone()).collect::<Vec<_>>().join(",");
+
+        tags.push((chunk.chunk_type, chunk.tag.clone(), chunk.namespace, params));
+    }
+
+    let tag = |chunk_type, name: &str, namespace, params: &str| (chunk_type, name.to_string(), namespace, params.to_string());
+
+    assert_eq!(tags, vec![
+        tag(ChunkType::OpenTag, "p", Namespace::Html, ""),
+        tag(ChunkType::OpenTag, "svg", Namespace::Svg, ""),
+        tag(ChunkType::CloseTag, "path", Namespace::Svg, "d"),
+        tag(ChunkType::OpenTag, "clipPath", Namespace::Svg, "viewBox"),
+        tag(ChunkType::CloseTag, "clipPath", Namespace::Svg, ""),
+        tag(ChunkType::CloseTag, "svg", Namespace::Svg, ""),
+        tag(ChunkType::OpenTag, "math", Namespace::MathMl, ""),
+        tag(ChunkType::OpenTag, "mi", Namespace::MathMl, ""),
+        tag(ChunkType::OpenTag, "b", Namespace::Html, ""),
+        tag(ChunkType::CloseTag, "b", Namespace::Html, ""),
+        tag(ChunkType::CloseTag, "mi", Namespace::MathMl, ""),
+        tag(ChunkType::CloseTag, "math", Namespace::MathMl, ""),
+    ]);
+
+    let document = Document::parse_html5("<svg><foreignObject><p>a</p></foreignObject><![CDATA[b]]></svg>\
+        <![CDATA[c]]><math><p>d");
+    let svg = document.find_element("svg").unwrap();
+    let p = document.find_element("p").unwrap();
+
+    assert_eq!(document[svg].as_element().unwrap().namespace, Namespace::Svg);
+    assert_eq!(child_names(&document, svg), vec!["foreignObject", "'b'"]);
+    assert_eq!(document[p].as_element().unwrap().namespace, Namespace::Html);
+    assert_eq!(child_names(&document, document[svg].parent().unwrap()), vec!["svg", "<!--[CDATA[c]]-->", "math", "p"]);
+}
+
+#[test]
+fn css_selectors_match_elements() {
+    let document = Document::parse_html5("<ul id=list lang=en-US><li id=a class='x y'>1<li id=b data-v=Foo-bar>2\
+        <li id=c class=x><a id=d href='https://e.com/p.pdf'>3</a><li id=e><input id=f type=checkbox checked disabled>\
+        </ul><p id=g></p><svg id=h><clipPath id=i viewBox='0 0 1 1'/></svg>");
+
+    let ids = |selector: &str| -> Vec<String> {
+        document.select(selector).unwrap().into_iter()
+            .map(|id| document[id].as_element().unwrap().attribute("id").unwrap().to_string())
+            .collect()
+    };
+
+    assert_eq!(ids("li.x"), vec!["a", "c"]);
+    assert_eq!(ids("ul > li:not(.x, #e)"), vec!["b"]);
+    assert_eq!(ids("#a ~ li:nth-child(odd)"), vec!["c"]);
+    assert_eq!(ids("li + li:nth-last-child(-n + 2)"), vec!["c", "e"]);
+    assert_eq!(ids("li:nth-child(2 of .x)"), vec!["c"]);
+    assert_eq!(ids("[data-v|=foo i], [class~=y]"), vec!["a", "b"]);
+    assert_eq!(ids("a[href^=https][href$='.PDF' i][href*=\"e.com\"]"), vec!["d"]);
+    assert_eq!(ids("li:has(> a, :checked)"), vec!["c", "e"]);
+    assert_eq!(ids("li:has(+ li > input)"), vec!["c"]);
+    assert_eq!(ids(":is(p, input):where(:empty:disabled, :only-of-type)"), vec!["f", "g"]);
+    assert_eq!(ids("li:lang(en):first-child, a:link, :root:hover"), vec!["a", "d"]);
+    assert_eq!(ids("svg clipPath, svg clippath[viewbox]"), vec!["i"]);
+    assert_eq!(ids("\\#\\61"), Vec::<String>::new());
+    assert_eq!(ids("#\\61"), vec!["a"]);
+
+    let error = document.select("p::before").unwrap_err();
+
+    assert_eq!(error.to_string(), "pseudo-elements are not supported at offset 2");
+    assert_eq!(document.select("p:unknown").unwrap_err().offset, 1);
+    assert_eq!(document.select("p >").unwrap_err().offset, 3);
+    assert_eq!(document.select("li:nth-child(2x)").unwrap_err().offset, 13);
+}
+
+#[test]
+fn streaming_selector_matches_while_parsing() {
+    let html = "<div class=a><p id=x>1<b>2</b></p><p lang=en><br class=a><span class=a>3</div><p class=a>4";
+    let mut parser = HtmlParser::new();
+    let mut selector = StreamingSelector::new("div p:lang(en), p > .a:not(span)").unwrap();
+    let mut opened = Vec::new();
+    let mut text = String::new();
+
+    parser.init(html);
+
+    while let Some(chunk) = parser.parse_next() {
+        if selector.process(chunk) {
+            opened.push(chunk.tag.clone());
+        }
+
+        if chunk.chunk_type == ChunkType::Text && selector.is_inside_match() {
+            text.push_str(&chunk.html);
+        }
+    }
+
+    selector.finish(html.len());
+
+    let div_end = html.find("</div>").unwrap();
+
+    assert_eq!(opened, vec!["p", "br"]);
+    assert_eq!(text, "3");
+    assert_eq!(selector.take_matches(), vec![
+        SelectorMatch { tag: "p".to_string(), start: html.find("<p lang").unwrap(), end: div_end },
+        SelectorMatch { tag: "br".to_string(), start: html.find("<br").unwrap(), end: html.find("<span").unwrap() },
+    ]);
+    assert!(selector.take_matches().is_empty());
+
+    // void wbr does not make p its child
+    let html = "<div><wbr><p>x</p></div>";
+    let mut selector = StreamingSelector::new("div > p").unwrap();
+
+    parser.init(html);
+
+    while let Some(chunk) = parser.parse_next() {
+        selector.process(chunk);
+    }
+
+    selector.finish(html.len());
+
+    assert_eq!(selector.take_matches(), vec![SelectorMatch { tag: "p".to_string(), start: 10, end: 18 }]);
+
+    // end tags that HTML lets to omit are implied
+    let count_matches = |selector: &str, html: &str| {
+        let mut parser = HtmlParser::new();
+        let mut selector = StreamingSelector::new(selector).unwrap();
+
+        parser.init(html);
+
+        while let Some(chunk) = parser.parse_next() {
+            selector.process(chunk);
+        }
+
+        selector.finish(html.len());
+        selector.take_matches().len()
+    };
+
+    assert_eq!(count_matches("ul > li", "<ul><li>a<li>b</ul>"), 2);
+    assert_eq!(count_matches("li li", "<ul><li>a<li>b</ul>"), 0);
+    assert_eq!(count_matches("li li", "<ul><li>a<ul><li>b</ul></ul>"), 1);
+    assert_eq!(count_matches("div > p", "<div><p>a<p>b</div>"), 2);
+    assert_eq!(count_matches("p span", "<p>a<div><span>"), 0);
+    assert_eq!(count_matches("tr > td", "<table><tr><td>1<td>2<tr><td>3</table>"), 3);
+    assert_eq!(count_matches("select > option", "<select><option>1<option>2<optgroup><option>3</select>"), 2);
+
+    let error = StreamingSelector::new("li + li").err().unwrap();
+
+    assert_eq!(error.to_string(), "sibling combinators are not supported by streaming selector at offset 3");
+    assert_eq!(StreamingSelector::new("ul p:first-child").err().unwrap().offset, 4);
+    assert_eq!(StreamingSelector::new(":is(a, :has(b))").err().unwrap().offset, 7);
+    assert!(StreamingSelector::new("ul:not(.a) > li[href^=x]").is_ok());
+}
+
+#[test]
+fn xpath_expressions_are_evaluated() {
+    let document = Document::parse_html5("<div id=main class=list lang=en><p>First <b>bold</b></p>\
+        <p class=x>  Second \n para </p><a href='/a'>A</a><a href='/b' title=t>B</a></div><!--note-->");
+
+    let string = |expression: &str| document.xpath(expression).unwrap().string(&document);
+    let names = |expression: &str| -> Vec<String> {
+        match document.xpath(expression).unwrap() {
+            XPathValue::NodeSet(nodes) => nodes.iter().map(|&node| match node {
+                XPathNode::Node(id) => document[id].name().unwrap_or("#node").to_string(),
+                XPathNode::Attribute(..) => format!("@{}", node.string_value(&document)),
+            }).collect(),
+            value => panic!("{:?} is not node set", value),
+        }
+    };
+
+    assert_eq!(names("//p"), vec!["p", "p"]);
+    assert_eq!(names("/html/body/DIV/*[position() > 2]"), vec!["a", "a"]);
+    assert_eq!(names("//a[contains(@href, 'b')]/@title | //div/@id"), vec!["@main", "@t"]);
+    assert_eq!(names("//b/ancestor::*[2] | //p[last()]/following-sibling::a[1]"), vec!["div", "a"]);
+    assert_eq!(names("(//a)[2]/preceding-sibling::*[2]/preceding::node()"), vec!["head", "p", "#node", "b", "#node"]);
+    assert_eq!(names("//p[b]/following::*[self::a or @class = 'x']"), vec!["p", "a", "a"]);
+    assert_eq!(names("//text()[normalize-space() = 'A']/.."), vec!["a"]);
+    assert_eq!(names("//comment()"), vec!["#node"]);
+
+    assert_eq!(document.xpath("count(//div[@id='main']/p)").unwrap(), XPathValue::Number(2.0));
+    assert_eq!(document.xpath("1 + 2 * 3 - 10 div 4 mod 2").unwrap(), XPathValue::Number(6.5));
+    assert_eq!(document.xpath("//p = 'First bold' and not(//table)").unwrap(), XPathValue::Boolean(true));
+    assert_eq!(document.xpath("//a/@href != '/a'").unwrap(), XPathValue::Boolean(true));
+    assert_eq!(document.xpath("//p[2]/b > 0 or lang('EN')").unwrap(), XPathValue::Boolean(false));
+    assert_eq!(document.xpath("//p[lang('en')] = //p[1]").unwrap(), XPathValue::Boolean(true));
+
+    assert_eq!(string("normalize-space(//p[@class='x'])"), "Second para");
+    assert_eq!(string("substring-after(//a[2]/@href, '/')"), "b");
+    assert_eq!(string("concat(substring('12345', 1.5, 2.6), translate('abc', 'abc', 'AB'))"), "234AB");
+    assert_eq!(string("string-length(//comment()) div 0"), "Infinity");
+    assert_eq!(string("-round(-2.5) * 1.5"), "3");
+    assert_eq!(string("name(id('main')) = local-name(//div)"), "true");
+
+    let error = document.xpath("//p[").unwrap_err();
+
+    assert_eq!(error.to_string(), "expected expression at offset 4");
+    assert_eq!(document.xpath("//p/foo()").unwrap_err().offset, 4);
+    assert_eq!(document.xpath("//p and $x").unwrap_err().message, "variables are not supported");
+    assert_eq!(document.xpath("count('a')").unwrap_err().offset, 0);
+    assert_eq!(document.xpath("string(1, 2)").unwrap_err().offset, 0);
+}
+
+#[test]
+fn documents_are_mutated_and_serialized() {
+    let mut document = Document::parse_html5("<!DOCTYPE html><title>a&amp;b</title><ul><li>1<li>2</ul>\
+        <p class=x>T&lt;x&gt;\u{A0}<br><script>if (a < b) {}</script><!--c--><svg><path/></svg>");
+
+    let body = document.find_element("body").unwrap();
+
+    assert_eq!(document.inner_html(body), "<ul><li>1</li><li>2</li></ul><p class=\"x\">T&lt;x&gt;&nbsp;<br>\
+        <script>if (a < b) {}</script><!--c--><svg><path></path></svg></p>");
+
+    let ul = document.find_element("ul").unwrap();
+    let p = document.find_element("p").unwrap();
+    let first = document[ul].first_child().unwrap();
+    let second = document[ul].last_child().unwrap();
+
+    document.append_child(ul, first);
+    document.set_text(second, "<two>");
+    document.insert_after(p, ul);
+
+    let b = document.create_node(NodeData::Element(Element::new("b")));
+
+    document.prepend_child(b, first);
+    document.prepend_child(p, b);
+    document.replace(document.find_element("br").unwrap(), first);
+    document.remove_children(document.find_element("svg").unwrap());
+    document.detach(document.find_element("script").unwrap());
+
+    {
+        let element = document.node_mut(p).as_element_mut().unwrap();
+
+        element.set_attribute("title", "say \"hi\" & <bye>");
+        element.set_attribute("class", "y");
+        assert_eq!(element.remove_attribute("class"), Some("y".to_string()));
+        assert_eq!(element.remove_attribute("class"), None);
+    }
+
+    assert_eq!(document.outer_html(p), "<p title=\"say &quot;hi&quot; &amp; &lt;bye&gt;\"><b></b>\
+        T&lt;x&gt;&nbsp;<li>1</li><!--c--><svg></svg></p>");
+    assert_eq!(document.to_html(), "<!DOCTYPE html><html><head><title>a&amp;b</title></head><body>\
+        <p title=\"say &quot;hi&quot; &amp; &lt;bye&gt;\"><b></b>T&lt;x&gt;&nbsp;<li>1</li><!--c--><svg></svg></p>\
+        <ul><li>&lt;two&gt;</li></ul></body></html>");
+}
+
+/// Returns names of children of node walking them backwards, so sibling links are checked both ways
+fn child_names_backwards(document: &Document, id: NodeId) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut child = document[id].last_child();
+
+    while let Some(current) = child {
+        assert_eq!(document[current].parent(), Some(id));
+        names.insert(0, document[current].name().unwrap_or_default().to_string());
+        child = document[current].prev_sibling();
+    }
+
+    names
+}
+
+#[test]
+fn nodes_already_in_place_are_not_moved() {
+    let mut document = Document::parse("<div><a></a><b></b><i></i></div>");
+    let div = document.find_element("div").unwrap();
+    let a = document.find_element("a").unwrap();
+    let b = document.find_element("b").unwrap();
+    let i = document.find_element("i").unwrap();
+
+    document.insert_after(a, b);
+    document.insert_before(b, a);
+    document.insert_before(b, b);
+    document.insert_after(b, b);
+    document.prepend_child(div, a);
+    document.append_child(div, i);
+    document.replace(b, b);
+
+    assert_eq!(child_names(&document, div), vec!["a", "b", "i"]);
+    assert_eq!(child_names_backwards(&document, div), vec!["a", "b", "i"]);
+
+    document.insert_after(i, a);
+    document.insert_before(b, i);
+    document.prepend_child(div, b);
+
+    assert_eq!(child_names(&document, div), vec!["b", "i", "a"]);
+    assert_eq!(child_names_backwards(&document, div), vec!["b", "i", "a"]);
+    assert_eq!(document.descendants(document.root()).count(), 4);
+}
+
+#[test]
+#[should_panic(expected = "into itself or its descendant")]
+fn ancestor_can_not_be_appended_to_descendant() {
+    let mut document = Document::parse("<div><p><b></b></p></div>");
+    let div = document.find_element("div").unwrap();
+    let b = document.find_element("b").unwrap();
+
+    document.append_child(b, div);
+}
+
+#[test]
+#[should_panic(expected = "into itself or its descendant")]
+fn node_can_not_be_inserted_into_itself() {
+    let mut document = Document::parse("<div><p></p></div>");
+    let p = document.find_element("p").unwrap();
+
+    document.prepend_child(p, p);
+}
+
+#[test]
+fn html_is_rewritten_while_streaming() {
+    let html = "<!DOCTYPE html><html><body>\n<a href='/x?a=1&amp;b=2' id=a>link</a>\n\
+        <IMG SRC=pic.png><img src=\"keep.png\" alt='x'/>\n\
+        <div class=ad><p>gone<b>bold</b></p></div><div class=wrap><span>kept</span></div>\n\
+        <script src=\"t.js\"></script><script>var a = '<p>';</script>\n\
+        <ul><li>one<li>two</ul><p>end";
+
+    let mut links = Vec::new();
+    let mut rewriter = HtmlRewriter::new();
+
+    rewriter.on("a[href]", |element| {
+        links.push(element.attribute("href").unwrap().to_string());
+        element.set_attribute("rel", "nofollow");
+    }).unwrap();
+    rewriter.on("img[src=\"pic.png\"]", |element| {
+        assert!(!element.can_have_content());
+        element.set_attribute("src", "big.png");
+        element.after("<br>", ContentType::Html);
+    }).unwrap();
+    rewriter.on("div.ad", |element| element.replace("<ad>", ContentType::Text)).unwrap();
+    rewriter.on("div.wrap", |element| element.remove_and_keep_content()).unwrap();
+    rewriter.on("span", |element| {
+        element.prepend("[", ContentType::Html);
+        element.append("]", ContentType::Html);
+    }).unwrap();
+    rewriter.on("script[src]", |element| element.remove()).unwrap();
+    rewriter.on("script:not([src])", |element| element.set_inner_content("a < b", ContentType::Html)).unwrap();
+    rewriter.on("li", |element| element.append("!", ContentType::Text)).unwrap();
+    rewriter.on("ul li", |element| element.before("*", ContentType::Text)).unwrap();
+    rewriter.on("body > p", |element| element.set_inner_content("the end", ContentType::Text)).unwrap();
+
+    assert!(rewriter.on("a + b", |_| {}).is_err());
+
+    let output = rewriter.rewrite_to_string(html);
+
+    drop(rewriter);
+
+    // the second li closes the first one like in browsers
+    assert_eq!(output, "<!DOCTYPE html><html><body>\n\
+        <a href=\"/x?a=1&amp;b=2\" id=\"a\" rel=\"nofollow\">link</a>\n\
+        <img src=\"big.png\"><br><img src=\"keep.png\" alt='x'/>\n\
+        &lt;ad&gt;<span>[kept]</span>\n\
+        <script>a < b</script>\n\
+        <ul>*<li>one!*<li>two!</ul><p>the end");
+    assert_eq!(links, vec!["/x?a=1&b=2".to_string()]);
+
+    // untouched HTML is copied as it is
+    let mut output = Vec::new();
+
+    HtmlRewriter::new().rewrite(html, &mut output).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), html);
+    // HTML is taken from parser, so data that is not UTF-8 is copied as it is
+    let mut parser = HtmlParser::new();
+    let mut rewriter = HtmlRewriter::new();
+    let mut output = Vec::new();
+
+    rewriter.on("b", |element| element.set_attribute("class", "y")).unwrap();
+    parser.init_bytes(b"<p>caf\xe9</p><b>x</b>".to_vec());
+    rewriter.rewrite_from_parser(&mut parser, &mut output).unwrap();
+
+    assert_eq!(output, b"<p>caf\xe9</p><b class=\"y\">x</b>".to_vec());
+}
+
+#[test]
+fn rewriter_closes_elements_implicitly() {
+    let mut rewriter = HtmlRewriter::new();
+
+    rewriter.on("li", |element| element.append("!", ContentType::Text)).unwrap();
+    rewriter.on("p", |element| element.append("?", ContentType::Text)).unwrap();
+    rewriter.on("td", |element| element.after("|", ContentType::Text)).unwrap();
+
+    assert_eq!(rewriter.rewrite_to_string("<ul><li>a<li>b</ul>"), "<ul><li>a!<li>b!</ul>");
+    assert_eq!(rewriter.rewrite_to_string("<p>a<div>b</div><p>c<span>d</span><p>e"), "<p>a?<div>b</div><p>c\
+        <span>d</span>?<p>e?");
+    assert_eq!(rewriter.rewrite_to_string("<table><tr><td>a<td>b<tr><td>c</table>"), "<table><tr><td>a|<td>b|\
+        <tr><td>c|</table>");
+    // li in nested list does not close li of outer list
+    assert_eq!(rewriter.rewrite_to_string("<li>a<ul><li>b</ul><li>c"), "<li>a<ul><li>b!</ul>!<li>c!");
+    // elements are not closed implicitly in SVG
+    assert_eq!(rewriter.rewrite_to_string("<svg><td>a<td>b</svg>"), "<svg><td>a<td>b||</svg>");
+}
+
+#[test]
+fn rewriter_rewrites_html_given_in_parts() {
+    let html = "<!DOCTYPE html><p class=x>caf\u{e9} &amp; <b title='a > b'>bold</b>\n\
+        <!-- <p> comment --><script>if (a < b) { c = '</p>'; }</script><ul><li>one<li>two</ul>\
+        <img src=pic.png><textarea><p></textarea>tail";
+
+    let mut rewriter = HtmlRewriter::new();
+
+    rewriter.on("p", |element| element.set_attribute("class", "y")).unwrap();
+    rewriter.on("b", |element| element.set_inner_content("<strong>", ContentType::Text)).unwrap();
+    rewriter.on("li", |element| element.append("!", ContentType::Text)).unwrap();
+    rewriter.on("img", |element| element.after("<br>", ContentType::Html)).unwrap();
+    rewriter.on("script", |element| element.remove()).unwrap();
+
+    let expected = rewriter.rewrite_to_string(html);
+
+    let mut parser = HtmlParser::new();
+
+    parser.decode_entities = true;
+
+    for size in 1..html.len() + 1 {
+        let mut stream = rewriter.stream(&mut parser, Vec::new());
+
+        // parts do not have to end at boundaries of chars
+        for part in html.as_bytes().chunks(size) {
+            stream.write(part).unwrap();
+        }
+
+        assert_eq!(String::from_utf8(stream.end().unwrap()).unwrap(), expected);
+    }
+
+    assert_eq!(expected, "<!DOCTYPE html><p class=\"y\">caf\u{e9} &amp; <b title='a > b'>&lt;strong&gt;</b>\n\
+        <!-- <p> comment --><ul><li>one!<li>two!</ul><img src=pic.png><br><textarea><p></textarea>tail");
+
+    // parser keeps only data that was not parsed yet
+    parser.clean_up();
+    parser.more_data = true;
+    parser.push_bytes(b"<p>ab");
+    assert_eq!(parser.parse_next().map(|chunk| chunk.tag.clone()), Some("p".to_string()));
+    assert!(parser.parse_next().is_none());
+    assert_eq!(parser.push_bytes(b"c<br>"), 3);
+    assert_eq!(parser.html_bytes(), Some(&b"abc<br>"[..]));
+    assert_eq!(parser.parse_next().map(|chunk| (chunk.html.clone(), chunk.chunk_offset)), Some(("abc".to_string(), 0)));
+    assert!(parser.parse_next().is_none());
+
+    parser.more_data = false;
+    assert_eq!(parser.parse_next().map(|chunk| chunk.tag.clone()), Some("br".to_string()));
+    assert!(parser.parse_next().is_none());
+}
+
+#[test]
+fn html_is_written_with_escaping_and_balanced_tags() {
+    let mut writer = HtmlWriter::new(Vec::new());
+
+    writer.raw("<!DOCTYPE html>").unwrap();
+    writer.start_tag("p", vec![("title", "\"a\" & <b>"), ("id", "x")]).unwrap();
+    writer.text("1 < 2 & 3 > 2\u{A0}").unwrap();
+    writer.start_tag("br", None).unwrap();
+    writer.start_tag("script", None).unwrap();
+    writer.text("if (a < b) {}").unwrap();
+    writer.end_tag().unwrap();
+    writer.comment("a -- b -").unwrap();
+    writer.start_tag("b", None).unwrap();
+    assert_eq!(writer.open_elements(), ["p", "b"]);
+    writer.end_tag_named("p").unwrap();
+    assert!(writer.end_tag().is_err());
+    writer.start_tag("ul", None).unwrap();
+    writer.start_tag("li", None).unwrap();
+
+    assert_eq!(String::from_utf8(writer.finish().unwrap()).unwrap(), "<!DOCTYPE html>\
+        <p title=\"&quot;a&quot; &amp; &lt;b&gt;\" id=\"x\">1 &lt; 2 &amp; 3 &gt; 2&nbsp;<br>\
+        <script>if (a < b) {}</script><!--a - - b - --><b></b></p><ul><li></li></ul>");
+
+    let mut writer = HtmlWriter::new(Vec::new());
+
+    writer.auto_close = false;
+    writer.xml_mode = true;
+    writer.start_tag("br", vec![("a", "\u{A0}")]).unwrap();
+    writer.text("\u{A0}").unwrap();
+    assert_eq!(writer.finish().unwrap(), "<br a=\"\u{A0}\">\u{A0}".as_bytes());
+
+    // chunks without comments and onclick params are written again
+    let mut parser = HtmlParser::new();
+    let mut writer = HtmlWriter::new(Vec::new());
+
+    parser.decode_entities = true;
+    parser.init("<!DOCTYPE html><div class=a onclick='x()'>a &amp; b<!-- c --><br/><img src=\"i.png\">\
+        <script>a < b</script><svg><path d=\"M0\"/></svg></span><p>end");
+
+    while let Some(chunk) = parser.parse_next() {
+        if chunk.chunk_type == ChunkType::Comment {
+            continue;
+        }
+
+        let onclick = chunk.param_names.iter().take(chunk.params_count).any(|name| name == "onclick");
+
+        if chunk.chunk_type == ChunkType::OpenTag && onclick {
+            let attributes = chunk.param_names.iter().zip(chunk.param_values.iter())
+                .take(chunk.params_count)
+                .filter(|&(name, _)| name != "onclick")
+                .map(|(name, value)| (name.as_str(), value.as_str()));
+
+            writer.start_tag(&chunk.tag, attributes).unwrap();
+        } else {
+            writer.write_chunk(chunk).unwrap();
+        }
+    }
+
+    assert_eq!(String::from_utf8(writer.finish().unwrap()).unwrap(), "<!DOCTYPE html><div class=\"a\">\
+        a &amp; b<br><img src=\"i.png\"><script>a < b</script><svg><path d=\"M0\"></path></svg><p>end</p></div>");
+    // comments and text of raw text elements can not end early
+    let mut writer = HtmlWriter::new(Vec::new());
+
+    writer.comment(">x").unwrap();
+    writer.comment("a--!>b").unwrap();
+    writer.start_tag("script", None).unwrap();
+    writer.text("s = '</SCRIPT>';").unwrap();
+    writer.end_tag().unwrap();
+
+    assert_eq!(String::from_utf8(writer.finish().unwrap()).unwrap(),
+        "<!-- >x--><!--a- -!>b--><script>s = '<\\/SCRIPT>';</script>");
+
+    // chunks that have their tags are written as they are
+    let mut writer = HtmlWriter::new(Vec::new());
+
+    parser.extract_between_tags_only = false;
+    writer.extract_between_tags_only = false;
+    parser.init("<p><script src=s.js>a</script><!-- c --></p>");
+
+    while let Some(chunk) = parser.parse_next() {
+        writer.write_chunk(chunk).unwrap();
+    }
+
+    assert_eq!(String::from_utf8(writer.finish().unwrap()).unwrap(), "<p><script src=s.js>a</script><!-- c --></p>");
+
+    // names that would end tags early and end tags in style are errors, nothing is written then
+    let mut writer = HtmlWriter::new(Vec::new());
+
+    assert!(writer.start_tag("a onclick=x", None).is_err());
+    assert!(writer.start_tag("a>", None).is_err());
+    assert!(writer.start_tag("", None).is_err());
+    assert!(writer.start_tag("a", vec![("onclick=x title", "y")]).is_err());
+    assert!(writer.start_tag("a", vec![("b>", "y")]).is_err());
+    assert!(writer.open_elements().is_empty());
+    writer.start_tag("style", None).unwrap();
+    assert_eq!(writer.text("a </STYLE> b").unwrap_err().kind(), ::std::io::ErrorKind::InvalidInput);
+    writer.text("p:after { content: '<\\/p>' }").unwrap();
+
+    assert_eq!(String::from_utf8(writer.finish().unwrap()).unwrap(), "<style>p:after { content: '<\\/p>' }</style>");
+}
+
+#[test]
+fn html_is_formatted() {
+    let html = "<!DOCTYPE html><html><head><title>T</title><style>p  {  }</style></head><body>\n\n<!-- c -->\
+        <div id=main class=\"x y\" data-note=\"some long value\"><p>Hello,   <b>big</b>\n world</p><p></p>\
+        <ul><li>one</li><li>two</li></ul><pre>  keep\n   this</pre><span>inline <div>block</div> tail</span>\
+        text<br>after &amp; <img src=a.png></div></body></html>";
+
+    let mut formatter = HtmlFormatter::new();
+
+    assert_eq!(formatter.format(html), "<!DOCTYPE html>\n<html>\n  <head>\n    <title>T</title>\n\
+        \x20   <style>p  {  }</style>\n  </head>\n  <body>\n    <!-- c -->\n\
+        \x20   <div id=\"main\" class=\"x y\" data-note=\"some long value\">\n\
+        \x20     <p>Hello, <b>big</b> world</p>\n      <p></p>\n      <ul>\n        <li>one</li>\n\
+        \x20       <li>two</li>\n      </ul>\n      <pre>  keep\n   this</pre>\n      <span>\n        inline\n\
+        \x20       <div>block</div>\n        tail\n      </span>\n\
+        \x20     text<br>after &amp; <img src=\"a.png\">\n    </div>\n  </body>\n</html>\n");
+
+    formatter.indent_width = 0;
+    formatter.max_line_length = 20;
+    formatter.sort_attributes = true;
+
+    let document = Document::parse("<div id=main class=\"x y\"><p>Some text that is long</p>\
+        <textarea> a\n b </textarea></div>");
+
+    assert_eq!(formatter.format_document(&document), "<div\n\tclass=\"x y\"\n\tid=\"main\">\n\t<p>\n\
+        \t\tSome text that is\n\t\tlong\n\t</p>\n\t<textarea> a\n b </textarea>\n</div>\n");
+
+    // inline tags and text runs are wrapped too
+    formatter.indent_width = 2;
+    formatter.max_line_length = 40;
+    formatter.sort_attributes = false;
+
+    let html = "<input zeta alpha class=\"some-class-name\" id=\"identifier-of-input\"><p>Some words <b>and \
+        bold words</b> that go on and on <a href=\"a-very-long-link-target.html\" title=\"link\">x</a>.</p>";
+
+    assert_eq!(formatter.format(html), "<input\n  zeta=\"\"\n  alpha=\"\"\n  class=\"some-class-name\"\n\
+        \x20 id=\"identifier-of-input\">\n<p>\n  Some words <b>and bold words</b> that\n  go on and on <a\n\
+        \x20   href=\"a-very-long-link-target.html\"\n    title=\"link\">x</a>.\n</p>\n");
+
+    // end tags are where browsers put them, SVG title is not block
+    let formatter = HtmlFormatter::new();
+
+    assert_eq!(formatter.format("<p>a<div>b</div><ul><li>1<li>2</ul><center>c</center><svg><title>t</title></svg>"),
+        "<p>a</p>\n<div>b</div>\n<ul>\n  <li>1</li>\n  <li>2</li>\n</ul>\n<center>c</center>\n\
+        <svg><title>t</title></svg>\n");
+    assert_eq!(formatter.format("<body class=x><p>a"), "<body class=\"x\">\n  <p>a</p>\n</body>\n");
+}
+
+/// Parses HTML following WHATWG tree construction and serializes it without whitespace between tags
+fn html5_tree_without_whitespace(html: &str) -> String {
+    let html = Document::parse_html5(html).to_html();
+
+    html.split_whitespace().collect::<Vec<_>>().join(" ").replace("> ", ">").replace(" <", "<")
+}
+
+#[test]
+fn html_is_minified() {
+    let html = "<!DOCTYPE html>\n<html>\n  <head>\n    <title> My   page </title>\n\
+        \x20   <script type=\"text/javascript\">var a  =  1;</script>\n  </head>\n  <body>\n    <!-- comment -->\n\
+        \x20   <!--[if IE]><p>ie</p><![endif]-->\n    <div class=\"a b\" id=\"main\" data-x=\"\">\n\
+        \x20     <p>Hello,   <b>big</b>\n world &amp; co</p>\n      <p>second</p>\n\
+        \x20     <ul>\n        <li>one</li>\n        <li>two</li>\n      </ul>\n      <pre>  keep\n   this</pre>\n\
+        \x20     <input type=\"text\" disabled=\"disabled\" value='say \"hi\"'>\n\
+        \x20     <table><tr><td>1</td><td>2</td></tr></table>\n      <a href=x><p>in link</p></a>\n\
+        \x20     <svg><path d='M0'/></svg>\n    </div>\n  </body>\n</html>\n";
+
+    let mut minifier = HtmlMinifier::new();
+
+    assert_eq!(minifier.minify(html), "<!DOCTYPE html><html><head><title> My page </title>\
+        <script>var a  =  1;</script><body><!--[if IE]><p>ie</p><![endif]-->\
+        <div class=\"a b\" id=main data-x><p>Hello, <b>big</b> world &amp; co<p>second<ul><li>one<li>two</ul>\
+        <pre>  keep\n   this</pre><input disabled value='say \"hi\"'><table><tr><td>1<td>2</table>\
+        <a href=x><p>in link</p></a> <svg><path d=M0 /></svg></div>");
+
+    // apart from whitespace, tree stays the same when comments and attributes are not removed or collapsed
+    minifier.remove_comments = false;
+    minifier.remove_default_attributes = false;
+    minifier.collapse_boolean_attributes = false;
+
+    assert_eq!(html5_tree_without_whitespace(&minifier.minify(html)), html5_tree_without_whitespace(html));
+
+    let mut minifier = HtmlMinifier::new();
+
+    minifier.collapse_whitespace = false;
+    minifier.remove_comments = false;
+    minifier.remove_optional_tags = false;
+    minifier.remove_attribute_quotes = false;
+    minifier.shorten_doctype = true;
+
+    let html = "<!DOCTYPE HTML PUBLIC \"-//W3C//DTD HTML 4.01//EN\">\n<p a=\"b\" c=''> x <!-- c --></p>";
+
+    assert_eq!(minifier.minify(html), "<!doctype html>\n<p a=\"b\" c=\"\"> x <!-- c --></p>");
+    // p breaks out of svg, so the stray </p> after it makes an empty p that must not be lost
+    let minifier = HtmlMinifier::new();
+    let html = "<p>x<svg><p>y</p></svg></p><ul><li>a<math><mi>b</mi></math></li><li>c</li></ul>";
+    let minified = minifier.minify(html);
+
+    assert_eq!(minified, "<p>x<svg><p>y</p></svg></p><ul><li>a<math><mi>b</mi></math><li>c</ul>");
+    assert_eq!(html5_tree_without_whitespace(&minified), html5_tree_without_whitespace(html));
+
+    // whitespace next to ul and tr is not rendered either
+    let minified = minifier.minify("<ul> <li>a</li> </ul> <span>b</span><table><tr><td>c</td></tr> <tr> </tr></table>");
+
+    assert_eq!(minified, "<ul><li>a</ul><span>b</span><table><tr><td>c<tr></table>");
+}
+
+#[test]
+fn documents_are_converted_to_text() {
+    let html = "<html><head><title>T</title><style>p {}</style></head><body>\n<h1>Title &amp; more</h1>\n\
+        <p>Some   <b>bold</b>\n text.</p><p>Line<br>break</p><ul>\n<li>one</li>\n<li>two<ol start=3><li>a</li>\
+        <li>b</li></ol></li></ul><script>var x;</script><noscript>no</noscript><table>\n<tr><th>Name</th>\n\
+        <th>Age</th></tr>\n<tr><td>Bob</td> <td>42</td></tr></table><pre>  a\n   b</pre>end <span>x</span></body></html>";
+
+    let document = Document::parse(html);
+
+    assert_eq!(document.to_text(), "Title & more\n\nSome bold text.\n\nLine\nbreak\n\n* one\n* two\n  3. a\n\
+        \x20 4. b\nName\tAge\nBob\t42\n  a\n   b\nend x");
+    assert_eq!(document.inner_text(document.find_element("ol").unwrap()), "3. a\n4. b");
+    assert_eq!(Document::parse("a<br><br>b <textarea> c  d </textarea>").to_text(), "a\n\nb  c  d ");
+    assert_eq!(Document::parse("<ol start=5 reversed><li>a<li>b</ol>").to_text(), "5. a\n4. b");
+    assert_eq!(Document::parse("<ol reversed><li>a</li><li>b</li><li>c</li></ol>").to_text(), "3. a\n2. b\n1. c");
+    assert_eq!(Document::parse("<ol reversed><li>a<li>b</ol>").to_text(), "2. a\n1. b");
+    assert_eq!(Document::parse("<ol reversed><div><li>a</li><li>b<ul><li>c</li></ul></li></div></ol>").to_text(),
+        "2. a\n1. b\n  * c");
+}
+
+#[test]
+fn html_is_converted_to_markdown() {
+    let html = "<html><head><base href='http://example.com/docs/guide/'><title>T</title></head><body>\n\
+        <h1>Intro &amp; <em>more</em></h1>\n<p>Some <strong>bold </strong>and <code>a`b</code> with 1*2.</p>\n\
+        <p><a href='../api.html#x' title='API'>the API</a>, <a href='http://a.org'>http://a.org</a> and \
+        <img src='pic.png' alt='[pic]'></p><ul><li>one</li><li>two<ol start=3><li>a</li><li>b</li></ol></li></ul>\
+        <blockquote><p>quoted</p><p>twice<br>over</p></blockquote><pre><code class='language-rust'>fn main() {\n}\n\
+        </code></pre><table><tr><th>Name</th><th>A|B</th></tr><tr><td>Bob</td><td><s>42</s></td></tr></table>\
+        <table><tr><td colspan=2>x</td></tr></table><p>H<sub>2</sub>O</p><hr><div># not heading</div></body></html>";
+
+    assert_eq!(MarkdownConverter::new().convert(html), "# Intro & *more*\n\nSome **bold** and ``a`b`` with 1\\*2.\n\n\
+        [the API](http://example.com/docs/api.html#x \"API\"), <http://a.org> and \
+        ![\\[pic\\]](http://example.com/docs/guide/pic.png)\n\n- one\n- two\n  3. a\n  4. b\n\n> quoted\n>\n\
+        > twice\\\n> over\n\n```rust\nfn main() {\n}\n```\n\n| Name | A\\|B |\n| --- | --- |\n| Bob | ~~42~~ |\n\n\
+        <table><tbody><tr><td colspan=\"2\">x</td></tr></tbody></table>\n\nH<sub>2</sub>O\n\n---\n\n\\# not heading\n");
+
+    let mut converter = MarkdownConverter::new();
+
+    converter.keep_html = false;
+    converter.bullet = '*';
+    converter.base_url = Some("https://x.org/a/b".to_string());
+
+    assert_eq!(converter.convert("<p>1. not <i>list</i></p><ul><li><p>a</p></li><li><a href=c>c</a></li></ul>\
+        <p>x<sup>2</sup><br></p><pre>```</pre>"), "1\\. not *list*\n\n* a\n\n* [c](https://x.org/a/c)\n\nx2\n\n\
+        ````\n```\n````\n");
+
+    // end tags that HTML lets to omit
+    assert_eq!(MarkdownConverter::new().convert("<p>a<p>b<ul><li>c<li>d</ul><ol><li>one<li>two<li>three</ol>\
+        <table><tr><th>x<th>y<tr><td>1<td>2</table>"), "a\n\nb\n\n- c\n- d\n\n1. one\n2. two\n3. three\n\n\
+        | x | y |\n| --- | --- |\n| 1 | 2 |\n");
+
+    // href of base element is relative to URL of the document
+    let mut converter = MarkdownConverter::new();
+
+    converter.base_url = Some("https://x.org/a/b".to_string());
+
+    assert_eq!(converter.convert("<base href=' ../c/ '><a href=' g '>g</a><base href='/d/'>"),
+        "[g](https://x.org/c/g)\n");
+    assert_eq!(yahap::markdown::resolve_url(" http://x.org/a/b ", " g "), "http://x.org/a/g");
+}
+
+fn assert_tokenizer_tests(file: &str) {
+    let failures = html5lib::tokenizer::run(file);
+
+    assert!(failures.is_empty(), "{} of {} failed:\n{}", failures.len(), file, failures.join("\n"));
+}
+
+#[test]
+fn html5lib_tokenizer_test1() {
+    assert_tokenizer_tests("test1.test");
+}
+
+#[test]
+fn html5lib_tokenizer_test2() {
+    assert_tokenizer_tests("test2.test");
+}
+
+#[test]
+fn html5lib_tokenizer_test3() {
+    assert_tokenizer_tests("test3.test");
+}
+
+#[test]
+fn html5lib_tokenizer_test4() {
+    assert_tokenizer_tests("test4.test");
+}
+
+#[test]
+fn html5lib_tokenizer_content_model_flags() {
+    assert_tokenizer_tests("contentModelFlags.test");
+}
+
+#[test]
+fn html5lib_tokenizer_domjs() {
+    assert_tokenizer_tests("domjs.test");
+}
+
+#[test]
+fn html5lib_tokenizer_entities() {
+    assert_tokenizer_tests("entities.test");
+}
+
+#[test]
+fn html5lib_tokenizer_named_entities() {
+    assert_tokenizer_tests("namedEntities.test");
+}
+
+#[test]
+fn html5lib_tokenizer_numeric_entities() {
+    assert_tokenizer_tests("numericEntities.test");
+}
+
+#[test]
+fn html5lib_tokenizer_escape_flag() {
+    assert_tokenizer_tests("escapeFlag.test");
+}
+
+#[test]
+fn html5lib_tokenizer_pending_spec_changes() {
+    assert_tokenizer_tests("pendingSpecChanges.test");
+}
+
+#[test]
+fn html5lib_tokenizer_unicode_chars() {
+    assert_tokenizer_tests("unicodeChars.test");
+}
+
+#[test]
+fn html5lib_tokenizer_unicode_chars_problematic() {
+    assert_tokenizer_tests("unicodeCharsProblematic.test");
+}
+
+fn assert_tree_construction_tests(file: &str) {
+    let failures = html5lib::tree_construction::run(file);
+
+    assert!(failures.is_empty(), "{} of {} failed:\n{}", failures.len(), file, failures.join("\n"));
+}
+
+#[test]
+fn html5lib_tree_adoption01() {
+    assert_tree_construction_tests("adoption01.dat");
+}
+
+#[test]
+fn html5lib_tree_adoption02() {
+    assert_tree_construction_tests("adoption02.dat");
+}
+
+#[test]
+fn html5lib_tree_comments01() {
+    assert_tree_construction_tests("comments01.dat");
+}
+
+#[test]
+fn html5lib_tree_doctype01() {
+    assert_tree_construction_tests("doctype01.dat");
+}
+
+#[test]
+fn html5lib_tree_domjs_unsafe() {
+    assert_tree_construction_tests("domjs-unsafe.dat");
+}
+
+#[test]
+fn html5lib_tree_entities01() {
+    assert_tree_construction_tests("entities01.dat");
+}
+
+#[test]
+fn html5lib_tree_entities02() {
+    assert_tree_construction_tests("entities02.dat");
+}
+
+#[test]
+fn html5lib_tree_foreign_fragment() {
+    assert_tree_construction_tests("foreign-fragment.dat");
+}
+
+#[test]
+fn html5lib_tree_html5test_com() {
+    assert_tree_construction_tests("html5test-com.dat");
+}
+
+#[test]
+fn html5lib_tree_inbody01() {
+    assert_tree_construction_tests("inbody01.dat");
+}
+
+#[test]
+fn html5lib_tree_isindex() {
+    assert_tree_construction_tests("isindex.dat");
+}
+
+#[test]
+fn html5lib_tree_main_element() {
+    assert_tree_construction_tests("main-element.dat");
+}
+
+#[test]
+fn html5lib_tree_math() {
+    assert_tree_construction_tests("math.dat");
+}
+
+#[test]
+fn html5lib_tree_menuitem_element() {
+    assert_tree_construction_tests("menuitem-element.dat");
+}
+
+#[test]
+fn html5lib_tree_namespace_sensitivity() {
+    assert_tree_construction_tests("namespace-sensitivity.dat");
+}
+
+#[test]
+fn html5lib_tree_noscript01() {
+    assert_tree_construction_tests("noscript01.dat");
+}
+
+#[test]
+fn html5lib_tree_pending_spec_changes_plain_text_unsafe() {
+    assert_tree_construction_tests("pending-spec-changes-plain-text-unsafe.dat");
+}
+
+#[test]
+fn html5lib_tree_pending_spec_changes() {
+    assert_tree_construction_tests("pending-spec-changes.dat");
+}
+
+#[test]
+fn html5lib_tree_plain_text_unsafe() {
+    assert_tree_construction_tests("plain-text-unsafe.dat");
+}
+
+#[test]
+fn html5lib_tree_ruby() {
+    assert_tree_construction_tests("ruby.dat");
+}
+
+#[test]
+fn html5lib_tree_scriptdata01() {
+    assert_tree_construction_tests("scriptdata01.dat");
+}
+
+#[test]
+fn html5lib_tree_tables01() {
+    assert_tree_construction_tests("tables01.dat");
+}
+
+#[test]
+fn html5lib_tree_template() {
+    assert_tree_construction_tests("template.dat");
+}
+
+#[test]
+fn html5lib_tree_tests1() {
+    assert_tree_construction_tests("tests1.dat");
+}
+
+#[test]
+fn html5lib_tree_tests10() {
+    assert_tree_construction_tests("tests10.dat");
+}
+
+#[test]
+fn html5lib_tree_tests11() {
+    assert_tree_construction_tests("tests11.dat");
+}
+
+#[test]
+fn html5lib_tree_tests12() {
+    assert_tree_construction_tests("tests12.dat");
+}
+
+#[test]
+fn html5lib_tree_tests14() {
+    assert_tree_construction_tests("tests14.dat");
+}
+
+#[test]
+fn html5lib_tree_tests15() {
+    assert_tree_construction_tests("tests15.dat");
+}
+
+#[test]
+fn html5lib_tree_tests16() {
+    assert_tree_construction_tests("tests16.dat");
+}
+
+#[test]
+fn html5lib_tree_tests17() {
+    assert_tree_construction_tests("tests17.dat");
+}
+
+#[test]
+fn html5lib_tree_tests18() {
+    assert_tree_construction_tests("tests18.dat");
+}
+
+#[test]
+fn html5lib_tree_tests19() {
+    assert_tree_construction_tests("tests19.dat");
+}
+
+#[test]
+fn html5lib_tree_tests2() {
+    assert_tree_construction_tests("tests2.dat");
+}
+
+#[test]
+fn html5lib_tree_tests20() {
+    assert_tree_construction_tests("tests20.dat");
+}
+
+#[test]
+fn html5lib_tree_tests21() {
+    assert_tree_construction_tests("tests21.dat");
+}
+
+#[test]
+fn html5lib_tree_tests22() {
+    assert_tree_construction_tests("tests22.dat");
+}
+
+#[test]
+fn html5lib_tree_tests23() {
+    assert_tree_construction_tests("tests23.dat");
+}
+
+#[test]
+fn html5lib_tree_tests24() {
+    assert_tree_construction_tests("tests24.dat");
+}
+
+#[test]
+fn html5lib_tree_tests25() {
+    assert_tree_construction_tests("tests25.dat");
+}
+
+#[test]
+fn html5lib_tree_tests26() {
+    assert_tree_construction_tests("tests26.dat");
+}
+
+#[test]
+fn html5lib_tree_tests3() {
+    assert_tree_construction_tests("tests3.dat");
+}
+
+#[test]
+fn html5lib_tree_tests4() {
+    assert_tree_construction_tests("tests4.dat");
+}
+
+#[test]
+fn html5lib_tree_tests5() {
+    assert_tree_construction_tests("tests5.dat");
+}
+
+#[test]
+fn html5lib_tree_tests6() {
+    assert_tree_construction_tests("tests6.dat");
+}
+
+#[test]
+fn html5lib_tree_tests7() {
+    assert_tree_construction_tests("tests7.dat");
+}
+
+#[test]
+fn html5lib_tree_tests8() {
+    assert_tree_construction_tests("tests8.dat");
+}
+
+#[test]
+fn html5lib_tree_tests9() {
+    assert_tree_construction_tests("tests9.dat");
+}
+
+#[test]
+fn html5lib_tree_tests_innerhtml_1() {
+    assert_tree_construction_tests("tests_innerHTML_1.dat");
+}
+
+#[test]
+fn html5lib_tree_tricky01() {
+    assert_tree_construction_tests("tricky01.dat");
+}
+
+#[test]
+fn html5lib_tree_webkit01() {
+    assert_tree_construction_tests("webkit01.dat");
+}
+
+#[test]
+fn html5lib_tree_webkit02() {
+    assert_tree_construction_tests("webkit02.dat");
+}